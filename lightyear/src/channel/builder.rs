@@ -42,6 +42,7 @@ pub struct ChannelContainer {
 ///     mode: ChannelMode::UnorderedUnreliable,
 ///     direction: ChannelDirection::Bidirectional,
 ///     priority: 1.0,
+///     ..default()
 /// });
 /// ```
 pub trait Channel: 'static {
@@ -121,6 +122,11 @@ pub struct ChannelSettings {
     pub direction: ChannelDirection,
     /// Sets the priority of the channel. The final priority of a message will be `MessagePriority * ChannelPriority`
     pub priority: f32,
+    /// What to do with the messages of this channel that could not be sent because of the bandwidth quota
+    pub unsent_message_policy: UnsentMessagePolicy,
+    /// If set, the packets containing messages of this channel are protected with forward error correction,
+    /// so that the receiver can rebuild a lost packet without waiting for a resend
+    pub fec: Option<FecSettings>,
 }
 
 impl Default for ChannelSettings {
@@ -129,10 +135,56 @@ impl Default for ChannelSettings {
             mode: ChannelMode::UnorderedUnreliable,
             direction: ChannelDirection::Bidirectional,
             priority: 1.0,
+            unsent_message_policy: UnsentMessagePolicy::default(),
+            fec: None,
         }
     }
 }
 
+/// [`FecSettings`] enable forward error correction (FEC) for a channel.
+///
+/// The packets that contain messages from FEC channels are grouped together, and after every `group_size` packets
+/// we send an extra parity packet (the XOR of all the packets in the group).
+/// If a single packet of the group is lost, the receiver can rebuild it from the other packets and the parity packet.
+///
+/// This is mostly useful for latency-critical unreliable channels with frequent small messages,
+/// at the cost of sending one extra packet every `group_size` packets.
+/// Packets that are too big for the parity packet to fit in the MTU (for example packets containing
+/// message fragments) are not protected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FecSettings {
+    /// Number of packets protected by each parity packet.
+    /// If multiple channels use FEC, the smallest group size is used for the connection.
+    pub group_size: u8,
+}
+
+impl Default for FecSettings {
+    fn default() -> Self {
+        Self { group_size: 4 }
+    }
+}
+
+/// [`UnsentMessagePolicy`] specifies what happens to the messages that could not be sent
+/// because the bandwidth quota was reached.
+///
+/// This only applies to unreliable channels: reliable channels already keep
+/// resending their messages until they are acked.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum UnsentMessagePolicy {
+    /// The messages are discarded
+    #[default]
+    Drop,
+    /// The messages are kept and we will try to send them again during the next send.
+    /// Their priority keeps getting accumulated while they are buffered, so that they don't get starved.
+    ///
+    /// At most `max_messages` messages (each fragment counts as one message) are buffered; when the buffer is full the
+    /// oldest messages are discarded first.
+    Buffer { max_messages: usize },
+    /// Only the newest message is kept and we will try to send it again during the next send.
+    /// This is useful for channels where only the latest state matters.
+    KeepNewest,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 /// ChannelMode specifies how messages are sent and received
 /// See more information [here](http://www.jenkinssoftware.com/raknet/manual/reliabilitytypes.html)
@@ -4,6 +4,7 @@ use anyhow::Result;
 use bytes::Bytes;
 use tracing::trace;
 
+use crate::packet::limits::DecodeError;
 use crate::packet::message::{FragmentData, MessageId, SingleData};
 use crate::packet::packet::FRAGMENT_SIZE;
 use crate::shared::time_manager::WrappedTime;
@@ -32,20 +33,56 @@ impl FragmentReceiver {
         })
     }
 
+    /// Number of fragmented messages that are currently being reassembled
+    pub fn num_pending_messages(&self) -> usize {
+        self.fragment_messages.len()
+    }
+
+    /// Discard the fragmented message that received a fragment the longest time ago.
+    /// Returns false if there was no message to discard
+    pub fn evict_oldest(&mut self) -> bool {
+        let Some(message_id) = self
+            .fragment_messages
+            .iter()
+            .min_by_key(|(message_id, c)| (c.last_received, **message_id))
+            .map(|(message_id, _)| *message_id)
+        else {
+            return false;
+        };
+        self.fragment_messages.remove(&message_id);
+        true
+    }
+
     pub fn receive_fragment(
         &mut self,
         fragment: FragmentData,
         current_time: Option<WrappedTime>,
     ) -> Result<Option<SingleData>> {
+        let invalid_fragment = DecodeError::InvalidFragment {
+            message_id: fragment.message_id,
+            fragment_id: fragment.fragment_id,
+            num_fragments: fragment.num_fragments,
+        };
+        // every fragment except the last one is full
+        if fragment.fragment_id >= fragment.num_fragments
+            || fragment.bytes.len() > FRAGMENT_SIZE
+            || (!fragment.is_last_fragment() && fragment.bytes.len() != FRAGMENT_SIZE)
+        {
+            return Err(invalid_fragment.into());
+        }
         let fragment_message = self
             .fragment_messages
             .entry(fragment.message_id)
             .or_insert_with(|| FragmentConstructor::new(fragment.num_fragments as usize));
+        // all the fragments of a message must agree on the number of fragments
+        if fragment_message.num_fragments != fragment.num_fragments as usize {
+            return Err(invalid_fragment.into());
+        }
 
         // completed the fragmented message!
         if let Some(payload) = fragment_message.receive_fragment(
             fragment.fragment_id as usize,
-            fragment.bytes.as_ref(),
+            fragment.bytes,
             current_time,
         )? {
             self.fragment_messages.remove(&fragment.message_id);
@@ -66,9 +103,9 @@ impl FragmentReceiver {
 pub struct FragmentConstructor {
     num_fragments: usize,
     num_received_fragments: usize,
-    received: Vec<bool>,
-    // bytes: Bytes,
-    bytes: Vec<u8>,
+    /// The fragments received so far. We only allocate the message once all the fragments are received,
+    /// so that a remote peer cannot make us allocate memory for fragments that it never sends.
+    fragments: Vec<Option<Bytes>>,
 
     last_received: Option<WrappedTime>,
 }
@@ -78,8 +115,7 @@ impl FragmentConstructor {
         Self {
             num_fragments,
             num_received_fragments: 0,
-            received: vec![false; num_fragments],
-            bytes: vec![0; num_fragments * FRAGMENT_SIZE],
+            fragments: vec![None; num_fragments],
             last_received: None,
         }
     }
@@ -87,31 +123,24 @@ impl FragmentConstructor {
     pub fn receive_fragment(
         &mut self,
         fragment_index: usize,
-        bytes: &[u8],
+        bytes: Bytes,
         received_time: Option<WrappedTime>,
     ) -> Result<Option<Bytes>> {
         self.last_received = received_time;
 
-        let is_last_fragment = fragment_index == self.num_fragments - 1;
-        // TODO: check sizes?
-
-        if !self.received[fragment_index] {
-            self.received[fragment_index] = true;
+        let fragment = &mut self.fragments[fragment_index];
+        if fragment.is_none() {
+            *fragment = Some(bytes);
             self.num_received_fragments += 1;
-
-            if is_last_fragment {
-                let len = (self.num_fragments - 1) * FRAGMENT_SIZE + bytes.len();
-                self.bytes.resize(len, 0);
-            }
-
-            let start = fragment_index * FRAGMENT_SIZE;
-            let end = start + bytes.len();
-            self.bytes[start..end].copy_from_slice(bytes);
         }
 
         if self.num_received_fragments == self.num_fragments {
             trace!("Received all fragments!");
-            let payload = std::mem::take(&mut self.bytes);
+            let fragments = std::mem::take(&mut self.fragments);
+            let mut payload = Vec::with_capacity(self.num_fragments * FRAGMENT_SIZE);
+            for bytes in fragments.iter().flatten() {
+                payload.extend_from_slice(bytes);
+            }
             return Ok(Some(payload.into()));
         }
 
@@ -145,4 +174,42 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_receive_invalid_fragment() {
+        let mut receiver = FragmentReceiver::new();
+        let message_bytes = Bytes::from(vec![1u8; FRAGMENT_SIZE * 2]);
+        let fragments =
+            FragmentSender::new().build_fragments(MessageId(0), None, message_bytes, 0.0);
+
+        // the fragment id is out of bounds
+        let mut fragment = fragments[0].clone();
+        fragment.fragment_id = 2;
+        let error = receiver.receive_fragment(fragment, None).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::InvalidFragment {
+                message_id: MessageId(0),
+                fragment_id: 2,
+                num_fragments: 2,
+            })
+        );
+
+        // a fragment that is not the last one must be full
+        let mut fragment = fragments[0].clone();
+        fragment.bytes = Bytes::from(vec![1u8; 10]);
+        assert!(receiver.receive_fragment(fragment, None).is_err());
+
+        // all the fragments must agree on the number of fragments
+        assert_eq!(
+            receiver
+                .receive_fragment(fragments[0].clone(), None)
+                .unwrap(),
+            None
+        );
+        assert_eq!(receiver.num_pending_messages(), 1);
+        let mut fragment = fragments[1].clone();
+        fragment.num_fragments = 3;
+        assert!(receiver.receive_fragment(fragment, None).is_err());
+    }
 }
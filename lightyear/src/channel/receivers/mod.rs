@@ -35,6 +35,15 @@ pub trait ChannelReceive {
 
     /// Reads a message from the internal buffer to get its content
     fn read_message(&mut self) -> Option<SingleData>;
+
+    /// Number of fragmented messages that are currently being reassembled
+    fn num_pending_fragmented_messages(&self) -> usize;
+
+    /// Discard the oldest fragmented message that is being reassembled, if the channel is unreliable
+    /// (the message could have been lost anyway). Returns false if no message was discarded
+    fn evict_oldest_fragmented_message(&mut self) -> bool {
+        false
+    }
 }
 
 /// This enum contains the various types of receivers available
@@ -77,6 +77,10 @@ impl ChannelReceive for OrderedReliableReceiver {
         self.pending_recv_message_id += 1;
         Some(message)
     }
+
+    fn num_pending_fragmented_messages(&self) -> usize {
+        self.fragment_receiver.num_pending_messages()
+    }
 }
 
 #[cfg(test)]
@@ -75,6 +75,10 @@ impl ChannelReceive for SequencedReliableReceiver {
             }
         }
     }
+
+    fn num_pending_fragmented_messages(&self) -> usize {
+        self.fragment_receiver.num_pending_messages()
+    }
 }
 
 #[cfg(test)]
@@ -74,6 +74,14 @@ impl ChannelReceive for SequencedUnreliableReceiver {
         self.recv_message_buffer.pop_front()
         // TODO: naia does a more optimized version by return a Vec<Message> instead of Option<Message>
     }
+
+    fn num_pending_fragmented_messages(&self) -> usize {
+        self.fragment_receiver.num_pending_messages()
+    }
+
+    fn evict_oldest_fragmented_message(&mut self) -> bool {
+        self.fragment_receiver.evict_oldest()
+    }
 }
 
 #[cfg(test)]
@@ -83,6 +83,14 @@ impl ChannelReceive for TickUnreliableReceiver {
             .map(|(_, data)| data)
         // TODO: naia does a more optimized version by return a Vec<Message> instead of Option<Message>
     }
+
+    fn num_pending_fragmented_messages(&self) -> usize {
+        self.fragment_receiver.num_pending_messages()
+    }
+
+    fn evict_oldest_fragmented_message(&mut self) -> bool {
+        self.fragment_receiver.evict_oldest()
+    }
 }
 
 #[cfg(test)]
@@ -99,6 +99,10 @@ impl ChannelReceive for UnorderedReliableReceiver {
         // receive oldest message in the buffer
         Some(message)
     }
+
+    fn num_pending_fragmented_messages(&self) -> usize {
+        self.fragment_receiver.num_pending_messages()
+    }
 }
 
 #[cfg(test)]
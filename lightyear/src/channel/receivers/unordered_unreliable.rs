@@ -50,6 +50,14 @@ impl ChannelReceive for UnorderedUnreliableReceiver {
     fn read_message(&mut self) -> Option<SingleData> {
         self.recv_message_buffer.pop_front()
     }
+
+    fn num_pending_fragmented_messages(&self) -> usize {
+        self.fragment_receiver.num_pending_messages()
+    }
+
+    fn evict_oldest_fragmented_message(&mut self) -> bool {
+        self.fragment_receiver.evict_oldest()
+    }
 }
 
 #[cfg(test)]
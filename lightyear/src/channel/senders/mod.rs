@@ -53,6 +53,18 @@ pub trait ChannelSend {
 
     /// Create a new receiver that will receive a message id when a sent message is acked
     fn subscribe_acks(&mut self) -> Receiver<MessageId>;
+
+    /// Returns the number of messages that were buffered or sent, but not acked yet.
+    /// Only reliable senders keep track of this
+    fn num_messages_in_flight(&self) -> usize {
+        0
+    }
+
+    /// Returns the number of messages (or fragments) that were resent because they were not acked in time.
+    /// Only reliable senders resend messages
+    fn num_messages_resent(&self) -> u64 {
+        0
+    }
 }
 
 /// Enum dispatch lets us derive ChannelSend on each enum variant
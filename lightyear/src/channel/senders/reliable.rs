@@ -61,6 +61,8 @@ pub struct ReliableSender {
 
     current_rtt: Duration,
     current_time: WrappedTime,
+    /// Number of messages (or fragments) that had to be resent because they were not acked in time
+    num_messages_resent: u64,
 }
 
 impl ReliableSender {
@@ -75,6 +77,7 @@ impl ReliableSender {
             fragment_sender: FragmentSender::new(),
             current_rtt: Duration::default(),
             current_time: WrappedTime::default(),
+            num_messages_resent: 0,
         }
     }
 }
@@ -206,6 +209,9 @@ impl ChannelSend for ReliableSender {
                             );
                             self.single_messages_to_send.push_back(message);
                             self.message_ids_to_send.insert(message_info);
+                            if last_sent.is_some() {
+                                self.num_messages_resent += 1;
+                            }
                             *last_sent = Some(self.current_time);
                         }
                     }
@@ -225,6 +231,9 @@ impl ChannelSend for ReliableSender {
                                 let message = f.data.clone();
                                 self.fragmented_messages_to_send.push_back(message);
                                 self.message_ids_to_send.insert(message_info);
+                                if f.last_sent.is_some() {
+                                    self.num_messages_resent += 1;
+                                }
                                 f.last_sent = Some(self.current_time);
                             }
                         })
@@ -268,6 +277,14 @@ impl ChannelSend for ReliableSender {
     fn subscribe_acks(&mut self) -> Receiver<MessageId> {
         todo!()
     }
+
+    fn num_messages_in_flight(&self) -> usize {
+        self.unacked_messages.len()
+    }
+
+    fn num_messages_resent(&self) -> u64 {
+        self.num_messages_resent
+    }
 }
 
 #[cfg(test)]
@@ -308,10 +325,18 @@ mod tests {
         sender.current_time += Duration::from_millis(200);
         sender.collect_messages_to_send();
         assert_eq!(sender.single_messages_to_send.len(), 1);
+        // the message is still waiting to be sent, so it is not collected again
+        assert_eq!(sender.num_messages_resent(), 0);
         assert_eq!(
             sender.single_messages_to_send.front().unwrap(),
             &SingleData::new(Some(MessageId(0)), message1.clone(), 1.0)
         );
+        // once the message has been sent, collecting it again counts as a resend
+        sender.send_packet();
+        sender.current_time += Duration::from_millis(200);
+        sender.collect_messages_to_send();
+        assert_eq!(sender.single_messages_to_send.len(), 1);
+        assert_eq!(sender.num_messages_resent(), 1);
 
         // Ack the first message
         sender.notify_message_delivered(&MessageAck {
@@ -319,6 +344,7 @@ mod tests {
             fragment_id: None,
         });
         assert_eq!(sender.unacked_messages.len(), 0);
+        assert_eq!(sender.num_messages_in_flight(), 0);
 
         // Advance by a time that is above the resend threshold
         sender.current_time += Duration::from_millis(200);
@@ -11,6 +11,8 @@ use crate::client::prediction::plugin::PredictionConfig;
 use crate::client::replication::ReplicationConfig;
 use crate::client::sync::SyncConfig;
 use crate::connection::client::NetConfig;
+use crate::packet::header::AckBitfieldSize;
+use crate::packet::limits::DecodeLimits;
 use crate::shared::config::{Mode, SharedConfig};
 use crate::shared::ping::manager::PingConfig;
 
@@ -56,6 +58,11 @@ pub struct PacketConfig {
     pub send_bandwidth_cap: Quota,
     /// If false, there is no bandwidth cap and all messages are sent as soon as possible
     pub bandwidth_cap_enabled: bool,
+    /// Number of packet ids (before the last received packet id) that we would like to ack in each packet header.
+    /// The window is negotiated with the server when the client connects, see [`AckBitfieldSize`]
+    pub ack_bitfield_size: AckBitfieldSize,
+    /// Limits applied to the messages received from the server
+    pub decode_limits: DecodeLimits,
 }
 
 impl Default for PacketConfig {
@@ -64,6 +71,8 @@ impl Default for PacketConfig {
             // 56 KB/s bandwidth cap
             send_bandwidth_cap: Quota::per_second(nonzero!(56000u32)),
             bandwidth_cap_enabled: false,
+            ack_bitfield_size: AckBitfieldSize::default(),
+            decode_limits: DecodeLimits::default(),
         }
     }
 }
@@ -84,6 +93,16 @@ impl PacketConfig {
         self.bandwidth_cap_enabled = true;
         self
     }
+
+    pub fn with_ack_bitfield_size(mut self, ack_bitfield_size: AckBitfieldSize) -> Self {
+        self.ack_bitfield_size = ack_bitfield_size;
+        self
+    }
+
+    pub fn with_decode_limits(mut self, decode_limits: DecodeLimits) -> Self {
+        self.decode_limits = decode_limits;
+        self
+    }
 }
 
 /// The configuration object that lets you create a `ClientPlugin` with the desired settings.
@@ -6,30 +6,42 @@ use bevy::prelude::{Entity, Local, Resource, World};
 use bevy::reflect::Reflect;
 use bevy::utils::Duration;
 use serde::Serialize;
-use tracing::{debug, info, trace, trace_span, warn};
+use tracing::{debug, error, info, trace, trace_span, warn};
 
-use crate::_reexport::{ClientMarker, EntityUpdatesChannel, PingChannel, ReplicationSend};
+use crate::_reexport::{
+    ClientMarker, EntityActionsChannel, EntityUpdatesChannel, PingChannel, ReplicationSend,
+};
+use crate::channel::builder::ChannelDirection;
 use crate::channel::senders::ChannelSend;
 use crate::client::config::PacketConfig;
 use crate::client::message::ClientMessage;
+use crate::client::replication::ReplicationConfig;
 use crate::client::sync::SyncConfig;
 use crate::inputs::native::input_buffer::InputBuffer;
+use crate::packet::header::AckBitfieldSize;
 use crate::packet::message_manager::MessageManager;
 use crate::packet::packet::Packet;
 use crate::packet::packet_manager::Payload;
 use crate::prelude::{Channel, ChannelKind, ClientId, Message, NetworkTarget};
 use crate::protocol::channel::ChannelRegistry;
+use crate::protocol::component::ComponentProtocol;
+use crate::protocol::registration::{map_registered_message_entities, RegisteredMessageIds};
 use crate::protocol::Protocol;
 use crate::serialize::reader::ReadBuffer;
 use crate::server::message::ServerMessage;
 use crate::shared::events::connection::ConnectionEvents;
+use crate::shared::handshake::HandshakeMessage;
 use crate::shared::ping::manager::{PingConfig, PingManager};
 use crate::shared::ping::message::SyncMessage;
-use crate::shared::replication::components::{Replicate, ReplicationGroupId};
+use crate::shared::replication::authority::Authority;
+use crate::shared::replication::components::{
+    Replicate, ReplicateRemoveBehaviour, ReplicationGroupId,
+};
 use crate::shared::replication::receive::ReplicationReceiver;
 use crate::shared::replication::send::ReplicationSender;
 use crate::shared::replication::ReplicationMessage;
 use crate::shared::replication::ReplicationMessageData;
+use crate::shared::stats::ConnectionStats;
 use crate::shared::tick_manager::Tick;
 use crate::shared::tick_manager::TickManager;
 use crate::shared::time_manager::TimeManager;
@@ -57,9 +69,12 @@ use super::sync::SyncManager;
 #[derive(Resource)]
 pub struct ConnectionManager<P: Protocol> {
     pub(crate) message_manager: MessageManager,
+    registered_messages: RegisteredMessageIds,
     pub(crate) replication_sender: ReplicationSender<P>,
     pub(crate) replication_receiver: ReplicationReceiver<P>,
     pub(crate) events: ConnectionEvents<P>,
+    /// What happens to the server entity when `Replicate` is removed from a client entity
+    replicate_remove_behaviour: ReplicateRemoveBehaviour,
 
     pub(crate) ping_manager: PingManager,
     pub(crate) sync_manager: SyncManager,
@@ -73,9 +88,31 @@ impl<P: Protocol> ConnectionManager<P> {
         sync_config: SyncConfig,
         ping_config: PingConfig,
         input_delay_ticks: u16,
+        replication_config: &ReplicationConfig,
+        registered_messages: RegisteredMessageIds,
     ) -> Self {
         // create the message manager and the channels
-        let mut message_manager = MessageManager::new(channel_registry, packet_config.into());
+        let ack_bitfield_size = packet_config.ack_bitfield_size;
+        let decode_limits = packet_config.decode_limits;
+        let mut message_manager = MessageManager::new(channel_registry, packet_config.into())
+            .with_decode_limits(decode_limits);
+        // ask the server to use a bigger ack window
+        if ack_bitfield_size != AckBitfieldSize::default() {
+            let message =
+                ClientMessage::<P>::Handshake(HandshakeMessage::Request { ack_bitfield_size });
+            message.emit_send_logs("EntityActionsChannel");
+            message_manager
+                .buffer_send(message, ChannelKind::of::<EntityActionsChannel>())
+                .expect("could not buffer the handshake message");
+        }
+        // let the server check that we registered the same types
+        let message = ClientMessage::<P>::Handshake(HandshakeMessage::Registry {
+            hash: registered_messages.registry_hash,
+        });
+        message.emit_send_logs("EntityActionsChannel");
+        message_manager
+            .buffer_send(message, ChannelKind::of::<EntityActionsChannel>())
+            .expect("could not buffer the handshake message");
         // get the acks-tracker for entity updates
         let update_acks_tracker = message_manager
             .channels
@@ -88,11 +125,15 @@ impl<P: Protocol> ConnectionManager<P> {
             message_manager.get_replication_update_send_receiver();
         let replication_sender =
             ReplicationSender::new(update_acks_tracker, replication_update_send_receiver);
-        let replication_receiver = ReplicationReceiver::new();
+        let replication_receiver = ReplicationReceiver::new(Authority::Server)
+            .with_lost_visibility(replication_config.lost_visibility)
+            .with_decode_limits(decode_limits);
         Self {
             message_manager,
+            registered_messages,
             replication_sender,
             replication_receiver,
+            replicate_remove_behaviour: replication_config.on_replicate_remove,
             ping_manager: PingManager::new(ping_config),
             sync_manager: SyncManager::new(sync_config, input_delay_ticks),
             events: ConnectionEvents::default(),
@@ -105,6 +146,11 @@ impl<P: Protocol> ConnectionManager<P> {
         self.sync_manager.is_synced()
     }
 
+    /// Get the network statistics of the connection to the server
+    pub fn stats(&self) -> ConnectionStats {
+        self.message_manager.stats(&self.ping_manager)
+    }
+
     pub(crate) fn received_new_server_tick(&self) -> bool {
         self.sync_manager.duration_since_latest_received_server_tick == Duration::default()
     }
@@ -154,6 +200,21 @@ impl<P: Protocol> ConnectionManager<P> {
         self.buffer_message(message.into(), channel, target)
     }
 
+    /// Send a message registered with [`AppRegistrationExt::register_message`](crate::prelude::AppRegistrationExt::register_message) to the server
+    pub fn send_registered_message<C: Channel, M: Message>(&mut self, message: M) -> Result<()> {
+        self.send_registered_message_to_target::<C, M>(message, NetworkTarget::None)
+    }
+
+    /// Send a registered message to the server, the message should be re-broadcasted according to the `target`
+    pub fn send_registered_message_to_target<C: Channel, M: Message>(
+        &mut self,
+        message: M,
+        target: NetworkTarget,
+    ) -> Result<()> {
+        let message = self.registered_messages.serialize(&message)?;
+        self.buffer_message(message.into(), ChannelKind::of::<C>(), target)
+    }
+
     pub(crate) fn buffer_message(
         &mut self,
         message: P::Message,
@@ -213,8 +274,14 @@ impl<P: Protocol> ConnectionManager<P> {
                 // keep track of the group associated with the message, so we can handle receiving an ACK for that message_id later
                 if should_track_ack {
                     self.replication_sender
-                        .updates_message_id_to_group_id
-                        .insert(message_id, (group_id, bevy_tick));
+                        .updates_message_buffered(message_id, group_id, bevy_tick);
+                } else {
+                    // the actions are sent reliably: all the changes until now will be received
+                    self.replication_sender
+                        .group_channels
+                        .entry(group_id)
+                        .or_default()
+                        .last_action_bevy_tick = Some(bevy_tick);
                 }
                 Ok(())
             })
@@ -287,6 +354,11 @@ impl<P: Protocol> ConnectionManager<P> {
                         ServerMessage::Message(mut message) => {
                             // map any entities inside the message
                             message.map_entities(&mut self.replication_receiver.remote_entity_map);
+                            let message = map_registered_message_entities(
+                                world,
+                                message,
+                                &mut self.replication_receiver.remote_entity_map,
+                            );
                             // buffer the message
                             self.events.push_message(channel_kind, message);
                         }
@@ -294,6 +366,66 @@ impl<P: Protocol> ConnectionManager<P> {
                             // buffer the replication message
                             self.replication_receiver.recv_message(replication, tick);
                         }
+                        ServerMessage::InitialSync(message) => {
+                            self.replication_receiver.initial_sync.recv_message(message);
+                        }
+                        ServerMessage::InitialSyncBatch(batch) => {
+                            if let Err(e) = self
+                                .replication_receiver
+                                .recv_initial_sync_batch(batch, tick)
+                            {
+                                error!("could not read the initial sync batch: {:?}", e);
+                            }
+                        }
+                        ServerMessage::Correction(mut message) => {
+                            // the entity is already our local entity, no need to map it
+                            let Some(mut entity_mut) = world.get_entity_mut(message.entity) else {
+                                debug!(entity = ?message.entity, "Received correction for an entity that does not exist");
+                                continue;
+                            };
+                            debug!(entity = ?message.entity, "Received correction");
+                            // but the component is the server value, so the entities it contains must be mapped
+                            message
+                                .component
+                                .map_entities(&mut self.replication_receiver.remote_entity_map);
+                            self.events.push_update_component(
+                                message.entity,
+                                (&message.component).into(),
+                                tick,
+                            );
+                            message.component.update(&mut entity_mut);
+                        }
+                        ServerMessage::Handshake(HandshakeMessage::Response {
+                            ack_bitfield_size,
+                            first_packet_id,
+                        }) => {
+                            debug!(
+                                ?ack_bitfield_size,
+                                "Negotiated the ack window with the server"
+                            );
+                            self.message_manager
+                                .set_recv_ack_bitfield_size(ack_bitfield_size, first_packet_id);
+                            let first_packet_id = self
+                                .message_manager
+                                .set_send_ack_bitfield_size(ack_bitfield_size);
+                            let message =
+                                ClientMessage::<P>::Handshake(HandshakeMessage::Confirm {
+                                    first_packet_id,
+                                });
+                            message.emit_send_logs("EntityActionsChannel");
+                            if let Err(e) = self
+                                .message_manager
+                                .buffer_send(message, ChannelKind::of::<EntityActionsChannel>())
+                            {
+                                error!("could not buffer the handshake message: {:?}", e);
+                            }
+                        }
+                        ServerMessage::Handshake(message) => {
+                            warn!(
+                                ?message,
+                                "Received unexpected handshake message from the server"
+                            );
+                        }
                         ServerMessage::Sync(ref sync) => {
                             match sync {
                                 SyncMessage::Ping(ping) => {
@@ -386,6 +518,7 @@ impl<P: Protocol> ConnectionManager<P> {
 
 impl<P: Protocol> ReplicationSend<P> for ConnectionManager<P> {
     type SetMarker = ClientMarker;
+    const DIRECTION: ChannelDirection = ChannelDirection::ClientToServer;
     fn update_priority(
         &mut self,
         replication_group_id: ReplicationGroupId,
@@ -401,6 +534,13 @@ impl<P: Protocol> ReplicationSend<P> for ConnectionManager<P> {
         vec![]
     }
 
+    fn remote_entity(&self, local_entity: Entity) -> Option<Entity> {
+        self.replication_receiver
+            .remote_entity_map
+            .get_remote(local_entity)
+            .copied()
+    }
+
     fn prepare_entity_spawn(
         &mut self,
         entity: Entity,
@@ -453,6 +593,58 @@ impl<P: Protocol> ReplicationSend<P> for ConnectionManager<P> {
         Ok(())
     }
 
+    fn prepare_entity_lost_visibility(
+        &mut self,
+        entity: Entity,
+        replicate: &Replicate<P>,
+        target: NetworkTarget,
+        system_current_tick: BevyTick,
+    ) -> Result<()> {
+        let group_id = replicate.replication_group.group_id(Some(entity));
+        self.replication_sender
+            .prepare_entity_lost_visibility(entity, group_id);
+        Ok(())
+    }
+
+    fn prepare_entity_detach(
+        &mut self,
+        entity: Entity,
+        replicate: &Replicate<P>,
+        target: NetworkTarget,
+        system_current_tick: BevyTick,
+    ) -> Result<()> {
+        let group_id = replicate.replication_group.group_id(Some(entity));
+        self.replication_sender
+            .prepare_entity_detach(entity, group_id);
+        Ok(())
+    }
+
+    fn changes_delivered(
+        &self,
+        entity: Entity,
+        replicate: &Replicate<P>,
+        change_tick: BevyTick,
+        current_tick: BevyTick,
+    ) -> bool {
+        let group_id = replicate.replication_group.group_id(Some(entity));
+        self.replication_sender
+            .is_delivered(group_id, change_tick, current_tick)
+    }
+
+    fn prepare_entity_dormancy(
+        &mut self,
+        entity: Entity,
+        replicate: &Replicate<P>,
+        target: NetworkTarget,
+        dormant: bool,
+        system_current_tick: BevyTick,
+    ) -> Result<()> {
+        let group_id = replicate.replication_group.group_id(Some(entity));
+        self.replication_sender
+            .prepare_entity_dormancy(entity, group_id, dormant);
+        Ok(())
+    }
+
     fn prepare_component_insert(
         &mut self,
         entity: Entity,
@@ -548,6 +740,10 @@ impl<P: Protocol> ReplicationSend<P> for ConnectionManager<P> {
     fn get_mut_replicate_component_cache(&mut self) -> &mut EntityHashMap<Replicate<P>> {
         &mut self.replication_sender.replicate_component_cache
     }
+
+    fn replicate_remove_behaviour(&self) -> ReplicateRemoveBehaviour {
+        self.replicate_remove_behaviour
+    }
     fn cleanup(&mut self, tick: Tick) {
         debug!("Running replication clean");
         // if it's been enough time since we last any action for the group, we can set the last_action_tick to None
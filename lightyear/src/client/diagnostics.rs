@@ -1,12 +1,20 @@
 use crate::client::networking::{is_connected, is_disconnected};
 use bevy::app::{App, Plugin, PostUpdate};
 use bevy::diagnostic::Diagnostics;
-use bevy::prelude::{not, Condition, IntoSystemConfigs, Real, Res, ResMut, Time};
+use bevy::prelude::{not, Condition, IntoSystemConfigs, Real, Res, ResMut, Resource, Time};
 
+use crate::client::connection::ConnectionManager;
 use crate::connection::client::{ClientConnection, NetClient};
-use crate::prelude::{Protocol, SharedConfig};
+use crate::prelude::{MainSet, Protocol, SharedConfig};
+use crate::shared::stats::ConnectionStats;
 use crate::transport::io::IoDiagnosticsPlugin;
 
+/// Network statistics of the connection to the server.
+///
+/// The resource is updated every frame after the packets are sent.
+#[derive(Resource, Default, Clone, Debug, PartialEq)]
+pub struct NetworkStats(pub ConnectionStats);
+
 pub struct ClientDiagnosticsPlugin<P> {
     _marker: std::marker::PhantomData<P>,
 }
@@ -28,14 +36,27 @@ fn io_diagnostics_system(
         IoDiagnosticsPlugin::update_diagnostics(&mut io.stats, &time, &mut diagnostics);
     }
 }
+
+fn network_stats_system<P: Protocol>(
+    connection_manager: Res<ConnectionManager<P>>,
+    mut network_stats: ResMut<NetworkStats>,
+) {
+    network_stats.0 = connection_manager.stats();
+}
+
 impl<P: Protocol> Plugin for ClientDiagnosticsPlugin<P> {
     fn build(&self, app: &mut App) {
         app.add_plugins(IoDiagnosticsPlugin);
+        app.init_resource::<NetworkStats>();
         app.add_systems(
             PostUpdate,
-            io_diagnostics_system.run_if(not(
-                SharedConfig::is_host_server_condition.or_else(is_disconnected)
-            )),
+            (
+                io_diagnostics_system,
+                network_stats_system::<P>.after(MainSet::SendPackets),
+            )
+                .run_if(not(
+                    SharedConfig::is_host_server_condition.or_else(is_disconnected)
+                )),
         );
     }
 }
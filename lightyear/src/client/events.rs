@@ -13,11 +13,18 @@
 //! }
 //! ```
 
-use crate::prelude::{ClientId, Protocol};
+use crate::channel::builder::ChannelDirection;
+use crate::client::connection::ConnectionManager;
+use crate::client::networking::is_disconnected;
+use crate::prelude::{ClientId, NetworkTarget, Protocol};
+use crate::shared::config::SharedConfig;
 use crate::shared::events::connection::ConnectionEvents;
+use crate::shared::events::network::{clear_network_event_queue, NetworkEventQueue};
 use crate::shared::events::plugin::EventsPlugin;
+use crate::shared::sets::{ClientMarker, InternalMainSet, NetworkEventSet};
 use bevy::app::{App, Plugin, PostUpdate};
-use bevy::prelude::{Event, Events};
+use bevy::prelude::{not, Condition, Event, Events, IntoSystemConfigs, ResMut};
+use tracing::error;
 
 /// Plugin that handles generating bevy [`Events`] related to networking and replication
 pub struct ClientEventsPlugin<P: Protocol> {
@@ -37,13 +44,47 @@ impl<P: Protocol> Plugin for ClientEventsPlugin<P> {
         app
             // EVENTS
             .add_event::<ConnectEvent>()
+            .add_event::<InitialSyncProgress>()
+            .add_event::<InitialSyncComplete>()
             // PLUGIN
             // TODO: it's annoying to have to keep that () around...
             //  revisit this.. maybe the into_iter_messages returns directly an object that
             //  can be created from Ctx and Message
             //  For Server it's the MessageEvent<M, ClientId>
             //  For Client it's MessageEvent<M> directly
-            .add_plugins(EventsPlugin::<P, ()>::default());
+            .add_plugins(EventsPlugin::<P, ()>::default())
+            // RESOURCES
+            .init_resource::<NetworkEventQueue>()
+            // SYSTEMS
+            .add_systems(
+                PostUpdate,
+                (
+                    send_network_events::<P>
+                        .in_set(InternalMainSet::<ClientMarker>::Send)
+                        .before(InternalMainSet::<ClientMarker>::SendPackets),
+                    clear_network_event_queue
+                        .after(NetworkEventSet::Queue)
+                        .run_if(
+                            not(SharedConfig::is_host_server_condition).and_then(is_disconnected),
+                        ),
+                ),
+            );
+    }
+}
+
+/// Send the network events that were written on the client to the server
+pub(crate) fn send_network_events<P: Protocol>(
+    mut queue: ResMut<NetworkEventQueue>,
+    mut connection_manager: ResMut<ConnectionManager<P>>,
+) {
+    for event in queue.0.drain(..) {
+        if !event.can_send(&ChannelDirection::ClientToServer) {
+            continue;
+        }
+        // the events are not rebroadcast to the other clients
+        let _ = connection_manager
+            .buffer_message(event.message.into(), event.channel, NetworkTarget::None)
+            .map_err(|e| error!("error sending network event: {:?}", e));
     }
 }
 
@@ -63,6 +104,26 @@ impl ConnectEvent {
     }
 }
 
+/// Bevy [`Event`] emitted on the client when it receives entities that are part of its initial sync
+/// (see [`InitialSyncConfig`](crate::prelude::server::InitialSyncConfig))
+#[derive(Event, Clone, Copy, Debug, PartialEq)]
+pub struct InitialSyncProgress {
+    /// Number of entities of the initial sync that were received so far
+    pub received: u32,
+    /// Total number of entities in the initial sync
+    pub total: u32,
+}
+
+impl InitialSyncProgress {
+    pub fn is_complete(&self) -> bool {
+        self.received >= self.total
+    }
+}
+
+/// Bevy [`Event`] emitted on the client when all the entities of its initial sync have been received
+#[derive(Event, Clone, Copy, Debug, PartialEq)]
+pub struct InitialSyncComplete;
+
 /// Bevy [`Event`] emitted on the client on the frame where the connection is disconnected
 pub type DisconnectEvent = crate::shared::events::components::DisconnectEvent<()>;
 /// Bevy [`Event`] emitted on the client to indicate the user input for the tick
@@ -71,6 +132,14 @@ pub type InputEvent<I> = crate::shared::events::components::InputEvent<I, ()>;
 pub type EntitySpawnEvent = crate::shared::events::components::EntitySpawnEvent<()>;
 /// Bevy [`Event`] emitted on the client when a EntityDespawn replication message is received
 pub type EntityDespawnEvent = crate::shared::events::components::EntityDespawnEvent<()>;
+/// Bevy [`Event`] emitted on the client when a replicated entity stops being visible to the client
+/// (see [`LostVisibilityBehaviour`](crate::client::replication::LostVisibilityBehaviour))
+pub type EntityLostVisibilityEvent =
+    crate::shared::events::components::EntityLostVisibilityEvent<()>;
+/// Bevy [`Event`] emitted on the client when a server entity stops being replicated without being despawned,
+/// and the local entity was converted to a plain local entity
+/// (see [`ReplicateRemoveBehaviour::Detach`](crate::prelude::ReplicateRemoveBehaviour::Detach))
+pub type EntityDetachEvent = crate::shared::events::components::EntityDetachEvent<()>;
 /// Bevy [`Event`] emitted on the client when a ComponentUpdate replication message is received
 pub type ComponentUpdateEvent<C> = crate::shared::events::components::ComponentUpdateEvent<C, ()>;
 /// Bevy [`Event`] emitted on the client when a ComponentInsert replication message is received
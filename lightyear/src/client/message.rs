@@ -8,6 +8,7 @@ use bitcode::{Decode, Encode};
 use crate::_reexport::{BitSerializable, MessageProtocol, ReadBuffer, WriteBuffer};
 use crate::prelude::{ChannelKind, NetworkTarget};
 use crate::protocol::Protocol;
+use crate::shared::handshake::HandshakeMessage;
 use crate::shared::ping::message::SyncMessage;
 use crate::shared::replication::{ReplicationMessage, ReplicationMessageData};
 
@@ -29,6 +30,10 @@ pub enum ClientMessage<P: Protocol> {
     // the reason why we include sync here instead of doing another MessageManager is so that
     // the sync messages can be added to packets that have other messages
     Sync(SyncMessage),
+    /// Negotiation of the connection settings
+    #[bitcode_hint(frequency = 1)]
+    #[bitcode(with_serde)]
+    Handshake(HandshakeMessage),
 }
 
 impl<P: Protocol> BitSerializable for ClientMessage<P> {
@@ -128,6 +133,9 @@ impl<P: Protocol> ClientMessage<P> {
                     }
                 }
             }
+            ClientMessage::Handshake(message) => {
+                trace!(channel = ?channel_name, ?message, "Sending handshake");
+            }
             ClientMessage::Sync(message) => match message {
                 SyncMessage::Ping(_) => {
                     trace!(channel = ?channel_name, "Sending ping");
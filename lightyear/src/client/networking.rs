@@ -12,7 +12,10 @@ use crate::_reexport::{ClientMarker, ReplicationSend};
 use crate::client::components::Confirmed;
 use crate::client::config::ClientConfig;
 use crate::client::connection::ConnectionManager;
-use crate::client::events::{ConnectEvent, DisconnectEvent, EntityDespawnEvent, EntitySpawnEvent};
+use crate::client::events::{
+    ConnectEvent, DisconnectEvent, EntityDespawnEvent, EntityDetachEvent,
+    EntityLostVisibilityEvent, EntitySpawnEvent, InitialSyncComplete,
+};
 use crate::client::interpolation::Interpolated;
 use crate::client::prediction::Predicted;
 use crate::client::sync::SyncSet;
@@ -21,9 +24,13 @@ use crate::connection::server::ServerConnections;
 use crate::prelude::{MainSet, SharedConfig, TickManager, TimeManager};
 use crate::protocol::component::ComponentProtocol;
 use crate::protocol::message::MessageProtocol;
+use crate::protocol::registration::ProtocolRegistry;
 use crate::protocol::Protocol;
 use crate::shared::config::Mode;
-use crate::shared::events::connection::{IterEntityDespawnEvent, IterEntitySpawnEvent};
+use crate::shared::events::connection::{
+    IterEntityDespawnEvent, IterEntityDetachEvent, IterEntityLostVisibilityEvent,
+    IterEntitySpawnEvent,
+};
 use crate::shared::sets::InternalMainSet;
 use crate::shared::tick_manager::TickEvent;
 use crate::shared::time_manager::is_client_ready_to_send;
@@ -167,9 +174,16 @@ pub(crate) fn receive<P: Protocol>(world: &mut World) {
 
                                                         // RECV PACKETS: buffer packets into message managers
                                                         while let Some(packet) = netclient.recv() {
-                                                            connection
-                                                                .recv_packet(packet, tick_manager.as_ref())
-                                                                .unwrap();
+                                                            // the packet could not be processed (for example because it exceeds the `DecodeLimits`):
+                                                            // we cannot trust the server anymore, disconnect
+                                                            if let Err(e) = connection.recv_packet(packet, tick_manager.as_ref()) {
+                                                                error!("Could not receive packet: {:?}. Disconnecting", e);
+                                                                let _ = netclient.disconnect().map_err(|e| {
+                                                                    error!("Error disconnecting: {:?}", e);
+                                                                });
+                                                                next_state.set(NetworkingState::Disconnected);
+                                                                break;
+                                                            }
                                                         }
                                                         // RECEIVE: receive packets from message managers
                                                         let mut events = connection.receive(
@@ -177,6 +191,13 @@ pub(crate) fn receive<P: Protocol>(world: &mut World) {
                                                             time_manager.as_ref(),
                                                             tick_manager.as_ref(),
                                                         );
+                                                        // Initial sync events
+                                                        if let Some(progress) = connection.replication_receiver.initial_sync.take_progress() {
+                                                            world.send_event(progress);
+                                                            if progress.is_complete() {
+                                                                world.send_event(InitialSyncComplete);
+                                                            }
+                                                        }
                                                         // TODO: run these in EventsPlugin!
                                                         // HANDLE EVENTS
                                                         if !events.is_empty() {
@@ -204,6 +225,28 @@ pub(crate) fn receive<P: Protocol>(world: &mut World) {
                                                                         .send(EntityDespawnEvent::new(entity, ()));
                                                                 }
                                                             }
+                                                            // LostVisibility event
+                                                            if events.has_entity_lost_visibility() {
+                                                                let mut entity_lost_visibility_event_writer = world
+                                                                    .get_resource_mut::<Events<EntityLostVisibilityEvent>>()
+                                                                    .unwrap();
+                                                                for (entity, _) in events.into_iter_entity_lost_visibility()
+                                                                {
+                                                                    entity_lost_visibility_event_writer
+                                                                        .send(EntityLostVisibilityEvent::new(entity, ()));
+                                                                }
+                                                            }
+                                                            // Detach event
+                                                            if events.has_entity_detach() {
+                                                                let mut entity_detach_event_writer = world
+                                                                    .get_resource_mut::<Events<EntityDetachEvent>>()
+                                                                    .unwrap();
+                                                                for (entity, _) in events.into_iter_entity_detach()
+                                                                {
+                                                                    entity_detach_event_writer
+                                                                        .send(EntityDetachEvent::new(entity, ()));
+                                                                }
+                                                            }
 
                                                             // Update component events (updates, inserts, removes)
                                                             P::Components::push_component_events(
@@ -455,6 +498,11 @@ fn rebuild_client_connection<P: Protocol>(world: &mut World) {
         client_config.sync,
         client_config.ping,
         client_config.prediction.input_delay_ticks,
+        &client_config.replication,
+        world
+            .get_resource::<ProtocolRegistry>()
+            .map(ProtocolRegistry::message_net_ids)
+            .unwrap_or_default(),
     );
     world.insert_resource(connection_manager);
 
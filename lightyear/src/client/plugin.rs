@@ -15,6 +15,7 @@ use crate::client::replication::ClientReplicationPlugin;
 use crate::connection::client::{ClientConnection, NetConfig};
 use crate::protocol::component::ComponentProtocol;
 use crate::protocol::message::MessageProtocol;
+use crate::protocol::registration::ProtocolRegistry;
 use crate::protocol::Protocol;
 use crate::server::plugin::ServerPlugin;
 use crate::shared::config::Mode;
@@ -62,6 +63,7 @@ impl<P: Protocol> Plugin for ClientPlugin<P> {
 
         app
             // RESOURCES //
+            .init_resource::<ProtocolRegistry>()
             .insert_resource(config.client_config.clone())
             .insert_resource(config.protocol.clone())
             // PLUGINS //
@@ -86,4 +88,14 @@ impl<P: Protocol> Plugin for ClientPlugin<P> {
                 });
         }
     }
+
+    fn finish(&self, app: &mut App) {
+        // assign the net ids now that every plugin had a chance to register its components and messages
+        let mut registry = app.world.resource_mut::<ProtocolRegistry>();
+        registry.finish();
+        // the systems that sync the registered components to the predicted/interpolated entities
+        // can only be added once all the components are registered
+        let registry = registry.clone();
+        registry.add_sync_systems(app);
+    }
 }
@@ -7,8 +7,10 @@ use crate::client::connection::ConnectionManager;
 use crate::client::sync::client_is_synced;
 use crate::prelude::client::InterpolationDelay;
 use crate::prelude::{Protocol, SharedConfig};
+use crate::shared::replication::authority::handle_authority_change;
+use crate::shared::replication::components::ReplicateRemoveBehaviour;
 use crate::shared::replication::plugin::ReplicationPlugin;
-use crate::shared::sets::InternalReplicationSet;
+use crate::shared::sets::{InternalMainSet, InternalReplicationSet};
 
 #[derive(Clone, Debug, Reflect)]
 pub struct ReplicationConfig {
@@ -16,6 +18,11 @@ pub struct ReplicationConfig {
     pub enable_send: bool,
     /// Set to true to enable receiving replication updates from the server
     pub enable_receive: bool,
+    /// What to do with a replicated entity that stops being visible to this client
+    pub lost_visibility: LostVisibilityBehaviour,
+    /// What happens to the server entities when the `Replicate` component is removed from a client entity,
+    /// for the entities that don't set [`Replicate::on_remove`](crate::prelude::Replicate::on_remove)
+    pub on_replicate_remove: ReplicateRemoveBehaviour,
 }
 
 impl Default for ReplicationConfig {
@@ -23,10 +30,35 @@ impl Default for ReplicationConfig {
         Self {
             enable_send: false,
             enable_receive: true,
+            lost_visibility: LostVisibilityBehaviour::default(),
+            on_replicate_remove: ReplicateRemoveBehaviour::default(),
         }
     }
 }
 
+/// What to do with a replicated entity that stops being visible to the client
+/// (for example because it left the client's rooms), while it still exists on the server.
+///
+/// In both cases an [`EntityLostVisibilityEvent`](crate::client::events::EntityLostVisibilityEvent) is emitted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum LostVisibilityBehaviour {
+    /// Despawn the local entity. If the entity becomes visible again, a new local entity is spawned
+    #[default]
+    Despawn,
+    /// Keep the local entity (for example to display the last-known position of an enemy, or to fade it out),
+    /// and add the [`LostVisibility`] marker to it. The entity doesn't receive any updates anymore.
+    ///
+    /// If the entity becomes visible again, the same local entity is re-used and the marker is removed.
+    /// If the entity is despawned on the server while it is not visible, the local entity is despawned as well.
+    /// The entity can be despawned locally at any time.
+    Keep,
+}
+
+/// Marker component added to the replicated entities that are not visible to the client anymore,
+/// with [`LostVisibilityBehaviour::Keep`]
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub struct LostVisibility;
+
 pub struct ClientReplicationPlugin<P: Protocol> {
     marker: std::marker::PhantomData<P>,
 }
@@ -67,5 +99,13 @@ impl<P: Protocol> Plugin for ClientReplicationPlugin<P> {
                     client_is_synced::<P>.and_then(not(SharedConfig::is_host_server_condition)),
                 ),
             );
+        if app.world.resource::<ClientConfig>().replication.enable_send {
+            // SYSTEMS
+            app.add_systems(
+                PreUpdate,
+                // start replicating the entities that the server gave us authority over
+                handle_authority_change::<P>.after(InternalMainSet::<ClientMarker>::Receive),
+            );
+        }
     }
 }
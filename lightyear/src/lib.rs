@@ -20,9 +20,10 @@ pub mod _reexport {
     pub use enum_delegate;
     pub use enum_dispatch::enum_dispatch;
     pub use paste::paste;
+    pub use serde;
 
     pub use lightyear_macros::{
-        component_protocol_internal, message_protocol_internal, ChannelInternal,
+        component_protocol_internal, message_protocol_internal, ChannelInternal, QuantizeInternal,
     };
 
     pub use crate::channel::builder::TickBufferChannel;
@@ -41,6 +42,9 @@ pub mod _reexport {
     };
     pub use crate::protocol::message::InputMessageKind;
     pub use crate::protocol::message::{MessageKind, MessageProtocol};
+    pub use crate::protocol::registration::{
+        push_registered_component_events, RegisteredComponent,
+    };
     pub use crate::protocol::{BitSerializable, EventContext};
     pub use crate::serialize::reader::ReadBuffer;
     pub use crate::serialize::wordbuffer::reader::ReadWordBuffer;
@@ -56,11 +60,14 @@ pub mod _reexport {
     pub use crate::shared::events::systems::{
         push_component_insert_events, push_component_remove_events, push_component_update_events,
     };
+    pub use crate::shared::replication::authority::AuthorityTransfer;
     pub use crate::shared::replication::components::ShouldBeInterpolated;
     pub use crate::shared::replication::resources::{
         receive::add_resource_receive_systems, send::add_resource_send_systems,
     };
-    pub use crate::shared::replication::systems::add_per_component_replication_send_systems;
+    pub use crate::shared::replication::systems::{
+        add_per_component_replication_send_systems, add_registered_replication_send_systems,
+    };
     pub use crate::shared::replication::ReplicationSend;
     pub use crate::shared::sets::{ClientMarker, ServerMarker};
     pub use crate::shared::time_manager::WrappedTime;
@@ -70,12 +77,12 @@ pub mod _reexport {
 
 /// Prelude containing commonly used types
 pub mod prelude {
-    pub use lightyear_macros::{component_protocol, message_protocol, Channel};
+    pub use lightyear_macros::{component_protocol, message_protocol, Channel, Quantize};
 
     pub use crate::channel::builder::TickBufferChannel;
     pub use crate::channel::builder::{
         Channel, ChannelBuilder, ChannelContainer, ChannelDirection, ChannelMode, ChannelSettings,
-        DefaultUnorderedUnreliableChannel, ReliableSettings,
+        DefaultUnorderedUnreliableChannel, FecSettings, ReliableSettings, UnsentMessagePolicy,
     };
     pub use crate::client::prediction::prespawn::PreSpawnedPlayerObject;
     pub use crate::connection::id::ClientId;
@@ -83,22 +90,32 @@ pub mod prelude {
     #[cfg(feature = "leafwing")]
     pub use crate::inputs::leafwing::LeafwingUserAction;
     pub use crate::inputs::native::UserAction;
+    pub use crate::packet::header::AckBitfieldSize;
+    pub use crate::packet::limits::DecodeLimits;
     pub use crate::packet::message::Message;
     pub use crate::protocol::channel::{ChannelKind, ChannelRegistry};
+    pub use crate::protocol::registration::{AppRegistrationExt, ProtocolRegistry};
+    pub use crate::protocol::schema::ProtocolSchema;
     pub use crate::protocol::Protocol;
     pub use crate::protocolize;
     pub use crate::shared::config::{Mode, SharedConfig};
     pub use crate::shared::ping::manager::PingConfig;
     pub use crate::shared::plugin::{NetworkIdentity, SharedPlugin};
+    pub use crate::shared::replication::authority::{Authority, HasAuthority};
     pub use crate::shared::replication::components::{
-        NetworkTarget, PrePredicted, ReplicationGroup, ReplicationMode, ShouldBePredicted,
+        NetworkTarget, PrePredicted, ReplicateRemoveBehaviour, ReplicationGroup,
+        ReplicationGroupId, ReplicationMode, ShouldBePredicted,
     };
+    pub use crate::shared::replication::dormancy::Dormant;
     pub use crate::shared::replication::entity_map::{ExternalMapper, RemoteEntityMap};
-    pub use crate::shared::replication::hierarchy::ParentSync;
+    pub use crate::shared::replication::hierarchy::{ChildrenSync, ParentSync};
     pub use crate::shared::replication::resources::{
-        ReplicateResource, ReplicateResourceExt, StopReplicateCommand, StopReplicateResourceExt,
+        PerClientResource, ReplicateResource, ReplicateResourceExt, ResourceRemoveEvent,
+        StopReplicateCommand, StopReplicateResourceExt,
     };
+    pub use crate::shared::replication::state::{ReplicateStatePlugin, ReplicatedState};
     pub use crate::shared::sets::{FixedUpdateSet, MainSet};
+    pub use crate::shared::stats::{ChannelStats, ConnectionStats};
     pub use crate::shared::tick_manager::TickManager;
     pub use crate::shared::tick_manager::{Tick, TickConfig};
     pub use crate::shared::time_manager::TimeManager;
@@ -111,9 +128,11 @@ pub mod prelude {
             ComponentSyncMode, Confirmed, LerpFn, SyncComponent, SyncMetadata,
         };
         pub use crate::client::config::{ClientConfig, NetcodeConfig, PacketConfig};
+        pub use crate::client::diagnostics::NetworkStats;
         pub use crate::client::events::{
             ComponentInsertEvent, ComponentRemoveEvent, ComponentUpdateEvent, ConnectEvent,
-            DisconnectEvent, EntityDespawnEvent, EntitySpawnEvent, InputEvent, MessageEvent,
+            DisconnectEvent, EntityDespawnEvent, EntityDetachEvent, EntityLostVisibilityEvent,
+            EntitySpawnEvent, InitialSyncComplete, InitialSyncProgress, InputEvent, MessageEvent,
         };
         pub use crate::client::input::{InputConfig, InputManager, InputSystemSet};
         #[cfg(feature = "leafwing")]
@@ -135,7 +154,9 @@ pub mod prelude {
         pub use crate::client::prediction::predicted_history::{ComponentState, PredictionHistory};
         pub use crate::client::prediction::rollback::{Rollback, RollbackState};
         pub use crate::client::prediction::{Predicted, PredictionDespawnCommandsExt};
-        pub use crate::client::replication::ReplicationConfig;
+        pub use crate::client::replication::{
+            LostVisibility, LostVisibilityBehaviour, ReplicationConfig,
+        };
         pub use crate::client::sync::SyncConfig;
         pub use crate::connection::client::{
             Authentication, ClientConnection, NetClient, NetConfig,
@@ -145,16 +166,25 @@ pub mod prelude {
     }
     pub mod server {
         pub use crate::server::config::{NetcodeConfig, PacketConfig, ServerConfig};
+        pub use crate::server::diagnostics::NetworkStats;
         pub use crate::server::events::{
             ComponentInsertEvent, ComponentRemoveEvent, ComponentUpdateEvent, ConnectEvent,
             DisconnectEvent, EntityDespawnEvent, EntitySpawnEvent, InputEvent, MessageEvent,
         };
         pub use crate::server::networking::{NetworkingState, ServerCommands};
+        pub use crate::server::persistence::WorldSnapshot;
         pub use crate::server::plugin::{PluginConfig, ServerPlugin};
         pub use crate::server::replication::{
             ReplicationConfig, ServerFilter, ServerReplicationSet,
         };
         pub use crate::server::room::{RoomId, RoomManager, RoomMut, RoomRef};
+        pub use crate::server::spatial::{
+            SpatialInterest, SpatialInterestConfig, SpatialInterestMode, SpatialInterestPlugin,
+            SpatialPosition, SpatialViewer,
+        };
+        pub use crate::server::validation::{AppValidationExt, ComponentValidators, Validation};
+        pub use crate::server::visibility::{AppVisibilityExt, VisibilityFilters};
+        pub use crate::shared::replication::initial_sync::InitialSyncConfig;
 
         pub use crate::connection::server::{
             NetConfig, NetServer, ServerConnection, ServerConnections,
@@ -0,0 +1,298 @@
+//! Forward error correction (FEC) for packets.
+//!
+//! The packets that contain messages from channels with [`FecSettings`](crate::channel::builder::FecSettings)
+//! are grouped together. For each group we send an extra [`ParityPacket`] that contains the XOR of all the packets
+//! in the group, which lets the receiver rebuild a single lost packet of the group without waiting for a resend.
+use std::collections::{HashMap, VecDeque};
+
+use bevy::utils::Duration;
+use tracing::trace;
+
+use crate::connection::netcode::MAX_PACKET_SIZE;
+use crate::packet::packet::{PacketId, ParityPacket, HEADER_BYTES};
+use crate::packet::packet_manager::Payload;
+use crate::shared::time_manager::WrappedTime;
+
+/// Maximum number of packets in a FEC group (limited by the size of the bitfield in the [`ParityPacket`])
+pub(crate) const MAX_FEC_GROUP_SIZE: u8 = u64::BITS as u8;
+
+/// Maximum size of a packet that can be protected by FEC, so that the parity packet still fits in the MTU.
+/// (parity packet: header + first_packet_id: 2, bitfield: 8, length_xor: 2, length of the parity bytes: 4)
+pub(crate) const MAX_FEC_PAYLOAD_BYTES: usize = MAX_PACKET_SIZE - HEADER_BYTES - 16;
+
+/// Number of received packets that we keep around to be able to rebuild a lost packet
+const FEC_RECEIVE_BUFFER_SIZE: usize = 256;
+
+/// A stored packet is evicted once we receive a packet whose id is this far ahead: its group is complete,
+/// so its parity packet should have arrived already.
+/// (a group spans at most [`MAX_FEC_GROUP_SIZE`] packet ids, we keep the same margin for reordering)
+const FEC_RECEIVE_MAX_PACKET_ID_DISTANCE: i16 = 2 * MAX_FEC_GROUP_SIZE as i16;
+
+/// A stored packet is evicted after this duration, in case the rest of its group (or the parity packet) was lost
+/// and no other packets arrive
+const FEC_RECEIVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Builds the parity packets for the packets that we send
+#[derive(Debug)]
+pub(crate) struct FecSender {
+    /// Number of packets protected by each parity packet
+    group_size: u8,
+    /// Id of the first packet of the current group
+    first_packet_id: Option<PacketId>,
+    packet_ids_bitfield: u64,
+    num_packets: u8,
+    length_xor: u16,
+    parity: Vec<u8>,
+}
+
+impl FecSender {
+    pub(crate) fn new(group_size: u8) -> Self {
+        Self {
+            group_size: group_size.clamp(1, MAX_FEC_GROUP_SIZE),
+            first_packet_id: None,
+            packet_ids_bitfield: 0,
+            num_packets: 0,
+            length_xor: 0,
+            parity: Vec::new(),
+        }
+    }
+
+    /// Add a packet that we are about to send to the current group.
+    ///
+    /// Returns the parity packets of the groups that are complete.
+    pub(crate) fn add_packet(&mut self, packet_id: PacketId, payload: &[u8]) -> Vec<ParityPacket> {
+        let mut parity_packets = Vec::new();
+        // the packet is too big for the parity packet to fit in the MTU, it cannot be protected
+        if payload.len() > MAX_FEC_PAYLOAD_BYTES {
+            trace!(?packet_id, "packet is too big to be protected by FEC");
+            parity_packets.extend(self.finish_group());
+            return parity_packets;
+        }
+        // the packet cannot be represented in the bitfield of the current group, start a new group
+        if self.first_packet_id.map_or(false, |first| {
+            !(0..MAX_FEC_GROUP_SIZE as i16).contains(&(packet_id - first))
+        }) {
+            parity_packets.extend(self.finish_group());
+        }
+        let first_packet_id = *self.first_packet_id.get_or_insert(packet_id);
+        self.packet_ids_bitfield |= 1 << (packet_id - first_packet_id);
+        self.num_packets += 1;
+        self.length_xor ^= payload.len() as u16;
+        if self.parity.len() < payload.len() {
+            self.parity.resize(payload.len(), 0);
+        }
+        self.parity
+            .iter_mut()
+            .zip(payload)
+            .for_each(|(parity, byte)| *parity ^= byte);
+
+        if self.num_packets >= self.group_size {
+            parity_packets.extend(self.finish_group());
+        }
+        parity_packets
+    }
+
+    /// Finish the current group and return its parity packet (if the group is not empty)
+    fn finish_group(&mut self) -> Option<ParityPacket> {
+        let first_packet_id = self.first_packet_id.take()?;
+        let parity_packet = ParityPacket {
+            first_packet_id,
+            packet_ids_bitfield: self.packet_ids_bitfield,
+            length_xor: self.length_xor,
+            parity: std::mem::take(&mut self.parity),
+        };
+        self.packet_ids_bitfield = 0;
+        self.num_packets = 0;
+        self.length_xor = 0;
+        Some(parity_packet)
+    }
+}
+
+/// Keeps track of the received packets to be able to rebuild a lost packet from a parity packet
+#[derive(Debug, Default)]
+pub(crate) struct FecReceiver {
+    /// Encoded bytes of the packets protected by FEC that we received recently
+    received_packets: HashMap<PacketId, Payload>,
+    /// Ids of the packets in `received_packets` with the time when they were received, from oldest to newest
+    received_packet_ids: VecDeque<(PacketId, WrappedTime)>,
+    /// Ids of the packets that were rebuilt from a parity packet, so that we can ignore them if they arrive later
+    recovered_packet_ids: VecDeque<PacketId>,
+    current_time: WrappedTime,
+}
+
+impl FecReceiver {
+    /// Evict the stored packets that have been waiting for the rest of their group for too long
+    pub(crate) fn update(&mut self, current_time: WrappedTime) {
+        self.current_time = current_time;
+        while let Some(&(oldest, received_time)) = self.received_packet_ids.front() {
+            let timed_out = (current_time - received_time)
+                .to_std()
+                .map_or(false, |elapsed| elapsed > FEC_RECEIVE_TIMEOUT);
+            if !timed_out {
+                break;
+            }
+            self.received_packet_ids.pop_front();
+            self.received_packets.remove(&oldest);
+        }
+    }
+
+    /// Returns true if the packet was already rebuilt from a parity packet
+    pub(crate) fn is_recovered(&self, packet_id: PacketId) -> bool {
+        self.recovered_packet_ids.contains(&packet_id)
+    }
+
+    /// Keep the encoded bytes of a received packet that is protected by FEC
+    pub(crate) fn store(&mut self, packet_id: PacketId, payload: Payload) {
+        if self.received_packets.insert(packet_id, payload).is_none() {
+            self.received_packet_ids
+                .push_back((packet_id, self.current_time));
+        }
+        // evict the packets whose group is complete
+        while let Some(&(oldest, _)) = self.received_packet_ids.front() {
+            if self.received_packet_ids.len() <= FEC_RECEIVE_BUFFER_SIZE
+                && packet_id - oldest <= FEC_RECEIVE_MAX_PACKET_ID_DISTANCE
+            {
+                break;
+            }
+            self.received_packet_ids.pop_front();
+            self.received_packets.remove(&oldest);
+        }
+    }
+
+    /// Try to rebuild the lost packet of a group from its parity packet.
+    ///
+    /// Returns the encoded bytes of the packet if exactly one packet of the group is missing.
+    pub(crate) fn recover(&mut self, parity_packet: &ParityPacket) -> Option<Payload> {
+        let mut missing_packet_ids = parity_packet
+            .packet_ids()
+            .filter(|id| !self.received_packets.contains_key(id));
+        let missing_packet_id = missing_packet_ids.next()?;
+        if missing_packet_ids.next().is_some() || self.is_recovered(missing_packet_id) {
+            return None;
+        }
+
+        let mut payload = parity_packet.parity.clone();
+        let mut length = parity_packet.length_xor;
+        for packet_id in parity_packet.packet_ids() {
+            let Some(received) = self.received_packets.get(&packet_id) else {
+                continue;
+            };
+            length ^= received.len() as u16;
+            payload
+                .iter_mut()
+                .zip(received)
+                .for_each(|(parity, byte)| *parity ^= byte);
+        }
+        if length as usize > payload.len() {
+            return None;
+        }
+        payload.truncate(length as usize);
+        trace!(packet_id = ?missing_packet_id, "rebuilt lost packet from FEC parity packet");
+
+        self.recovered_packet_ids.push_back(missing_packet_id);
+        if self.recovered_packet_ids.len() > FEC_RECEIVE_BUFFER_SIZE {
+            self.recovered_packet_ids.pop_front();
+        }
+        Some(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::packet_manager::PacketBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_recover_lost_packet() {
+        let mut sender = FecSender::new(3);
+        let mut receiver = FecReceiver::default();
+        let payloads = [vec![1, 2, 3], vec![4, 5], vec![6, 7, 8, 9]];
+
+        assert!(sender.add_packet(PacketId(10), &payloads[0]).is_empty());
+        assert!(sender.add_packet(PacketId(12), &payloads[1]).is_empty());
+        let parity_packets = sender.add_packet(PacketId(13), &payloads[2]);
+        assert_eq!(parity_packets.len(), 1);
+        let parity_packet = &parity_packets[0];
+        assert_eq!(
+            parity_packet.packet_ids().collect::<Vec<_>>(),
+            vec![PacketId(10), PacketId(12), PacketId(13)]
+        );
+
+        // the second packet is lost
+        receiver.store(PacketId(10), payloads[0].clone());
+        receiver.store(PacketId(13), payloads[2].clone());
+        assert_eq!(receiver.recover(parity_packet), Some(payloads[1].clone()));
+        assert!(receiver.is_recovered(PacketId(12)));
+        // the packet cannot be rebuilt twice
+        assert_eq!(receiver.recover(parity_packet), None);
+    }
+
+    #[test]
+    fn test_cannot_recover_multiple_lost_packets() {
+        let mut sender = FecSender::new(3);
+        let mut receiver = FecReceiver::default();
+        sender.add_packet(PacketId(0), &[1]);
+        sender.add_packet(PacketId(1), &[2]);
+        let parity_packets = sender.add_packet(PacketId(2), &[3]);
+
+        receiver.store(PacketId(0), vec![1]);
+        assert_eq!(receiver.recover(&parity_packets[0]), None);
+    }
+
+    #[test]
+    fn test_evict_incomplete_groups() {
+        let mut receiver = FecReceiver::default();
+        receiver.store(PacketId(0), vec![1]);
+        receiver.store(PacketId(1), vec![2]);
+        // the packets are evicted once a packet far enough ahead is received
+        receiver.store(
+            PacketId(1 + FEC_RECEIVE_MAX_PACKET_ID_DISTANCE as u16),
+            vec![3],
+        );
+        assert_eq!(receiver.received_packets.len(), 2);
+        assert!(!receiver.received_packets.contains_key(&PacketId(0)));
+
+        // or after a timeout, even if no other packets are received
+        receiver.update(WrappedTime::default() + FEC_RECEIVE_TIMEOUT);
+        assert_eq!(receiver.received_packets.len(), 2);
+        receiver.update(WrappedTime::default() + FEC_RECEIVE_TIMEOUT * 2);
+        assert!(receiver.received_packets.is_empty());
+        assert!(receiver.received_packet_ids.is_empty());
+    }
+
+    #[test]
+    fn test_group_too_far_apart() {
+        let mut sender = FecSender::new(3);
+        assert!(sender.add_packet(PacketId(0), &[1]).is_empty());
+        // the packet id does not fit in the bitfield of the current group: the group is finished early
+        let parity_packets = sender.add_packet(PacketId(100), &[2]);
+        assert_eq!(parity_packets.len(), 1);
+        assert_eq!(
+            parity_packets[0].packet_ids().collect::<Vec<_>>(),
+            vec![PacketId(0)]
+        );
+        assert_eq!(sender.first_packet_id, Some(PacketId(100)));
+
+        // packets that are too big are not protected
+        let parity_packets = sender.add_packet(PacketId(101), &[0; MAX_FEC_PAYLOAD_BYTES + 1]);
+        assert_eq!(parity_packets.len(), 1);
+        assert_eq!(sender.first_packet_id, None);
+    }
+
+    #[test]
+    fn test_parity_packet_fits_in_mtu() {
+        let mut sender = FecSender::new(MAX_FEC_GROUP_SIZE);
+        let mut builder = PacketBuilder::new();
+        let mut parity_packets = vec![];
+        for i in 0..MAX_FEC_GROUP_SIZE {
+            parity_packets
+                .extend(sender.add_packet(PacketId(i as u16), &[u8::MAX; MAX_FEC_PAYLOAD_BYTES]));
+        }
+        assert_eq!(parity_packets.len(), 1);
+        let packet = builder.build_parity_packet(parity_packets.pop().unwrap());
+        // encoding panics if the packet is bigger than the MTU
+        let payload = builder.encode_packet(&packet).unwrap();
+        assert!(payload.len() <= MAX_PACKET_SIZE);
+    }
+}
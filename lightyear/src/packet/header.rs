@@ -1,19 +1,80 @@
+use bevy::reflect::Reflect;
 use bevy::utils::HashMap;
+use bitcode::encoding::{Fixed, Gamma};
 use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
 use serde::{Deserialize, Serialize};
 use tracing::trace;
 
-use bitcode::{Decode, Encode};
-
 use crate::_reexport::WrappedTime;
 use crate::packet::packet::PacketId;
 use crate::packet::packet_type::PacketType;
 use crate::packet::stats_manager::PacketStatsManager;
 use crate::prelude::TimeManager;
+use crate::protocol::BitSerializable;
+use crate::serialize::reader::ReadBuffer;
+use crate::serialize::writer::WriteBuffer;
 use crate::shared::tick_manager::Tick;
 
+/// Number of packet ids (before the last received packet id) that are acked in each packet header.
+///
+/// A bigger ack window makes it less likely that acks get lost at high send rates or under bursty packet loss
+/// (which would cause spurious resends), at the cost of a slightly bigger header when packets are lost.
+///
+/// The size of the window is negotiated once when the client connects: both peers use the smallest of
+/// the size requested by the client and the size allowed by the server.
+/// Until then (and for fragment and parity packets, whose size is fixed), the default 32-bit window is used.
+#[derive(
+    Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Reflect,
+)]
+pub enum AckBitfieldSize {
+    /// Ack the last 32 packets (in addition to the last received packet)
+    #[default]
+    Bits32,
+    /// Ack the last 64 packets (in addition to the last received packet)
+    Bits64,
+    /// Ack the last 128 packets (in addition to the last received packet)
+    Bits128,
+}
+
+impl AckBitfieldSize {
+    /// Number of bits in the ack bitfield
+    pub fn num_bits(&self) -> u8 {
+        match self {
+            AckBitfieldSize::Bits32 => 32,
+            AckBitfieldSize::Bits64 => 64,
+            AckBitfieldSize::Bits128 => 128,
+        }
+    }
+
+    /// Mask that keeps only the bits of the bitfield that are part of the window
+    fn mask(&self) -> u128 {
+        u128::MAX >> (MAX_ACK_BITFIELD_SIZE - self.num_bits())
+    }
+
+    /// Maximum number of bits that the header takes in addition to the header with the default window
+    /// (the bigger bitfield, and the bigger number of bytes of the bitfield)
+    pub(crate) fn extra_header_bits(&self) -> usize {
+        let default_num_bits = AckBitfieldSize::default().num_bits();
+        let extra_length_bits = if *self == AckBitfieldSize::default() {
+            0
+        } else {
+            4
+        };
+        (self.num_bits() - default_num_bits) as usize + extra_length_bits
+    }
+
+    /// Window used in the header of a packet: only [`PacketType::Data`] packets can use a bigger window than
+    /// the default one, so that the fragment and parity packets (which have a fixed maximum size) still fit in the MTU
+    fn for_packet_type(self, packet_type: PacketType) -> Self {
+        match packet_type {
+            PacketType::Data => self,
+            _ => AckBitfieldSize::default(),
+        }
+    }
+}
+
 /// Header included at the start of all packets
-#[derive(Encode, Decode, Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub(crate) struct PacketHeader {
     // TODO: this seems useless besides Data vs DataFragment
     /// Type of the packet sent
@@ -22,10 +83,15 @@ pub(crate) struct PacketHeader {
     pub(crate) packet_id: PacketId,
     /// Last ack-ed packet id received by the sender
     last_ack_packet_id: PacketId,
-    /// Bitfield of the last 32 packet ids before `ack_id`
-    /// (this means that in total we send acks for 33 packet-ids)
+    /// Number of packet ids before `last_ack_packet_id` that are included in `ack_bitfield`.
+    ///
+    /// This is not written in the header: when a header is decoded it is set to the maximum size,
+    /// and the [`PacketHeaderManager`] only reads the bits of the window negotiated for the connection.
+    ack_bitfield_size: AckBitfieldSize,
+    /// Bitfield of the last `ack_bitfield_size` packet ids before `ack_id`
+    /// (this means that with a 32-bit window we send acks for 33 packet-ids)
     /// See more information at: [GafferOnGames](https://gafferongames.com/post/reliability_ordering_and_congestion_avoidance_over_udp/)
-    ack_bitfield: u32,
+    ack_bitfield: u128,
     /// Current tick
     pub(crate) tick: Tick,
 }
@@ -36,7 +102,7 @@ impl PacketHeader {
     ///
     /// i is 0-indexed. So 0 represents the first bit of the bitfield (starting from the right)
     fn get_bitfield_bit(&self, i: u8) -> bool {
-        assert!(i < ACK_BITFIELD_SIZE);
+        assert!(i < self.ack_bitfield_size.num_bits());
         self.ack_bitfield & (1 << i) != 0
     }
 
@@ -45,8 +111,55 @@ impl PacketHeader {
     }
 }
 
-// we can only send acks for the last 32 packets ids before the last received packet
-const ACK_BITFIELD_SIZE: u8 = 32;
+impl BitSerializable for PacketHeader {
+    fn encode(&self, writer: &mut impl WriteBuffer) -> anyhow::Result<()> {
+        writer.encode(&self.packet_type, Fixed)?;
+        writer.encode(&self.packet_id, Fixed)?;
+        writer.encode(&self.last_ack_packet_id, Fixed)?;
+        // most packets are usually received, so we write the bitfield of the packets that were NOT received
+        // and we only write the bytes up to the last non-zero byte
+        let nack_bitfield = !self.ack_bitfield & self.ack_bitfield_size.mask();
+        let num_bytes = (u128::BITS - nack_bitfield.leading_zeros() + 7) / 8;
+        writer.encode(&(num_bytes as u8), Gamma)?;
+        for byte in &nack_bitfield.to_le_bytes()[..num_bytes as usize] {
+            writer.encode(byte, Fixed)?;
+        }
+        writer.encode(&self.tick, Fixed)
+    }
+
+    fn decode(reader: &mut impl ReadBuffer) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let packet_type = reader.decode::<PacketType>(Fixed)?;
+        let packet_id = reader.decode::<PacketId>(Fixed)?;
+        let last_ack_packet_id = reader.decode::<PacketId>(Fixed)?;
+        // the size of the window of the remote is not known when decoding, so we read the bitfield
+        // with the maximum size
+        let ack_bitfield_size = AckBitfieldSize::Bits128;
+        let num_bytes = reader.decode::<u8>(Gamma)?;
+        if num_bytes > ack_bitfield_size.num_bits() / 8 {
+            anyhow::bail!("ack bitfield is bigger than the ack window");
+        }
+        let mut nack_bytes = [0; MAX_ACK_BITFIELD_SIZE as usize / 8];
+        for byte in nack_bytes.iter_mut().take(num_bytes as usize) {
+            *byte = reader.decode::<u8>(Fixed)?;
+        }
+        let nack_bitfield = u128::from_le_bytes(nack_bytes);
+        let tick = reader.decode::<Tick>(Fixed)?;
+        Ok(Self {
+            packet_type,
+            packet_id,
+            last_ack_packet_id,
+            ack_bitfield_size,
+            ack_bitfield: !nack_bitfield & ack_bitfield_size.mask(),
+            tick,
+        })
+    }
+}
+
+// we can send acks for at most the last 128 packets ids before the last received packet
+const MAX_ACK_BITFIELD_SIZE: u8 = 128;
 // we can only buffer up to `MAX_SEND_PACKET_QUEUE_SIZE` packets for sending
 const MAX_SEND_PACKET_QUEUE_SIZE: u8 = 255;
 const CLEAR_UNACKED_PACKETS_DELAY: chrono::Duration = chrono::Duration::milliseconds(5000);
@@ -69,8 +182,14 @@ pub struct PacketHeaderManager {
     // ack_notification_receiver: Receiver<PacketId>,
 
     // keep track of the packets that were received (last packet received and the
-    // `ACK_BITFIELD_SIZE` packets before that)
+    // `MAX_ACK_BITFIELD_SIZE` packets before that)
     recv_buffer: ReceiveBuffer,
+    /// Number of packet ids that we ack in the headers of the packets we send
+    send_ack_bitfield_size: AckBitfieldSize,
+    /// Number of packet ids that the remote acks in the headers of the packets it sends
+    recv_ack_bitfield_size: AckBitfieldSize,
+    /// The packets sent by the remote before this packet id still use the default ack window
+    recv_ack_bitfield_size_start: Option<PacketId>,
     // copy of current time so that we don't pollute the function signatures to much
     current_time: WrappedTime,
 }
@@ -85,12 +204,66 @@ impl PacketHeaderManager {
             // sent_packets_not_acked: HashSet::with_capacity(MAX_SEND_PACKET_QUEUE_SIZE as usize),
             sent_packets_not_acked: HashMap::new(),
             recv_buffer: ReceiveBuffer::new(),
+            send_ack_bitfield_size: AckBitfieldSize::default(),
+            recv_ack_bitfield_size: AckBitfieldSize::default(),
+            recv_ack_bitfield_size_start: None,
             // ack_notification_sender,
             // ack_notification_receiver,
             current_time: WrappedTime::default(),
         }
     }
 
+    /// Number of packet ids that we ack in the headers of the packets we send
+    pub(crate) fn send_ack_bitfield_size(&self) -> AckBitfieldSize {
+        self.send_ack_bitfield_size
+    }
+
+    /// Set the number of packet ids that we ack in the headers of the packets we send.
+    ///
+    /// Returns the id of the first packet that will use the new window, so that the remote knows
+    /// how to read our headers.
+    pub(crate) fn set_send_ack_bitfield_size(
+        &mut self,
+        ack_bitfield_size: AckBitfieldSize,
+    ) -> PacketId {
+        self.send_ack_bitfield_size = ack_bitfield_size;
+        self.next_packet_id
+    }
+
+    /// Set the number of packet ids that the remote acks in the headers of the packets it sends,
+    /// starting from the packet `first_packet_id`
+    pub(crate) fn set_recv_ack_bitfield_size(
+        &mut self,
+        ack_bitfield_size: AckBitfieldSize,
+        first_packet_id: PacketId,
+    ) {
+        self.recv_ack_bitfield_size = ack_bitfield_size;
+        self.recv_ack_bitfield_size_start = Some(first_packet_id);
+    }
+
+    /// Number of packet ids acked in the header of a packet sent by the remote
+    fn recv_ack_bitfield_size(&mut self, header: &PacketHeader) -> AckBitfieldSize {
+        if let Some(start) = self.recv_ack_bitfield_size_start {
+            let diff = header.packet_id - start;
+            // the packet was sent before the remote started using the negotiated window
+            if diff < 0 {
+                return AckBitfieldSize::default();
+            }
+            // packets cannot be that late, so we don't need to remember the start anymore
+            // (which would become ambiguous once the packet ids wrap around)
+            if diff > i16::MAX / 2 {
+                self.recv_ack_bitfield_size_start = None;
+            }
+        }
+        self.recv_ack_bitfield_size
+            .for_packet_type(header.packet_type)
+    }
+
+    /// Statistics about the packets sent and received on this connection
+    pub(crate) fn stats_manager(&self) -> &PacketStatsManager {
+        &self.stats_manager
+    }
+
     pub(crate) fn update(&mut self, time_manager: &TimeManager) {
         self.current_time = time_manager.current_time();
         self.stats_manager.update(time_manager);
@@ -142,7 +315,10 @@ impl PacketHeaderManager {
             self.stats_manager.sent_packet_acked();
             newly_acked_packets.push(packet);
         }
-        for i in 1..=ACK_BITFIELD_SIZE {
+        let ack_bitfield_size = self
+            .recv_ack_bitfield_size(header)
+            .min(header.ack_bitfield_size);
+        for i in 1..=ack_bitfield_size.num_bits() {
             let packet_id = PacketId(header.last_ack_packet_id.wrapping_sub(i as u16));
             if header.get_bitfield_bit(i - 1) {
                 if let Some(packet) = self.update_sent_packets_not_acked(&packet_id) {
@@ -179,11 +355,13 @@ impl PacketHeaderManager {
             Some(id) => id,
             None => PacketId(u16::MAX),
         };
+        let ack_bitfield_size = self.send_ack_bitfield_size.for_packet_type(packet_type);
         let outgoing_header = PacketHeader {
             packet_type,
             packet_id: self.next_packet_id,
             last_ack_packet_id,
-            ack_bitfield: self.recv_buffer.get_bitfield(),
+            ack_bitfield_size,
+            ack_bitfield: self.recv_buffer.get_bitfield(ack_bitfield_size),
             // TODO: we send the tick, later. Seems a bit dangerous...
             tick: Tick(0),
         };
@@ -201,9 +379,9 @@ impl PacketHeaderManager {
 pub struct ReceiveBuffer {
     /// The packet id of the most recent packet received
     last_recv_packet_id: Option<PacketId>,
-    /// Use a ring buffer of ACK_BITFIELD_SIZE to track if we received the last
-    /// ACK_BITFIELD_SIZE packets prior to the last received packet
-    buffer: ConstGenericRingBuffer<bool, { ACK_BITFIELD_SIZE as usize }>,
+    /// Use a ring buffer of MAX_ACK_BITFIELD_SIZE to track if we received the last
+    /// MAX_ACK_BITFIELD_SIZE packets prior to the last received packet
+    buffer: ConstGenericRingBuffer<bool, { MAX_ACK_BITFIELD_SIZE as usize }>,
 }
 
 impl Default for ReceiveBuffer {
@@ -231,7 +409,7 @@ impl ReceiveBuffer {
             return;
         }
 
-        let bitfield_size = ACK_BITFIELD_SIZE as i16;
+        let bitfield_size = MAX_ACK_BITFIELD_SIZE as i16;
         let diff = self.last_recv_packet_id.unwrap() - id;
         if diff > bitfield_size {
             return;
@@ -264,14 +442,19 @@ impl ReceiveBuffer {
     }
 
     /// Convert the Receive Buffer to the bitfield that we need to send in the PacketHeader
-    fn get_bitfield(&self) -> u32 {
-        let mut ack_bitfield: u32 = 0;
+    fn get_bitfield(&self, ack_bitfield_size: AckBitfieldSize) -> u128 {
+        let num_bits = ack_bitfield_size.num_bits();
+        let mut ack_bitfield: u128 = 0;
         // mask starting from the left
-        let mut mask = 1 << (ACK_BITFIELD_SIZE - 1);
+        let mut mask = 1 << (num_bits - 1);
 
         // iter goes from the item pushed the longest ago (to the left of the bitfield)
         // to the items pushed most recently (to the right of the bitfield)
-        for exists in self.buffer.iter() {
+        for exists in self
+            .buffer
+            .iter()
+            .skip((MAX_ACK_BITFIELD_SIZE - num_bits) as usize)
+        {
             if *exists {
                 ack_bitfield |= mask;
             }
@@ -284,9 +467,8 @@ impl ReceiveBuffer {
 // TODO: add test for notification of packet delivered
 #[cfg(test)]
 mod tests {
-    use bitcode::encoding::Fixed;
-
     use crate::_reexport::*;
+    use crate::packet::packet::HEADER_BYTES;
 
     use super::*;
 
@@ -294,17 +476,20 @@ mod tests {
     fn test_recv_buffer() {
         let recv_buffer = ReceiveBuffer::new();
         assert_eq!(recv_buffer.last_recv_packet_id, None);
-        assert_eq!(recv_buffer.get_bitfield(), 0);
+        assert_eq!(recv_buffer.get_bitfield(AckBitfieldSize::Bits32), 0);
 
         // add a most recent packet, and perform some assertions
         fn add_most_recent_packet(
             mut buffer: ReceiveBuffer,
             id: u16,
-            expected_bitfield: u32,
+            expected_bitfield: u128,
         ) -> ReceiveBuffer {
             buffer.recv_packet(PacketId(id));
             assert_eq!(buffer.last_recv_packet_id, Some(PacketId(id)));
-            assert_eq!(buffer.get_bitfield(), expected_bitfield);
+            assert_eq!(
+                buffer.get_bitfield(AckBitfieldSize::Bits32),
+                expected_bitfield
+            );
             buffer
         }
 
@@ -314,31 +499,56 @@ mod tests {
         // receive one more packet with increment 1
         let recv_buffer = add_most_recent_packet(recv_buffer, 1, 1);
 
-        // receive a packet where the ACK_BITFIELD_SIZE > diff_id > 0
-        let recv_buffer = add_most_recent_packet(recv_buffer, 3, 0b0000_0110u32);
+        // receive a packet where the 32 > diff_id > 0
+        let recv_buffer = add_most_recent_packet(recv_buffer, 3, 0b0000_0110);
 
-        // receive another packet where the ACK_BITFIELD_SIZE > diff_id > 0
-        let mut recv_buffer = add_most_recent_packet(recv_buffer, 6, 0b0011_0100u32);
+        // receive another packet where the 32 > diff_id > 0
+        let mut recv_buffer = add_most_recent_packet(recv_buffer, 6, 0b0011_0100);
 
         // receive a packet which is in the past
-        // -ACK_BITFIELD_SIZE < diff_id < 0
+        // -32 < diff_id < 0
         recv_buffer.recv_packet(PacketId(2));
         assert_eq!(recv_buffer.last_recv_packet_id, Some(PacketId(6)));
-        assert_eq!(recv_buffer.get_bitfield(), 0b0011_1100u32);
+        assert_eq!(
+            recv_buffer.get_bitfield(AckBitfieldSize::Bits32),
+            0b0011_1100
+        );
 
         // receive a packet that is far ahead
-        // diff > ACK_BITFIELD_SIZE
+        // diff > 32
         let recv_buffer = add_most_recent_packet(recv_buffer, 50, 0);
 
         // receive a packet at the max far ahead
-        // diff == ACK_BITFIELD_SIZE
+        // diff == 32
         let mut recv_buffer = add_most_recent_packet(recv_buffer, 82, 1 << (32 - 1));
 
         // receive a packet that is too far in the past
-        // diff_id < -ACK_BITFIELD_SIZE
+        // diff_id < -32
         recv_buffer.recv_packet(PacketId(49));
         assert_eq!(recv_buffer.last_recv_packet_id, Some(PacketId(82)));
-        assert_eq!(recv_buffer.get_bitfield(), 1 << (32 - 1));
+        assert_eq!(
+            recv_buffer.get_bitfield(AckBitfieldSize::Bits32),
+            1 << (32 - 1)
+        );
+        // but it is still in the window for a bigger bitfield
+        assert_eq!(
+            recv_buffer.get_bitfield(AckBitfieldSize::Bits64),
+            (1 << (32 - 1)) | (1 << 32)
+        );
+    }
+
+    /// Check that the decoded header acks the same packets as the original header, within its window
+    fn assert_same_acks(header: &PacketHeader, read_header: &PacketHeader) {
+        let mask = header.ack_bitfield_size.mask();
+        assert_eq!(read_header.ack_bitfield & mask, header.ack_bitfield);
+        assert_eq!(
+            PacketHeader {
+                ack_bitfield_size: header.ack_bitfield_size,
+                ack_bitfield: header.ack_bitfield,
+                ..read_header.clone()
+            },
+            *header
+        );
     }
 
     #[test]
@@ -347,17 +557,101 @@ mod tests {
             packet_type: PacketType::Data,
             packet_id: PacketId(27),
             last_ack_packet_id: PacketId(13),
+            ack_bitfield_size: AckBitfieldSize::Bits32,
             ack_bitfield: 3,
             tick: Tick(0),
         };
         let mut writer = WriteWordBuffer::with_capacity(50);
-        writer.encode(&header, Fixed)?;
+        header.encode(&mut writer)?;
         let data = writer.finish_write();
+        assert!(data.len() <= HEADER_BYTES);
 
         let mut reader = ReadWordBuffer::start_read(data);
-        let read_header = reader.decode::<PacketHeader>(Fixed)?;
+        let read_header = PacketHeader::decode(&mut reader)?;
 
-        assert_eq!(header, read_header);
+        assert_same_acks(&header, &read_header);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serde_header_ack_bitfield_size() -> anyhow::Result<()> {
+        for (ack_bitfield_size, ack_bitfield) in [
+            // all packets were received: the bitfield takes almost no space
+            (AckBitfieldSize::Bits64, u64::MAX as u128),
+            (AckBitfieldSize::Bits128, !(1 << 100)),
+            (AckBitfieldSize::Bits128, 0),
+            // the biggest header with the default window fits in `HEADER_BYTES`
+            (AckBitfieldSize::Bits32, 0),
+        ] {
+            let header = PacketHeader {
+                packet_type: PacketType::Data,
+                packet_id: PacketId(27),
+                last_ack_packet_id: PacketId(13),
+                ack_bitfield_size,
+                ack_bitfield,
+                tick: Tick(3),
+            };
+            let mut writer = WriteWordBuffer::with_capacity(50);
+            header.encode(&mut writer)?;
+            assert!(
+                writer.num_bits_written()
+                    <= HEADER_BYTES * 8 + ack_bitfield_size.extra_header_bits()
+            );
+            let data = writer.finish_write();
+
+            let mut reader = ReadWordBuffer::start_read(data);
+            let read_header = PacketHeader::decode(&mut reader)?;
+            assert_same_acks(&header, &read_header);
+        }
         Ok(())
     }
+
+    #[test]
+    fn test_negotiate_ack_bitfield_size() {
+        let mut sender = PacketHeaderManager::new();
+        let mut receiver = PacketHeaderManager::new();
+        // the receiver sent 100 packets, and the sender received all of them except the first one
+        for i in 0..100 {
+            receiver.prepare_send_packet_header(PacketType::Data);
+            if i > 0 {
+                sender.recv_buffer.recv_packet(PacketId(i));
+            }
+        }
+        let encode_decode = |header: PacketHeader| {
+            let mut writer = WriteWordBuffer::with_capacity(50);
+            header.encode(&mut writer).unwrap();
+            let data = writer.finish_write();
+            PacketHeader::decode(&mut ReadWordBuffer::start_read(data)).unwrap()
+        };
+
+        // the sender starts using a bigger window, but the receiver does not know it yet:
+        // it only reads the default window, which is safe
+        let first_packet_id = sender.set_send_ack_bitfield_size(AckBitfieldSize::Bits128);
+        let header = encode_decode(sender.prepare_send_packet_header(PacketType::Data));
+        assert_eq!(header.packet_id, first_packet_id);
+        assert_eq!(receiver.process_recv_packet_header(&header).len(), 33);
+
+        // a packet sent with the default window before the switch arrives late:
+        // the packets outside of its window must not be considered acked
+        let mut late_sender = PacketHeaderManager::new();
+        late_sender.next_packet_id = PacketId(first_packet_id.wrapping_sub(1));
+        late_sender.recv_buffer = std::mem::take(&mut sender.recv_buffer);
+        let late_header = encode_decode(late_sender.prepare_send_packet_header(PacketType::Data));
+        sender.recv_buffer = std::mem::take(&mut late_sender.recv_buffer);
+
+        receiver.set_recv_ack_bitfield_size(AckBitfieldSize::Bits128, first_packet_id);
+        assert!(receiver.process_recv_packet_header(&late_header).is_empty());
+
+        // the next packets use the bigger window: all the other received packets are acked
+        let header = encode_decode(sender.prepare_send_packet_header(PacketType::Data));
+        assert_eq!(receiver.process_recv_packet_header(&header).len(), 99 - 33);
+        assert_eq!(
+            receiver.sent_packets_not_acked().keys().collect::<Vec<_>>(),
+            vec![&PacketId(0)]
+        );
+
+        // fragment packets always use the default window
+        let header = sender.prepare_send_packet_header(PacketType::DataFragment);
+        assert_eq!(header.ack_bitfield_size, AckBitfieldSize::Bits32);
+    }
 }
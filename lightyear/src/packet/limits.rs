@@ -0,0 +1,215 @@
+//! Limits applied to the data received from a remote peer.
+//!
+//! The packets we receive cannot be trusted: a malicious peer could for example announce a fragmented message
+//! with a lot of fragments, or start many fragmented messages that it never completes, to make us allocate
+//! a lot of memory. Data that exceeds the [`DecodeLimits`] is rejected with a [`DecodeError`].
+use bevy::prelude::Reflect;
+
+use crate::packet::message::{FragmentIndex, MessageContainer, MessageId};
+use crate::packet::packet::FRAGMENT_SIZE;
+
+/// Limits applied when decoding the messages received from a remote peer.
+///
+/// Note that the collections decoded with `bitcode` (for example a `Vec` inside a message) never allocate
+/// more elements than there are remaining bits in the buffer, so `max_message_size` also bounds
+/// the allocations done while decoding a message. `max_collection_len` can be used to reject collections
+/// that are longer than what the protocol expects.
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct DecodeLimits {
+    /// Maximum size in bytes of a message (after its fragments have been reassembled)
+    pub max_message_size: usize,
+    /// Maximum number of fragments of a fragmented message
+    pub max_fragments_per_message: FragmentIndex,
+    /// Maximum number of fragmented messages that are being reassembled at the same time on a connection.
+    ///
+    /// When the limit is exceeded, the oldest incomplete messages of the unreliable channels are discarded;
+    /// the connection is only rejected if the messages of the reliable channels exceed the limit
+    pub max_pending_fragmented_messages: usize,
+    /// Maximum number of elements of a collection (`Vec`, `HashMap`, `String`, etc.) decoded from a message
+    pub max_collection_len: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            // the biggest message that can be sent
+            max_message_size: FragmentIndex::MAX as usize * FRAGMENT_SIZE,
+            max_fragments_per_message: FragmentIndex::MAX,
+            max_pending_fragmented_messages: 64,
+            // a collection of bytes cannot be bigger than the biggest message
+            max_collection_len: FragmentIndex::MAX as usize * FRAGMENT_SIZE,
+        }
+    }
+}
+
+impl DecodeLimits {
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    pub fn with_max_fragments_per_message(
+        mut self,
+        max_fragments_per_message: FragmentIndex,
+    ) -> Self {
+        self.max_fragments_per_message = max_fragments_per_message;
+        self
+    }
+
+    pub fn with_max_pending_fragmented_messages(
+        mut self,
+        max_pending_fragmented_messages: usize,
+    ) -> Self {
+        self.max_pending_fragmented_messages = max_pending_fragmented_messages;
+        self
+    }
+
+    pub fn with_max_collection_len(mut self, max_collection_len: usize) -> Self {
+        self.max_collection_len = max_collection_len;
+        self
+    }
+
+    /// Decode data received from the remote peer, rejecting the collections longer than `max_collection_len`
+    pub(crate) fn decode<T>(&self, f: impl FnOnce() -> T) -> T {
+        bitcode::with_len_limit(self.max_collection_len, f)
+    }
+
+    /// Check that a received message (or fragment) respects the limits
+    pub(crate) fn check_message(&self, message: &MessageContainer) -> Result<(), DecodeError> {
+        match message {
+            MessageContainer::Single(data) => {
+                if data.bytes.len() > self.max_message_size {
+                    return Err(DecodeError::MessageTooBig {
+                        size: data.bytes.len(),
+                        max: self.max_message_size,
+                    });
+                }
+            }
+            MessageContainer::Fragment(fragment) => {
+                if fragment.num_fragments > self.max_fragments_per_message {
+                    return Err(DecodeError::TooManyFragments {
+                        num_fragments: fragment.num_fragments,
+                        max: self.max_fragments_per_message,
+                    });
+                }
+                // every fragment except the last one is full, so this is the smallest possible size of the message
+                let min_size =
+                    (fragment.num_fragments as usize).saturating_sub(1) * FRAGMENT_SIZE + 1;
+                if min_size > self.max_message_size {
+                    return Err(DecodeError::MessageTooBig {
+                        size: min_size,
+                        max: self.max_message_size,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when the data received from a remote peer is invalid or exceeds the [`DecodeLimits`]
+///
+/// On the server, the client that sent the data is disconnected.
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    #[error("message of at least {size} bytes exceeds the maximum message size of {max} bytes")]
+    MessageTooBig { size: usize, max: usize },
+    #[error(
+        "fragmented message with {num_fragments} fragments exceeds the maximum of {max} fragments"
+    )]
+    TooManyFragments {
+        num_fragments: FragmentIndex,
+        max: FragmentIndex,
+    },
+    #[error(
+        "invalid fragment {fragment_id} of message {message_id:?} with {num_fragments} fragments"
+    )]
+    InvalidFragment {
+        message_id: MessageId,
+        fragment_id: FragmentIndex,
+        num_fragments: FragmentIndex,
+    },
+    #[error("more than {max} fragmented messages are pending reassembly")]
+    TooManyPendingFragmentedMessages { max: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::packet::message::{FragmentData, SingleData};
+    use crate::serialize::reader::ReadBuffer;
+    use crate::serialize::wordbuffer::reader::ReadWordBuffer;
+    use crate::serialize::wordbuffer::writer::WriteWordBuffer;
+    use crate::serialize::writer::WriteBuffer;
+
+    use super::*;
+
+    fn fragment(num_fragments: FragmentIndex) -> MessageContainer {
+        MessageContainer::Fragment(FragmentData {
+            message_id: MessageId(0),
+            tick: None,
+            fragment_id: 0,
+            num_fragments,
+            bytes: Bytes::from(vec![0; FRAGMENT_SIZE]),
+            priority: 1.0,
+        })
+    }
+
+    #[test]
+    fn test_check_message() {
+        let limits = DecodeLimits::default()
+            .with_max_message_size(2 * FRAGMENT_SIZE)
+            .with_max_fragments_per_message(4);
+
+        let single = SingleData::new(None, Bytes::from(vec![0; 2 * FRAGMENT_SIZE]), 1.0);
+        assert_eq!(limits.check_message(&single.into()), Ok(()));
+        let single = SingleData::new(None, Bytes::from(vec![0; 2 * FRAGMENT_SIZE + 1]), 1.0);
+        assert_eq!(
+            limits.check_message(&single.into()),
+            Err(DecodeError::MessageTooBig {
+                size: 2 * FRAGMENT_SIZE + 1,
+                max: 2 * FRAGMENT_SIZE
+            })
+        );
+
+        assert_eq!(limits.check_message(&fragment(2)), Ok(()));
+        // the message would be bigger than the maximum message size
+        assert_eq!(
+            limits.check_message(&fragment(3)),
+            Err(DecodeError::MessageTooBig {
+                size: 2 * FRAGMENT_SIZE + 1,
+                max: 2 * FRAGMENT_SIZE
+            })
+        );
+        assert_eq!(
+            limits.check_message(&fragment(5)),
+            Err(DecodeError::TooManyFragments {
+                num_fragments: 5,
+                max: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_collection_len() -> anyhow::Result<()> {
+        let limits = DecodeLimits::default().with_max_collection_len(4);
+        let mut writer = WriteWordBuffer::with_capacity(64);
+        writer.serialize(&vec![0u32; 4])?;
+        writer.serialize(&"hello".to_string())?;
+        let bytes = writer.finish_write().to_vec();
+
+        let mut reader = ReadWordBuffer::start_read(&bytes);
+        assert_eq!(
+            limits.decode(|| reader.deserialize::<Vec<u32>>())?,
+            vec![0; 4]
+        );
+        // the string is longer than the limit
+        assert!(limits.decode(|| reader.deserialize::<String>()).is_err());
+        // the limit only applies to the data decoded with `DecodeLimits::decode`
+        let mut reader = ReadWordBuffer::start_read(&bytes);
+        reader.deserialize::<Vec<u32>>()?;
+        assert_eq!(reader.deserialize::<String>()?, "hello");
+        Ok(())
+    }
+}
@@ -4,6 +4,7 @@ use bytes::Bytes;
 
 use bitcode::encoding::{Fixed, Gamma};
 
+use crate::packet::limits::DecodeError;
 use crate::packet::packet::FRAGMENT_SIZE;
 use crate::protocol::{BitSerializable, EventContext};
 use crate::serialize::reader::ReadBuffer;
@@ -206,6 +207,14 @@ impl FragmentData {
         let tick = reader.decode::<Option<Tick>>(Fixed)?;
         let fragment_id = reader.decode::<FragmentIndex>(Gamma)?;
         let num_fragments = reader.decode::<FragmentIndex>(Gamma)?;
+        if fragment_id >= num_fragments {
+            return Err(DecodeError::InvalidFragment {
+                message_id,
+                fragment_id,
+                num_fragments,
+            }
+            .into());
+        }
         let bytes = if fragment_id == num_fragments - 1 {
             // let num_bytes = reader.decode::<usize>(Gamma)?;
             // let num_bytes_non_zero = std::num::NonZeroUsize::new(num_bytes)
@@ -216,6 +225,14 @@ impl FragmentData {
             // TODO: avoid the extra copy
             //  - maybe have the encoding of bytes be
             let read_bytes = reader.decode::<Vec<u8>>(Fixed)?;
+            if read_bytes.len() > FRAGMENT_SIZE {
+                return Err(DecodeError::InvalidFragment {
+                    message_id,
+                    fragment_id,
+                    num_fragments,
+                }
+                .into());
+            }
             Bytes::from(read_bytes)
         } else {
             // Serde does not handle arrays well (https://github.com/serde-rs/serde/issues/573)
@@ -1,4 +1,4 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
 use anyhow::{anyhow, Context};
 use bevy::ptr::UnsafeCellDeref;
@@ -6,13 +6,16 @@ use bevy::reflect::Reflect;
 use bitcode::buffer::BufferTrait;
 use bitcode::word_buffer::WordBuffer;
 use crossbeam_channel::Receiver;
-use tracing::{info, trace};
+use tracing::{error, info, trace};
 
 use crate::channel::builder::ChannelContainer;
 use crate::channel::receivers::ChannelReceive;
 use crate::channel::senders::ChannelSend;
-use crate::packet::message::{FragmentData, MessageAck, MessageId, SingleData};
-use crate::packet::packet::{Packet, PacketId, MTU_PAYLOAD_BYTES};
+use crate::packet::fec::{FecReceiver, FecSender};
+use crate::packet::header::AckBitfieldSize;
+use crate::packet::limits::{DecodeError, DecodeLimits};
+use crate::packet::message::{FragmentData, MessageAck, MessageContainer, MessageId, SingleData};
+use crate::packet::packet::{Packet, PacketData, PacketId, MTU_PAYLOAD_BYTES};
 use crate::packet::packet_manager::{PacketBuilder, Payload, PACKET_BUFFER_CAPACITY};
 use crate::packet::priority_manager::{PriorityConfig, PriorityManager};
 use crate::protocol::channel::{ChannelKind, ChannelRegistry};
@@ -23,6 +26,7 @@ use crate::serialize::wordbuffer::reader::{BufferPool, ReadWordBuffer};
 use crate::serialize::wordbuffer::writer::WriteWordBuffer;
 use crate::serialize::writer::WriteBuffer;
 use crate::shared::ping::manager::PingManager;
+use crate::shared::stats::{ChannelStats, ConnectionStats};
 use crate::shared::tick_manager::Tick;
 use crate::shared::tick_manager::TickManager;
 use crate::shared::time_manager::TimeManager;
@@ -47,23 +51,117 @@ pub struct MessageManager {
     writer: WriteWordBuffer,
     // read_buffer: WordBuffer,
     reader_pool: BufferPool,
+    /// Statistics for each channel
+    channel_stats: HashMap<ChannelKind, ChannelStats>,
+    /// Total number of bytes sent (including the packet headers)
+    bytes_sent: u64,
+    /// Channels that use forward error correction
+    fec_channels: HashSet<NetId>,
+    /// Builds the parity packets for the packets of the FEC channels (if any channel uses FEC)
+    fec_sender: Option<FecSender>,
+    /// Rebuilds lost packets of the FEC channels (if any channel uses FEC)
+    fec_receiver: Option<FecReceiver>,
+    /// Number of lost packets that were rebuilt using forward error correction
+    packets_recovered: u64,
+    /// Limits applied to the messages received from the remote peer
+    decode_limits: DecodeLimits,
 }
 
 impl MessageManager {
     pub fn new(channel_registry: &ChannelRegistry, priority_config: PriorityConfig) -> Self {
+        let channels = channel_registry.channels();
+        let channel_stats = channels
+            .keys()
+            .map(|kind| (*kind, ChannelStats::default()))
+            .collect();
+        let fec_channels = channels
+            .iter()
+            .filter(|(_, channel)| channel.setting.fec.is_some())
+            .filter_map(|(kind, _)| channel_registry.get_net_from_kind(kind).copied())
+            .collect::<HashSet<_>>();
+        // if multiple channels use FEC, use the smallest group size
+        let fec_group_size = channels
+            .values()
+            .filter_map(|channel| channel.setting.fec.map(|fec| fec.group_size))
+            .min();
         Self {
             packet_manager: PacketBuilder::new(),
             priority_manager: PriorityManager::new(priority_config),
-            channels: channel_registry.channels(),
+            channels,
             channel_registry: channel_registry.clone(),
             packet_to_message_ack_map: HashMap::new(),
             writer: WriteWordBuffer::with_capacity(PACKET_BUFFER_CAPACITY),
             // TODO: it looks like we don't really need the pool this case, we can just keep re-using the same buffer
             reader_pool: BufferPool::new(1),
             // read_buffer: WordBuffer::with_capacity(MTU_PAYLOAD_BYTES),
+            channel_stats,
+            bytes_sent: 0,
+            fec_channels,
+            fec_sender: fec_group_size.map(FecSender::new),
+            fec_receiver: fec_group_size.map(|_| FecReceiver::default()),
+            packets_recovered: 0,
+            decode_limits: DecodeLimits::default(),
         }
     }
 
+    /// Get the network statistics of this connection
+    pub(crate) fn stats(&self, ping_manager: &PingManager) -> ConnectionStats {
+        let stats_manager = self.packet_manager.header_manager.stats_manager();
+        let packet_stats = stats_manager.total_stats();
+        let mut channels = self.channel_stats.clone();
+        for (channel_kind, channel) in self.channels.iter() {
+            let channel_stats = channels.entry(*channel_kind).or_default();
+            channel_stats.messages_in_flight = channel.sender.num_messages_in_flight();
+            channel_stats.messages_resent = channel.sender.num_messages_resent();
+        }
+        ConnectionStats {
+            rtt: ping_manager.rtt(),
+            jitter: ping_manager.jitter(),
+            packet_loss: stats_manager.packet_loss(),
+            packets_sent: packet_stats.num_sent_packets as u64,
+            packets_received: packet_stats.num_received_packets as u64,
+            packets_acked: packet_stats.num_sent_packets_acked as u64,
+            packets_lost: packet_stats.num_sent_packets_lost as u64,
+            bytes_sent: self.bytes_sent,
+            packets_recovered: self.packets_recovered,
+            channels,
+        }
+    }
+
+    /// Number of packet ids that we ack in the headers of the packets we send
+    pub(crate) fn ack_bitfield_size(&self) -> AckBitfieldSize {
+        self.packet_manager.header_manager.send_ack_bitfield_size()
+    }
+
+    /// Start using the negotiated ack window in the headers of the packets we send.
+    ///
+    /// Returns the id of the first packet that uses the window, which must be sent to the remote
+    pub(crate) fn set_send_ack_bitfield_size(
+        &mut self,
+        ack_bitfield_size: AckBitfieldSize,
+    ) -> PacketId {
+        self.packet_manager
+            .header_manager
+            .set_send_ack_bitfield_size(ack_bitfield_size)
+    }
+
+    /// The remote uses the negotiated ack window in its packets, starting from `first_packet_id`
+    pub(crate) fn set_recv_ack_bitfield_size(
+        &mut self,
+        ack_bitfield_size: AckBitfieldSize,
+        first_packet_id: PacketId,
+    ) {
+        self.packet_manager
+            .header_manager
+            .set_recv_ack_bitfield_size(ack_bitfield_size, first_packet_id);
+    }
+
+    /// Set the limits applied to the messages received from the remote peer
+    pub(crate) fn with_decode_limits(mut self, decode_limits: DecodeLimits) -> Self {
+        self.decode_limits = decode_limits;
+        self
+    }
+
     pub(crate) fn get_replication_update_send_receiver(&mut self) -> Receiver<MessageId> {
         self.priority_manager
             .subscribe_replication_update_sent_messages()
@@ -77,6 +175,10 @@ impl MessageManager {
         tick_manager: &TickManager,
     ) {
         self.packet_manager.header_manager.update(time_manager);
+        self.priority_manager.update(time_manager);
+        if let Some(fec_receiver) = &mut self.fec_receiver {
+            fec_receiver.update(time_manager.current_time());
+        }
         for channel in self.channels.values_mut() {
             channel
                 .sender
@@ -148,7 +250,8 @@ impl MessageManager {
             }
         }
         // return early if there are no messages to send
-        if !has_data_to_send {
+        // (including messages that could not be sent previously because of the bandwidth quota)
+        if !has_data_to_send && !self.priority_manager.has_buffered_messages() {
             return Ok(vec![]);
         }
 
@@ -157,13 +260,31 @@ impl MessageManager {
         let (data_to_send, num_bytes_added_to_limiter) = self.priority_manager.priority_filter(
             data_to_send,
             &self.channel_registry,
+            &mut self.channel_stats,
             current_tick,
         );
+        for (channel_id, (single_data, fragment_data)) in data_to_send.iter() {
+            let channel_kind = self
+                .channel_registry
+                .get_kind_from_net_id(*channel_id)
+                .context("cannot find channel kind")?;
+            self.channel_stats
+                .entry(*channel_kind)
+                .or_default()
+                .record_sent(single_data, fragment_data);
+        }
 
-        let packets = self.packet_manager.build_packets(data_to_send);
+        // the messages of the FEC channels are put in separate packets, so that only those packets are protected
+        let (fec_data_to_send, data_to_send): (BTreeMap<_, _>, BTreeMap<_, _>) = data_to_send
+            .into_iter()
+            .partition(|(channel_id, _)| self.fec_channels.contains(channel_id));
+        let mut packets = self.packet_manager.build_packets(data_to_send);
+        let num_unprotected_packets = packets.len();
+        packets.extend(self.packet_manager.build_packets(fec_data_to_send));
 
         let mut bytes = Vec::new();
-        for mut packet in packets {
+        let mut parity_packets = Vec::new();
+        for (i, mut packet) in packets.into_iter().enumerate() {
             trace!(num_messages = ?packet.data.num_messages(), "sending packet");
             let packet_id = packet.header().packet_id;
 
@@ -172,6 +293,11 @@ impl MessageManager {
 
             // Step 2. Get the packets to send over the network
             let payload = self.packet_manager.encode_packet(&packet)?;
+            if i >= num_unprotected_packets {
+                if let Some(fec_sender) = &mut self.fec_sender {
+                    parity_packets.extend(fec_sender.add_packet(packet_id, &payload));
+                }
+            }
             bytes.push(payload);
             // io.send(payload, &self.remote_addr)?;
 
@@ -202,9 +328,17 @@ impl MessageManager {
                 })?;
         }
 
+        // send the parity packets of the FEC groups that are complete
+        for parity_packet in parity_packets {
+            let mut packet = self.packet_manager.build_parity_packet(parity_packet);
+            packet.header.tick = current_tick;
+            bytes.push(self.packet_manager.encode_packet(&packet)?);
+        }
+
+        let total_bytes_sent = bytes.iter().map(|b| b.len() as u32).sum::<u32>();
+        self.bytes_sent += total_bytes_sent as u64;
         // adjust the real amount of bytes that we sent through the limiter (to account for the actual packet size)
         if self.priority_manager.config.enabled {
-            let total_bytes_sent = bytes.iter().map(|b| b.len() as u32).sum::<u32>();
             if let Ok(remaining_bytes_to_add) =
                 (total_bytes_sent - num_bytes_added_to_limiter).try_into()
             {
@@ -222,6 +356,40 @@ impl MessageManager {
     /// Update the acks, and put the messages from the packets in internal buffers
     /// Returns the tick of the packet
     pub fn recv_packet(&mut self, packet: Packet) -> anyhow::Result<Tick> {
+        let mut recovered_packet = None;
+        if let Some(fec_receiver) = &mut self.fec_receiver {
+            let packet_id = packet.header().packet_id;
+            // the packet was already rebuilt from a parity packet
+            if fec_receiver.is_recovered(packet_id) {
+                trace!(
+                    ?packet_id,
+                    "ignoring packet that was already recovered with FEC"
+                );
+                return Ok(packet.header().tick);
+            }
+            if let PacketData::Parity(parity_packet) = &packet.data {
+                if let Some(payload) = fec_receiver.recover(parity_packet) {
+                    match Packet::decode(&mut ReadWordBuffer::start_read(payload.as_slice())) {
+                        Ok(packet) => recovered_packet = Some(packet),
+                        Err(e) => error!("could not decode packet recovered with FEC: {:?}", e),
+                    }
+                }
+            } else if packet.contains_channel(&self.fec_channels) {
+                // keep the packet around to be able to rebuild other packets of the group
+                fec_receiver.store(packet_id, self.packet_manager.encode_packet(&packet)?);
+            }
+        }
+        let tick = self.process_packet(packet)?;
+        if let Some(recovered_packet) = recovered_packet {
+            self.packets_recovered += 1;
+            self.process_packet(recovered_packet)?;
+        }
+        Ok(tick)
+    }
+
+    /// Update the acks, and put the messages from the packet in internal buffers
+    /// Returns the tick of the packet
+    fn process_packet(&mut self, packet: Packet) -> anyhow::Result<Tick> {
         // Step 1. Parse the packet
         let tick = packet.header().tick;
         trace!(?packet, "Received packet");
@@ -255,6 +423,7 @@ impl MessageManager {
         }
 
         // Step 4. Put the messages from the packet in the internal buffers for each channel
+        let mut received_fragment = false;
         for (channel_net_id, messages) in packet.data.contents() {
             let channel_kind = self
                 .channel_registry
@@ -272,14 +441,46 @@ impl MessageManager {
                 messages,
                 channel_kind
             );
+            let channel_stats = self.channel_stats.entry(*channel_kind).or_default();
             for mut message in messages {
+                self.decode_limits.check_message(&message)?;
+                received_fragment |= matches!(message, MessageContainer::Fragment(_));
+                channel_stats.record_received(&message);
                 message.set_tick(tick);
                 channel.receiver.buffer_recv(message)?;
             }
         }
+        if received_fragment {
+            self.check_pending_fragmented_messages()?;
+        }
         Ok(tick)
     }
 
+    /// Check that the remote peer is not reassembling too many fragmented messages at the same time.
+    ///
+    /// The oldest incomplete messages of the unreliable channels are discarded first (an honest peer can
+    /// accumulate them on a lossy link); the limit is only an error if it is exceeded by the reliable channels
+    fn check_pending_fragmented_messages(&mut self) -> Result<(), DecodeError> {
+        let mut num_pending = self
+            .channels
+            .values()
+            .map(|channel| channel.receiver.num_pending_fragmented_messages())
+            .sum::<usize>();
+        while num_pending > self.decode_limits.max_pending_fragmented_messages {
+            if !self
+                .channels
+                .values_mut()
+                .any(|channel| channel.receiver.evict_oldest_fragmented_message())
+            {
+                return Err(DecodeError::TooManyPendingFragmentedMessages {
+                    max: self.decode_limits.max_pending_fragmented_messages,
+                });
+            }
+            num_pending -= 1;
+        }
+        Ok(())
+    }
+
     /// Read all the messages in the internal buffers that are ready to be processed
     // TODO: this is where naia converts the messages to events and pushes them to an event queue
     //  let be conservative and just return the messages right now. We could switch to an iterator
@@ -293,15 +494,26 @@ impl MessageManager {
                 //  we can just have a single buffer, and keep re-using that buffer
                 trace!(pool_len = ?self.reader_pool.0.len(), "read from message manager");
                 let mut reader = self.reader_pool.start_read(single_data.bytes.as_ref());
-                let message = M::decode(&mut reader).expect("Could not decode message");
+                let message = self.decode_limits.decode(|| M::decode(&mut reader));
                 // return the buffer to the pool
                 self.reader_pool.attach(reader);
+                let message = match message {
+                    Ok(message) => message,
+                    Err(e) => {
+                        error!(?channel_kind, "could not decode message: {:?}", e);
+                        continue;
+                    }
+                };
 
                 // SAFETY: when we receive the message, we set the tick of the message to the header tick
                 // so every message has a tick
                 messages.push((single_data.tick.unwrap(), message));
             }
             if !messages.is_empty() {
+                self.channel_stats
+                    .entry(*channel_kind)
+                    .or_default()
+                    .messages_received += messages.len() as u64;
                 map.insert(*channel_kind, messages);
             }
         }
@@ -315,6 +527,7 @@ impl MessageManager {
 mod tests {
     use std::collections::HashMap;
 
+    use bevy::prelude::default;
     use bevy::utils::Duration;
 
     use crate::_reexport::*;
@@ -582,4 +795,150 @@ mod tests {
         assert_eq!(update_acks_tracker.try_recv()?, message_id);
         Ok(())
     }
+
+    #[test]
+    /// Check that a lost packet of a FEC channel can be rebuilt from the parity packet
+    fn test_message_manager_fec() -> Result<(), anyhow::Error> {
+        let mut channel_registry = ChannelRegistry::new();
+        channel_registry.add::<Channel1>(ChannelSettings {
+            mode: ChannelMode::UnorderedUnreliable,
+            fec: Some(FecSettings { group_size: 2 }),
+            ..default()
+        });
+        channel_registry.add::<Channel2>(ChannelSettings {
+            mode: ChannelMode::UnorderedUnreliable,
+            ..default()
+        });
+        let mut client_message_manager =
+            MessageManager::new(&channel_registry, PriorityConfig::default());
+        let mut server_message_manager =
+            MessageManager::new(&channel_registry, PriorityConfig::default());
+        let channel_kind_1 = ChannelKind::of::<Channel1>();
+        let channel_kind_2 = ChannelKind::of::<Channel2>();
+        let message_a = MyMessageProtocol::Message1(Message1("a".to_string()));
+        let message_b = MyMessageProtocol::Message1(Message1("b".to_string()));
+
+        // the messages of the FEC channel are sent in a separate packet
+        client_message_manager.buffer_send(message_a.clone(), channel_kind_2)?;
+        client_message_manager.buffer_send(message_a.clone(), channel_kind_1)?;
+        let mut packet_bytes = client_message_manager.send_packets(Tick(0))?;
+        assert_eq!(packet_bytes.len(), 2);
+        // the group is complete: a parity packet is added
+        client_message_manager.buffer_send(message_b.clone(), channel_kind_1)?;
+        packet_bytes.extend(client_message_manager.send_packets(Tick(1))?);
+        assert_eq!(packet_bytes.len(), 4);
+
+        // the first packet of the FEC channel is lost
+        let lost_packet = packet_bytes.remove(1);
+        for packet_byte in packet_bytes.iter_mut() {
+            let packet = Packet::decode(&mut ReadWordBuffer::start_read(packet_byte.as_slice()))?;
+            server_message_manager.recv_packet(packet)?;
+        }
+        let data = server_message_manager.read_messages();
+        assert_eq!(
+            data.get(&channel_kind_1).unwrap(),
+            &vec![(Tick(1), message_b.clone()), (Tick(0), message_a.clone())]
+        );
+        assert_eq!(
+            data.get(&channel_kind_2).unwrap(),
+            &vec![(Tick(0), message_a.clone())]
+        );
+        assert_eq!(
+            server_message_manager
+                .stats(&PingManager::new(PingConfig::default()))
+                .packets_recovered,
+            1
+        );
+
+        // the lost packet arrives late: it is ignored because it was already recovered
+        let packet = Packet::decode(&mut ReadWordBuffer::start_read(lost_packet.as_slice()))?;
+        server_message_manager.recv_packet(packet)?;
+        assert!(server_message_manager
+            .read_messages::<MyMessageProtocol>()
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    /// Check that the messages received from the remote peer that exceed the decode limits are rejected
+    fn test_message_manager_decode_limits() -> Result<(), anyhow::Error> {
+        let mut channel_registry = ChannelRegistry::new();
+        channel_registry.add::<Channel1>(ChannelSettings {
+            mode: ChannelMode::UnorderedUnreliable,
+            ..default()
+        });
+        let channel_kind = ChannelKind::of::<Channel1>();
+        let mut client_message_manager =
+            MessageManager::new(&channel_registry, PriorityConfig::default());
+        let big_message = MyMessageProtocol::Message1(Message1("a".repeat(FRAGMENT_SIZE * 3)));
+        client_message_manager.buffer_send(big_message.clone(), channel_kind)?;
+        client_message_manager.buffer_send(big_message.clone(), channel_kind)?;
+        // each message is split in 4 fragments
+        let packet_bytes = client_message_manager.send_packets(Tick(0))?;
+        assert_eq!(packet_bytes.len(), 8);
+        let decode = |i: usize| {
+            Packet::decode(&mut ReadWordBuffer::start_read(packet_bytes[i].as_slice())).unwrap()
+        };
+
+        // the message is too big
+        let mut server_message_manager =
+            MessageManager::new(&channel_registry, PriorityConfig::default())
+                .with_decode_limits(DecodeLimits::default().with_max_message_size(FRAGMENT_SIZE));
+        let error = server_message_manager.recv_packet(decode(0)).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<DecodeError>(),
+            Some(DecodeError::MessageTooBig { .. })
+        ));
+
+        // too many fragmented messages are reassembled at the same time on an unreliable channel:
+        // the oldest one is discarded
+        let mut server_message_manager =
+            MessageManager::new(&channel_registry, PriorityConfig::default()).with_decode_limits(
+                DecodeLimits::default().with_max_pending_fragmented_messages(1),
+            );
+        server_message_manager.recv_packet(decode(0))?;
+        server_message_manager.recv_packet(decode(4))?;
+        for i in 1..4 {
+            server_message_manager.recv_packet(decode(i))?;
+        }
+        assert!(server_message_manager
+            .read_messages::<MyMessageProtocol>()
+            .is_empty());
+        for i in 5..8 {
+            server_message_manager.recv_packet(decode(i))?;
+        }
+        assert_eq!(
+            server_message_manager.read_messages::<MyMessageProtocol>()[&channel_kind]
+                .iter()
+                .map(|(_, message)| message.clone())
+                .collect::<Vec<_>>(),
+            vec![big_message.clone()]
+        );
+
+        // too many fragmented messages are reassembled at the same time on a reliable channel
+        let mut channel_registry = ChannelRegistry::new();
+        channel_registry.add::<Channel1>(ChannelSettings {
+            mode: ChannelMode::UnorderedReliable(ReliableSettings::default()),
+            ..default()
+        });
+        let mut client_message_manager =
+            MessageManager::new(&channel_registry, PriorityConfig::default());
+        client_message_manager.buffer_send(big_message.clone(), channel_kind)?;
+        client_message_manager.buffer_send(big_message.clone(), channel_kind)?;
+        let packet_bytes = client_message_manager.send_packets(Tick(0))?;
+        let decode = |i: usize| {
+            Packet::decode(&mut ReadWordBuffer::start_read(packet_bytes[i].as_slice())).unwrap()
+        };
+        let mut server_message_manager =
+            MessageManager::new(&channel_registry, PriorityConfig::default()).with_decode_limits(
+                DecodeLimits::default().with_max_pending_fragmented_messages(1),
+            );
+        server_message_manager.recv_packet(decode(0))?;
+        let error = server_message_manager.recv_packet(decode(4)).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::TooManyPendingFragmentedMessages { max: 1 })
+        );
+        Ok(())
+    }
 }
@@ -19,9 +19,15 @@ Packets that are over the maximum packet size will be fragmented into multiple [
 [`FragmentedPacket`]: packet::FragmentedPacket
 */
 
+/// Forward error correction: rebuild lost packets from parity packets
+pub(crate) mod fec;
+
 /// Manages the [`PacketHeader`](header::PacketHeader) which includes important packet information
 pub mod header;
 
+/// Limits applied to the data received from a remote peer
+pub mod limits;
+
 /// Defines the [`Message`](message::Message) struct, which is a piece of serializable data
 pub mod message;
 
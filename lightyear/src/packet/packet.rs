@@ -1,8 +1,10 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
+use anyhow::Context;
 use bitcode::encoding::{Fixed, Gamma};
 
 use crate::connection::netcode::MAX_PACKET_SIZE;
+use crate::packet::fec::MAX_FEC_PAYLOAD_BYTES;
 use crate::packet::header::PacketHeader;
 use crate::packet::message::{FragmentData, MessageAck, MessageContainer, SingleData};
 use crate::packet::packet_type::PacketType;
@@ -16,10 +18,11 @@ use crate::utils::wrapping_id::wrapping_id;
 // Internal id that we assign to each packet sent over the network
 wrapping_id!(PacketId);
 
-/// Maximum number of bytes to write the header
+/// Maximum number of bytes to write the header (with the default ack window)
 /// PacketType: 2 bits
+/// Number of bytes of the ack bitfield: 5 bits
 /// Rest: 10 bytes
-const HEADER_BYTES: usize = 11;
+pub(crate) const HEADER_BYTES: usize = 11;
 /// The maximum of bytes that the payload of the packet can contain (excluding the header)
 /// remove 1 byte for byte alignment at the end
 pub(crate) const MTU_PAYLOAD_BYTES: usize = MAX_PACKET_SIZE - HEADER_BYTES - 1;
@@ -221,6 +224,64 @@ impl BitSerializable for FragmentedPacket {
     }
 }
 
+/// A parity packet used for forward error correction.
+///
+/// Contains the XOR of the encoded bytes of a group of packets, so that the receiver can rebuild
+/// one lost packet of the group.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParityPacket {
+    /// Id of the first packet of the group
+    pub(crate) first_packet_id: PacketId,
+    /// Bitfield of the packets included in the group: the i-th bit (starting from the right)
+    /// is set if the packet `first_packet_id + i` is part of the group
+    pub(crate) packet_ids_bitfield: u64,
+    /// XOR of the lengths of the encoded packets of the group
+    pub(crate) length_xor: u16,
+    /// XOR of the encoded bytes of the packets of the group (shorter packets are padded with zeros)
+    pub(crate) parity: Vec<u8>,
+}
+
+impl ParityPacket {
+    /// Iterate through the ids of the packets of the group
+    pub(crate) fn packet_ids(&self) -> impl Iterator<Item = PacketId> + '_ {
+        (0..u64::BITS as u16)
+            .filter(|i| self.packet_ids_bitfield & (1 << i) != 0)
+            .map(|i| PacketId(self.first_packet_id.wrapping_add(i)))
+    }
+}
+
+impl BitSerializable for ParityPacket {
+    fn encode(&self, writer: &mut impl WriteBuffer) -> anyhow::Result<()> {
+        writer.encode(&self.first_packet_id, Fixed)?;
+        writer.encode(&self.packet_ids_bitfield, Fixed)?;
+        writer.encode(&self.length_xor, Fixed)?;
+        writer.encode(self.parity.as_slice(), Fixed)
+    }
+
+    fn decode(reader: &mut impl ReadBuffer) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let first_packet_id = reader.decode::<PacketId>(Fixed)?;
+        let packet_ids_bitfield = reader.decode::<u64>(Fixed)?;
+        let length_xor = reader.decode::<u16>(Fixed)?;
+        // reject the oversized payloads before allocating them
+        let parity =
+            bitcode::with_len_limit(MAX_FEC_PAYLOAD_BYTES, || reader.decode::<Vec<u8>>(Fixed))
+                .with_context(|| {
+                    format!(
+                        "could not decode parity payload of at most {MAX_FEC_PAYLOAD_BYTES} bytes"
+                    )
+                })?;
+        Ok(Self {
+            first_packet_id,
+            packet_ids_bitfield,
+            length_xor,
+            parity,
+        })
+    }
+}
+
 /// Abstraction for data that is sent over the network
 ///
 /// Every packet knows how to serialize itself into a list of Single Packets that can
@@ -229,6 +290,7 @@ impl BitSerializable for FragmentedPacket {
 pub(crate) enum PacketData {
     Single(SinglePacket),
     Fragmented(FragmentedPacket),
+    Parity(ParityPacket),
 }
 
 impl PacketData {
@@ -238,6 +300,7 @@ impl PacketData {
             PacketData::Fragmented(fragmented_packet) => {
                 1 + fragmented_packet.packet.num_messages()
             }
+            PacketData::Parity(_) => 0,
         }
     }
     pub(crate) fn contents(self) -> HashMap<NetId, Vec<MessageContainer>> {
@@ -263,6 +326,7 @@ impl PacketData {
                         .extend(message_containers);
                 }
             }
+            PacketData::Parity(_) => {}
         }
         res
     }
@@ -279,6 +343,25 @@ impl Packet {
         match &self.data {
             PacketData::Single(single_packet) => single_packet.data.is_empty(),
             PacketData::Fragmented(fragmented_packet) => fragmented_packet.packet.data.is_empty(),
+            PacketData::Parity(_) => true,
+        }
+    }
+
+    /// Returns true if the packet contains data for one of the given channels
+    pub(crate) fn contains_channel(&self, channels: &HashSet<NetId>) -> bool {
+        match &self.data {
+            PacketData::Single(single_packet) => {
+                single_packet.data.keys().any(|id| channels.contains(id))
+            }
+            PacketData::Fragmented(fragmented_packet) => {
+                channels.contains(&fragmented_packet.channel_id)
+                    || fragmented_packet
+                        .packet
+                        .data
+                        .keys()
+                        .any(|id| channels.contains(id))
+            }
+            PacketData::Parity(_) => false,
         }
     }
 
@@ -287,16 +370,17 @@ impl Packet {
         // use encode to force Fixed encoding
         // should still use gamma for packet type
         // TODO: add test
-        writer.encode(&self.header, Fixed)?;
+        self.header.encode(writer)?;
         match &self.data {
             PacketData::Single(single_packet) => single_packet.encode(writer),
             PacketData::Fragmented(fragmented_packet) => fragmented_packet.encode(writer),
+            PacketData::Parity(parity_packet) => parity_packet.encode(writer),
         }
     }
 
     /// Decode a packet from the read buffer. The read buffer will only contain the bytes for a single packet
     pub fn decode(reader: &mut impl ReadBuffer) -> anyhow::Result<Packet> {
-        let header = reader.decode::<PacketHeader>(Fixed)?;
+        let header = PacketHeader::decode(reader)?;
         let packet_type = header.get_packet_type();
         match packet_type {
             PacketType::Data => {
@@ -312,6 +396,13 @@ impl Packet {
                     header,
                     data: PacketData::Fragmented(fragmented_packet),
                 })
+            }
+            PacketType::FecParity => {
+                let parity_packet = ParityPacket::decode(reader)?;
+                Ok(Self {
+                    header,
+                    data: PacketData::Parity(parity_packet),
+                })
             } // _ => Err(anyhow::anyhow!("Packet type not supported")),
         }
     }
@@ -329,6 +420,7 @@ impl Packet {
             PacketData::Fragmented(fragmented_packet) => {
                 fragmented_packet.packet.add_channel(channel);
             }
+            PacketData::Parity(_) => unreachable!("cannot add channels to a parity packet"),
         }
     }
 
@@ -338,6 +430,7 @@ impl Packet {
             PacketData::Fragmented(fragmented_packet) => {
                 fragmented_packet.packet.add_message(channel, message);
             }
+            PacketData::Parity(_) => unreachable!("cannot add messages to a parity packet"),
         }
     }
 
@@ -347,6 +440,7 @@ impl Packet {
         match &self.data {
             PacketData::Single(single_packet) => single_packet.num_messages(),
             PacketData::Fragmented(fragmented_packet) => fragmented_packet.packet.num_messages(),
+            PacketData::Parity(_) => 0,
         }
     }
 
@@ -354,6 +448,7 @@ impl Packet {
         match &self.data {
             PacketData::Single(single_packet) => single_packet.message_acks(),
             PacketData::Fragmented(fragmented_packet) => fragmented_packet.message_acks(),
+            PacketData::Parity(_) => HashMap::new(),
         }
     }
 }
@@ -532,4 +627,28 @@ mod tests {
         assert_eq!(packet, decoded_packet);
         Ok(())
     }
+
+    #[test]
+    fn test_decode_parity_packet_too_big() -> anyhow::Result<()> {
+        let mut packet = ParityPacket {
+            first_packet_id: PacketId(0),
+            packet_ids_bitfield: 1,
+            length_xor: 0,
+            parity: vec![1; MAX_FEC_PAYLOAD_BYTES],
+        };
+        let mut write_buffer = WriteWordBuffer::with_capacity(MAX_PACKET_SIZE);
+        packet.encode(&mut write_buffer)?;
+        let packet_bytes = write_buffer.finish_write();
+        let mut reader = ReadWordBuffer::start_read(packet_bytes);
+        assert_eq!(ParityPacket::decode(&mut reader)?, packet);
+
+        // a parity payload that is bigger than any packet protected by FEC is rejected
+        packet.parity.push(1);
+        let mut write_buffer = WriteWordBuffer::with_capacity(MAX_PACKET_SIZE);
+        packet.encode(&mut write_buffer)?;
+        let packet_bytes = write_buffer.finish_write();
+        let mut reader = ReadWordBuffer::start_read(packet_bytes);
+        assert!(ParityPacket::decode(&mut reader).is_err());
+        Ok(())
+    }
 }
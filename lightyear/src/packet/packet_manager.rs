@@ -7,7 +7,8 @@ use crate::connection::netcode::MAX_PACKET_SIZE;
 use crate::packet::header::PacketHeaderManager;
 use crate::packet::message::{FragmentData, MessageContainer, SingleData};
 use crate::packet::packet::{
-    FragmentedPacket, Packet, PacketData, SinglePacket, FRAGMENT_SIZE, MTU_PAYLOAD_BYTES,
+    FragmentedPacket, Packet, PacketData, ParityPacket, SinglePacket, FRAGMENT_SIZE,
+    MTU_PAYLOAD_BYTES,
 };
 use crate::packet::packet_type::PacketType;
 use crate::protocol::registry::NetId;
@@ -89,6 +90,12 @@ impl PacketBuilder {
         //     .serialize(packet.header())
         //     .expect("Failed to serialize header, this should never happen");
         // TODO: need to reserver HEADER_BYTES bits?
+        // the header of data packets can be bigger than `HEADER_BYTES` if a bigger ack window was negotiated
+        let extra_header_bits = self
+            .header_manager
+            .send_ack_bitfield_size()
+            .extra_header_bits();
+        self.try_write_buffer.reserve_bits(extra_header_bits);
         let header = self
             .header_manager
             .prepare_send_packet_header(PacketType::Data);
@@ -98,6 +105,17 @@ impl PacketBuilder {
         }
     }
 
+    /// Build a parity packet used for forward error correction
+    pub(crate) fn build_parity_packet(&mut self, parity_packet: ParityPacket) -> Packet {
+        let header = self
+            .header_manager
+            .prepare_send_packet_header(PacketType::FecParity);
+        Packet {
+            header,
+            data: PacketData::Parity(parity_packet),
+        }
+    }
+
     pub(crate) fn build_new_fragment_packet(
         &mut self,
         channel_id: NetId,
@@ -21,4 +21,7 @@ pub enum PacketType {
     // A packet containing actual data, but which is fragmented into multiple parts
     #[bitcode_hint(frequency = 5)]
     DataFragment,
+    // A parity packet used for forward error correction, that can be used to rebuild a lost packet
+    #[bitcode_hint(frequency = 1)]
+    FecParity,
 }
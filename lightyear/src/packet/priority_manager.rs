@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::num::NonZeroU32;
 
 use crossbeam_channel::{Receiver, Sender};
@@ -7,15 +7,23 @@ use nonzero_ext::*;
 use tracing::{debug, error, trace};
 
 use crate::_reexport::EntityUpdatesChannel;
+use crate::channel::builder::UnsentMessagePolicy;
 use crate::packet::message::{FragmentData, MessageContainer, MessageId, SingleData};
 use crate::prelude::{ChannelKind, ChannelRegistry, Tick};
 use crate::protocol::registry::NetId;
+use crate::shared::stats::ChannelStats;
+use crate::shared::time_manager::{TimeManager, WrappedTime};
 
 #[derive(Debug)]
 pub struct BufferedMessage {
+    /// Priority of the message. It gets accumulated every time the message could not be sent
     priority: f32,
+    /// Priority that gets added to the accumulated priority every time the message could not be sent
+    base_priority: f32,
     channel_net_id: NetId,
     message_container: MessageContainer,
+    /// Time when we first tried to send the message
+    first_send_attempt: WrappedTime,
 }
 
 #[derive(Debug, Clone)]
@@ -59,10 +67,13 @@ pub(crate) struct PriorityManager {
     pub(crate) config: PriorityConfig,
     // TODO: can I do without this limiter?
     pub(crate) limiter: DefaultDirectRateLimiter,
-    // Messages that could not be sent because of the bandwidth quota
-    // buffered_data: Vec<BufferedMessage>,
+    /// Messages that could not be sent because of the bandwidth quota, and that we will try to send again
+    /// (depending on the [`UnsentMessagePolicy`] of their channel).
+    /// For each channel, the messages are stored from oldest to newest.
+    buffered_data: BTreeMap<NetId, VecDeque<BufferedMessage>>,
     /// List of senders to notify when a replication update message is actually sent (included in packet)
     replication_update_senders: Vec<Sender<MessageId>>,
+    current_time: WrappedTime,
 }
 
 impl PriorityManager {
@@ -70,11 +81,16 @@ impl PriorityManager {
         Self {
             config: config.clone(),
             limiter: DefaultDirectRateLimiter::direct(config.bandwidth_quota),
-            // buffered_data: Vec::new(),
+            buffered_data: BTreeMap::new(),
             replication_update_senders: Vec::new(),
+            current_time: WrappedTime::default(),
         }
     }
 
+    pub(crate) fn update(&mut self, time_manager: &TimeManager) {
+        self.current_time = time_manager.current_time();
+    }
+
     /// Create a channel to notify when a replication update message is actually sent (included in packet)
     /// (as opposed to dropped because of the bandwidth quota)
     pub(crate) fn subscribe_replication_update_sent_messages(&mut self) -> Receiver<MessageId> {
@@ -83,14 +99,23 @@ impl PriorityManager {
         receiver
     }
 
+    /// Returns true if some messages that could not be sent previously are still waiting to be sent
+    pub(crate) fn has_buffered_messages(&self) -> bool {
+        !self.buffered_data.is_empty()
+    }
+
     // TODO: maybe accumulat ethe used_bytes in the priority_manager instead of returning here?
     /// Filter the messages by priority and bandwidth quota
     /// Returns the list of messages that we can send, along with the amount of bytes we used
     /// in the rate limiter.
+    ///
+    /// The time spent by messages in the buffer and the number of discarded messages are recorded
+    /// in `channel_stats`.
     pub(crate) fn priority_filter(
         &mut self,
         data: Vec<(NetId, (VecDeque<SingleData>, VecDeque<FragmentData>))>,
         channel_registry: &ChannelRegistry,
+        channel_stats: &mut HashMap<ChannelKind, ChannelStats>,
         tick: Tick,
     ) -> (
         BTreeMap<NetId, (VecDeque<SingleData>, VecDeque<FragmentData>)>,
@@ -108,63 +133,76 @@ impl PriorityManager {
         }
 
         // compute the priority of each new message
-        let mut all_messages = data
-            .into_iter()
-            .flat_map(|(net_id, (single, fragment))| {
-                let channel_priority = channel_registry
-                    .get_builder_from_net_id(net_id)
-                    .unwrap()
-                    .settings
-                    .priority;
-                trace!(?channel_priority, num_single=?single.len(), "channel priority");
-                single
-                    .into_iter()
-                    .map(move |mut single| {
-                        // TODO: this only needs to be done for the messages that are not sent!
-                        //  (and for messages that are not replication messages?)
-                        // set the initial send tick of the message
-                        // we do this because the receiver needs to know at which tick the message was intended to be sent
-                        // (for example which tick the EntityAction corresponds to), not the tick of the packet header
-                        // when the message was actually sent, which could be later because of bandwidth quota
-                        if single.tick.is_none() {
-                            single.tick = Some(tick);
-                        }
-                        BufferedMessage {
-                            priority: single.priority * channel_priority,
-                            channel_net_id: net_id,
-                            message_container: MessageContainer::Single(single),
-                        }
-                    })
-                    .chain(fragment.into_iter().map(move |mut fragment| {
-                        if fragment.tick.is_none() {
-                            fragment.tick = Some(tick);
-                        }
-                        BufferedMessage {
-                            priority: fragment.priority * channel_priority,
-                            channel_net_id: net_id,
-                            message_container: MessageContainer::Fragment(fragment),
-                        }
-                    }))
-            })
+        let current_time = self.current_time;
+        let new_messages = data.into_iter().flat_map(|(net_id, (single, fragment))| {
+            let channel_priority = channel_registry
+                .get_builder_from_net_id(net_id)
+                .unwrap()
+                .settings
+                .priority;
+            trace!(?channel_priority, num_single=?single.len(), "channel priority");
+            single
+                .into_iter()
+                .map(move |mut single| {
+                    // TODO: this only needs to be done for the messages that are not sent!
+                    //  (and for messages that are not replication messages?)
+                    // set the initial send tick of the message
+                    // we do this because the receiver needs to know at which tick the message was intended to be sent
+                    // (for example which tick the EntityAction corresponds to), not the tick of the packet header
+                    // when the message was actually sent, which could be later because of bandwidth quota
+                    if single.tick.is_none() {
+                        single.tick = Some(tick);
+                    }
+                    BufferedMessage {
+                        priority: single.priority * channel_priority,
+                        base_priority: single.priority * channel_priority,
+                        channel_net_id: net_id,
+                        message_container: MessageContainer::Single(single),
+                        first_send_attempt: current_time,
+                    }
+                })
+                .chain(fragment.into_iter().map(move |mut fragment| {
+                    if fragment.tick.is_none() {
+                        fragment.tick = Some(tick);
+                    }
+                    BufferedMessage {
+                        priority: fragment.priority * channel_priority,
+                        base_priority: fragment.priority * channel_priority,
+                        channel_net_id: net_id,
+                        message_container: MessageContainer::Fragment(fragment),
+                        first_send_attempt: current_time,
+                    }
+                }))
+        });
+        // the messages that were buffered during previous sends are older, so they come first.
+        // We store them as Options so that we can take the messages out of the list while keeping
+        // the remaining messages ordered from oldest to newest
+        let mut all_messages = std::mem::take(&mut self.buffered_data)
+            .into_values()
+            .flatten()
+            .chain(new_messages)
+            .map(Some)
             .collect::<Vec<_>>();
-        // // NOTE: maybe we cannot do this, because for some channels we need to know
-        // //  if the message was actually sent or not? (reliable channels)
-        // // add all new messages to the list of messages that could not be sent
-        // self.buffered_data.extend(all_messages);
 
         // sort from highest priority to lower
-        // self.buffered_data
-        all_messages.sort_by(|a, b| a.priority.partial_cmp(&b.priority).unwrap());
+        // (the sort is stable, so for equal priorities the older messages are sent first)
+        let mut sorted_indices = (0..all_messages.len()).collect::<Vec<_>>();
+        let priority = |i: &usize| all_messages[*i].as_ref().unwrap().priority;
+        sorted_indices.sort_by(|a, b| priority(b).total_cmp(&priority(a)));
         trace!(
             "all messages to send, sorted by priority: {:?}",
-            all_messages
+            sorted_indices
+                .iter()
+                .map(|i| &all_messages[*i])
+                .collect::<Vec<_>>()
         );
 
         // select the top messages with the rate limiter
         let mut data_to_send: BTreeMap<NetId, (VecDeque<SingleData>, VecDeque<FragmentData>)> =
             BTreeMap::new();
         let mut bytes_used = 0;
-        while let Some(buffered_message) = all_messages.pop() {
+        for index in sorted_indices {
+            let buffered_message = all_messages[index].as_ref().unwrap();
             trace!(channel=?buffered_message.channel_net_id, "Sending message with priority {:?}", buffered_message.priority);
             // we don't use the exact size of the message, but the size of the bytes
             // we will adjust for this later
@@ -181,16 +219,26 @@ impl PriorityManager {
 
             // keep track of the bytes we added to the rate limiter
             bytes_used += message_bytes;
+            let buffered_message = all_messages[index].take().unwrap();
 
             // the message is allowed, add it to the list of messages to send
             let channel_data = data_to_send
                 .entry(buffered_message.channel_net_id)
                 .or_insert((VecDeque::new(), VecDeque::new()));
 
-            // notify the replication sender that the message was actually sent
             let channel_kind = channel_registry
                 .get_kind_from_net_id(buffered_message.channel_net_id)
                 .unwrap();
+            // keep track of how long the message had to wait because of the bandwidth quota
+            // (the latency and the number of samples are recorded together so that the average is correct)
+            if let Ok(queue_latency) = (current_time - buffered_message.first_send_attempt).to_std()
+            {
+                let stats = channel_stats.entry(*channel_kind).or_default();
+                stats.total_queue_latency += queue_latency;
+                stats.num_queue_latency_samples += 1;
+            }
+
+            // notify the replication sender that the message was actually sent
             if channel_kind == &ChannelKind::of::<EntityUpdatesChannel>()
                 || channel_kind == &ChannelKind::of::<EntityUpdatesChannel>()
             {
@@ -216,13 +264,19 @@ impl PriorityManager {
             }
         }
 
-        // all the other messages that don't make the cut, we just drop
-        // - unreliable messages: they are unreliable so it's ok
-        // - reliable messages: they will be retried later, maybe with higher priority?
+        // all the other messages that don't make the cut are either dropped or buffered, depending on the
+        // [`UnsentMessagePolicy`] of their channel
+        // - unreliable messages: they are dropped, unless the channel asks for them to be buffered
+        // - reliable messages: they are always dropped because they will be retried later by the reliable sender
         // - unreliable entity updates: the replication sender keeps track for each entity of when we were able to send an update
         //   - PROBLEM: we could have the entity action not get sent (bandwidth), and then the priority still drops because the entity update
         //     was sent right after...
         // - reliable entity actions:
+        let num_messages_discarded = self.buffer_unsent_messages(
+            all_messages.into_iter().flatten(),
+            channel_registry,
+            channel_stats,
+        );
         let num_messages_sent = data_to_send
             .values()
             .map(|(single, fragment)| single.len() + fragment.len())
@@ -230,9 +284,228 @@ impl PriorityManager {
         debug!(
             bytes_sent = ?bytes_used,
             ?num_messages_sent,
-            num_messages_discarded = ?all_messages.len(),
+            ?num_messages_discarded,
+            num_messages_buffered = ?self.buffered_data.values().map(|m| m.len()).sum::<usize>(),
             "priority filter done.");
 
         (data_to_send, bytes_used)
     }
+
+    /// Store the messages that could not be sent according to the [`UnsentMessagePolicy`] of their channel.
+    /// The messages must be ordered from oldest to newest.
+    ///
+    /// Returns the number of messages that were discarded
+    fn buffer_unsent_messages(
+        &mut self,
+        unsent_messages: impl Iterator<Item = BufferedMessage>,
+        channel_registry: &ChannelRegistry,
+        channel_stats: &mut HashMap<ChannelKind, ChannelStats>,
+    ) -> usize {
+        for mut buffered_message in unsent_messages {
+            // accumulate the priority, so that messages that keep getting buffered don't get starved
+            // by newer messages
+            buffered_message.priority += buffered_message.base_priority;
+            self.buffered_data
+                .entry(buffered_message.channel_net_id)
+                .or_default()
+                .push_back(buffered_message);
+        }
+        let mut num_messages_discarded = 0;
+        self.buffered_data.retain(|net_id, messages| {
+            let settings = &channel_registry
+                .get_builder_from_net_id(*net_id)
+                .unwrap()
+                .settings;
+            let mut num_channel_messages_discarded = 0;
+            // reliable channels already resend the messages that were not acked
+            if settings.mode.is_reliable() {
+                num_channel_messages_discarded += messages.len();
+                messages.clear();
+            } else {
+                match settings.unsent_message_policy {
+                    UnsentMessagePolicy::Drop => {
+                        num_channel_messages_discarded += messages.len();
+                        messages.clear();
+                    }
+                    UnsentMessagePolicy::Buffer { max_messages } => {
+                        while messages.len() > max_messages {
+                            num_channel_messages_discarded += pop_oldest_message(messages);
+                        }
+                    }
+                    UnsentMessagePolicy::KeepNewest => {
+                        let num_to_keep = num_fragments_of_newest_message(messages);
+                        while messages.len() > num_to_keep {
+                            num_channel_messages_discarded += pop_oldest_message(messages);
+                        }
+                    }
+                }
+            }
+            if num_channel_messages_discarded > 0 {
+                let channel_kind = channel_registry.get_kind_from_net_id(*net_id).unwrap();
+                channel_stats
+                    .entry(*channel_kind)
+                    .or_default()
+                    .messages_dropped += num_channel_messages_discarded as u64;
+            }
+            num_messages_discarded += num_channel_messages_discarded;
+            !messages.is_empty()
+        });
+        num_messages_discarded
+    }
+}
+
+/// Remove the oldest message from the buffer (if the message is fragmented, all its fragments are removed)
+///
+/// Returns the number of fragments that were removed
+fn pop_oldest_message(messages: &mut VecDeque<BufferedMessage>) -> usize {
+    let Some(oldest) = messages.pop_front() else {
+        return 0;
+    };
+    let mut num_removed = 1;
+    if let MessageContainer::Fragment(fragment) = &oldest.message_container {
+        while let Some(MessageContainer::Fragment(next)) =
+            messages.front().map(|m| &m.message_container)
+        {
+            if next.message_id != fragment.message_id {
+                break;
+            }
+            messages.pop_front();
+            num_removed += 1;
+        }
+    }
+    num_removed
+}
+
+/// Number of buffered fragments that belong to the newest message (1 if the message is not fragmented)
+fn num_fragments_of_newest_message(messages: &VecDeque<BufferedMessage>) -> usize {
+    match messages.back().map(|m| &m.message_container) {
+        None => 0,
+        Some(MessageContainer::Single(_)) => 1,
+        Some(MessageContainer::Fragment(newest)) => messages
+            .iter()
+            .rev()
+            .take_while(|m| {
+                matches!(&m.message_container, MessageContainer::Fragment(f) if f.message_id == newest.message_id)
+            })
+            .count(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::default;
+    use bytes::Bytes;
+
+    use lightyear_macros::ChannelInternal;
+
+    use crate::channel::builder::{Channel, ChannelMode, ChannelSettings};
+
+    use super::*;
+
+    #[derive(ChannelInternal)]
+    struct DropChannel;
+
+    #[derive(ChannelInternal)]
+    struct BufferChannel;
+
+    #[derive(ChannelInternal)]
+    struct KeepNewestChannel;
+
+    fn single_messages(
+        net_id: NetId,
+        bytes: &[u8],
+    ) -> (NetId, (VecDeque<SingleData>, VecDeque<FragmentData>)) {
+        let single = bytes
+            .iter()
+            .map(|b| SingleData::new(None, Bytes::from(vec![*b; 40]), 1.0))
+            .collect();
+        (net_id, (single, VecDeque::new()))
+    }
+
+    /// Returns the first byte and the priority of each buffered message of a channel
+    fn buffered(manager: &PriorityManager, net_id: NetId) -> Vec<(u8, f32)> {
+        manager
+            .buffered_data
+            .get(&net_id)
+            .map(|messages| {
+                messages
+                    .iter()
+                    .map(|m| (m.message_container.bytes()[0], m.priority))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_unsent_message_policy() {
+        let mut registry = ChannelRegistry::new();
+        registry.add::<BufferChannel>(ChannelSettings {
+            mode: ChannelMode::UnorderedUnreliable,
+            unsent_message_policy: UnsentMessagePolicy::Buffer { max_messages: 2 },
+            ..default()
+        });
+        registry.add::<KeepNewestChannel>(ChannelSettings {
+            mode: ChannelMode::UnorderedUnreliable,
+            unsent_message_policy: UnsentMessagePolicy::KeepNewest,
+            ..default()
+        });
+        registry.add::<DropChannel>(ChannelSettings {
+            mode: ChannelMode::UnorderedUnreliable,
+            ..default()
+        });
+        let buffer_id = *registry.get_net_from_kind(&BufferChannel::kind()).unwrap();
+        let newest_id = *registry
+            .get_net_from_kind(&KeepNewestChannel::kind())
+            .unwrap();
+        let drop_id = *registry.get_net_from_kind(&DropChannel::kind()).unwrap();
+
+        // the quota only lets us send 2 messages of 40 bytes, and does not get replenished during the test
+        let mut manager = PriorityManager::new(PriorityConfig {
+            bandwidth_quota: Quota::per_hour(nonzero!(100u32)),
+            enabled: true,
+        });
+
+        // all messages have the same priority, so the oldest ones are sent first
+        let data = vec![
+            single_messages(buffer_id, &[1, 2, 3, 4]),
+            single_messages(newest_id, &[5, 6]),
+            single_messages(drop_id, &[0]),
+        ];
+        let mut stats = HashMap::new();
+        let (sent, _) = manager.priority_filter(data, &registry, &mut stats, Tick(0));
+        assert_eq!(sent.len(), 1);
+        let sent_bytes = sent[&buffer_id]
+            .0
+            .iter()
+            .map(|single| single.bytes[0])
+            .collect::<Vec<_>>();
+        assert_eq!(sent_bytes, vec![1, 2]);
+        assert_eq!(buffered(&manager, buffer_id), vec![(3, 2.0), (4, 2.0)]);
+        assert_eq!(buffered(&manager, newest_id), vec![(6, 2.0)]);
+        assert!(buffered(&manager, drop_id).is_empty());
+        assert_eq!(stats[&BufferChannel::kind()].messages_dropped, 0);
+        assert_eq!(stats[&KeepNewestChannel::kind()].messages_dropped, 1);
+        assert_eq!(stats[&DropChannel::kind()].messages_dropped, 1);
+        // a latency sample is only recorded for the messages that were actually sent
+        assert_eq!(stats[&BufferChannel::kind()].num_queue_latency_samples, 2);
+        assert_eq!(
+            stats[&KeepNewestChannel::kind()].num_queue_latency_samples,
+            0
+        );
+
+        // there is no bandwidth left: the buffered messages keep accumulating priority
+        let (sent, _) = manager.priority_filter(vec![], &registry, &mut stats, Tick(1));
+        assert!(sent.is_empty());
+        assert!(manager.has_buffered_messages());
+        assert_eq!(buffered(&manager, buffer_id), vec![(3, 3.0), (4, 3.0)]);
+        assert_eq!(buffered(&manager, newest_id), vec![(6, 3.0)]);
+
+        // new messages are added after the buffered messages
+        let data = vec![single_messages(buffer_id, &[7])];
+        let (sent, _) = manager.priority_filter(data, &registry, &mut stats, Tick(2));
+        assert!(sent.is_empty());
+        // the oldest message got evicted because the buffer is full
+        assert_eq!(buffered(&manager, buffer_id), vec![(4, 4.0), (7, 2.0)]);
+        assert_eq!(stats[&BufferChannel::kind()].messages_dropped, 1);
+    }
 }
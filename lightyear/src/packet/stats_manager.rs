@@ -8,11 +8,11 @@ use crate::utils::ready_buffer::ReadyBuffer;
 type PacketStatsBuffer = ReadyBuffer<WrappedTime, PacketStats>;
 
 #[derive(Default, Copy, Clone, Debug, PartialEq, AddAssign, SubAssign)]
-struct PacketStats {
-    num_sent_packets: u32,
-    num_sent_packets_acked: u32,
-    num_sent_packets_lost: u32,
-    num_received_packets: u32,
+pub(crate) struct PacketStats {
+    pub(crate) num_sent_packets: u32,
+    pub(crate) num_sent_packets_acked: u32,
+    pub(crate) num_sent_packets_lost: u32,
+    pub(crate) num_received_packets: u32,
 }
 
 #[derive(Default)]
@@ -26,6 +26,8 @@ pub(crate) struct PacketStatsManager {
     rolling_stats: PacketStats,
     /// stats accumulated for the current frame
     current_stats: PacketStats,
+    /// sum of the stats since the start of the connection
+    total_stats: PacketStats,
     /// Duration of the rolling buffer of stats to compute packet statistics
     stats_buffer_duration: Duration,
     final_stats: FinalStats,
@@ -45,6 +47,7 @@ impl PacketStatsManager {
             rolling_stats: PacketStats::default(),
             // stats accumulated for the current frame
             current_stats: PacketStats::default(),
+            total_stats: PacketStats::default(),
             stats_buffer_duration,
             final_stats: FinalStats::default(),
         }
@@ -61,6 +64,7 @@ impl PacketStatsManager {
         // add the current stats to the rolling stats
         let current_stats = std::mem::take(&mut self.current_stats);
         self.rolling_stats += current_stats;
+        self.total_stats += current_stats;
         self.stats_buffer
             .add_item(time_manager.current_time(), current_stats);
 
@@ -79,6 +83,17 @@ impl PacketStatsManager {
         }
     }
 
+    /// Ratio of sent packets that were lost, computed over the rolling stats buffer
+    pub(crate) fn packet_loss(&self) -> f32 {
+        self.final_stats.packet_loss
+    }
+
+    /// Stats accumulated since the start of the connection
+    /// (the stats of the current frame are only added on the next update)
+    pub(crate) fn total_stats(&self) -> PacketStats {
+        self.total_stats
+    }
+
     // TODO: we could just emit raw stats, and then compute packet loss over an interval using prometheus/grafana
     /// Notify that a packet was sent
     pub(crate) fn sent_packet(&mut self) {
@@ -175,5 +190,15 @@ mod tests {
         );
         packet_stats_manager.compute_stats();
         assert_eq!(packet_stats_manager.final_stats.packet_loss, 1.0 / 2.0);
+        // the total stats are never removed
+        assert_eq!(
+            packet_stats_manager.total_stats(),
+            PacketStats {
+                num_sent_packets: 4,
+                num_sent_packets_acked: 1,
+                num_sent_packets_lost: 2,
+                num_received_packets: 0,
+            }
+        );
     }
 }
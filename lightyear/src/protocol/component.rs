@@ -3,7 +3,9 @@ use std::any::TypeId;
 use std::fmt::{Debug, Display};
 use std::hash::Hash;
 
-use bevy::prelude::{App, Component, Entity, EntityMapper, EntityWorldMut, TypePath, World};
+use bevy::prelude::{
+    App, Component, Entity, EntityMapper, EntityRef, EntityWorldMut, TypePath, World,
+};
 use bevy::reflect::{FromReflect, GetTypeRegistration};
 use bevy::utils::HashMap;
 use cfg_if::cfg_if;
@@ -14,10 +16,14 @@ use serde::{Deserialize, Serialize};
 
 use crate::client::components::{ComponentSyncMode, LerpFn, SyncMetadata};
 use crate::prelude::{Message, PreSpawnedPlayerObject};
+use crate::protocol::registration::RegisteredComponent;
+use crate::protocol::registry::NetId;
+use crate::protocol::schema::ComponentSchema;
 use crate::protocol::{BitSerializable, EventContext, Protocol};
 use crate::shared::events::connection::{
     IterComponentInsertEvent, IterComponentRemoveEvent, IterComponentUpdateEvent,
 };
+use crate::shared::replication::authority::{Authority, AuthorityTransfer};
 use crate::shared::replication::components::ShouldBePredicted;
 use crate::shared::replication::components::{PrePredicted, ShouldBeInterpolated};
 use crate::shared::replication::ReplicationSend;
@@ -41,18 +47,28 @@ pub trait ComponentProtocol:
     + From<ShouldBeInterpolated>
     + TryInto<ShouldBePredicted>
     + TryInto<PrePredicted>
+    + From<Authority>
+    + TryInto<AuthorityTransfer>
+    + From<RegisteredComponent>
 {
     type Protocol: Protocol;
 
     /// Map from the type-id to the component kind for each component in the protocol
     fn type_ids() -> HashMap<TypeId, <Self::Protocol as Protocol>::ComponentKinds>;
 
+    /// Describe every component of the protocol; the net id of a component is the index of its variant
+    fn schema() -> Vec<ComponentSchema>;
+
     /// Apply a ComponentInsert to an entity
     fn insert(self, entity: &mut EntityWorldMut);
 
     /// Apply a ComponentUpdate to an entity
     fn update(self, entity: &mut EntityWorldMut);
 
+    /// Clone all the components of the protocol that are present on the entity
+    /// (except the components registered at runtime)
+    fn entity_components(entity: &EntityRef) -> Vec<Self>;
+
     /// Add systems to send component inserts/removes/updates
     fn add_per_component_replication_send_systems<R: ReplicationSend<Self::Protocol>>(
         app: &mut App,
@@ -171,10 +187,15 @@ cfg_if!(
             + FromType<ShouldBeInterpolated>
             + FromType<PrePredicted>
             + FromType<PreSpawnedPlayerObject>
+            + FromType<Authority>
+            + FromType<AuthorityTransfer>
             + FromType<ActionState<<Self::Protocol as Protocol>::LeafwingInput1>>
             + FromType<ActionState<<Self::Protocol as Protocol>::LeafwingInput2>>
         {
             type Protocol: Protocol;
+
+            /// Kind of the component registered at runtime with the given [`NetId`]
+            fn from_registered(net_id: NetId) -> Self;
         }
     } else {
         pub trait ComponentProtocolKind:
@@ -201,8 +222,13 @@ cfg_if!(
             + FromType<ShouldBeInterpolated>
             + FromType<PrePredicted>
             + FromType<PreSpawnedPlayerObject>
+            + FromType<Authority>
+            + FromType<AuthorityTransfer>
         {
             type Protocol: Protocol;
+
+            /// Kind of the component registered at runtime with the given [`NetId`]
+            fn from_registered(net_id: NetId) -> Self;
         }
     }
 );
@@ -211,6 +237,9 @@ cfg_if!(
 pub trait ComponentKindBehaviour {
     /// Remove the component for an entity
     fn remove(self, entity: &mut EntityWorldMut);
+
+    /// Returns true if the updates of the component are delta-compressed
+    fn delta_compression(&self) -> bool;
 }
 
 // /// Trait to convert a component type into the corresponding ComponentProtocolKind
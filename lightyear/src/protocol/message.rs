@@ -8,7 +8,9 @@ use serde::Serialize;
 
 use crate::inputs::native::input_buffer::InputMessage;
 use crate::packet::message::Message;
+use crate::protocol::registration::RegisteredMessage;
 use crate::protocol::registry::TypeKind;
+use crate::protocol::schema::MessageSchema;
 use crate::protocol::{BitSerializable, EventContext, Protocol};
 #[cfg(feature = "leafwing")]
 use crate::shared::events::components::InputMessageEvent;
@@ -40,6 +42,8 @@ pub trait MessageProtocol:
     + Sync
     + From<InputMessage<<<Self as MessageProtocol>::Protocol as Protocol>::Input>>
     + TryInto<InputMessage<<<Self as MessageProtocol>::Protocol as Protocol>::Input>, Error = ()>
+    + From<RegisteredMessage>
+    + TryInto<RegisteredMessage, Error = ()>
 {
     type Protocol: Protocol;
 
@@ -52,6 +56,9 @@ pub trait MessageProtocol:
     /// Returns true if the message is an input message
     fn input_message_kind(&self) -> InputMessageKind;
 
+    /// Describe every message of the protocol; the net id of a message is the index of its variant
+    fn schema() -> Vec<MessageSchema>;
+
     // TODO: combine these 2 into a single function that takes app?
     /// Add events to the app
     fn add_events<Ctx: EventContext>(app: &mut App);
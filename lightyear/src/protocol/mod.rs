@@ -19,6 +19,7 @@ use crate::channel::builder::{Channel, ChannelSettings};
 use crate::protocol::channel::ChannelRegistry;
 use crate::protocol::component::{ComponentProtocol, ComponentProtocolKind};
 use crate::protocol::message::MessageProtocol;
+use crate::protocol::schema::ProtocolSchema;
 use crate::serialize::reader::ReadBuffer;
 use crate::serialize::writer::WriteBuffer;
 use crate::shared::replication::ReplicationSend;
@@ -35,6 +36,12 @@ pub(crate) mod message;
 /// Provides a mapping from a type to a unique identifier that can be serialized
 pub(crate) mod registry;
 
+/// Registers components and messages at runtime, without adding them to the protocol enums
+pub mod registration;
+
+/// Exports a machine-readable description of the protocol
+pub mod schema;
+
 // TODO: how to make components or messages or inputs optional? Just by having an implementation for () ?
 /// The [`Protocol`] trait defines the various channels, inputs, messages and components that will be used to transmit information between
 /// the client and server.
@@ -98,6 +105,14 @@ pub trait Protocol: Send + Sync + Clone + Debug + Resource + TypePath + 'static
 
     fn add_channel<C: Channel>(&mut self, settings: ChannelSettings) -> &mut Self;
     fn channel_registry(&self) -> &ChannelRegistry;
+
+    /// Describe the channels, messages, components and inputs of the protocol, with the ids used
+    /// to identify them on the network.
+    ///
+    /// The schema can be exported to JSON with [`ProtocolSchema::to_json`] to be used by external tools.
+    fn schema(&self) -> ProtocolSchema {
+        ProtocolSchema::new(self)
+    }
 }
 
 /// This macro is used to build the [`Protocol`] struct.
@@ -161,32 +176,38 @@ macro_rules! protocolize {
                         direction: ChannelDirection::Bidirectional,
                         // we want to send the entity actions as soon as possible
                         priority: 10.0,
+                        ..default()
                     });
                     protocol.add_channel::<EntityUpdatesChannel>(ChannelSettings {
                         mode: ChannelMode::UnorderedUnreliableWithAcks,
                         direction: ChannelDirection::Bidirectional,
                         priority: 1.0,
+                        ..default()
                     });
                     protocol.add_channel::<PingChannel>(ChannelSettings {
                         mode: ChannelMode::SequencedUnreliable,
                         direction: ChannelDirection::Bidirectional,
                         // we always want to include the ping in the packet
                         priority: 1000.0,
+                        ..default()
                     });
                     protocol.add_channel::<InputChannel>(ChannelSettings {
                         mode: ChannelMode::UnorderedUnreliable,
                         direction: ChannelDirection::ClientToServer,
                         priority: 3.0,
+                        ..default()
                     });
                     protocol.add_channel::<DefaultUnorderedUnreliableChannel>(ChannelSettings {
                         mode: ChannelMode::UnorderedUnreliable,
                         direction: ChannelDirection::Bidirectional,
                         priority: 1.0,
+                        ..default()
                     });
                     protocol.add_channel::<TickBufferChannel>(ChannelSettings {
                         mode: ChannelMode::TickBuffered,
                         direction: ChannelDirection::ClientToServer,
                         priority: 1.0,
+                        ..default()
                     });
                     protocol
                 }
@@ -302,32 +323,38 @@ macro_rules! protocolize {
                         direction: ChannelDirection::Bidirectional,
                         // we want to send the entity actions as soon as possible
                         priority: 10.0,
+                        ..default()
                     });
                     protocol.add_channel::<EntityUpdatesChannel>(ChannelSettings {
                         mode: ChannelMode::UnorderedUnreliableWithAcks,
                         direction: ChannelDirection::Bidirectional,
                         priority: 1.0,
+                        ..default()
                     });
                     protocol.add_channel::<PingChannel>(ChannelSettings {
                         mode: ChannelMode::SequencedUnreliable,
                         direction: ChannelDirection::Bidirectional,
                         // we always want to include the ping in the packet
                         priority: 1000.0,
+                        ..default()
                     });
                     protocol.add_channel::<InputChannel>(ChannelSettings {
                         mode: ChannelMode::UnorderedUnreliable,
                         direction: ChannelDirection::ClientToServer,
                         priority: 3.0,
+                        ..default()
                     });
                     protocol.add_channel::<DefaultUnorderedUnreliableChannel>(ChannelSettings {
                         mode: ChannelMode::UnorderedUnreliable,
                         direction: ChannelDirection::Bidirectional,
                         priority: 1.0,
+                        ..default()
                     });
                     protocol.add_channel::<TickBufferChannel>(ChannelSettings {
                         mode: ChannelMode::TickBuffered,
                         direction: ChannelDirection::ClientToServer,
                         priority: 1.0,
+                        ..default()
                     });
                     protocol
                 }
@@ -0,0 +1,1197 @@
+//! Register components and messages at runtime, without adding them to the protocol enums
+//!
+//! The [`ComponentProtocol`](crate::protocol::component::ComponentProtocol) and
+//! [`MessageProtocol`](crate::protocol::message::MessageProtocol) enums need to list every networked type in a single place,
+//! which is not possible when the types are defined by plugins that live in different crates.
+//! Instead, each plugin can register its own types on the [`App`]:
+//! ```rust,ignore
+//! app.register_component::<Health>(ChannelDirection::ServerToClient, ComponentSyncMode::Simple);
+//! app.register_message::<Chat>();
+//! app.register_network_event::<Explosion, Channel1>(ChannelDirection::ServerToClient, NetworkTarget::All);
+//! ```
+//! Bevy events registered with [`AppRegistrationExt::register_network_event`] are sent as registered messages;
+//! see [`network events`](crate::shared::events::network).
+//!
+//! Components that implement [`Reflect`] but not `Serialize`/`Deserialize` can be registered with
+//! [`AppRegistrationExt::register_reflect_component`]; they are serialized via `bevy_reflect`.
+//!
+//! The registered types are sent over the network using their [`NetId`], which is assigned once all the plugins
+//! have been built by sorting the types by name. This means that the client and the server must register the same types:
+//! when a client connects, it sends a hash of its registered types to the server, which disconnects the client
+//! if the hash doesn't match its own.
+//!
+//! Registered components currently have some limitations compared to the components of the [`ComponentProtocol`](crate::protocol::component::ComponentProtocol):
+//! - the entities they contain are not mapped
+//! - [`ComponentSyncMode::Full`] is not supported
+//! - their replication cannot be customized per entity via the [`Replicate`](crate::prelude::Replicate) component
+use std::any::{Any, TypeId};
+
+use anyhow::Context;
+use bevy::ecs::component::ComponentId;
+use bevy::ecs::entity::MapEntities;
+use bevy::ecs::query::QueryFilter;
+use bevy::ecs::reflect::{AppTypeRegistry, ReflectComponent};
+use bevy::prelude::{
+    Added, App, Changed, Commands, Component, Entity, EntityRef, EntityWorldMut, Event, Events,
+    IntoSystemConfigs, IntoSystemSetConfigs, Mut, PostUpdate, PreUpdate, Query, Resource, Update,
+    With, World,
+};
+use bevy::reflect::serde::{TypedReflectDeserializer, TypedReflectSerializer};
+use bevy::reflect::{FromReflect, GetTypeRegistration, Reflect, TypePath, TypeRegistry};
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::channel::builder::{Channel, ChannelDirection};
+use crate::client::components::{ComponentSyncMode, Confirmed};
+use crate::client::interpolation::plugin::InterpolationSet;
+use crate::client::prediction::plugin::PredictionSet;
+use crate::connection::id::ClientId;
+use crate::packet::message::Message;
+use crate::prelude::NetworkTarget;
+use crate::protocol::channel::ChannelKind;
+use crate::protocol::component::ComponentProtocolKind;
+use crate::protocol::message::{MessageKind, MessageProtocol};
+use crate::protocol::registry::NetId;
+use crate::protocol::{EventContext, Protocol};
+use crate::serialize::reader::ReadBuffer;
+use crate::serialize::wordbuffer::reader::ReadWordBuffer;
+use crate::serialize::wordbuffer::writer::WriteWordBuffer;
+use crate::serialize::writer::WriteBuffer;
+use crate::shared::events::components::{
+    ComponentInsertEvent, ComponentRemoveEvent, ComponentUpdateEvent, MessageEvent,
+};
+use crate::shared::events::connection::{
+    IterComponentInsertEvent, IterComponentRemoveEvent, IterComponentUpdateEvent, IterMessageEvent,
+};
+use crate::shared::events::network::{
+    queue_network_events, NetworkEventQueue, NetworkEventSettings, ReceivedNetworkEvents,
+};
+use crate::shared::replication::entity_map::RemoteEntityMap;
+use crate::shared::sets::{MainSet, NetworkEventSet};
+
+/// A component that was registered with [`AppRegistrationExt::register_component`], in serialized form
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RegisteredComponent {
+    net_id: NetId,
+    bytes: Vec<u8>,
+}
+
+impl crate::protocol::component::ComponentBehaviour for RegisteredComponent {}
+
+impl RegisteredComponent {
+    /// Serialize the registered component of an entity
+    pub(crate) fn from_entity(
+        registration: &ComponentRegistration,
+        world: &World,
+        entity: &EntityRef,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            net_id: registration.net_id,
+            bytes: (registration.fns.serialize)(world, entity)?,
+        })
+    }
+
+    pub fn net_id(&self) -> NetId {
+        self.net_id
+    }
+
+    /// Insert the component on the entity
+    pub fn insert(self, entity: &mut EntityWorldMut) {
+        let Some(registration) = entity
+            .world()
+            .get_resource::<ProtocolRegistry>()
+            .and_then(|registry| registry.component(self.net_id))
+        else {
+            error!(net_id = ?self.net_id, "received a component that is not registered");
+            return;
+        };
+        let insert = registration.fns.insert;
+        let _ = insert(&self.bytes, entity)
+            .map_err(|e| error!("error inserting registered component: {:?}", e));
+    }
+
+    /// Update the component on the entity (or insert it if it is missing)
+    pub fn update(self, entity: &mut EntityWorldMut) {
+        let Some(registration) = entity
+            .world()
+            .get_resource::<ProtocolRegistry>()
+            .and_then(|registry| registry.component(self.net_id))
+        else {
+            error!(net_id = ?self.net_id, "received a component that is not registered");
+            return;
+        };
+        let update = registration.fns.update;
+        let _ = update(&self.bytes, entity)
+            .map_err(|e| error!("error updating registered component: {:?}", e));
+    }
+
+    /// Remove the registered component with the given [`NetId`] from the entity
+    pub fn remove(net_id: NetId, entity: &mut EntityWorldMut) {
+        let Some(registration) = entity
+            .world()
+            .get_resource::<ProtocolRegistry>()
+            .and_then(|registry| registry.component(net_id))
+        else {
+            error!(?net_id, "received a component that is not registered");
+            return;
+        };
+        let remove = registration.remove;
+        remove(entity);
+    }
+}
+
+/// A message that was registered with [`AppRegistrationExt::register_message`], in serialized form
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RegisteredMessage {
+    net_id: NetId,
+    bytes: Vec<u8>,
+}
+
+impl RegisteredMessage {
+    pub(crate) fn new<M: Message>(net_id: NetId, message: &M) -> anyhow::Result<Self> {
+        Ok(Self {
+            net_id,
+            bytes: serialize(message)?,
+        })
+    }
+
+    pub fn net_id(&self) -> NetId {
+        self.net_id
+    }
+}
+
+/// Net ids of the registered messages, used by the connection managers to send registered messages
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RegisteredMessageIds {
+    net_ids: HashMap<TypeId, NetId>,
+    /// Hash of all the registered types, exchanged when a client connects (see [`ProtocolRegistry::hash`])
+    pub(crate) registry_hash: u64,
+}
+
+impl RegisteredMessageIds {
+    pub(crate) fn serialize<M: Message>(&self, message: &M) -> anyhow::Result<RegisteredMessage> {
+        let net_id = self.net_ids.get(&TypeId::of::<M>()).with_context(|| {
+            format!(
+                "the message {} is not registered",
+                std::any::type_name::<M>()
+            )
+        })?;
+        RegisteredMessage::new(*net_id, message)
+    }
+}
+
+fn serialize<T: Message>(value: &T) -> anyhow::Result<Vec<u8>> {
+    let mut writer = WriteWordBuffer::with_capacity(64);
+    value.encode(&mut writer)?;
+    Ok(writer.finish_write().to_vec())
+}
+
+fn deserialize<T: Message>(bytes: &[u8]) -> anyhow::Result<T> {
+    let mut reader = ReadWordBuffer::start_read(bytes);
+    T::decode(&mut reader)
+}
+
+/// Functions used to emit the bevy events for a registered component
+struct ComponentEventFns<Ctx> {
+    insert: fn(&mut World, &mut dyn Iterator<Item = (Entity, Ctx)>),
+    update: fn(&mut World, &mut dyn Iterator<Item = (Entity, Ctx)>),
+    remove: fn(&mut World, &mut dyn Iterator<Item = (Entity, Ctx)>),
+}
+
+impl<Ctx> Clone for ComponentEventFns<Ctx> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Ctx> Copy for ComponentEventFns<Ctx> {}
+
+impl<Ctx: EventContext> ComponentEventFns<Ctx> {
+    fn new<C: Component>() -> Self {
+        Self {
+            insert: |world, events| {
+                world
+                    .resource_mut::<Events<ComponentInsertEvent<C, Ctx>>>()
+                    .extend(events.map(|(entity, ctx)| ComponentInsertEvent::new(entity, ctx)))
+            },
+            update: |world, events| {
+                world
+                    .resource_mut::<Events<ComponentUpdateEvent<C, Ctx>>>()
+                    .extend(events.map(|(entity, ctx)| ComponentUpdateEvent::new(entity, ctx)))
+            },
+            remove: |world, events| {
+                world
+                    .resource_mut::<Events<ComponentRemoveEvent<C, Ctx>>>()
+                    .extend(events.map(|(entity, ctx)| ComponentRemoveEvent::new(entity, ctx)))
+            },
+        }
+    }
+}
+
+/// Functions used to serialize a registered component, and to write it on the receiving entity
+#[derive(Clone, Copy)]
+struct ComponentFns {
+    serialize: fn(&World, &EntityRef) -> anyhow::Result<Vec<u8>>,
+    insert: fn(&[u8], &mut EntityWorldMut) -> anyhow::Result<()>,
+    update: fn(&[u8], &mut EntityWorldMut) -> anyhow::Result<()>,
+    add_sync_systems: fn(&mut App, ComponentSyncMode),
+}
+
+impl ComponentFns {
+    /// The component is serialized with its serde implementation
+    fn serde<C: Component + Message>() -> Self {
+        Self {
+            serialize: |_, entity| {
+                let component = entity
+                    .get::<C>()
+                    .context("the entity does not have the component")?;
+                serialize(component)
+            },
+            insert: |bytes, entity| {
+                entity.insert(deserialize::<C>(bytes)?);
+                Ok(())
+            },
+            update: |bytes, entity| {
+                let component = deserialize::<C>(bytes)?;
+                if let Some(mut c) = entity.get_mut::<C>() {
+                    *c = component;
+                } else {
+                    entity.insert(component);
+                }
+                Ok(())
+            },
+            add_sync_systems: |app, mode| add_sync_systems::<C>(app, mode, C::clone),
+        }
+    }
+
+    /// The component is serialized via `bevy_reflect` and written with its [`ReflectComponent`]
+    fn reflect<C: Component + Reflect + FromReflect + TypePath>() -> Self {
+        Self {
+            serialize: |world, entity| {
+                let component = entity
+                    .get::<C>()
+                    .context("the entity does not have the component")?;
+                let registry = world.resource::<AppTypeRegistry>().read();
+                bitcode::serialize(&TypedReflectSerializer::new(
+                    component.as_reflect(),
+                    &registry,
+                ))
+                .context("error serializing the reflect component")
+            },
+            insert: |bytes, entity| {
+                let type_registry = entity.world().resource::<AppTypeRegistry>().clone();
+                let registry = type_registry.read();
+                let (component, reflect_component) = deserialize_reflect::<C>(bytes, &registry)?;
+                reflect_component.insert(entity, component.as_reflect(), &registry);
+                Ok(())
+            },
+            update: |bytes, entity| {
+                let type_registry = entity.world().resource::<AppTypeRegistry>().clone();
+                let registry = type_registry.read();
+                let (component, reflect_component) = deserialize_reflect::<C>(bytes, &registry)?;
+                reflect_component.apply_or_insert(entity, component.as_reflect(), &registry);
+                Ok(())
+            },
+            add_sync_systems: |app, mode| {
+                add_sync_systems::<C>(app, mode, |component| {
+                    C::from_reflect(component.as_reflect())
+                        .expect("a component can always be built from itself")
+                })
+            },
+        }
+    }
+}
+
+/// Deserialize a component that was serialized via `bevy_reflect`, and return it with its [`ReflectComponent`]
+fn deserialize_reflect<'a, C: TypePath>(
+    bytes: &[u8],
+    registry: &'a TypeRegistry,
+) -> anyhow::Result<(Box<dyn Reflect>, &'a ReflectComponent)> {
+    let registration = registry.get(TypeId::of::<C>()).with_context(|| {
+        format!(
+            "{} is not registered in the AppTypeRegistry",
+            C::type_path()
+        )
+    })?;
+    let reflect_component = registration
+        .data::<ReflectComponent>()
+        .with_context(|| format!("{} does not reflect Component", C::type_path()))?;
+    let component = bitcode::serde::deserialize_seed(
+        bytes,
+        TypedReflectDeserializer::new(registration, registry),
+    )
+    .context("error deserializing the reflect component")?;
+    Ok((component, reflect_component))
+}
+
+/// Type-erased functions to replicate a component that was registered at runtime
+#[derive(Clone)]
+pub(crate) struct ComponentRegistration {
+    pub(crate) name: &'static str,
+    pub(crate) type_id: TypeId,
+    pub(crate) component_id: ComponentId,
+    pub(crate) direction: ChannelDirection,
+    pub(crate) mode: ComponentSyncMode,
+    pub(crate) net_id: NetId,
+    fns: ComponentFns,
+    remove: fn(&mut EntityWorldMut),
+    client_events: ComponentEventFns<()>,
+    server_events: ComponentEventFns<ClientId>,
+}
+
+impl ComponentRegistration {
+    fn new<C: Component>(
+        component_id: ComponentId,
+        direction: ChannelDirection,
+        mode: ComponentSyncMode,
+        fns: ComponentFns,
+    ) -> Self {
+        Self {
+            name: std::any::type_name::<C>(),
+            type_id: TypeId::of::<C>(),
+            component_id,
+            direction,
+            mode,
+            net_id: 0,
+            fns,
+            remove: |entity| {
+                entity.remove::<C>();
+            },
+            client_events: ComponentEventFns::new::<C>(),
+            server_events: ComponentEventFns::new::<C>(),
+        }
+    }
+
+    /// Returns true if the component can be sent in the given direction
+    pub(crate) fn can_send(&self, direction: &ChannelDirection) -> bool {
+        self.direction == ChannelDirection::Bidirectional || &self.direction == direction
+    }
+
+    fn event_fns<Ctx: EventContext>(&self) -> Option<ComponentEventFns<Ctx>> {
+        (&self.client_events as &dyn Any)
+            .downcast_ref::<ComponentEventFns<Ctx>>()
+            .or_else(|| (&self.server_events as &dyn Any).downcast_ref())
+            .copied()
+    }
+}
+
+/// Function used to emit the bevy event for a registered message
+type MessageEventFn<Ctx> = fn(&mut World, &[u8], Ctx) -> anyhow::Result<()>;
+
+/// Function used to map the entities of a registered message, in serialized form
+type MapEntitiesFn = fn(&[u8], &mut RemoteEntityMap) -> anyhow::Result<Vec<u8>>;
+
+/// Type-erased functions to receive a message that was registered at runtime
+#[derive(Clone)]
+pub(crate) struct MessageRegistration {
+    pub(crate) name: &'static str,
+    pub(crate) type_id: TypeId,
+    pub(crate) net_id: NetId,
+    client_event: MessageEventFn<()>,
+    server_event: MessageEventFn<ClientId>,
+    map_entities: Option<MapEntitiesFn>,
+}
+
+impl MessageRegistration {
+    fn new<M: Message>() -> Self {
+        Self {
+            name: std::any::type_name::<M>(),
+            type_id: TypeId::of::<M>(),
+            net_id: 0,
+            client_event: send_message_event::<M, ()>,
+            server_event: send_message_event::<M, ClientId>,
+            map_entities: None,
+        }
+    }
+
+    /// The message is a bevy event that is emitted directly on the receiving side
+    fn network_event<E: Event + Message>(map_entities: Option<MapEntitiesFn>) -> Self {
+        Self {
+            name: std::any::type_name::<E>(),
+            type_id: TypeId::of::<E>(),
+            net_id: 0,
+            client_event: send_network_event::<E, ()>,
+            server_event: send_network_event::<E, ClientId>,
+            map_entities,
+        }
+    }
+
+    fn event_fn<Ctx: EventContext>(&self) -> Option<MessageEventFn<Ctx>> {
+        (&self.client_event as &dyn Any)
+            .downcast_ref::<MessageEventFn<Ctx>>()
+            .or_else(|| (&self.server_event as &dyn Any).downcast_ref())
+            .copied()
+    }
+}
+
+fn send_message_event<M: Message, Ctx: EventContext>(
+    world: &mut World,
+    bytes: &[u8],
+    ctx: Ctx,
+) -> anyhow::Result<()> {
+    let message = deserialize::<M>(bytes)?;
+    world
+        .resource_mut::<Events<MessageEvent<M, Ctx>>>()
+        .send(MessageEvent::new(message, ctx));
+    Ok(())
+}
+
+fn send_network_event<E: Event + Message, Ctx: EventContext>(
+    world: &mut World,
+    bytes: &[u8],
+    _: Ctx,
+) -> anyhow::Result<()> {
+    let event = deserialize::<E>(bytes)?;
+    let id = world.resource_mut::<Events<E>>().send(event);
+    // remember the event so that it doesn't get sent back to the remote
+    world.resource_mut::<ReceivedNetworkEvents<E>>().push(id);
+    Ok(())
+}
+
+fn map_event_entities<E: Message + MapEntities>(
+    bytes: &[u8],
+    entity_map: &mut RemoteEntityMap,
+) -> anyhow::Result<Vec<u8>> {
+    let mut event = deserialize::<E>(bytes)?;
+    event.map_entities(entity_map);
+    serialize(&event)
+}
+
+/// Resource that contains all the components and messages that were registered at runtime.
+///
+/// Once all the plugins are built, the registry is finished: the types are sorted by name and get assigned
+/// their [`NetId`]. No new types can be registered after that.
+#[derive(Resource, Clone, Default)]
+pub struct ProtocolRegistry {
+    components: Vec<ComponentRegistration>,
+    messages: Vec<MessageRegistration>,
+    finished: bool,
+}
+
+impl ProtocolRegistry {
+    /// Returns true if the net ids of the registered types have been assigned
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Get the [`NetId`] of a registered component
+    pub fn component_net_id<C: Component>(&self) -> Option<NetId> {
+        self.finished.then_some(())?;
+        self.components
+            .iter()
+            .find(|registration| registration.type_id == TypeId::of::<C>())
+            .map(|registration| registration.net_id)
+    }
+
+    /// Get the [`NetId`] of a registered message
+    pub fn message_net_id<M: Message>(&self) -> Option<NetId> {
+        self.finished.then_some(())?;
+        self.messages
+            .iter()
+            .find(|registration| registration.type_id == TypeId::of::<M>())
+            .map(|registration| registration.net_id)
+    }
+
+    /// Assign the net ids of the registered types.
+    ///
+    /// This can be called several times (for example by both the client and server plugins)
+    pub(crate) fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.components
+            .sort_by_key(|registration| registration.name);
+        for (net_id, registration) in self.components.iter_mut().enumerate() {
+            registration.net_id = net_id as NetId;
+        }
+        self.messages.sort_by_key(|registration| registration.name);
+        for (net_id, registration) in self.messages.iter_mut().enumerate() {
+            registration.net_id = net_id as NetId;
+        }
+        self.finished = true;
+    }
+
+    /// Hash of the names of all the registered types, in the order of their [`NetId`].
+    ///
+    /// The client and the server must have the same hash, otherwise they would not agree on the meaning of the net ids.
+    /// Returns 0 if no types are registered.
+    pub fn hash(&self) -> u64 {
+        if self.components.is_empty() && self.messages.is_empty() {
+            return 0;
+        }
+        // FNV-1a, so that the hash doesn't depend on the platform or on the version of the standard library
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let names = self
+            .components
+            .iter()
+            .map(|registration| registration.name)
+            .chain(std::iter::once(""))
+            .chain(self.messages.iter().map(|registration| registration.name));
+        for name in names {
+            // the separator makes sure that different lists of names don't have the same bytes
+            for byte in name.bytes().chain(std::iter::once(0)) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+
+    pub(crate) fn components(&self) -> &[ComponentRegistration] {
+        &self.components
+    }
+
+    pub(crate) fn messages(&self) -> &[MessageRegistration] {
+        &self.messages
+    }
+
+    pub(crate) fn component(&self, net_id: NetId) -> Option<&ComponentRegistration> {
+        self.components.get(net_id as usize)
+    }
+
+    /// Net ids of the registered messages, or nothing if the registry is not finished yet
+    pub(crate) fn message_net_ids(&self) -> RegisteredMessageIds {
+        if !self.finished {
+            return RegisteredMessageIds::default();
+        }
+        RegisteredMessageIds {
+            net_ids: self
+                .messages
+                .iter()
+                .map(|registration| (registration.type_id, registration.net_id))
+                .collect(),
+            registry_hash: self.hash(),
+        }
+    }
+
+    /// Map the entities contained in a registered message that was received
+    fn map_entities(
+        &self,
+        message: &mut RegisteredMessage,
+        entity_map: &mut RemoteEntityMap,
+    ) -> anyhow::Result<()> {
+        let Some(map_entities) = self
+            .messages
+            .get(message.net_id as usize)
+            .and_then(|registration| registration.map_entities)
+        else {
+            return Ok(());
+        };
+        message.bytes = map_entities(&message.bytes, entity_map)?;
+        Ok(())
+    }
+
+    fn check_not_finished(&self, name: &str) {
+        assert!(
+            !self.finished,
+            "cannot register {} after the ProtocolRegistry has been finished. Types must be registered while the plugins are built",
+            name
+        );
+    }
+
+    /// Add the prediction/interpolation systems for the registered components
+    pub(crate) fn add_sync_systems(&self, app: &mut App) {
+        for registration in &self.components {
+            (registration.fns.add_sync_systems)(app, registration.mode);
+        }
+    }
+}
+
+/// Extension trait to register networked types on the [`App`] at runtime.
+///
+/// The registered types don't need to be part of the protocol enums, which lets plugins defined in
+/// different crates add their own networked types.
+pub trait AppRegistrationExt {
+    /// Register a component that will be replicated in the given direction.
+    ///
+    /// The `mode` defines how the component is synced from the [`Confirmed`] entity to the predicted/interpolated entities.
+    /// [`ComponentSyncMode::Full`] is not supported for registered components.
+    fn register_component<C: Component + Message>(
+        &mut self,
+        direction: ChannelDirection,
+        mode: ComponentSyncMode,
+    ) -> &mut Self;
+
+    /// Register a component that will be replicated in the given direction, for components that implement
+    /// [`Reflect`] but not `Serialize`/`Deserialize` (for example the components of other crates).
+    ///
+    /// The component is serialized via `bevy_reflect`, which is less efficient than [`register_component`](Self::register_component).
+    /// It is written on the receiving entity with its [`ReflectComponent`], so the type must have the `#[reflect(Component)]` attribute,
+    /// and the types of its fields must be registered in the [`AppTypeRegistry`].
+    fn register_reflect_component<
+        C: Component + Reflect + FromReflect + TypePath + GetTypeRegistration,
+    >(
+        &mut self,
+        direction: ChannelDirection,
+        mode: ComponentSyncMode,
+    ) -> &mut Self;
+
+    /// Register a message that can be sent with `send_registered_message`
+    fn register_message<M: Message>(&mut self) -> &mut Self;
+
+    /// Register a bevy [`Event`] that is sent to the remote every time it is written with an `EventWriter`.
+    ///
+    /// The event is sent on the channel `C`, and can be read on the remote with an `EventReader`.
+    /// The server sends the event to the clients in `target`; the events written by a client are only sent to the server.
+    fn register_network_event<E: Event + Message, C: Channel>(
+        &mut self,
+        direction: ChannelDirection,
+        target: NetworkTarget,
+    ) -> &mut Self;
+
+    /// Register a bevy [`Event`] that is tied to an entity, and is sent to the remote every time it is written
+    /// with an `EventWriter`.
+    ///
+    /// The server only sends the event to the clients in `target` that the entity is currently replicated to
+    /// (which takes the room visibility into account); events for entities that are not replicated are dropped.
+    /// The entities contained in the event are mapped on the remote.
+    fn register_entity_network_event<E: Event + Message + MapEntities, C: Channel>(
+        &mut self,
+        direction: ChannelDirection,
+        target: NetworkTarget,
+        entity: fn(&E) -> Entity,
+    ) -> &mut Self;
+}
+
+impl AppRegistrationExt for App {
+    fn register_component<C: Component + Message>(
+        &mut self,
+        direction: ChannelDirection,
+        mode: ComponentSyncMode,
+    ) -> &mut Self {
+        register_component_with_fns::<C>(self, direction, mode, ComponentFns::serde::<C>())
+    }
+
+    fn register_reflect_component<
+        C: Component + Reflect + FromReflect + TypePath + GetTypeRegistration,
+    >(
+        &mut self,
+        direction: ChannelDirection,
+        mode: ComponentSyncMode,
+    ) -> &mut Self {
+        self.register_type::<C>();
+        register_component_with_fns::<C>(self, direction, mode, ComponentFns::reflect::<C>())
+    }
+
+    fn register_message<M: Message>(&mut self) -> &mut Self {
+        let name = std::any::type_name::<M>();
+        let mut registry = self
+            .world
+            .get_resource_or_insert_with(ProtocolRegistry::default);
+        registry.check_not_finished(name);
+        if registry
+            .messages
+            .iter()
+            .any(|registration| registration.type_id == TypeId::of::<M>())
+        {
+            return self;
+        }
+        registry.messages.push(MessageRegistration::new::<M>());
+        self.add_event::<MessageEvent<M, ()>>()
+            .add_event::<MessageEvent<M, ClientId>>()
+    }
+
+    fn register_network_event<E: Event + Message, C: Channel>(
+        &mut self,
+        direction: ChannelDirection,
+        target: NetworkTarget,
+    ) -> &mut Self {
+        register_network_event_with_settings::<E>(
+            self,
+            NetworkEventSettings {
+                channel: ChannelKind::of::<C>(),
+                direction,
+                target,
+                entity: None,
+            },
+            None,
+        )
+    }
+
+    fn register_entity_network_event<E: Event + Message + MapEntities, C: Channel>(
+        &mut self,
+        direction: ChannelDirection,
+        target: NetworkTarget,
+        entity: fn(&E) -> Entity,
+    ) -> &mut Self {
+        register_network_event_with_settings::<E>(
+            self,
+            NetworkEventSettings {
+                channel: ChannelKind::of::<C>(),
+                direction,
+                target,
+                entity: Some(entity),
+            },
+            Some(map_event_entities::<E>),
+        )
+    }
+}
+
+fn register_network_event_with_settings<E: Event + Message>(
+    app: &mut App,
+    settings: NetworkEventSettings<E>,
+    map_entities: Option<MapEntitiesFn>,
+) -> &mut App {
+    let name = std::any::type_name::<E>();
+    let mut registry = app
+        .world
+        .get_resource_or_insert_with(ProtocolRegistry::default);
+    registry.check_not_finished(name);
+    if registry
+        .messages
+        .iter()
+        .any(|registration| registration.type_id == TypeId::of::<E>())
+    {
+        return app;
+    }
+    registry
+        .messages
+        .push(MessageRegistration::network_event::<E>(map_entities));
+    app.add_event::<E>()
+        .init_resource::<ReceivedNetworkEvents<E>>()
+        .init_resource::<NetworkEventQueue>()
+        .configure_sets(PostUpdate, NetworkEventSet::Queue.before(MainSet::Send))
+        .add_systems(
+            PostUpdate,
+            queue_network_events::<E>(settings).in_set(NetworkEventSet::Queue),
+        )
+}
+
+/// Map the entities contained in a message that was received, if it is a registered network event tied to an entity
+pub(crate) fn map_registered_message_entities<M: MessageProtocol>(
+    world: &World,
+    message: M,
+    entity_map: &mut RemoteEntityMap,
+) -> M {
+    if message.kind() != MessageKind::of::<RegisteredMessage>() {
+        return message;
+    }
+    let Some(registry) = world.get_resource::<ProtocolRegistry>() else {
+        return message;
+    };
+    let Ok(mut registered) = message.try_into() else {
+        unreachable!("the message is a RegisteredMessage");
+    };
+    let _ = registry
+        .map_entities(&mut registered, entity_map)
+        .map_err(|e| {
+            error!(
+                "error mapping the entities of a registered message: {:?}",
+                e
+            )
+        });
+    registered.into()
+}
+
+fn register_component_with_fns<C: Component>(
+    app: &mut App,
+    direction: ChannelDirection,
+    mode: ComponentSyncMode,
+    fns: ComponentFns,
+) -> &mut App {
+    let name = std::any::type_name::<C>();
+    assert!(
+        mode != ComponentSyncMode::Full,
+        "ComponentSyncMode::Full is not supported for the registered component {}. Add it to the ComponentProtocol instead",
+        name
+    );
+    let component_id = app.world.init_component::<C>();
+    let mut registry = app
+        .world
+        .get_resource_or_insert_with(ProtocolRegistry::default);
+    registry.check_not_finished(name);
+    if registry
+        .components
+        .iter()
+        .any(|registration| registration.type_id == TypeId::of::<C>())
+    {
+        return app;
+    }
+    registry.components.push(ComponentRegistration::new::<C>(
+        component_id,
+        direction,
+        mode,
+        fns,
+    ));
+    app.add_event::<ComponentInsertEvent<C, ()>>()
+        .add_event::<ComponentUpdateEvent<C, ()>>()
+        .add_event::<ComponentRemoveEvent<C, ()>>()
+        .add_event::<ComponentInsertEvent<C, ClientId>>()
+        .add_event::<ComponentUpdateEvent<C, ClientId>>()
+        .add_event::<ComponentRemoveEvent<C, ClientId>>()
+}
+
+/// Copy the component from the confirmed entity to the predicted and interpolated entities.
+///
+/// The component is copied if it matches the filter `F`, or if the predicted/interpolated entity doesn't have it yet
+/// (the predicted/interpolated entities can be spawned after the component was added on the confirmed entity)
+fn sync_registered_component<C: Component, F: QueryFilter>(
+    copy: fn(&C) -> C,
+) -> impl FnMut(Commands, Query<(Entity, &Confirmed, &C)>, Query<(), F>, Query<(), With<C>>) {
+    move |mut commands, confirmed, filter, with_component| {
+        for (entity, confirmed, component) in confirmed.iter() {
+            let matches_filter = filter.contains(entity);
+            for target in [confirmed.predicted, confirmed.interpolated]
+                .into_iter()
+                .flatten()
+            {
+                if !matches_filter && with_component.contains(target) {
+                    continue;
+                }
+                if let Some(mut target) = commands.get_entity(target) {
+                    target.insert(copy(component));
+                }
+            }
+        }
+    }
+}
+
+fn add_sync_systems<C: Component>(app: &mut App, mode: ComponentSyncMode, copy: fn(&C) -> C) {
+    match mode {
+        ComponentSyncMode::Simple => {
+            app.add_systems(
+                PreUpdate,
+                sync_registered_component::<C, Changed<C>>(copy)
+                    .in_set(PredictionSet::SpawnHistory),
+            );
+            app.add_systems(
+                Update,
+                sync_registered_component::<C, Changed<C>>(copy)
+                    .in_set(InterpolationSet::SpawnHistory),
+            );
+        }
+        ComponentSyncMode::Once => {
+            app.add_systems(
+                PreUpdate,
+                sync_registered_component::<C, Added<C>>(copy).in_set(PredictionSet::SpawnHistory),
+            );
+            app.add_systems(
+                Update,
+                sync_registered_component::<C, Added<C>>(copy)
+                    .in_set(InterpolationSet::SpawnHistory),
+            );
+        }
+        ComponentSyncMode::Full | ComponentSyncMode::None => {}
+    }
+}
+
+/// Emit the bevy events for the registered components that were received
+pub fn push_registered_component_events<
+    P: Protocol,
+    E: IterComponentInsertEvent<P, Ctx>
+        + IterComponentRemoveEvent<P, Ctx>
+        + IterComponentUpdateEvent<P, Ctx>,
+    Ctx: EventContext,
+>(
+    world: &mut World,
+    events: &mut E,
+) {
+    if !world.contains_resource::<ProtocolRegistry>() {
+        return;
+    }
+    world.resource_scope(|world, registry: Mut<ProtocolRegistry>| {
+        for registration in registry.components.iter() {
+            let Some(fns) = registration.event_fns::<Ctx>() else {
+                return;
+            };
+            let kind = P::ComponentKinds::from_registered(registration.net_id);
+            if events.has_component_insert_by_kind(kind) {
+                (fns.insert)(world, &mut events.iter_component_insert_by_kind(kind));
+            }
+            if events.has_component_update_by_kind(kind) {
+                (fns.update)(world, &mut events.iter_component_update_by_kind(kind));
+            }
+            if events.has_component_remove_by_kind(kind) {
+                (fns.remove)(world, &mut events.iter_component_remove_by_kind(kind));
+            }
+        }
+    });
+}
+
+/// Emit the bevy events for the registered messages that were received
+pub fn push_registered_message_events<
+    P: Protocol,
+    E: IterMessageEvent<P, Ctx>,
+    Ctx: EventContext,
+>(
+    world: &mut World,
+    events: &mut E,
+) {
+    if !events.has_messages::<RegisteredMessage>() {
+        return;
+    }
+    let Some(registry) = world.get_resource::<ProtocolRegistry>() else {
+        error!("received registered messages but there is no ProtocolRegistry");
+        return;
+    };
+    let event_fns = registry
+        .messages
+        .iter()
+        .map(|registration| registration.event_fn::<Ctx>())
+        .collect::<Option<Vec<_>>>();
+    let Some(event_fns) = event_fns else {
+        return;
+    };
+    for (message, ctx) in events.into_iter_messages::<RegisteredMessage>() {
+        let Some(event_fn) = event_fns.get(message.net_id as usize) else {
+            error!(net_id = ?message.net_id, "received a message that is not registered");
+            continue;
+        };
+        let _ = event_fn(world, &message.bytes, ctx)
+            .map_err(|e| error!("error receiving registered message: {:?}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use bevy::utils::Duration;
+    use serde::{Deserialize, Serialize};
+
+    use crate::prelude::client::*;
+    use crate::prelude::*;
+    use crate::tests::protocol::*;
+    use crate::tests::stepper::{BevyStepper, Step};
+
+    #[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct Health(u32);
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct Chat(String);
+
+    /// Component that can only be serialized via reflection
+    #[derive(Component, Reflect, Debug, PartialEq)]
+    #[reflect(Component)]
+    struct Score {
+        points: u32,
+        history: Vec<i16>,
+    }
+
+    #[derive(Resource, Default)]
+    struct ReceivedChats(Vec<Chat>);
+
+    fn receive_chats(
+        mut events: EventReader<MessageEvent<Chat>>,
+        mut received: ResMut<ReceivedChats>,
+    ) {
+        received
+            .0
+            .extend(events.read().map(|event| event.message().clone()));
+    }
+
+    #[test]
+    fn test_registered_component_and_message() {
+        let frame_duration = Duration::from_millis(10);
+        let tick_duration = Duration::from_millis(10);
+        let shared_config = SharedConfig {
+            tick: TickConfig::new(tick_duration),
+            ..Default::default()
+        };
+        let link_conditioner = LinkConditionerConfig {
+            incoming_latency: Duration::from_millis(0),
+            incoming_jitter: Duration::from_millis(0),
+            incoming_loss: 0.0,
+        };
+        let mut stepper = BevyStepper::new(
+            shared_config,
+            SyncConfig::default().speedup_factor(1.0),
+            PredictionConfig::default(),
+            InterpolationConfig::default(),
+            link_conditioner,
+            frame_duration,
+        );
+        for app in [&mut stepper.server_app, &mut stepper.client_app] {
+            app.register_component::<Health>(
+                ChannelDirection::ServerToClient,
+                ComponentSyncMode::Simple,
+            )
+            .register_reflect_component::<Score>(
+                ChannelDirection::ServerToClient,
+                ComponentSyncMode::Simple,
+            )
+            .register_message::<Chat>()
+            // the types of the fields of a reflect component must also be registered
+            .register_type::<Vec<i16>>();
+        }
+        stepper.client_app.init_resource::<ReceivedChats>();
+        stepper.client_app.add_systems(Update, receive_chats);
+        stepper.init();
+        assert_eq!(
+            stepper
+                .server_app
+                .world
+                .resource::<ProtocolRegistry>()
+                .component_net_id::<Health>(),
+            Some(0)
+        );
+
+        // the schema reports the delta compression of the `Registered` component variant
+        let schema = protocol()
+            .schema()
+            .with_registry(stepper.server_app.world.resource::<ProtocolRegistry>());
+        let registered = schema
+            .components
+            .iter()
+            .find(|component| component.name == "Registered")
+            .unwrap();
+        assert_eq!(schema.registered_components.len(), 2);
+        assert!(schema
+            .registered_components
+            .iter()
+            .all(|component| component.delta_compression == registered.delta_compression));
+
+        // the registered component is replicated
+        let server_entity = stepper
+            .server_app
+            .world
+            .spawn((Health(10), Replicate::default()))
+            .id();
+        stepper.frame_step();
+        stepper.frame_step();
+        let client_entity = *stepper
+            .client_app
+            .world
+            .resource::<ClientConnectionManager>()
+            .replication_receiver
+            .remote_entity_map
+            .get_local(server_entity)
+            .unwrap();
+        assert_eq!(
+            stepper.client_app.world.get::<Health>(client_entity),
+            Some(&Health(10))
+        );
+
+        // updates are replicated
+        stepper
+            .server_app
+            .world
+            .get_mut::<Health>(server_entity)
+            .unwrap()
+            .0 = 5;
+        stepper.frame_step();
+        stepper.frame_step();
+        assert_eq!(
+            stepper.client_app.world.get::<Health>(client_entity),
+            Some(&Health(5))
+        );
+
+        // removals are replicated
+        stepper
+            .server_app
+            .world
+            .entity_mut(server_entity)
+            .remove::<Health>();
+        stepper.frame_step();
+        stepper.frame_step();
+        assert!(stepper
+            .client_app
+            .world
+            .get::<Health>(client_entity)
+            .is_none());
+
+        // components registered via reflection are replicated
+        let server_entity = stepper
+            .server_app
+            .world
+            .spawn((
+                Score {
+                    points: 3,
+                    history: vec![1, -2],
+                },
+                Replicate::default(),
+            ))
+            .id();
+        stepper.frame_step();
+        stepper.frame_step();
+        let client_entity = *stepper
+            .client_app
+            .world
+            .resource::<ClientConnectionManager>()
+            .replication_receiver
+            .remote_entity_map
+            .get_local(server_entity)
+            .unwrap();
+        assert_eq!(
+            stepper.client_app.world.get::<Score>(client_entity),
+            Some(&Score {
+                points: 3,
+                history: vec![1, -2],
+            })
+        );
+        stepper
+            .server_app
+            .world
+            .get_mut::<Score>(server_entity)
+            .unwrap()
+            .history
+            .push(4);
+        stepper.frame_step();
+        stepper.frame_step();
+        assert_eq!(
+            stepper.client_app.world.get::<Score>(client_entity),
+            Some(&Score {
+                points: 3,
+                history: vec![1, -2, 4],
+            })
+        );
+
+        // registered messages are received as MessageEvents
+        stepper
+            .server_app
+            .world
+            .resource_mut::<ServerConnectionManager>()
+            .send_registered_message::<Channel1, _>(ClientId::Netcode(111), Chat("hi".to_string()))
+            .unwrap();
+        stepper.frame_step();
+        stepper.frame_step();
+        assert_eq!(
+            stepper.client_app.world.resource::<ReceivedChats>().0,
+            vec![Chat("hi".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_registry_mismatch_disconnects_client() {
+        let mut stepper = BevyStepper::new(
+            SharedConfig::default(),
+            SyncConfig::default(),
+            PredictionConfig::default(),
+            InterpolationConfig::default(),
+            LinkConditionerConfig {
+                incoming_latency: Duration::from_millis(0),
+                incoming_jitter: Duration::from_millis(0),
+                incoming_loss: 0.0,
+            },
+            Duration::from_millis(10),
+        );
+        stepper.server_app.register_component::<Health>(
+            ChannelDirection::ServerToClient,
+            ComponentSyncMode::Simple,
+        );
+        stepper.client_app.register_message::<Chat>();
+        stepper.init();
+        assert_ne!(
+            stepper
+                .server_app
+                .world
+                .resource::<ProtocolRegistry>()
+                .hash(),
+            stepper
+                .client_app
+                .world
+                .resource::<ProtocolRegistry>()
+                .hash()
+        );
+        for _ in 0..10 {
+            stepper.frame_step();
+        }
+        // the client registered different types: the server disconnected it
+        assert!(stepper
+            .server_app
+            .world
+            .resource::<ServerConnectionManager>()
+            .connection_stats(ClientId::Netcode(111))
+            .is_none());
+    }
+}
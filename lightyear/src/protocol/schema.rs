@@ -0,0 +1,285 @@
+//! Export a machine-readable description of the [`Protocol`]
+//!
+//! Tools that are not written with bevy (admin tools, packet dissectors, load-test bots, etc.) need to know how the
+//! protocol is defined to decode the packets. [`ProtocolSchema`] lists every channel, message, component and input
+//! of the protocol along with the [`NetId`] used to identify them on the wire.
+//!
+//! ```rust,ignore
+//! let json = protocol.schema().with_registry(&registry).to_json();
+//! std::fs::write("protocol.json", json)?;
+//! ```
+use bevy::utils::get_short_name;
+use serde::{Deserialize, Serialize};
+
+use crate::channel::builder::{ChannelMode, ChannelSettings};
+use crate::client::components::ComponentSyncMode;
+use crate::protocol::channel::ChannelRegistry;
+use crate::protocol::component::ComponentProtocol;
+use crate::protocol::message::MessageProtocol;
+use crate::protocol::registration::ProtocolRegistry;
+use crate::protocol::registry::NetId;
+use crate::protocol::Protocol;
+
+/// Description of a [`Protocol`], that can be exported to JSON with [`ProtocolSchema::to_json`]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProtocolSchema {
+    pub channels: Vec<ChannelSchema>,
+    /// The variants of the [`MessageProtocol`] enum; the net id is the index of the variant
+    pub messages: Vec<MessageSchema>,
+    /// The variants of the [`ComponentProtocol`] enum; the net id is the index of the variant
+    pub components: Vec<ComponentSchema>,
+    pub inputs: Vec<InputSchema>,
+    /// The messages registered at runtime, which are sent inside the `Registered` message variant
+    pub registered_messages: Vec<MessageSchema>,
+    /// The components registered at runtime, which are sent inside the `Registered` component variant
+    pub registered_components: Vec<ComponentSchema>,
+}
+
+/// Description of a [`Channel`](crate::channel::builder::Channel) and its [`ChannelSettings`]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChannelSchema {
+    pub net_id: NetId,
+    pub name: String,
+    /// Name of the [`ChannelMode`]
+    pub mode: String,
+    /// Only set for reliable channels
+    pub reliable: Option<ReliableSchema>,
+    pub direction: String,
+    pub priority: f32,
+    pub unsent_message_policy: String,
+    /// Only set if the channel uses forward error correction
+    pub fec_group_size: Option<u8>,
+}
+
+/// Description of the [`ReliableSettings`](crate::channel::builder::ReliableSettings) of a channel
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReliableSchema {
+    pub rtt_resend_factor: f32,
+    pub rtt_resend_min_delay_ms: f64,
+}
+
+/// Description of a message
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MessageSchema {
+    pub net_id: NetId,
+    pub name: String,
+    /// Full path of the rust type of the message
+    pub type_name: String,
+}
+
+/// Description of a component
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ComponentSchema {
+    pub net_id: NetId,
+    pub name: String,
+    /// Full path of the rust type of the component
+    pub type_name: String,
+    /// The [`ComponentSyncMode`](crate::prelude::client::ComponentSyncMode) of the component, if it is synced
+    /// to the predicted/interpolated entities
+    pub sync_mode: Option<String>,
+    /// True if the updates of the component are delta-compressed
+    pub delta_compression: bool,
+}
+
+/// Description of an input type
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InputSchema {
+    pub name: String,
+    /// Full path of the rust type of the input
+    pub type_name: String,
+}
+
+impl ProtocolSchema {
+    pub fn new<P: Protocol>(protocol: &P) -> Self {
+        #[allow(unused_mut)]
+        let mut inputs = vec![InputSchema {
+            name: "Input".to_string(),
+            type_name: std::any::type_name::<P::Input>().to_string(),
+        }];
+        #[cfg(feature = "leafwing")]
+        {
+            inputs.push(InputSchema {
+                name: "LeafwingInput1".to_string(),
+                type_name: std::any::type_name::<P::LeafwingInput1>().to_string(),
+            });
+            inputs.push(InputSchema {
+                name: "LeafwingInput2".to_string(),
+                type_name: std::any::type_name::<P::LeafwingInput2>().to_string(),
+            });
+        }
+        Self {
+            channels: channels_schema(protocol.channel_registry()),
+            messages: P::Message::schema(),
+            components: P::Components::schema(),
+            inputs,
+            registered_messages: vec![],
+            registered_components: vec![],
+        }
+    }
+
+    /// Add the components and messages that were registered at runtime.
+    ///
+    /// The [`ProtocolRegistry`] must be finished (i.e. the app's plugins are finished) for the net ids to be assigned.
+    pub fn with_registry(mut self, registry: &ProtocolRegistry) -> Self {
+        // the registered components are sent inside the `Registered` variant of the ComponentProtocol
+        let delta_compression = self
+            .components
+            .iter()
+            .find(|component| component.name == "Registered")
+            .map_or(false, |component| component.delta_compression);
+        self.registered_messages = registry
+            .messages()
+            .iter()
+            .map(|registration| MessageSchema {
+                net_id: registration.net_id,
+                name: get_short_name(registration.name),
+                type_name: registration.name.to_string(),
+            })
+            .collect();
+        self.registered_components = registry
+            .components()
+            .iter()
+            .map(|registration| ComponentSchema {
+                net_id: registration.net_id,
+                name: get_short_name(registration.name),
+                type_name: registration.name.to_string(),
+                sync_mode: match registration.mode {
+                    ComponentSyncMode::None => None,
+                    mode => Some(format!("{:?}", mode)),
+                },
+                delta_compression,
+            })
+            .collect();
+        self
+    }
+
+    /// Serialize the schema to JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("the schema can always be serialized to JSON")
+    }
+}
+
+fn channels_schema(registry: &ChannelRegistry) -> Vec<ChannelSchema> {
+    let mut channels = registry
+        .kind_map
+        .kind_map
+        .iter()
+        .filter_map(|(kind, net_id)| {
+            let settings = &registry.get_builder_from_kind(kind)?.settings;
+            Some(channel_schema(
+                *net_id,
+                registry.name(kind).unwrap_or_default(),
+                settings,
+            ))
+        })
+        .collect::<Vec<_>>();
+    channels.sort_by_key(|channel| channel.net_id);
+    channels
+}
+
+fn channel_schema(net_id: NetId, name: &str, settings: &ChannelSettings) -> ChannelSchema {
+    let (mode, reliable) = match &settings.mode {
+        ChannelMode::UnorderedUnreliableWithAcks => ("UnorderedUnreliableWithAcks", None),
+        ChannelMode::UnorderedUnreliable => ("UnorderedUnreliable", None),
+        ChannelMode::SequencedUnreliable => ("SequencedUnreliable", None),
+        ChannelMode::UnorderedReliable(reliable) => ("UnorderedReliable", Some(reliable)),
+        ChannelMode::SequencedReliable(reliable) => ("SequencedReliable", Some(reliable)),
+        ChannelMode::OrderedReliable(reliable) => ("OrderedReliable", Some(reliable)),
+        ChannelMode::TickBuffered => ("TickBuffered", None),
+    };
+    ChannelSchema {
+        net_id,
+        name: name.to_string(),
+        mode: mode.to_string(),
+        reliable: reliable.map(|reliable| ReliableSchema {
+            rtt_resend_factor: reliable.rtt_resend_factor,
+            rtt_resend_min_delay_ms: reliable.rtt_resend_min_delay.as_secs_f64() * 1000.0,
+        }),
+        direction: format!("{:?}", settings.direction),
+        priority: settings.priority,
+        unsent_message_policy: format!("{:?}", settings.unsent_message_policy),
+        fec_group_size: settings.fec.map(|fec| fec.group_size),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::default;
+
+    use crate::prelude::*;
+    use crate::tests::protocol::*;
+
+    use super::*;
+
+    #[test]
+    fn test_protocol_schema() {
+        let schema = protocol().schema();
+
+        let channel = schema
+            .channels
+            .iter()
+            .find(|channel| channel.name == Channel1::name())
+            .unwrap();
+        assert_eq!(channel.mode, "UnorderedUnreliable");
+        assert_eq!(channel.reliable, None);
+        // the net ids of the channels are unique
+        for (i, channel) in schema.channels.iter().enumerate() {
+            assert_eq!(channel.net_id as usize, i);
+        }
+
+        // the net id of a message is the index of its variant
+        assert_eq!(
+            schema.messages[0],
+            MessageSchema {
+                net_id: 0,
+                name: "Message1".to_string(),
+                type_name: std::any::type_name::<Message1>().to_string(),
+            }
+        );
+        let component = schema
+            .components
+            .iter()
+            .find(|component| component.name == "Component1")
+            .unwrap();
+        assert_eq!(component.net_id, 0);
+        assert_eq!(component.sync_mode.as_deref(), Some("Full"));
+        assert!(schema
+            .components
+            .iter()
+            .any(|component| component.name == "Registered"));
+        assert_eq!(schema.inputs[0].type_name, std::any::type_name::<MyInput>());
+    }
+
+    #[test]
+    fn test_schema_to_json() {
+        let schema = ProtocolSchema {
+            channels: vec![channel_schema(
+                0,
+                "channel",
+                &ChannelSettings {
+                    mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
+                    ..default()
+                },
+            )],
+            messages: vec![MessageSchema {
+                net_id: 0,
+                name: "Chat".to_string(),
+                type_name: "a::\"Chat\"".to_string(),
+            }],
+            components: vec![],
+            inputs: vec![],
+            registered_messages: vec![],
+            registered_components: vec![],
+        };
+        assert_eq!(
+            schema.to_json(),
+            concat!(
+                r#"{"channels":[{"net_id":0,"name":"channel","mode":"OrderedReliable","#,
+                r#""reliable":{"rtt_resend_factor":1.5,"rtt_resend_min_delay_ms":0.0},"#,
+                r#""direction":"Bidirectional","priority":1.0,"unsent_message_policy":"Drop","fec_group_size":null}],"#,
+                r#""messages":[{"net_id":0,"name":"Chat","type_name":"a::\"Chat\""}],"#,
+                r#""components":[],"inputs":[],"registered_messages":[],"registered_components":[]}"#
+            )
+        );
+    }
+}
@@ -0,0 +1,406 @@
+//! Bit-packed serialization of quantized values.
+//!
+//! These types are used by the code generated by the `#[derive(Quantize)]` macro:
+//! - floats and vectors annotated with `#[protocol(quantize(min = -1000.0, max = 1000.0, bits = 18))]`
+//!   are clamped to `[min, max]` and serialized as integers of `bits` bits ([`Quantized`])
+//! - integers annotated with `#[protocol(bits = 5)]` are serialized with `bits` bits ([`Packed`]).
+//!   Values that don't fit are clamped
+//!
+//! With lightyear's serializer the values are written with exactly the requested number of bits;
+//! other serde formats see tuple structs containing a single integer.
+use std::fmt::Formatter;
+use std::marker::PhantomData;
+
+use bevy::math::{DVec2, DVec3, Quat, Vec2, Vec3, Vec4};
+use serde::de::{Error, SeqAccess, Visitor};
+use serde::ser::{SerializeTuple, SerializeTupleStruct};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Maximum number of floats in a [`Quantizable`] type
+pub const MAX_DIMENSIONS: usize = 4;
+
+/// An integer serialized with `BITS` bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packed<const BITS: u8>(pub u64);
+
+impl<const BITS: u8> Packed<BITS> {
+    /// Largest value that can be represented with `BITS` bits
+    pub const MAX: u64 = u64::MAX >> (64 - BITS as u32);
+
+    pub fn from_int<T: PackedInt>(value: T) -> Self {
+        Self(value.pack(BITS))
+    }
+
+    pub fn to_int<T: PackedInt>(self) -> T {
+        T::unpack(self.0, BITS)
+    }
+}
+
+impl<const BITS: u8> Serialize for Packed<BITS> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // the bitcode serializer writes the field with `BITS` bits
+        let mut tuple_struct =
+            serializer.serialize_tuple_struct(bitcode::serde::PACKED_BITS, BITS as usize)?;
+        tuple_struct.serialize_field(&self.0)?;
+        tuple_struct.end()
+    }
+}
+
+impl<'de, const BITS: u8> Deserialize<'de> for Packed<BITS> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PackedVisitor<const BITS: u8>;
+
+        impl<'de, const BITS: u8> Visitor<'de> for PackedVisitor<BITS> {
+            type Value = Packed<BITS>;
+
+            fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+                write!(formatter, "an integer of {} bits", BITS)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let value = seq
+                    .next_element::<u64>()?
+                    .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+                if value > Packed::<BITS>::MAX {
+                    return Err(A::Error::custom(format!(
+                        "value {} does not fit in {} bits",
+                        value, BITS
+                    )));
+                }
+                Ok(Packed(value))
+            }
+        }
+
+        deserializer.deserialize_tuple_struct(
+            bitcode::serde::PACKED_BITS,
+            BITS as usize,
+            PackedVisitor::<BITS>,
+        )
+    }
+}
+
+/// Integers that can be serialized with a reduced number of bits
+pub trait PackedInt: Copy {
+    /// Convert the value to an integer of `bits` bits, clamping it if it doesn't fit
+    fn pack(self, bits: u8) -> u64;
+
+    fn unpack(packed: u64, bits: u8) -> Self;
+}
+
+macro_rules! impl_packed_uint {
+    ($($t:ty),*) => {
+        $(
+            impl PackedInt for $t {
+                fn pack(self, bits: u8) -> u64 {
+                    (self as u64).min(u64::MAX >> (64 - bits as u32))
+                }
+
+                fn unpack(packed: u64, _bits: u8) -> Self {
+                    packed.min(<$t>::MAX as u64) as $t
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_packed_int {
+    ($($t:ty),*) => {
+        $(
+            impl PackedInt for $t {
+                fn pack(self, bits: u8) -> u64 {
+                    let max = (i64::MAX >> (64 - bits as u32)) as i128;
+                    let value = (self as i128).clamp(-max - 1, max) as i64;
+                    // zigzag encoding, so that small negative values use few bits
+                    ((value << 1) ^ (value >> 63)) as u64
+                }
+
+                fn unpack(packed: u64, _bits: u8) -> Self {
+                    let value = ((packed >> 1) as i64) ^ -((packed & 1) as i64);
+                    value.clamp(<$t>::MIN as i64, <$t>::MAX as i64) as $t
+                }
+            }
+        )*
+    };
+}
+
+impl_packed_uint!(u8, u16, u32, u64, usize);
+impl_packed_int!(i8, i16, i32, i64, isize);
+
+/// Floats or vectors of floats that can be quantized
+pub trait Quantizable: Sized {
+    /// Number of floats in the value
+    const DIMENSIONS: usize;
+
+    /// The floats of the value; only the first `DIMENSIONS` elements are used
+    fn to_floats(&self) -> [f64; MAX_DIMENSIONS];
+
+    fn from_floats(floats: [f64; MAX_DIMENSIONS]) -> Self;
+}
+
+impl Quantizable for f32 {
+    const DIMENSIONS: usize = 1;
+
+    fn to_floats(&self) -> [f64; MAX_DIMENSIONS] {
+        [*self as f64, 0.0, 0.0, 0.0]
+    }
+
+    fn from_floats(floats: [f64; MAX_DIMENSIONS]) -> Self {
+        floats[0] as f32
+    }
+}
+
+impl Quantizable for f64 {
+    const DIMENSIONS: usize = 1;
+
+    fn to_floats(&self) -> [f64; MAX_DIMENSIONS] {
+        [*self, 0.0, 0.0, 0.0]
+    }
+
+    fn from_floats(floats: [f64; MAX_DIMENSIONS]) -> Self {
+        floats[0]
+    }
+}
+
+impl Quantizable for Vec2 {
+    const DIMENSIONS: usize = 2;
+
+    fn to_floats(&self) -> [f64; MAX_DIMENSIONS] {
+        [self.x as f64, self.y as f64, 0.0, 0.0]
+    }
+
+    fn from_floats(floats: [f64; MAX_DIMENSIONS]) -> Self {
+        Vec2::new(floats[0] as f32, floats[1] as f32)
+    }
+}
+
+impl Quantizable for Vec3 {
+    const DIMENSIONS: usize = 3;
+
+    fn to_floats(&self) -> [f64; MAX_DIMENSIONS] {
+        [self.x as f64, self.y as f64, self.z as f64, 0.0]
+    }
+
+    fn from_floats(floats: [f64; MAX_DIMENSIONS]) -> Self {
+        Vec3::new(floats[0] as f32, floats[1] as f32, floats[2] as f32)
+    }
+}
+
+impl Quantizable for Vec4 {
+    const DIMENSIONS: usize = 4;
+
+    fn to_floats(&self) -> [f64; MAX_DIMENSIONS] {
+        self.as_dvec4().to_array()
+    }
+
+    fn from_floats(floats: [f64; MAX_DIMENSIONS]) -> Self {
+        Vec4::from_array(floats.map(|f| f as f32))
+    }
+}
+
+impl Quantizable for DVec2 {
+    const DIMENSIONS: usize = 2;
+
+    fn to_floats(&self) -> [f64; MAX_DIMENSIONS] {
+        [self.x, self.y, 0.0, 0.0]
+    }
+
+    fn from_floats(floats: [f64; MAX_DIMENSIONS]) -> Self {
+        DVec2::new(floats[0], floats[1])
+    }
+}
+
+impl Quantizable for DVec3 {
+    const DIMENSIONS: usize = 3;
+
+    fn to_floats(&self) -> [f64; MAX_DIMENSIONS] {
+        [self.x, self.y, self.z, 0.0]
+    }
+
+    fn from_floats(floats: [f64; MAX_DIMENSIONS]) -> Self {
+        DVec3::new(floats[0], floats[1], floats[2])
+    }
+}
+
+/// The rotation is quantized component-wise, so a range of `[-1.0, 1.0]` should be used.
+/// The quaternion is normalized after being dequantized.
+impl Quantizable for Quat {
+    const DIMENSIONS: usize = 4;
+
+    fn to_floats(&self) -> [f64; MAX_DIMENSIONS] {
+        Vec4::from(*self).as_dvec4().to_array()
+    }
+
+    fn from_floats(floats: [f64; MAX_DIMENSIONS]) -> Self {
+        Quat::from_array(floats.map(|f| f as f32)).normalize()
+    }
+}
+
+/// A [`Quantizable`] value where each float is serialized as an integer of `BITS` bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quantized<T, const BITS: u8> {
+    values: [u64; MAX_DIMENSIONS],
+    marker: PhantomData<T>,
+}
+
+impl<T: Quantizable, const BITS: u8> Quantized<T, BITS> {
+    /// Quantize the value; each float is clamped to `[min, max]`
+    pub fn new(value: &T, min: f64, max: f64) -> Self {
+        let steps = Packed::<BITS>::MAX as f64;
+        let floats = value.to_floats();
+        let mut values = [0; MAX_DIMENSIONS];
+        for (value, f) in values.iter_mut().zip(&floats[..T::DIMENSIONS]) {
+            let ratio = (f.clamp(min, max) - min) / (max - min);
+            // NaN is mapped to `min`
+            *value = (ratio * steps).round() as u64;
+        }
+        Self {
+            values,
+            marker: PhantomData,
+        }
+    }
+
+    /// Get back the value that was quantized with the range `[min, max]`
+    pub fn get(&self, min: f64, max: f64) -> T {
+        let steps = Packed::<BITS>::MAX as f64;
+        T::from_floats(
+            self.values
+                .map(|v| min + (v.min(Packed::<BITS>::MAX) as f64 / steps) * (max - min)),
+        )
+    }
+}
+
+impl<T: Quantizable, const BITS: u8> Serialize for Quantized<T, BITS> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(T::DIMENSIONS)?;
+        for value in &self.values[..T::DIMENSIONS] {
+            tuple.serialize_element(&Packed::<BITS>(*value))?;
+        }
+        tuple.end()
+    }
+}
+
+impl<'de, T: Quantizable, const BITS: u8> Deserialize<'de> for Quantized<T, BITS> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct QuantizedVisitor<T, const BITS: u8>(PhantomData<T>);
+
+        impl<'de, T: Quantizable, const BITS: u8> Visitor<'de> for QuantizedVisitor<T, BITS> {
+            type Value = Quantized<T, BITS>;
+
+            fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+                write!(formatter, "{} quantized floats", T::DIMENSIONS)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut values = [0; MAX_DIMENSIONS];
+                for (i, value) in values[..T::DIMENSIONS].iter_mut().enumerate() {
+                    *value = seq
+                        .next_element::<Packed<BITS>>()?
+                        .ok_or_else(|| A::Error::invalid_length(i, &self))?
+                        .0;
+                }
+                Ok(Quantized {
+                    values,
+                    marker: PhantomData,
+                })
+            }
+        }
+
+        deserializer.deserialize_tuple(T::DIMENSIONS, QuantizedVisitor::<T, BITS>(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::_reexport::QuantizeInternal;
+    use crate::serialize::reader::ReadBuffer;
+    use crate::serialize::wordbuffer::reader::ReadWordBuffer;
+    use crate::serialize::wordbuffer::writer::WriteWordBuffer;
+    use crate::serialize::writer::WriteBuffer;
+
+    use super::*;
+
+    #[test]
+    fn test_packed_int() {
+        assert_eq!(Packed::<5>::from_int(3u8).to_int::<u8>(), 3);
+        // values that don't fit are clamped
+        assert_eq!(Packed::<5>::from_int(100u32).to_int::<u32>(), 31);
+        assert_eq!(Packed::<5>::from_int(-7i16).to_int::<i16>(), -7);
+        assert_eq!(Packed::<5>::from_int(-100i16).to_int::<i16>(), -16);
+        assert_eq!(Packed::<5>::from_int(100i64).to_int::<i64>(), 15);
+        assert_eq!(Packed::<64>::from_int(i64::MIN).to_int::<i64>(), i64::MIN);
+        assert_eq!(Packed::<64>::from_int(u64::MAX).to_int::<u64>(), u64::MAX);
+    }
+
+    #[test]
+    fn test_quantized() {
+        let value = Vec3::new(-1000.0, 12.3456, 2000.0);
+        let quantized = Quantized::<Vec3, 18>::new(&value, -1000.0, 1000.0);
+        let result = quantized.get(-1000.0, 1000.0);
+        // the precision is 2000 / (2^18 - 1)
+        assert_eq!(result.x, -1000.0);
+        assert!((result.y - 12.3456).abs() < 0.004);
+        // values are clamped to the range
+        assert_eq!(result.z, 1000.0);
+    }
+
+    #[test]
+    fn test_serialize_packed() -> anyhow::Result<()> {
+        let value = (
+            Quantized::<Vec2, 18>::new(&Vec2::new(1.0, -1.0), -10.0, 10.0),
+            Packed::<5>::from_int(17u8),
+        );
+        let mut writer = WriteWordBuffer::with_capacity(10);
+        writer.serialize(&value)?;
+        let bytes = writer.finish_write();
+        // 2 * 18 + 5 bits
+        assert_eq!(bytes.len(), 6);
+
+        let mut reader = ReadWordBuffer::start_read(bytes);
+        let (quantized, packed) = reader.deserialize::<(Quantized<Vec2, 18>, Packed<5>)>()?;
+        assert_eq!(quantized, value.0);
+        assert_eq!(packed.to_int::<u8>(), 17);
+        Ok(())
+    }
+
+    #[derive(QuantizeInternal, Debug, Clone, PartialEq)]
+    struct Player {
+        #[protocol(quantize(min = -1000.0, max = 1000.0, bits = 18))]
+        position: Vec3,
+        #[protocol(quantize(min = -1, max = 1, bits = 12))]
+        rotation: Quat,
+        #[protocol(bits = 7)]
+        health: u8,
+        #[protocol(bits = 5)]
+        score_delta: i32,
+        name: String,
+    }
+
+    #[derive(QuantizeInternal, Debug, Clone, PartialEq)]
+    struct Speed(#[protocol(quantize(min = 0.0, max = 10.0, bits = 8))] f32);
+
+    #[test]
+    fn test_derive_quantize() -> anyhow::Result<()> {
+        let player = Player {
+            position: Vec3::new(1.0, -200.0, 999.0),
+            rotation: Quat::from_rotation_y(1.0),
+            health: 100,
+            score_delta: -3,
+            name: "a".to_string(),
+        };
+        let mut writer = WriteWordBuffer::with_capacity(20);
+        writer.serialize(&(player.clone(), Speed(2.5)))?;
+        let bytes = writer.finish_write();
+        // 3 * 18 + 4 * 12 + 7 + 5 + 8 (string length + 1 byte) + 8 bits
+        assert_eq!(bytes.len(), 17);
+
+        let mut reader = ReadWordBuffer::start_read(bytes);
+        let (result, speed) = reader.deserialize::<(Player, Speed)>()?;
+        assert!(result.position.abs_diff_eq(player.position, 0.01));
+        assert!(result.rotation.abs_diff_eq(player.rotation, 0.001));
+        assert_eq!(result.health, 100);
+        assert_eq!(result.score_delta, -3);
+        assert_eq!(result.name, "a");
+        assert!((speed.0 - 2.5).abs() < 0.02);
+        Ok(())
+    }
+}
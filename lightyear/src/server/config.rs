@@ -5,6 +5,8 @@ use nonzero_ext::nonzero;
 
 use crate::connection::netcode::Key;
 use crate::connection::server::NetConfig;
+use crate::packet::header::AckBitfieldSize;
+use crate::packet::limits::DecodeLimits;
 use crate::server::replication::ReplicationConfig;
 use crate::shared::config::SharedConfig;
 use crate::shared::ping::manager::PingConfig;
@@ -56,6 +58,11 @@ pub struct PacketConfig {
     pub per_client_send_bandwidth_cap: Quota,
     /// If false, there is no bandwidth cap and all messages are sent as soon as possible
     pub bandwidth_cap_enabled: bool,
+    /// Biggest number of packet ids (before the last received packet id) that can be acked in each packet header.
+    /// The window is negotiated with each client when it connects, see [`AckBitfieldSize`]
+    pub ack_bitfield_size: AckBitfieldSize,
+    /// Limits applied to the messages received from the clients
+    pub decode_limits: DecodeLimits,
 }
 
 impl Default for PacketConfig {
@@ -64,6 +71,8 @@ impl Default for PacketConfig {
             // 56 KB/s bandwidth cap
             per_client_send_bandwidth_cap: Quota::per_second(nonzero!(56000u32)),
             bandwidth_cap_enabled: false,
+            ack_bitfield_size: AckBitfieldSize::default(),
+            decode_limits: DecodeLimits::default(),
         }
     }
 }
@@ -84,6 +93,16 @@ impl PacketConfig {
         self.bandwidth_cap_enabled = true;
         self
     }
+
+    pub fn with_ack_bitfield_size(mut self, ack_bitfield_size: AckBitfieldSize) -> Self {
+        self.ack_bitfield_size = ack_bitfield_size;
+        self
+    }
+
+    pub fn with_decode_limits(mut self, decode_limits: DecodeLimits) -> Self {
+        self.decode_limits = decode_limits;
+        self
+    }
 }
 
 /// Configuration for the server plugin
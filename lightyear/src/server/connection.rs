@@ -6,16 +6,18 @@ use bevy::prelude::{Entity, Resource, World};
 use bevy::utils::{HashMap, HashSet};
 use hashbrown::hash_map::Entry;
 use serde::Serialize;
-use tracing::{debug, info, trace, trace_span, warn};
+use tracing::{debug, error, info, trace, trace_span, warn};
 
 use crate::_reexport::{
-    EntityUpdatesChannel, FromType, InputMessageKind, MessageProtocol, PingChannel,
-    ReplicationSend, ServerMarker, ShouldBeInterpolated,
+    EntityActionsChannel, EntityUpdatesChannel, FromType, InputMessageKind, MessageProtocol,
+    PingChannel, ReplicationSend, ServerMarker, ShouldBeInterpolated,
 };
+use crate::channel::builder::ChannelDirection;
 use crate::channel::senders::ChannelSend;
 use crate::client::message::ClientMessage;
 use crate::connection::id::ClientId;
-use crate::inputs::native::input_buffer::InputBuffer;
+use crate::inputs::native::input_buffer::{InputBuffer, InputMessage};
+use crate::packet::header::AckBitfieldSize;
 use crate::packet::message_manager::MessageManager;
 use crate::packet::packet::Packet;
 use crate::packet::packet_manager::Payload;
@@ -23,19 +25,28 @@ use crate::prelude::{
     Channel, ChannelKind, Message, Mode, PreSpawnedPlayerObject, ShouldBePredicted,
 };
 use crate::protocol::channel::ChannelRegistry;
+use crate::protocol::registration::{map_registered_message_entities, RegisteredMessageIds};
 use crate::protocol::Protocol;
 use crate::serialize::reader::ReadBuffer;
 use crate::server::config::PacketConfig;
 use crate::server::events::ServerEvents;
 use crate::server::message::ServerMessage;
 use crate::shared::events::connection::ConnectionEvents;
+use crate::shared::handshake::HandshakeMessage;
 use crate::shared::ping::manager::{PingConfig, PingManager};
 use crate::shared::ping::message::SyncMessage;
-use crate::shared::replication::components::{NetworkTarget, Replicate, ReplicationGroupId};
+use crate::shared::replication::authority::Authority;
+use crate::shared::replication::components::{
+    NetworkTarget, Replicate, ReplicateRemoveBehaviour, ReplicationGroupId,
+};
+use crate::shared::replication::initial_sync::{
+    InitialSyncBatchMessage, InitialSyncConfig, InitialSyncMessage, InitialSyncSender,
+};
 use crate::shared::replication::receive::ReplicationReceiver;
 use crate::shared::replication::send::ReplicationSender;
 use crate::shared::replication::ReplicationMessage;
 use crate::shared::replication::ReplicationMessageData;
+use crate::shared::stats::ConnectionStats;
 use crate::shared::tick_manager::Tick;
 use crate::shared::tick_manager::TickManager;
 use crate::shared::time_manager::TimeManager;
@@ -52,13 +63,18 @@ pub struct ConnectionManager<P: Protocol> {
     /// Stores the last `Replicate` component for each replicated entity owned by the current world (the world that sends replication updates)
     /// Needed to know the value of the Replicate component after the entity gets despawned, to know how we replicate the EntityDespawn
     replicate_component_cache: EntityHashMap<Entity, Replicate<P>>,
+    /// What happens to the client entities when `Replicate` is removed from a server entity
+    replicate_remove_behaviour: ReplicateRemoveBehaviour,
 
     // list of clients that connected since the last time we sent replication messages
     // (we want to keep track of them because we need to replicate the entire world state to them)
     pub(crate) new_clients: Vec<ClientId>,
+    /// Clients that are receiving the world state in chunks, if the initial sync is enabled
+    pub(crate) initial_sync: Option<InitialSyncSender>,
 
     packet_config: PacketConfig,
     ping_config: PingConfig,
+    registered_messages: RegisteredMessageIds,
 }
 
 impl<P: Protocol> ConnectionManager<P> {
@@ -66,24 +82,43 @@ impl<P: Protocol> ConnectionManager<P> {
         channel_registry: ChannelRegistry,
         packet_config: PacketConfig,
         ping_config: PingConfig,
+        replicate_remove_behaviour: ReplicateRemoveBehaviour,
+        initial_sync: Option<InitialSyncConfig>,
+        registered_messages: RegisteredMessageIds,
     ) -> Self {
         Self {
             connections: HashMap::default(),
             channel_registry,
             events: ServerEvents::new(),
             replicate_component_cache: EntityHashMap::default(),
+            replicate_remove_behaviour,
             new_clients: vec![],
+            initial_sync: initial_sync.map(InitialSyncSender::new),
             packet_config,
             ping_config,
+            registered_messages,
         }
     }
 
-    /// Find the list of clients that should receive the replication message
+    /// Find the list of clients that should receive the replication message for an entity
+    ///
+    /// The clients that are still waiting to receive the entity as part of their initial sync are excluded.
     pub(crate) fn apply_replication(
-        &mut self,
+        &self,
+        entity: Entity,
         target: NetworkTarget,
     ) -> Box<dyn Iterator<Item = ClientId>> {
-        let connected_clients = self.connections.keys().copied().collect::<Vec<_>>();
+        let pending_clients = self
+            .initial_sync
+            .as_ref()
+            .map(|initial_sync| initial_sync.pending_clients(entity))
+            .unwrap_or_default();
+        let connected_clients = self
+            .connections
+            .keys()
+            .copied()
+            .filter(|client_id| !pending_clients.contains(client_id))
+            .collect::<Vec<_>>();
         match target {
             NetworkTarget::All => {
                 // TODO: maybe only send stuff when the client is time-synced ?
@@ -124,6 +159,32 @@ impl<P: Protocol> ConnectionManager<P> {
             .context("client id not found")
     }
 
+    /// Get the network statistics of the connection with a given client
+    pub fn connection_stats(&self, client_id: ClientId) -> Option<ConnectionStats> {
+        self.connections
+            .get(&client_id)
+            .map(|connection| connection.stats())
+    }
+
+    /// Set a priority multiplier for a replication group, for a given client.
+    ///
+    /// The priority of the group (set with [`ReplicationGroup::set_priority`](crate::prelude::ReplicationGroup::set_priority))
+    /// is multiplied by this value when sending updates to this client. When the bandwidth is limited, this lets you
+    /// update the entities that are relevant for a client (for example the entities that are close to the client's camera)
+    /// more often than the others.
+    ///
+    /// Returns an error if the client is not connected, or if the multiplier is negative or not finite.
+    pub fn set_priority_multiplier(
+        &mut self,
+        client_id: ClientId,
+        group_id: ReplicationGroupId,
+        multiplier: f32,
+    ) -> Result<()> {
+        self.connection_mut(client_id)?
+            .replication_sender
+            .update_priority_multiplier(group_id, multiplier)
+    }
+
     pub(crate) fn connection_mut(&mut self, client_id: ClientId) -> Result<&mut Connection<P>> {
         self.connections
             .get_mut(&client_id)
@@ -144,9 +205,11 @@ impl<P: Protocol> ConnectionManager<P> {
 
             info!("New connection from id: {}", client_id);
             let connection = Connection::new(
+                client_id,
                 &self.channel_registry,
                 self.packet_config.clone(),
                 self.ping_config.clone(),
+                self.registered_messages.registry_hash,
             );
             self.events.push_connection(client_id);
             self.new_clients.push(client_id);
@@ -163,6 +226,9 @@ impl<P: Protocol> ConnectionManager<P> {
         info!("Client {} disconnected", client_id);
         self.events.push_disconnection(client_id);
         self.connections.remove(&client_id);
+        if let Some(initial_sync) = self.initial_sync.as_mut() {
+            initial_sync.remove_client(client_id);
+        }
     }
 
     /// Get the inputs for all clients for the given tick
@@ -245,6 +311,29 @@ impl<P: Protocol> ConnectionManager<P> {
         self.send_message_to_target::<C, M>(message, NetworkTarget::Only(vec![client_id]))
     }
 
+    /// Queues up a message registered with [`AppRegistrationExt::register_message`](crate::prelude::AppRegistrationExt::register_message)
+    /// to be sent to a list of clients
+    pub fn send_registered_message_to_target<C: Channel, M: Message>(
+        &mut self,
+        message: M,
+        target: NetworkTarget,
+    ) -> Result<()> {
+        let message = self.registered_messages.serialize(&message)?;
+        self.buffer_message(message.into(), ChannelKind::of::<C>(), target)
+    }
+
+    /// Queues up a registered message to be sent to a client
+    pub fn send_registered_message<C: Channel, M: Message>(
+        &mut self,
+        client_id: ClientId,
+        message: M,
+    ) -> Result<()> {
+        self.send_registered_message_to_target::<C, M>(
+            message,
+            NetworkTarget::Only(vec![client_id]),
+        )
+    }
+
     /// Buffer all the replication messages to send.
     /// Keep track of the bevy Change Tick: when a message is acked, we know that we only have to send
     /// the updates since that Change Tick
@@ -254,9 +343,16 @@ impl<P: Protocol> ConnectionManager<P> {
         bevy_tick: BevyTick,
     ) -> Result<()> {
         let _span = trace_span!("buffer_replication_messages").entered();
+        let initial_sync = &self.initial_sync;
         self.connections
-            .values_mut()
-            .try_for_each(move |c| c.buffer_replication_messages(tick, bevy_tick))
+            .iter_mut()
+            .try_for_each(move |(client_id, c)| {
+                // the messages sent during the initial sync of the client are compressed together
+                let compress = initial_sync
+                    .as_ref()
+                    .map_or(false, |initial_sync| initial_sync.has_batch(*client_id));
+                c.buffer_replication_messages(tick, bevy_tick, compress)
+            })
     }
 
     pub(crate) fn receive(
@@ -293,6 +389,12 @@ pub struct Connection<P: Protocol> {
     pub(crate) replication_sender: ReplicationSender<P>,
     pub(crate) replication_receiver: ReplicationReceiver<P>,
     pub(crate) events: ConnectionEvents<P>,
+    /// Biggest ack window that the client can negotiate
+    max_ack_bitfield_size: AckBitfieldSize,
+    /// Hash of the types registered at runtime, that the client must match
+    registry_hash: u64,
+    /// The client registered different types than us: it must be disconnected
+    pub(crate) registry_mismatch: bool,
 
     pub(crate) ping_manager: PingManager,
     /// Stores the inputs that we have received from the client.
@@ -308,12 +410,17 @@ pub struct Connection<P: Protocol> {
 
 impl<P: Protocol> Connection<P> {
     pub(crate) fn new(
+        client_id: ClientId,
         channel_registry: &ChannelRegistry,
         packet_config: PacketConfig,
         ping_config: PingConfig,
+        registry_hash: u64,
     ) -> Self {
         // create the message manager and the channels
-        let mut message_manager = MessageManager::new(channel_registry, packet_config.into());
+        let max_ack_bitfield_size = packet_config.ack_bitfield_size;
+        let decode_limits = packet_config.decode_limits;
+        let mut message_manager = MessageManager::new(channel_registry, packet_config.into())
+            .with_decode_limits(decode_limits);
         // get the acks-tracker for entity updates
         let update_acks_tracker = message_manager
             .channels
@@ -326,11 +433,15 @@ impl<P: Protocol> Connection<P> {
             message_manager.get_replication_update_send_receiver();
         let replication_sender =
             ReplicationSender::new(update_acks_tracker, replication_update_send_receiver);
-        let replication_receiver = ReplicationReceiver::new();
+        let replication_receiver = ReplicationReceiver::new(Authority::Client(client_id))
+            .with_decode_limits(decode_limits);
         Self {
             message_manager,
             replication_sender,
             replication_receiver,
+            max_ack_bitfield_size,
+            registry_hash,
+            registry_mismatch: false,
             ping_manager: PingManager::new(ping_config),
             input_buffer: InputBuffer::default(),
             last_input: None,
@@ -339,6 +450,11 @@ impl<P: Protocol> Connection<P> {
         }
     }
 
+    /// Get the network statistics of this connection
+    pub fn stats(&self) -> ConnectionStats {
+        self.message_manager.stats(&self.ping_manager)
+    }
+
     pub(crate) fn update(&mut self, time_manager: &TimeManager, tick_manager: &TickManager) {
         self.message_manager
             .update(time_manager, &self.ping_manager, tick_manager);
@@ -364,23 +480,68 @@ impl<P: Protocol> Connection<P> {
         Ok(())
     }
 
+    /// Send back to the client the corrections produced by the server validators
+    pub(crate) fn buffer_correction_messages(&mut self) -> Result<()> {
+        let channel = ChannelKind::of::<EntityActionsChannel>();
+        std::mem::take(&mut self.replication_receiver.corrections)
+            .into_iter()
+            .try_for_each(|correction| {
+                let message = ServerMessage::<P>::Correction(correction);
+                message.emit_send_logs("EntityActionsChannel");
+                self.message_manager.buffer_send(message, channel)?;
+                Ok(())
+            })
+    }
+
+    /// Notify the client of the number of entities that are part of its initial sync
+    pub(crate) fn buffer_initial_sync_message(&mut self, total: u32) -> Result<()> {
+        let message = ServerMessage::<P>::InitialSync(InitialSyncMessage { total });
+        let channel = ChannelKind::of::<EntityActionsChannel>();
+        message.emit_send_logs("EntityActionsChannel");
+        self.message_manager.buffer_send(message, channel)?;
+        Ok(())
+    }
+
+    /// Buffer the replication messages to send.
+    ///
+    /// If `compress_actions` is true, the entity actions messages are compressed and sent together in a single
+    /// [`InitialSyncBatchMessage`].
     pub(crate) fn buffer_replication_messages(
         &mut self,
         tick: Tick,
         bevy_tick: BevyTick,
+        compress_actions: bool,
     ) -> Result<()> {
+        let mut batch = vec![];
+        let mut batch_priority = 0.0_f32;
         self.replication_sender
             .finalize(tick)
             .into_iter()
             .try_for_each(|(channel, group_id, message_data, priority)| {
                 let should_track_ack = matches!(message_data, ReplicationMessageData::Updates(_));
+                if !should_track_ack {
+                    // the actions are sent reliably: all the changes until now will be received
+                    self.replication_sender
+                        .group_channels
+                        .entry(group_id)
+                        .or_default()
+                        .last_action_bevy_tick = Some(bevy_tick);
+                    if compress_actions {
+                        batch.push(ReplicationMessage {
+                            group_id,
+                            data: message_data,
+                        });
+                        batch_priority = batch_priority.max(priority);
+                        return Ok(());
+                    }
+                }
                 let channel_name = self
                     .message_manager
                     .channel_registry
                     .name(&channel)
                     .unwrap_or("unknown")
                     .to_string();
-                let message = ClientMessage::<P>::Replication(ReplicationMessage {
+                let message = ServerMessage::<P>::Replication(ReplicationMessage {
                     group_id,
                     data: message_data,
                 });
@@ -393,11 +554,21 @@ impl<P: Protocol> Connection<P> {
                 // keep track of the group associated with the message, so we can handle receiving an ACK for that message_id later
                 if should_track_ack {
                     self.replication_sender
-                        .updates_message_id_to_group_id
-                        .insert(message_id, (group_id, bevy_tick));
+                        .updates_message_buffered(message_id, group_id, bevy_tick);
                 }
-                Ok(())
-            })
+                Ok::<(), anyhow::Error>(())
+            })?;
+        if !batch.is_empty() {
+            let message =
+                ServerMessage::<P>::InitialSyncBatch(InitialSyncBatchMessage::compress(&batch)?);
+            message.emit_send_logs("EntityActionsChannel");
+            self.message_manager.buffer_send_with_priority(
+                message,
+                ChannelKind::of::<EntityActionsChannel>(),
+                batch_priority,
+            )?;
+        }
+        Ok(())
     }
 
     /// Send packets that are ready to be sent
@@ -470,6 +641,11 @@ impl<P: Protocol> Connection<P> {
                             );
                             // map any entities inside the message
                             message.map_entities(&mut self.replication_receiver.remote_entity_map);
+                            let message = map_registered_message_entities(
+                                world,
+                                message,
+                                &mut self.replication_receiver.remote_entity_map,
+                            );
                             if target != NetworkTarget::None {
                                 self.messages_to_rebroadcast.push((
                                     message.clone(),
@@ -485,7 +661,8 @@ impl<P: Protocol> Connection<P> {
                                     self.events.push_input_message(message);
                                 }
                                 InputMessageKind::Native => {
-                                    let input_message = message.try_into().unwrap();
+                                    let input_message: InputMessage<P::Input> =
+                                        message.try_into().unwrap();
                                     debug!("Received input message: {:?}", input_message.end_tick);
                                     self.input_buffer.update_from_message(input_message);
                                 }
@@ -499,6 +676,52 @@ impl<P: Protocol> Connection<P> {
                             // buffer the replication message
                             self.replication_receiver.recv_message(replication, tick);
                         }
+                        ClientMessage::Handshake(HandshakeMessage::Request {
+                            ack_bitfield_size,
+                        }) => {
+                            let ack_bitfield_size =
+                                ack_bitfield_size.min(self.max_ack_bitfield_size);
+                            debug!(
+                                ?ack_bitfield_size,
+                                "Negotiated the ack window with the client"
+                            );
+                            let first_packet_id = self
+                                .message_manager
+                                .set_send_ack_bitfield_size(ack_bitfield_size);
+                            let message =
+                                ServerMessage::<P>::Handshake(HandshakeMessage::Response {
+                                    ack_bitfield_size,
+                                    first_packet_id,
+                                });
+                            message.emit_send_logs("EntityActionsChannel");
+                            if let Err(e) = self
+                                .message_manager
+                                .buffer_send(message, ChannelKind::of::<EntityActionsChannel>())
+                            {
+                                error!("could not buffer the handshake message: {:?}", e);
+                            }
+                        }
+                        ClientMessage::Handshake(HandshakeMessage::Confirm { first_packet_id }) => {
+                            let ack_bitfield_size = self.message_manager.ack_bitfield_size();
+                            self.message_manager
+                                .set_recv_ack_bitfield_size(ack_bitfield_size, first_packet_id);
+                        }
+                        ClientMessage::Handshake(HandshakeMessage::Registry { hash }) => {
+                            if hash != self.registry_hash {
+                                error!(
+                                    client_hash = ?hash,
+                                    server_hash = ?self.registry_hash,
+                                    "The client did not register the same components and messages as the server"
+                                );
+                                self.registry_mismatch = true;
+                            }
+                        }
+                        ClientMessage::Handshake(message) => {
+                            warn!(
+                                ?message,
+                                "Received unexpected handshake message from the client"
+                            );
+                        }
                         ClientMessage::Sync(ref sync) => {
                             match sync {
                                 SyncMessage::Ping(ping) => {
@@ -538,6 +761,10 @@ impl<P: Protocol> Connection<P> {
                 });
         }
 
+        if let Err(e) = self.buffer_correction_messages() {
+            error!("Error buffering correction messages: {:?}", e);
+        }
+
         // TODO: do i really need this? I could just create events in this function directly?
         //  why do i need to make events a field of the connection?
         //  is it because of push_connection?
@@ -556,6 +783,7 @@ impl<P: Protocol> Connection<P> {
 
 impl<P: Protocol> ReplicationSend<P> for ConnectionManager<P> {
     type SetMarker = ServerMarker;
+    const DIRECTION: ChannelDirection = ChannelDirection::ServerToClient;
 
     fn update_priority(
         &mut self,
@@ -578,6 +806,29 @@ impl<P: Protocol> ReplicationSend<P> for ConnectionManager<P> {
         self.new_clients.clone()
     }
 
+    fn remote_entity(&self, local_entity: Entity) -> Option<Entity> {
+        self.connections.values().find_map(|connection| {
+            connection
+                .replication_receiver
+                .remote_entity_map
+                .get_remote(local_entity)
+                .copied()
+        })
+    }
+
+    fn full_sync_clients(&self, entity: Entity) -> Vec<ClientId> {
+        match &self.initial_sync {
+            Some(initial_sync) => initial_sync.batch_clients(entity),
+            None => self.new_clients.clone(),
+        }
+    }
+
+    fn initial_sync_clients(&self, entity: Entity) -> Vec<ClientId> {
+        self.initial_sync
+            .as_ref()
+            .map_or(vec![], |initial_sync| initial_sync.batch_clients(entity))
+    }
+
     fn prepare_entity_spawn(
         &mut self,
         entity: Entity,
@@ -588,41 +839,48 @@ impl<P: Protocol> ReplicationSend<P> for ConnectionManager<P> {
         trace!(?entity, "Prepare entity spawn to client");
         let group_id = replicate.replication_group.group_id(Some(entity));
         // TODO: should we have additional state tracking so that we know we are in the process of sending this entity to clients?
-        self.apply_replication(target).try_for_each(|client_id| {
-            // trace!(
-            //     ?client_id,
-            //     ?entity,
-            //     "Send entity spawn for tick {:?}",
-            //     self.tick_manager.tick()
-            // );
-            let replication_sender = &mut self.connection_mut(client_id)?.replication_sender;
-            // update the collect changes tick
-            // replication_sender
-            //     .group_channels
-            //     .entry(group)
-            //     .or_default()
-            //     .update_collect_changes_since_this_tick(system_current_tick);
-            replication_sender.prepare_entity_spawn(entity, group_id);
-            // if we need to do prediction/interpolation, send a marker component to indicate that to the client
-            if replicate.prediction_target.should_send_to(&client_id) {
-                replication_sender.prepare_component_insert(
-                    entity,
-                    group_id,
-                    P::Components::from(ShouldBePredicted),
-                );
-            }
-            if replicate.interpolation_target.should_send_to(&client_id) {
-                replication_sender.prepare_component_insert(
-                    entity,
-                    group_id,
-                    P::Components::from(ShouldBeInterpolated),
-                );
-            }
-            // also set the priority for the group when we spawn it
-            self.update_priority(group_id, client_id, replicate.replication_group.priority())?;
+        self.apply_replication(entity, target)
+            .try_for_each(|client_id| {
+                // trace!(
+                //     ?client_id,
+                //     ?entity,
+                //     "Send entity spawn for tick {:?}",
+                //     self.tick_manager.tick()
+                // );
+                let initial_sync = self.initial_sync.as_ref().map_or(false, |initial_sync| {
+                    initial_sync.is_in_batch(client_id, entity)
+                });
+                let replication_sender = &mut self.connection_mut(client_id)?.replication_sender;
+                // update the collect changes tick
+                // replication_sender
+                //     .group_channels
+                //     .entry(group)
+                //     .or_default()
+                //     .update_collect_changes_since_this_tick(system_current_tick);
+                replication_sender.prepare_entity_spawn(entity, group_id);
+                if initial_sync {
+                    replication_sender.prepare_entity_initial_sync(entity, group_id);
+                }
+                // if we need to do prediction/interpolation, send a marker component to indicate that to the client
+                if replicate.prediction_target.should_send_to(&client_id) {
+                    replication_sender.prepare_component_insert(
+                        entity,
+                        group_id,
+                        P::Components::from(ShouldBePredicted),
+                    );
+                }
+                if replicate.interpolation_target.should_send_to(&client_id) {
+                    replication_sender.prepare_component_insert(
+                        entity,
+                        group_id,
+                        P::Components::from(ShouldBeInterpolated),
+                    );
+                }
+                // also set the priority for the group when we spawn it
+                self.update_priority(group_id, client_id, replicate.replication_group.priority())?;
 
-            Ok(())
-        })
+                Ok(())
+            })
     }
 
     fn prepare_entity_despawn(
@@ -633,23 +891,94 @@ impl<P: Protocol> ReplicationSend<P> for ConnectionManager<P> {
         system_current_tick: BevyTick,
     ) -> Result<()> {
         let group_id = replicate.replication_group.group_id(Some(entity));
-        self.apply_replication(target).try_for_each(|client_id| {
-            // trace!(
-            //     ?entity,
-            //     ?client_id,
-            //     "Send entity despawn for tick {:?}",
-            //     self.tick_manager.tick()
-            // );
-            let replication_sender = &mut self.connection_mut(client_id)?.replication_sender;
-            // update the collect changes tick
-            // replication_sender
-            //     .group_channels
-            //     .entry(group)
-            //     .or_default()
-            //     .update_collect_changes_since_this_tick(system_current_tick);
-            replication_sender.prepare_entity_despawn(entity, group_id);
-            Ok(())
-        })
+        self.apply_replication(entity, target)
+            .try_for_each(|client_id| {
+                // trace!(
+                //     ?entity,
+                //     ?client_id,
+                //     "Send entity despawn for tick {:?}",
+                //     self.tick_manager.tick()
+                // );
+                let replication_sender = &mut self.connection_mut(client_id)?.replication_sender;
+                // update the collect changes tick
+                // replication_sender
+                //     .group_channels
+                //     .entry(group)
+                //     .or_default()
+                //     .update_collect_changes_since_this_tick(system_current_tick);
+                replication_sender.prepare_entity_despawn(entity, group_id);
+                Ok(())
+            })
+    }
+
+    fn prepare_entity_lost_visibility(
+        &mut self,
+        entity: Entity,
+        replicate: &Replicate<P>,
+        target: NetworkTarget,
+        system_current_tick: BevyTick,
+    ) -> Result<()> {
+        let group_id = replicate.replication_group.group_id(Some(entity));
+        self.apply_replication(entity, target)
+            .try_for_each(|client_id| {
+                self.connection_mut(client_id)?
+                    .replication_sender
+                    .prepare_entity_lost_visibility(entity, group_id);
+                Ok(())
+            })
+    }
+
+    fn prepare_entity_detach(
+        &mut self,
+        entity: Entity,
+        replicate: &Replicate<P>,
+        target: NetworkTarget,
+        system_current_tick: BevyTick,
+    ) -> Result<()> {
+        let group_id = replicate.replication_group.group_id(Some(entity));
+        self.apply_replication(entity, target)
+            .try_for_each(|client_id| {
+                self.connection_mut(client_id)?
+                    .replication_sender
+                    .prepare_entity_detach(entity, group_id);
+                Ok(())
+            })
+    }
+
+    fn changes_delivered(
+        &self,
+        entity: Entity,
+        replicate: &Replicate<P>,
+        change_tick: BevyTick,
+        current_tick: BevyTick,
+    ) -> bool {
+        let group_id = replicate.replication_group.group_id(Some(entity));
+        self.apply_replication(entity, replicate.replicated_to())
+            .all(|client_id| {
+                self.connections.get(&client_id).map_or(true, |connection| {
+                    connection
+                        .replication_sender
+                        .is_delivered(group_id, change_tick, current_tick)
+                })
+            })
+    }
+
+    fn prepare_entity_dormancy(
+        &mut self,
+        entity: Entity,
+        replicate: &Replicate<P>,
+        target: NetworkTarget,
+        dormant: bool,
+        system_current_tick: BevyTick,
+    ) -> Result<()> {
+        let group_id = replicate.replication_group.group_id(Some(entity));
+        self.apply_replication(entity, target)
+            .try_for_each(|client_id| {
+                self.connection_mut(client_id)?
+                    .replication_sender
+                    .prepare_entity_dormancy(entity, group_id, dormant);
+                Ok(())
+            })
     }
 
     // TODO: perf gain if we batch this? (send vec of components) (same for update/removes)
@@ -686,7 +1015,7 @@ impl<P: Protocol> ReplicationSend<P> for ConnectionManager<P> {
             actual_target = replicate.prediction_target.clone();
         }
 
-        self.apply_replication(actual_target)
+        self.apply_replication(entity, actual_target)
             .try_for_each(|client_id| {
                 // trace!(
                 //     ?entity,
@@ -716,23 +1045,24 @@ impl<P: Protocol> ReplicationSend<P> for ConnectionManager<P> {
     ) -> Result<()> {
         let group_id = replicate.replication_group.group_id(Some(entity));
         debug!(?entity, ?component_kind, "Sending RemoveComponent");
-        self.apply_replication(target).try_for_each(|client_id| {
-            let replication_sender = &mut self.connection_mut(client_id)?.replication_sender;
-            // TODO: I don't think it's actually correct to only correct the changes since that action.
-            // what if we do:
-            // - Frame 1: update is ACKED
-            // - Frame 2: update
-            // - Frame 3: action
-            // - Frame 4: send
-            // then we won't send the frame-2 update because we only collect changes since frame 3
-            // replication_sender
-            //     .group_channels
-            //     .entry(group)
-            //     .or_default()
-            //     .update_collect_changes_since_this_tick(system_current_tick);
-            replication_sender.prepare_component_remove(entity, group_id, component_kind);
-            Ok(())
-        })
+        self.apply_replication(entity, target)
+            .try_for_each(|client_id| {
+                let replication_sender = &mut self.connection_mut(client_id)?.replication_sender;
+                // TODO: I don't think it's actually correct to only correct the changes since that action.
+                // what if we do:
+                // - Frame 1: update is ACKED
+                // - Frame 2: update
+                // - Frame 3: action
+                // - Frame 4: send
+                // then we won't send the frame-2 update because we only collect changes since frame 3
+                // replication_sender
+                //     .group_channels
+                //     .entry(group)
+                //     .or_default()
+                //     .update_collect_changes_since_this_tick(system_current_tick);
+                replication_sender.prepare_component_remove(entity, group_id, component_kind);
+                Ok(())
+            })
     }
 
     fn prepare_component_update(
@@ -754,7 +1084,7 @@ impl<P: Protocol> ReplicationSend<P> for ConnectionManager<P> {
         );
 
         let group_id = replicate.group_id(Some(entity));
-        self.apply_replication(target).try_for_each(|client_id| {
+        self.apply_replication(entity, target).try_for_each(|client_id| {
             // TODO: should we have additional state tracking so that we know we are in the process of sending this entity to clients?
             let replication_sender = &mut self.connection_mut(client_id)?.replication_sender;
             let collect_changes_since_this_tick = replication_sender
@@ -802,6 +1132,10 @@ impl<P: Protocol> ReplicationSend<P> for ConnectionManager<P> {
         &mut self.replicate_component_cache
     }
 
+    fn replicate_remove_behaviour(&self) -> ReplicateRemoveBehaviour {
+        self.replicate_remove_behaviour
+    }
+
     fn cleanup(&mut self, tick: Tick) {
         debug!("Running replication clean");
         for connection in self.connections.values_mut() {
@@ -0,0 +1,50 @@
+use bevy::app::{App, Plugin, PostUpdate};
+use bevy::prelude::{IntoSystemConfigs, Res, ResMut, Resource};
+use bevy::utils::HashMap;
+
+use crate::connection::id::ClientId;
+use crate::prelude::{MainSet, Protocol};
+use crate::server::connection::ConnectionManager;
+use crate::server::networking::is_started;
+use crate::shared::stats::ConnectionStats;
+
+/// Network statistics of the connection with each client.
+///
+/// The resource is updated every frame after the packets are sent.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct NetworkStats(pub HashMap<ClientId, ConnectionStats>);
+
+pub struct ServerDiagnosticsPlugin<P> {
+    _marker: std::marker::PhantomData<P>,
+}
+
+impl<P> Default for ServerDiagnosticsPlugin<P> {
+    fn default() -> Self {
+        Self {
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+fn network_stats_system<P: Protocol>(
+    connection_manager: Res<ConnectionManager<P>>,
+    mut network_stats: ResMut<NetworkStats>,
+) {
+    network_stats.0 = connection_manager
+        .connections
+        .iter()
+        .map(|(client_id, connection)| (*client_id, connection.stats()))
+        .collect();
+}
+
+impl<P: Protocol> Plugin for ServerDiagnosticsPlugin<P> {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetworkStats>();
+        app.add_systems(
+            PostUpdate,
+            network_stats_system::<P>
+                .after(MainSet::SendPackets)
+                .run_if(is_started),
+        );
+    }
+}
@@ -2,16 +2,18 @@
 use bevy::ecs::entity::EntityHash;
 use bevy::prelude::*;
 use bevy::utils::HashMap;
-use tracing::trace;
+use tracing::{error, trace};
 
 use crate::_reexport::{
     FromType, IterComponentInsertEvent, IterComponentRemoveEvent, IterComponentUpdateEvent,
     ServerMarker,
 };
+use crate::channel::builder::ChannelDirection;
 use crate::connection::id::ClientId;
 #[cfg(feature = "leafwing")]
 use crate::inputs::leafwing::{InputMessage, LeafwingUserAction};
 use crate::packet::message::Message;
+use crate::prelude::NetworkTarget;
 use crate::protocol::Protocol;
 use crate::server::connection::ConnectionManager;
 use crate::server::networking::{clear_events, is_started};
@@ -20,8 +22,10 @@ use crate::shared::events::connection::IterInputMessageEvent;
 use crate::shared::events::connection::{
     ConnectionEvents, IterEntityDespawnEvent, IterEntitySpawnEvent, IterMessageEvent,
 };
+use crate::shared::events::network::{clear_network_event_queue, NetworkEventQueue};
 use crate::shared::events::plugin::EventsPlugin;
-use crate::shared::sets::InternalMainSet;
+use crate::shared::replication::components::Replicate;
+use crate::shared::sets::{InternalMainSet, NetworkEventSet};
 
 type EntityHashMap<K, V> = hashbrown::HashMap<K, V, EntityHash>;
 
@@ -43,8 +47,57 @@ impl<P: Protocol> Plugin for ServerEventsPlugin<P> {
         app
             // PLUGIN
             .add_plugins(EventsPlugin::<P, ClientId>::default())
+            // RESOURCES
+            .init_resource::<NetworkEventQueue>()
             // SYSTEM_SET
-            .add_systems(PostUpdate, clear_events::<P>.run_if(is_started));
+            .add_systems(PostUpdate, clear_events::<P>.run_if(is_started))
+            .add_systems(
+                PostUpdate,
+                (
+                    send_network_events::<P>
+                        .in_set(InternalMainSet::<ServerMarker>::Send)
+                        .before(InternalMainSet::<ServerMarker>::SendPackets),
+                    clear_network_event_queue
+                        .after(NetworkEventSet::Queue)
+                        .run_if(not(is_started)),
+                ),
+            );
+    }
+}
+
+/// Send the network events that were written on the server to the clients
+pub(crate) fn send_network_events<P: Protocol>(
+    mut queue: ResMut<NetworkEventQueue>,
+    mut connection_manager: ResMut<ConnectionManager<P>>,
+    query: Query<&Replicate<P>>,
+) {
+    for event in queue.0.drain(..) {
+        if !event.can_send(&ChannelDirection::ServerToClient) {
+            continue;
+        }
+        let target = match event.entity {
+            None => event.target,
+            Some(entity) => {
+                // only send the event to the clients that the entity is replicated to
+                let Ok(replicate) = query.get(entity) else {
+                    trace!(
+                        ?entity,
+                        "dropping network event for an entity that is not replicated"
+                    );
+                    continue;
+                };
+                let mut target = replicate.replicated_to();
+                target.intersection(event.target);
+                NetworkTarget::Only(
+                    connection_manager
+                        .apply_replication(entity, target)
+                        .collect(),
+                )
+            }
+        };
+        let _ = connection_manager
+            .buffer_message(event.message.into(), event.channel, target)
+            .map_err(|e| error!("error sending network event: {:?}", e));
     }
 }
 
@@ -214,80 +267,65 @@ impl<P: Protocol> IterEntityDespawnEvent<ClientId> for ServerEvents<P> {
 }
 
 impl<P: Protocol> IterComponentUpdateEvent<P, ClientId> for ServerEvents<P> {
-    fn iter_component_update<C: Component>(
+    fn iter_component_update_by_kind(
         &mut self,
-    ) -> Box<dyn Iterator<Item = (Entity, ClientId)> + '_>
-    where
-        P::ComponentKinds: FromType<C>,
-    {
-        Box::new(self.events.iter_mut().flat_map(|(client_id, events)| {
+        kind: P::ComponentKinds,
+    ) -> Box<dyn Iterator<Item = (Entity, ClientId)> + '_> {
+        Box::new(self.events.iter_mut().flat_map(move |(client_id, events)| {
             let updates = events
-                .iter_component_update::<C>()
+                .iter_component_update_by_kind(kind)
                 .map(|(entity, _)| entity);
             let client_ids = std::iter::once(*client_id).cycle();
             updates.zip(client_ids)
         }))
     }
 
-    fn has_component_update<C: Component>(&self) -> bool
-    where
-        P::ComponentKinds: FromType<C>,
-    {
+    fn has_component_update_by_kind(&self, kind: P::ComponentKinds) -> bool {
         self.events
             .iter()
-            .any(|(_, connection_events)| connection_events.has_component_update::<C>())
+            .any(|(_, connection_events)| connection_events.has_component_update_by_kind(kind))
     }
 }
 
 impl<P: Protocol> IterComponentRemoveEvent<P, ClientId> for ServerEvents<P> {
-    fn iter_component_remove<C: Component>(
+    fn iter_component_remove_by_kind(
         &mut self,
-    ) -> Box<dyn Iterator<Item = (Entity, ClientId)> + '_>
-    where
-        P::ComponentKinds: FromType<C>,
-    {
-        Box::new(self.events.iter_mut().flat_map(|(client_id, events)| {
+        kind: P::ComponentKinds,
+    ) -> Box<dyn Iterator<Item = (Entity, ClientId)> + '_> {
+        Box::new(self.events.iter_mut().flat_map(move |(client_id, events)| {
             let updates = events
-                .iter_component_remove::<C>()
+                .iter_component_remove_by_kind(kind)
                 .map(|(entity, _)| entity);
             let client_ids = std::iter::once(*client_id).cycle();
             updates.zip(client_ids)
         }))
     }
 
-    fn has_component_remove<C: Component>(&self) -> bool
-    where
-        P::ComponentKinds: FromType<C>,
-    {
+    fn has_component_remove_by_kind(&self, kind: P::ComponentKinds) -> bool {
         self.events
             .iter()
-            .any(|(_, connection_events)| connection_events.has_component_remove::<C>())
+            .any(|(_, connection_events)| connection_events.has_component_remove_by_kind(kind))
     }
 }
 
 impl<P: Protocol> IterComponentInsertEvent<P, ClientId> for ServerEvents<P> {
-    fn iter_component_insert<C: Component>(
+    fn iter_component_insert_by_kind(
         &mut self,
-    ) -> Box<dyn Iterator<Item = (Entity, ClientId)> + '_>
-    where
-        P::ComponentKinds: FromType<C>,
-    {
-        Box::new(self.events.iter_mut().flat_map(|(client_id, events)| {
+        kind: P::ComponentKinds,
+    ) -> Box<dyn Iterator<Item = (Entity, ClientId)> + '_> {
+        Box::new(self.events.iter_mut().flat_map(move |(client_id, events)| {
             let updates = events
-                .iter_component_insert::<C>()
+                .iter_component_insert_by_kind(kind)
                 .map(|(entity, _)| entity);
             let client_ids = std::iter::once(*client_id).cycle();
             updates.zip(client_ids)
         }))
     }
 
-    fn has_component_insert<C: Component>(&self) -> bool
-    where
-        P::ComponentKinds: FromType<C>,
-    {
+    fn has_component_insert_by_kind(&self, kind: P::ComponentKinds) -> bool {
         self.events
             .iter()
-            .any(|(_, connection_events)| connection_events.has_component_insert::<C>())
+            .any(|(_, connection_events)| connection_events.has_component_insert_by_kind(kind))
     }
 }
 
@@ -6,7 +6,10 @@ use bitcode::{Decode, Encode};
 
 use crate::_reexport::{BitSerializable, MessageProtocol, ReadBuffer, WriteBuffer};
 use crate::prelude::Protocol;
+use crate::server::validation::CorrectionMessage;
+use crate::shared::handshake::HandshakeMessage;
 use crate::shared::ping::message::SyncMessage;
+use crate::shared::replication::initial_sync::{InitialSyncBatchMessage, InitialSyncMessage};
 use crate::shared::replication::{ReplicationMessage, ReplicationMessageData};
 
 #[derive(Encode, Decode, Clone, Debug)]
@@ -21,6 +24,20 @@ pub enum ServerMessage<P: Protocol> {
     // the sync messages can be added to packets that have other messages
     #[bitcode_hint(frequency = 1)]
     Sync(SyncMessage),
+    /// Number of entities that are part of the initial sync of the client
+    #[bitcode_hint(frequency = 1)]
+    InitialSync(InitialSyncMessage),
+    /// Compressed replication messages sent during the initial sync of the client
+    #[bitcode_hint(frequency = 1)]
+    InitialSyncBatch(InitialSyncBatchMessage),
+    /// Value of a component that was rejected by a server validator
+    #[bitcode_hint(frequency = 1)]
+    #[bitcode(with_serde)]
+    Correction(CorrectionMessage<P::Components>),
+    /// Negotiation of the connection settings
+    #[bitcode_hint(frequency = 1)]
+    #[bitcode(with_serde)]
+    Handshake(HandshakeMessage),
 }
 
 impl<P: Protocol> BitSerializable for ServerMessage<P> {
@@ -122,6 +139,19 @@ impl<P: Protocol> ServerMessage<P> {
                     }
                 }
             }
+            ServerMessage::InitialSync(message) => {
+                trace!(channel = ?channel_name, total = message.total, "Sending initial sync");
+            }
+            ServerMessage::InitialSyncBatch(message) => {
+                trace!(channel = ?channel_name, size = message.bytes.len(), "Sending initial sync batch");
+            }
+            ServerMessage::Correction(message) => {
+                let kind: P::ComponentKinds = (&message.component).into();
+                trace!(channel = ?channel_name, entity = ?message.entity, ?kind, "Sending correction");
+            }
+            ServerMessage::Handshake(message) => {
+                trace!(channel = ?channel_name, ?message, "Sending handshake");
+            }
             ServerMessage::Sync(message) => match message {
                 SyncMessage::Ping(_) => {
                     trace!(channel = ?channel_name, "Sending ping");
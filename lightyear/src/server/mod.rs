@@ -7,14 +7,24 @@ pub mod config;
 
 pub mod connection;
 
+pub mod diagnostics;
+
 pub mod events;
 
 mod input;
 
+pub mod persistence;
+
 pub mod plugin;
 
 pub mod room;
 
+pub mod spatial;
+
+pub mod validation;
+
+pub mod visibility;
+
 #[cfg_attr(docsrs, doc(cfg(feature = "leafwing")))]
 #[cfg(feature = "leafwing")]
 pub mod input_leafwing;
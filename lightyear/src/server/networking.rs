@@ -11,6 +11,7 @@ use crate::connection::client::{ClientConnection, NetClient};
 use crate::connection::server::{NetConfig, NetServer, ServerConnection, ServerConnections};
 use crate::prelude::{MainSet, Mode, TickManager, TimeManager};
 use crate::protocol::message::MessageProtocol;
+use crate::protocol::registration::ProtocolRegistry;
 use crate::protocol::Protocol;
 use crate::server::config::ServerConfig;
 use crate::server::connection::ConnectionManager;
@@ -116,6 +117,15 @@ pub(crate) fn receive<P: Protocol>(world: &mut World) {
                                                 let _ = netserver
                                                     .try_update(delta.as_secs_f64())
                                                     .map_err(|e| error!("Error updating netcode server: {:?}", e));
+                                                // disconnect the clients that did not register the same types as the server
+                                                for (client_id, connection) in connection_manager.connections.iter_mut() {
+                                                    if netservers.client_server_map.get(client_id) == Some(&server_idx)
+                                                        && std::mem::take(&mut connection.registry_mismatch) {
+                                                        netserver.disconnect(*client_id).unwrap_or_else(|e| {
+                                                            error!(?client_id, "Error disconnecting client: {:?}", e);
+                                                        });
+                                                    }
+                                                }
                                                 for client_id in netserver.new_connections().iter().copied() {
                                                     netservers.client_server_map.insert(client_id, server_idx);
                                                     connection_manager.add(client_id);
@@ -143,7 +153,19 @@ pub(crate) fn receive<P: Protocol>(world: &mut World) {
                                                     // TODO: use connection to apply on BOTH message manager and replication manager
                                                     if let Ok(connection) = connection_manager
                                                         .connection_mut(client_id) {
-                                                        connection.recv_packet(packet, tick_manager.as_ref()).expect("could not receive packet");
+                                                        // the packet could not be processed (for example because it exceeds the `DecodeLimits`):
+                                                        // we cannot trust this client anymore, disconnect it
+                                                        if let Err(e) = connection.recv_packet(packet, tick_manager.as_ref()) {
+                                                            error!(?client_id, "Could not receive packet: {:?}. Disconnecting client", e);
+                                                            netserver.disconnect(client_id).unwrap_or_else(|e| {
+                                                                error!(?client_id, "Error disconnecting client: {:?}", e);
+                                                            });
+                                                            // the new disconnections are reset at the next update, so we remove the connection right away
+                                                            if netservers.client_server_map.remove(&client_id).is_some() {
+                                                                connection_manager.remove(client_id);
+                                                                room_manager.client_disconnect(client_id);
+                                                            }
+                                                        }
                                                     } else {
                                                         // it's still possible to receive some packets from a client that just disconnected.
                                                         // (multiple packets arrived at the same time from that client)
@@ -270,6 +292,9 @@ pub(crate) fn send<P: Protocol>(
     // clear the list of newly connected clients
     // (cannot just use the ConnectionEvent because it is cleared after each frame)
     connection_manager.new_clients.clear();
+    if let Some(initial_sync) = connection_manager.initial_sync.as_mut() {
+        initial_sync.finish_send();
+    }
 }
 
 /// Clear the received events
@@ -321,6 +346,12 @@ fn rebuild_server_connections<P: Protocol>(world: &mut World) {
         world.resource::<P>().channel_registry().clone(),
         server_config.packet,
         server_config.ping,
+        server_config.replication.on_replicate_remove,
+        server_config.replication.initial_sync.clone(),
+        world
+            .get_resource::<ProtocolRegistry>()
+            .map(ProtocolRegistry::message_net_ids)
+            .unwrap_or_default(),
     );
     world.insert_resource(connection_manager);
 
@@ -0,0 +1,450 @@
+//! # Persistence
+//!
+//! Save the replicated entities of the server [`World`] to disk, and restore them after a restart.
+//!
+//! A [`WorldSnapshot`] contains every entity with a [`Replicate`] component: its components from the
+//! `ComponentProtocol` (including the components registered at runtime), its replication settings
+//! (targets, [`ReplicationGroup`](crate::prelude::ReplicationGroup), etc.) and the rooms it belongs to.
+//! The components are serialized with the same serialization as the protocol, and the snapshot includes the
+//! schema of the `ComponentProtocol` and the hash of the [`ProtocolRegistry`], so a save file cannot be loaded by a
+//! server with a different protocol or with different types registered at runtime.
+//!
+//! When the snapshot is restored, the entities are spawned again with new ids; the entities that are referenced
+//! inside the components are mapped to the new entities with [`MapEntities`](bevy::ecs::entity::MapEntities).
+//!
+//! ```rust,ignore
+//! // on shutdown
+//! WorldSnapshot::<MyProtocol>::from_world(&app.world).save("world.save")?;
+//!
+//! // on startup
+//! WorldSnapshot::<MyProtocol>::load("world.save")?.restore(&mut app.world)?;
+//! ```
+//!
+//! The replicated resources and the `per_component_metadata` of [`Replicate`] are not part of the snapshot.
+use std::num::NonZeroU16;
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use bevy::ecs::entity::{EntityHash, MapEntities};
+use bevy::hierarchy::{BuildWorldChildren, Parent};
+use bevy::prelude::{Entity, EntityMapper, EntityRef, World};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::prelude::{NetworkTarget, ParentSync, PrePredicted, ShouldBePredicted};
+use crate::protocol::component::{ComponentProtocol, FromType};
+use crate::protocol::registration::{ProtocolRegistry, RegisteredComponent};
+use crate::protocol::schema::ComponentSchema;
+use crate::protocol::Protocol;
+use crate::serialize::reader::ReadBuffer;
+use crate::serialize::wordbuffer::reader::ReadWordBuffer;
+use crate::serialize::wordbuffer::writer::WriteWordBuffer;
+use crate::serialize::writer::WriteBuffer;
+use crate::server::room::{RoomId, RoomManager};
+use crate::shared::replication::authority::AuthorityTransfer;
+use crate::shared::replication::components::{
+    Replicate, ReplicateRemoveBehaviour, ReplicationGroup, ReplicationMode, ShouldBeInterpolated,
+};
+use crate::shared::replication::entity_map::RemoteEntityMap;
+use crate::shared::replication::resources::ReplicateResourceEntity;
+
+type EntityHashMap<K, V> = hashbrown::HashMap<K, V, EntityHash>;
+
+/// The replication group of an entity
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum SavedReplicationGroup {
+    /// The group is restored as is
+    Group(ReplicationGroup),
+    /// The group id is the id of an ancestor of the entity (the root of a replicated hierarchy), so it is
+    /// mapped to the restored ancestor
+    FromAncestor { ancestor: Entity, priority: f32 },
+}
+
+impl SavedReplicationGroup {
+    fn new(entity: EntityRef, world: &World, group: ReplicationGroup) -> Self {
+        let group_id = group.group_id(Some(entity.id()));
+        let mut parent = entity.get::<Parent>();
+        while let Some(ancestor) = parent.map(Parent::get) {
+            if ancestor.to_bits() == group_id.0 {
+                return Self::FromAncestor {
+                    ancestor,
+                    priority: group.priority(),
+                };
+            }
+            parent = world.get::<Parent>(ancestor);
+        }
+        Self::Group(group)
+    }
+
+    fn into_group(self, entity_map: &mut RemoteEntityMap) -> ReplicationGroup {
+        match self {
+            Self::Group(group) => group,
+            Self::FromAncestor { ancestor, priority } => {
+                ReplicationGroup::new_id(entity_map.map_entity(ancestor).to_bits())
+                    .set_priority(priority)
+            }
+        }
+    }
+}
+
+/// The replication settings of an entity
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct SavedReplicate {
+    replication_target: NetworkTarget,
+    prediction_target: NetworkTarget,
+    interpolation_target: NetworkTarget,
+    replication_mode: ReplicationMode,
+    on_remove: Option<ReplicateRemoveBehaviour>,
+    dormancy: Option<NonZeroU16>,
+    replication_group: SavedReplicationGroup,
+    replicate_hierarchy: bool,
+}
+
+impl SavedReplicate {
+    fn new<P: Protocol>(replicate: &Replicate<P>, entity: EntityRef, world: &World) -> Self {
+        Self {
+            replication_target: replicate.replication_target.clone(),
+            prediction_target: replicate.prediction_target.clone(),
+            interpolation_target: replicate.interpolation_target.clone(),
+            replication_mode: replicate.replication_mode,
+            on_remove: replicate.on_remove,
+            dormancy: replicate.dormancy,
+            replication_group: SavedReplicationGroup::new(
+                entity,
+                world,
+                replicate.replication_group,
+            ),
+            replicate_hierarchy: replicate.replicate_hierarchy,
+        }
+    }
+
+    fn into_replicate<P: Protocol>(self, entity_map: &mut RemoteEntityMap) -> Replicate<P> {
+        Replicate {
+            replication_target: self.replication_target,
+            prediction_target: self.prediction_target,
+            interpolation_target: self.interpolation_target,
+            replication_mode: self.replication_mode,
+            on_remove: self.on_remove,
+            dormancy: self.dormancy,
+            replication_group: self.replication_group.into_group(entity_map),
+            replicate_hierarchy: self.replicate_hierarchy,
+            ..Default::default()
+        }
+    }
+}
+
+/// A replicated entity in a [`WorldSnapshot`]
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+struct SavedEntity<P: Protocol> {
+    /// The id of the entity when the snapshot was taken
+    entity: Entity,
+    /// The replicated parent of the entity (from [`ParentSync`]) when the snapshot was taken
+    parent: Option<Entity>,
+    replicate: SavedReplicate,
+    rooms: Vec<RoomId>,
+    components: Vec<P::Components>,
+}
+
+/// A snapshot of all the replicated entities of the server [`World`], that can be saved to disk
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct WorldSnapshot<P: Protocol> {
+    /// Schema of the `ComponentProtocol` that was used to create the snapshot
+    schema: Vec<ComponentSchema>,
+    /// Hash of the types registered at runtime (see [`ProtocolRegistry::hash`]), because the net ids of the
+    /// registered components depend on all the registered types
+    registry_hash: u64,
+    entities: Vec<SavedEntity<P>>,
+}
+
+impl<P: Protocol> WorldSnapshot<P> {
+    /// Take a snapshot of all the entities of the world that have a [`Replicate`] component
+    pub fn from_world(world: &World) -> Self {
+        let room_manager = world.get_resource::<RoomManager>();
+        let registry = world.get_resource::<ProtocolRegistry>();
+        let entities = world
+            .iter_entities()
+            .filter(|entity| !entity.contains::<ReplicateResourceEntity>())
+            .filter_map(|entity| {
+                let replicate = entity.get::<Replicate<P>>()?;
+                let mut components = P::Components::entity_components(&entity);
+                components.retain(|component| !Self::is_internal(component.into()));
+                for registration in registry.iter().flat_map(|registry| registry.components()) {
+                    if !entity.contains_id(registration.component_id) {
+                        continue;
+                    }
+                    match RegisteredComponent::from_entity(registration, world, &entity) {
+                        Ok(component) => components.push(component.into()),
+                        Err(e) => error!("error saving registered component: {:?}", e),
+                    }
+                }
+                Some(SavedEntity {
+                    entity: entity.id(),
+                    parent: entity.get::<ParentSync>().and_then(ParentSync::parent),
+                    replicate: SavedReplicate::new(replicate, entity, world),
+                    rooms: room_manager
+                        .map(|room_manager| room_manager.entity_rooms(entity.id()).collect())
+                        .unwrap_or_default(),
+                    components,
+                })
+            })
+            .collect();
+        Self {
+            schema: P::Components::schema(),
+            registry_hash: registry.map_or(0, ProtocolRegistry::hash),
+            entities,
+        }
+    }
+
+    /// Number of entities in the snapshot
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Serialize the snapshot
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut writer = WriteWordBuffer::with_capacity(1024);
+        writer.serialize(self)?;
+        Ok(writer.finish_write().to_vec())
+    }
+
+    /// Deserialize a snapshot. Fails if the snapshot was created with a different `ComponentProtocol`
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = ReadWordBuffer::start_read(bytes);
+        let snapshot: Self = reader.deserialize()?;
+        if snapshot.schema != P::Components::schema() {
+            bail!("the snapshot was created with a different ComponentProtocol");
+        }
+        Ok(snapshot)
+    }
+
+    /// Write the snapshot to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes()?)
+            .with_context(|| format!("could not write the snapshot to {}", path.display()))
+    }
+
+    /// Read a snapshot from a file
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("could not read the snapshot from {}", path.display()))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Spawn the entities of the snapshot in the world, and start replicating them.
+    ///
+    /// Returns the map from the entities of the snapshot to the new entities.
+    /// Fails if the types registered at runtime in the world are not the same as when the snapshot was created.
+    pub fn restore(self, world: &mut World) -> anyhow::Result<EntityHashMap<Entity, Entity>> {
+        let registry_hash = world
+            .get_resource::<ProtocolRegistry>()
+            .map_or(0, ProtocolRegistry::hash);
+        if self.registry_hash != registry_hash {
+            bail!(
+                "the snapshot was created with different types registered in the ProtocolRegistry"
+            );
+        }
+        // spawn all the entities first, so that the entities referenced in the components can be mapped
+        let mut entity_map = RemoteEntityMap::default();
+        for saved in &self.entities {
+            entity_map.insert(saved.entity, world.spawn_empty().id());
+        }
+        for saved in self.entities {
+            let entity = entity_map.map_entity(saved.entity);
+            let mut entity_mut = world.entity_mut(entity);
+            for mut component in saved.components {
+                component.map_entities(&mut entity_map);
+                component.insert(&mut entity_mut);
+            }
+            // rebuild the hierarchy from the replicated parent
+            if let Some(saved_parent) = saved.parent {
+                match entity_map.get_local(saved_parent) {
+                    Some(parent) => {
+                        entity_mut.set_parent(*parent);
+                    }
+                    None => {
+                        warn!(
+                            ?saved_parent,
+                            "the parent of the entity is not part of the snapshot, the entity is restored without a parent"
+                        );
+                        entity_mut.insert(ParentSync::default());
+                    }
+                }
+            }
+            entity_mut.insert(saved.replicate.into_replicate::<P>(&mut entity_map));
+            if !saved.rooms.is_empty() {
+                let mut room_manager = world.get_resource_or_insert_with(RoomManager::default);
+                for room in saved.rooms {
+                    room_manager.add_entity(entity, room);
+                }
+            }
+        }
+        Ok(entity_map
+            .to_remote()
+            .iter()
+            .map(|(local, remote)| (*remote, *local))
+            .collect())
+    }
+
+    /// The markers that are added by the replication systems are not saved
+    fn is_internal(kind: P::ComponentKinds) -> bool {
+        kind == <P::ComponentKinds as FromType<ShouldBePredicted>>::from_type()
+            || kind == <P::ComponentKinds as FromType<ShouldBeInterpolated>>::from_type()
+            || kind == <P::ComponentKinds as FromType<PrePredicted>>::from_type()
+            || kind == <P::ComponentKinds as FromType<AuthorityTransfer>>::from_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::{default, App};
+
+    use crate::protocol::registration::AppRegistrationExt;
+
+    use crate::tests::protocol::Replicate;
+    use crate::tests::protocol::*;
+    use crate::tests::stepper::{BevyStepper, Step};
+
+    use super::*;
+
+    #[test]
+    fn test_save_and_restore() {
+        let mut stepper = BevyStepper::default();
+        let parent = stepper
+            .server_app
+            .world
+            .spawn((
+                Component1(1.0),
+                Replicate {
+                    replication_mode: ReplicationMode::Room,
+                    ..default()
+                },
+            ))
+            .id();
+        let child = stepper.server_app.world.spawn(Component2(2.0)).id();
+        stepper.server_app.world.entity_mut(parent).add_child(child);
+        let reference = stepper
+            .server_app
+            .world
+            .spawn((Component4(parent), Replicate::default()))
+            .id();
+        // a group id chosen by the user that happens to be the id of a saved entity
+        let grouped = stepper
+            .server_app
+            .world
+            .spawn((
+                Component1(3.0),
+                Replicate {
+                    replication_group: ReplicationGroup::new_id(parent.to_bits()),
+                    ..default()
+                },
+            ))
+            .id();
+        stepper
+            .server_app
+            .world
+            .resource_mut::<RoomManager>()
+            .add_entity(parent, RoomId(1));
+        stepper.frame_step();
+
+        let bytes = WorldSnapshot::<MyProtocol>::from_world(&stepper.server_app.world)
+            .to_bytes()
+            .unwrap();
+        let snapshot = WorldSnapshot::<MyProtocol>::from_bytes(&bytes).unwrap();
+        assert_eq!(snapshot.len(), 4);
+
+        // restore the snapshot in a new world
+        let mut world = World::new();
+        let entity_map = snapshot.restore(&mut world).unwrap();
+        let new_parent = entity_map[&parent];
+        let new_child = entity_map[&child];
+        let new_reference = entity_map[&reference];
+
+        // 1. the components and the replication settings are restored
+        assert_eq!(world.get::<Component1>(new_parent), Some(&Component1(1.0)));
+        assert_eq!(world.get::<Component2>(new_child), Some(&Component2(2.0)));
+        assert_eq!(
+            world.get::<Replicate>(new_parent).unwrap().replication_mode,
+            ReplicationMode::Room
+        );
+        assert!(world
+            .resource::<RoomManager>()
+            .has_entity(new_parent, RoomId(1)));
+
+        // 2. the entity references are mapped to the new entities
+        assert_eq!(
+            world.get::<Component4>(new_reference),
+            Some(&Component4(new_parent))
+        );
+        assert_eq!(world.get::<Parent>(new_child).unwrap().get(), new_parent);
+        assert_eq!(
+            world.get::<Replicate>(new_child).unwrap().replication_group,
+            ReplicationGroup::new_id(new_parent.to_bits())
+        );
+
+        // 3. the group ids that are not derived from an ancestor are not mapped
+        assert_ne!(new_parent, parent);
+        assert_eq!(
+            world
+                .get::<Replicate>(entity_map[&grouped])
+                .unwrap()
+                .replication_group,
+            ReplicationGroup::new_id(parent.to_bits())
+        );
+    }
+
+    #[test]
+    fn test_restore_parent_not_in_snapshot() {
+        let mut stepper = BevyStepper::default();
+        // the parent is not replicated, so it is not saved
+        let parent = stepper.server_app.world.spawn_empty().id();
+        let child = stepper
+            .server_app
+            .world
+            .spawn((Replicate::default(), ParentSync::default()))
+            .set_parent(parent)
+            .id();
+        stepper.frame_step();
+        let snapshot = WorldSnapshot::<MyProtocol>::from_world(&stepper.server_app.world);
+
+        // the id of the parent already exists in the new world
+        let mut world = World::new();
+        for _ in 0..=parent.index() {
+            world.spawn_empty();
+        }
+        let entity_map = snapshot.restore(&mut world).unwrap();
+        let new_child = entity_map[&child];
+        assert!(world.get::<Parent>(new_child).is_none());
+        assert_eq!(
+            world.get::<ParentSync>(new_child),
+            Some(&ParentSync::default())
+        );
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct Chat(String);
+
+    #[test]
+    fn test_restore_with_different_registry() {
+        let mut stepper = BevyStepper::default();
+        stepper
+            .server_app
+            .world
+            .spawn((Component1(1.0), Replicate::default()));
+        stepper.frame_step();
+        let snapshot = WorldSnapshot::<MyProtocol>::from_world(&stepper.server_app.world);
+
+        // the server registered other types at runtime: the net ids of the registered components could be different
+        let mut app = App::new();
+        app.register_message::<Chat>();
+        assert!(snapshot.restore(&mut app.world).is_err());
+        assert_eq!(app.world.entities().len(), 0);
+    }
+}
@@ -6,8 +6,10 @@ use bevy::prelude::*;
 
 use crate::protocol::component::ComponentProtocol;
 use crate::protocol::message::MessageProtocol;
+use crate::protocol::registration::ProtocolRegistry;
 use crate::protocol::Protocol;
 use crate::server::connection::ConnectionManager;
+use crate::server::diagnostics::ServerDiagnosticsPlugin;
 use crate::server::events::ServerEventsPlugin;
 use crate::server::input::InputPlugin;
 use crate::server::networking::ServerNetworkingPlugin;
@@ -52,6 +54,7 @@ impl<P: Protocol> Plugin for ServerPlugin<P> {
 
         app
             // RESOURCES //
+            .init_resource::<ProtocolRegistry>()
             .insert_resource(config.server_config.clone())
             .insert_resource(config.protocol.clone())
             // PLUGINS
@@ -60,10 +63,17 @@ impl<P: Protocol> Plugin for ServerPlugin<P> {
             .add_plugins(InputPlugin::<P>::default())
             .add_plugins(RoomPlugin::<P>::default())
             .add_plugins(ServerReplicationPlugin::<P>::default())
+            .add_plugins(ServerDiagnosticsPlugin::<P>::default())
             .add_plugins(SharedPlugin::<P> {
                 // TODO: move shared config out of server_config?
                 config: config.server_config.shared.clone(),
                 ..default()
             });
     }
+
+    fn finish(&self, app: &mut App) {
+        // assign the net ids now that every plugin had a chance to register its components and messages
+        let mut registry = app.world.resource_mut::<ProtocolRegistry>();
+        registry.finish();
+    }
 }
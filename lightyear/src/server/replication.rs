@@ -7,12 +7,16 @@ use crate::client::interpolation::Interpolated;
 use crate::client::prediction::Predicted;
 use crate::connection::client::NetClient;
 use crate::prelude::client::ClientConnection;
-use crate::prelude::{Mode, PrePredicted, Protocol, SharedConfig};
+use crate::prelude::{ClientId, Mode, PrePredicted, Protocol, SharedConfig};
 use crate::server::config::ServerConfig;
 use crate::server::connection::ConnectionManager;
 use crate::server::networking::is_started;
 use crate::server::prediction::compute_hash;
-use crate::shared::replication::components::Replicate;
+use crate::server::room::ClientVisibility;
+use crate::shared::replication::components::{
+    Replicate, ReplicateRemoveBehaviour, ReplicationMode,
+};
+use crate::shared::replication::initial_sync::InitialSyncConfig;
 use crate::shared::replication::plugin::ReplicationPlugin;
 use crate::shared::sets::{InternalMainSet, InternalReplicationSet};
 
@@ -22,6 +26,12 @@ pub struct ReplicationConfig {
     /// Set to true to disable replicating this server's entities to clients
     pub enable_send: bool,
     pub enable_receive: bool,
+    /// What happens to the client entities when the `Replicate` component is removed from a server entity,
+    /// for the entities that don't set [`Replicate::on_remove`]
+    pub on_replicate_remove: ReplicateRemoveBehaviour,
+    /// If set, the replicated entities are sent to the newly connected clients in chunks, in priority order,
+    /// instead of all at once (see [`InitialSyncConfig`])
+    pub initial_sync: Option<InitialSyncConfig>,
 }
 
 impl Default for ReplicationConfig {
@@ -29,6 +39,8 @@ impl Default for ReplicationConfig {
         Self {
             enable_send: true,
             enable_receive: false,
+            on_replicate_remove: ReplicateRemoveBehaviour::default(),
+            initial_sync: None,
         }
     }
 }
@@ -76,10 +88,24 @@ impl<P: Protocol> Plugin for ServerReplicationPlugin<P> {
                     .before(InternalReplicationSet::<ServerMarker>::SendComponentUpdates)
                     .in_set(InternalReplicationSet::<ServerMarker>::All),
             )
+            .configure_sets(
+                PostUpdate,
+                // the entities sent for the initial sync need to be selected before we wake up the dormant entities
+                // and gather the updates
+                InternalReplicationSet::<ServerMarker>::PrepareInitialSync
+                    .before(InternalReplicationSet::<ServerMarker>::HandleDormancy)
+                    .in_set(InternalReplicationSet::<ServerMarker>::All)
+                    .in_set(InternalMainSet::<ServerMarker>::Send),
+            )
             // SYSTEMS
             .add_systems(
                 PostUpdate,
-                compute_hash::<P>.in_set(InternalReplicationSet::<ServerMarker>::SetPreSpawnedHash),
+                (
+                    compute_hash::<P>
+                        .in_set(InternalReplicationSet::<ServerMarker>::SetPreSpawnedHash),
+                    prepare_initial_sync::<P>
+                        .in_set(InternalReplicationSet::<ServerMarker>::PrepareInitialSync),
+                ),
             );
 
         // HOST-SERVER
@@ -92,6 +118,62 @@ impl<P: Protocol> Plugin for ServerReplicationPlugin<P> {
     }
 }
 
+/// Start the initial sync of the newly connected clients, and select the entities that are sent
+/// during this send to the clients that are waiting for their initial sync
+fn prepare_initial_sync<P: Protocol>(
+    mut connection_manager: ResMut<ConnectionManager<P>>,
+    query: Query<(Entity, &Replicate<P>)>,
+) {
+    // enable split borrows
+    let connection_manager = &mut *connection_manager;
+    let Some(initial_sync) = connection_manager.initial_sync.as_mut() else {
+        return;
+    };
+    // the entities in rooms are part of the initial sync if they are visible to the client when it starts
+    // (the room caches are updated before); the entities that become visible later are sent right away
+    let should_send = |client_id: ClientId, replicate: &Replicate<P>| {
+        replicate.replication_target.should_send_to(&client_id)
+            && match replicate.replication_mode {
+                ReplicationMode::NetworkTarget => true,
+                ReplicationMode::Room => replicate
+                    .replication_clients_cache
+                    .get(&client_id)
+                    .map_or(false, |visibility| {
+                        !matches!(visibility, ClientVisibility::Lost)
+                    }),
+            }
+    };
+    for client_id in connection_manager.new_clients.iter() {
+        let Some(connection) = connection_manager.connections.get(client_id) else {
+            continue;
+        };
+        let entities = query
+            .iter()
+            .filter(|(_, replicate)| should_send(*client_id, replicate))
+            .map(|(entity, replicate)| {
+                let priority = replicate.replication_group.priority()
+                    * connection
+                        .replication_sender
+                        .priority_multiplier(replicate.group_id(Some(entity)));
+                (entity, priority)
+            })
+            .collect();
+        initial_sync.start(*client_id, entities);
+    }
+    for (client_id, total) in initial_sync.next_batch(|client_id, entity| {
+        query
+            .get(entity)
+            .map_or(false, |(_, replicate)| should_send(client_id, replicate))
+    }) {
+        let _ = connection_manager
+            .connection_mut(client_id)
+            .and_then(|connection| connection.buffer_initial_sync_message(total))
+            .map_err(|e| {
+                error!("error sending initial sync message: {:?}", e);
+            });
+    }
+}
+
 /// Filter to use to get all entities that are not client-side replicated entities
 #[derive(QueryFilter)]
 pub struct ServerFilter {
@@ -5,8 +5,8 @@
 use bevy::app::App;
 use bevy::ecs::entity::EntityHash;
 use bevy::prelude::{
-    Entity, IntoSystemConfigs, IntoSystemSetConfigs, Plugin, PostUpdate, Query, RemovedComponents,
-    Res, ResMut, Resource, SystemSet,
+    Entity, IntoSystemConfigs, IntoSystemSetConfigs, Mut, Plugin, PostUpdate, Query,
+    RemovedComponents, Res, ResMut, Resource, SystemSet, World,
 };
 use bevy::reflect::Reflect;
 use bevy::utils::{HashMap, HashSet};
@@ -16,7 +16,8 @@ use crate::_reexport::ServerMarker;
 use crate::connection::id::ClientId;
 use crate::protocol::Protocol;
 use crate::server::networking::is_started;
-use crate::shared::replication::components::{DespawnTracker, Replicate};
+use crate::server::visibility::VisibilityFilters;
+use crate::shared::replication::components::{DespawnTracker, Replicate, ReplicationMode};
 use crate::shared::sets::InternalReplicationSet;
 use crate::shared::time_manager::is_server_ready_to_send;
 use crate::utils::wrapping_id::wrapping_id;
@@ -112,7 +113,8 @@ pub enum RoomSystemSets {
 impl<P: Protocol> Plugin for RoomPlugin<P> {
     fn build(&self, app: &mut App) {
         // RESOURCES
-        app.init_resource::<RoomManager>();
+        app.init_resource::<RoomManager>()
+            .init_resource::<VisibilityFilters>();
         // SETS
         app.configure_sets(
             PostUpdate,
@@ -137,7 +139,11 @@ impl<P: Protocol> Plugin for RoomPlugin<P> {
         app.add_systems(
             PostUpdate,
             (
-                update_entity_replication_cache::<P>
+                (
+                    update_entity_replication_cache::<P>,
+                    apply_visibility_filters::<P>.run_if(has_visibility_filters),
+                )
+                    .chain()
                     .in_set(RoomSystemSets::UpdateReplicationCaches),
                 (clear_entity_replication_cache::<P>, clean_entity_despawns)
                     .in_set(RoomSystemSets::RoomBookkeeping),
@@ -201,6 +207,16 @@ impl RoomManager {
         self.data.rooms.get(&room_id)
     }
 
+    /// Returns the rooms that the entity is in
+    pub fn entity_rooms(&self, entity: Entity) -> impl Iterator<Item = RoomId> + '_ {
+        self.data
+            .entity_to_rooms
+            .get(&entity)
+            .into_iter()
+            .flatten()
+            .copied()
+    }
+
     fn add_client_internal(&mut self, room_id: RoomId, client_id: ClientId) {
         self.data
             .client_to_rooms
@@ -434,6 +450,48 @@ pub enum ClientVisibility {
     Maintained,
 }
 
+impl ClientVisibility {
+    /// Returns true if the entity is currently replicated to the client
+    pub(crate) fn is_visible(visibility: Option<ClientVisibility>) -> bool {
+        matches!(
+            visibility,
+            Some(ClientVisibility::Gained | ClientVisibility::Maintained)
+        )
+    }
+
+    /// Update the visibility of the entity for the client, when it should now be `visible`.
+    pub(crate) fn update<P: Protocol>(
+        replicate: &mut Mut<Replicate<P>>,
+        client_id: ClientId,
+        visible: bool,
+    ) {
+        let visibility = replicate.replication_clients_cache.get(&client_id).copied();
+        match (visibility, visible) {
+            (None, true) => {
+                replicate
+                    .replication_clients_cache
+                    .insert(client_id, ClientVisibility::Gained);
+            }
+            // the visibility was lost during this send interval, so the entity is still replicated
+            (Some(ClientVisibility::Lost), true) => {
+                replicate
+                    .replication_clients_cache
+                    .insert(client_id, ClientVisibility::Maintained);
+            }
+            // the entity hasn't been spawned on the client yet, so there is nothing to despawn
+            (Some(ClientVisibility::Gained), false) => {
+                replicate.replication_clients_cache.remove(&client_id);
+            }
+            (Some(ClientVisibility::Maintained), false) => {
+                replicate
+                    .replication_clients_cache
+                    .insert(client_id, ClientVisibility::Lost);
+            }
+            _ => {}
+        }
+    }
+}
+
 // TODO: (perf) split this into 4 separate functions that access RoomManager in parallel?
 //  (we only use the ids in events, so we can read them in parallel)
 /// Update each entities' replication-client-list based on the room events
@@ -528,11 +586,61 @@ fn update_entity_replication_cache<P: Protocol>(
     }
 }
 
+fn has_visibility_filters(filters: Res<VisibilityFilters>) -> bool {
+    !filters.is_empty()
+}
+
+/// Apply the [`VisibilityFilters`] on top of the room visibility: an entity is visible to a client
+/// if they share a room and if all the filters accept it
+fn apply_visibility_filters<P: Protocol>(world: &mut World) {
+    let mut query = world.query::<(Entity, &Replicate<P>)>();
+    let mut changes = Vec::new();
+    let room_manager = world.resource::<RoomManager>();
+    let filters = world.resource::<VisibilityFilters>();
+    for (entity, replicate) in query.iter(world) {
+        if replicate.replication_mode != ReplicationMode::Room {
+            continue;
+        }
+        // only the clients that share a room with the entity can see it
+        let Some(rooms) = room_manager.data.entity_to_rooms.get(&entity) else {
+            continue;
+        };
+        let clients: HashSet<ClientId> = rooms
+            .iter()
+            .filter_map(|room_id| room_manager.data.rooms.get(room_id))
+            .flat_map(|room| room.clients.iter().copied())
+            .collect();
+        for client_id in clients {
+            let visible = filters.is_visible(client_id, world.entity(entity));
+            let was_visible = ClientVisibility::is_visible(
+                replicate.replication_clients_cache.get(&client_id).copied(),
+            );
+            if visible != was_visible {
+                changes.push((entity, client_id, visible));
+            }
+        }
+    }
+    for (entity, client_id, visible) in changes {
+        trace!(?entity, ?client_id, ?visible, "visibility filter changed");
+        if let Some(mut replicate) = world.get_mut::<Replicate<P>>(entity) {
+            ClientVisibility::update(&mut replicate, client_id, visible);
+        }
+    }
+}
+
 /// After replication, update the Replication Cache:
 /// - Visibility Gained becomes Visibility Maintained
 /// - Visibility Lost gets removed from the cache
 fn clear_entity_replication_cache<P: Protocol>(mut query: Query<&mut Replicate<P>>) {
     for mut replicate in query.iter_mut() {
+        // only trigger change detection if the cache actually needs to be updated
+        if replicate
+            .replication_clients_cache
+            .values()
+            .all(|visibility| *visibility == ClientVisibility::Maintained)
+        {
+            continue;
+        }
         replicate
             .replication_clients_cache
             .retain(|_, visibility| match visibility {
@@ -719,12 +827,12 @@ mod tests {
             .is_empty());
 
         stepper.frame_step();
-        // Check that the entity gets despawned on client
+        // Check that the entity gets despawned on client, with a lost visibility event
         assert_eq!(
             stepper
                 .client_app
                 .world
-                .resource::<Events<EntityDespawnEvent>>()
+                .resource::<Events<EntityLostVisibilityEvent>>()
                 .len(),
             1
         );
@@ -879,12 +987,12 @@ mod tests {
             .is_empty());
 
         stepper.frame_step();
-        // Check that the entity gets despawned on client
+        // Check that the entity gets despawned on client, with a lost visibility event
         assert_eq!(
             stepper
                 .client_app
                 .world
-                .resource::<Events<EntityDespawnEvent>>()
+                .resource::<Events<EntityLostVisibilityEvent>>()
                 .len(),
             1
         );
@@ -0,0 +1,578 @@
+//! # Spatial interest management
+//!
+//! This module contains a plugin that performs interest management automatically, based on the position of
+//! the entities. The visibility of each entity is updated so that the entity is replicated only to the clients
+//! that are close enough to it.
+//!
+//! The plugin manages the visibility of every replicated entity that has the [`SpatialInterest`] marker component,
+//! uses [`ReplicationMode::Room`] and has the position component; these entities should not be added to
+//! [`Rooms`](crate::server::room::Room) manually. The [`VisibilityFilters`] also apply to these entities.
+//!
+//! The entities are bucketed by cell, so that each client only checks the entities in the cells close to its viewers.
+use bevy::app::App;
+use bevy::ecs::entity::EntityHash;
+use bevy::math::{IVec3, Vec3};
+use bevy::prelude::{
+    Component, Entity, IntoSystemConfigs, Mut, Plugin, PostUpdate, Resource, Transform, With, World,
+};
+use bevy::reflect::Reflect;
+use bevy::utils::HashMap;
+use tracing::trace;
+
+use crate::connection::id::ClientId;
+use crate::protocol::Protocol;
+use crate::server::room::{ClientVisibility, RoomSystemSets};
+use crate::server::visibility::VisibilityFilters;
+use crate::shared::replication::components::{Replicate, ReplicationMode};
+
+type EntityHashMap<K, V> = hashbrown::HashMap<K, V, EntityHash>;
+type EntityHashSet<K> = hashbrown::HashSet<K, EntityHash>;
+
+/// Component that can provide the position of an entity for spatial interest management
+pub trait SpatialPosition: Component {
+    /// Returns the position of the entity in world space
+    fn position(&self) -> Vec3;
+}
+
+impl SpatialPosition for Transform {
+    fn position(&self) -> Vec3 {
+        self.translation
+    }
+}
+
+/// Marks a replicated entity whose visibility is managed by the [`SpatialInterestPlugin`].
+///
+/// The entity must use [`ReplicationMode::Room`]; the other entities that use [`ReplicationMode::Room`]
+/// keep being managed by the rooms.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub struct SpatialInterest;
+
+/// Marks the entity from which a client views the world (usually the entity controlled by the client).
+///
+/// The entities that are close to one of the client's viewers are replicated to that client.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct SpatialViewer {
+    /// The client that views the world from this entity
+    pub client_id: ClientId,
+    /// Overrides the default view radius of [`SpatialInterestMode::Radius`]. Unused in the grid mode.
+    pub radius: Option<f32>,
+}
+
+impl SpatialViewer {
+    pub fn new(client_id: ClientId) -> Self {
+        Self {
+            client_id,
+            radius: None,
+        }
+    }
+
+    /// Set a custom view radius for this client
+    pub fn with_radius(mut self, radius: f32) -> Self {
+        self.radius = Some(radius);
+        self
+    }
+}
+
+/// How to compute which entities are close to a client
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub enum SpatialInterestMode {
+    /// The world is divided in a uniform grid of cubic cells.
+    /// An entity is visible to a client if its cell is at most `view_distance` cells away from the cell
+    /// of one of the client's viewers.
+    Grid { cell_size: f32, view_distance: u32 },
+    /// An entity is visible to a client if it is within `radius` of one of the client's viewers.
+    /// The radius can be overridden per viewer with [`SpatialViewer::radius`].
+    Radius { radius: f32 },
+}
+
+/// Configuration of the spatial interest management
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct SpatialInterestConfig {
+    pub mode: SpatialInterestMode,
+    /// Distance by which an entity has to move past a boundary before its visibility changes.
+    /// This avoids spawning and despawning entities repeatedly when they move back and forth around a boundary.
+    /// - in grid mode, an entity keeps its current cell until it is `hysteresis` away from it
+    /// - in radius mode, a visible entity stays visible until it is farther than `radius + hysteresis`
+    pub hysteresis: f32,
+}
+
+impl Default for SpatialInterestConfig {
+    fn default() -> Self {
+        Self {
+            mode: SpatialInterestMode::Grid {
+                cell_size: 100.0,
+                view_distance: 1,
+            },
+            hysteresis: 5.0,
+        }
+    }
+}
+
+impl SpatialInterestConfig {
+    /// Compute the cell of an entity, given the cell that it was in previously.
+    /// The entity only changes cell if it is further than `hysteresis` from its previous cell.
+    fn cell(&self, cell_size: f32, position: Vec3, previous: Option<IVec3>) -> IVec3 {
+        let cell = (position / cell_size).floor().as_ivec3();
+        match previous {
+            Some(previous) if previous != cell => {
+                let min = previous.as_vec3() * cell_size - self.hysteresis;
+                let max = (previous + IVec3::ONE).as_vec3() * cell_size + self.hysteresis;
+                if position.cmpge(min).all() && position.cmple(max).all() {
+                    previous
+                } else {
+                    cell
+                }
+            }
+            _ => cell,
+        }
+    }
+
+    /// Size of the cells in which the entities are bucketed.
+    /// In radius mode, the cells are large enough to contain the default view radius (plus the hysteresis)
+    fn cell_size(&self) -> f32 {
+        match self.mode {
+            SpatialInterestMode::Grid { cell_size, .. } => cell_size,
+            SpatialInterestMode::Radius { radius } => (radius + self.hysteresis).max(f32::EPSILON),
+        }
+    }
+
+    /// Maximum distance (in cells, along each axis) between the cell of a viewer and the cell of an entity
+    /// that is visible from that viewer
+    fn view_distance(&self, viewer: &ViewerState) -> u32 {
+        match self.mode {
+            SpatialInterestMode::Grid { view_distance, .. } => view_distance,
+            SpatialInterestMode::Radius { radius } => {
+                let radius = viewer.radius.unwrap_or(radius) + self.hysteresis;
+                (radius / self.cell_size()).ceil() as u32
+            }
+        }
+    }
+
+    /// Returns true if an entity is visible from a viewer
+    fn is_visible(&self, was_visible: bool, entity: &SpatialState, viewer: &ViewerState) -> bool {
+        match self.mode {
+            SpatialInterestMode::Grid { view_distance, .. } => {
+                (entity.cell - viewer.state.cell).abs().max_element() <= view_distance as i32
+            }
+            SpatialInterestMode::Radius { radius } => {
+                let mut radius = viewer.radius.unwrap_or(radius);
+                if was_visible {
+                    radius += self.hysteresis;
+                }
+                entity.position.distance_squared(viewer.state.position) <= radius * radius
+            }
+        }
+    }
+}
+
+/// Position and cell of an entity during the current update
+#[derive(Clone, Copy, Debug)]
+struct SpatialState {
+    position: Vec3,
+    cell: IVec3,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ViewerState {
+    state: SpatialState,
+    radius: Option<f32>,
+}
+
+/// Bookkeeping of the spatial interest management
+#[derive(Resource, Default, Debug)]
+struct SpatialInterestData {
+    /// Current cell of each entity (including the viewers)
+    cells: EntityHashMap<Entity, IVec3>,
+    /// Entities that were visible to each client during the previous update
+    visible: HashMap<ClientId, EntityHashSet<Entity>>,
+}
+
+impl SpatialInterestData {
+    fn update_state(
+        &mut self,
+        config: &SpatialInterestConfig,
+        cells: &mut EntityHashMap<Entity, IVec3>,
+        entity: Entity,
+        position: Vec3,
+    ) -> SpatialState {
+        let cell = match config.mode {
+            SpatialInterestMode::Grid { cell_size, .. } => {
+                config.cell(cell_size, position, self.cells.get(&entity).copied())
+            }
+            SpatialInterestMode::Radius { .. } => {
+                (position / config.cell_size()).floor().as_ivec3()
+            }
+        };
+        cells.insert(entity, cell);
+        SpatialState { position, cell }
+    }
+}
+
+/// Plugin that updates the visibility of replicated entities based on their distance to the clients' [`SpatialViewer`]s.
+///
+/// `Pos` is the component that holds the position of the entities, for example [`Transform`].
+pub struct SpatialInterestPlugin<P: Protocol, Pos: SpatialPosition> {
+    config: SpatialInterestConfig,
+    _marker: std::marker::PhantomData<(P, Pos)>,
+}
+
+impl<P: Protocol, Pos: SpatialPosition> SpatialInterestPlugin<P, Pos> {
+    pub fn new(config: SpatialInterestConfig) -> Self {
+        Self {
+            config,
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<P: Protocol, Pos: SpatialPosition> Default for SpatialInterestPlugin<P, Pos> {
+    fn default() -> Self {
+        Self::new(SpatialInterestConfig::default())
+    }
+}
+
+impl<P: Protocol, Pos: SpatialPosition> Plugin for SpatialInterestPlugin<P, Pos> {
+    fn build(&self, app: &mut App) {
+        // RESOURCES
+        app.insert_resource(self.config)
+            .init_resource::<SpatialInterestData>();
+        // SYSTEMS
+        app.add_systems(
+            PostUpdate,
+            update_spatial_visibility::<P, Pos>.in_set(RoomSystemSets::UpdateReplicationCaches),
+        );
+    }
+}
+
+/// Call `f` with the buckets of the cells that are at most `distance` cells away from `center`
+fn for_each_bucket_in_range(
+    buckets: &HashMap<IVec3, Vec<(Entity, SpatialState)>>,
+    center: IVec3,
+    distance: u32,
+    mut f: impl FnMut(&[(Entity, SpatialState)]),
+) {
+    let side = 2 * distance as u64 + 1;
+    if side.saturating_mul(side).saturating_mul(side) > buckets.len() as u64 {
+        // there are fewer occupied cells than cells in range
+        for (cell, bucket) in buckets.iter() {
+            if (*cell - center).abs().max_element() as u32 <= distance {
+                f(bucket);
+            }
+        }
+        return;
+    }
+    let distance = distance as i32;
+    for x in -distance..=distance {
+        for y in -distance..=distance {
+            for z in -distance..=distance {
+                if let Some(bucket) = buckets.get(&(center + IVec3::new(x, y, z))) {
+                    f(bucket);
+                }
+            }
+        }
+    }
+}
+
+/// Update the replication cache of each [`SpatialInterest`] entity based on its distance to the viewers of each client
+/// (and on the [`VisibilityFilters`])
+fn update_spatial_visibility<P: Protocol, Pos: SpatialPosition>(world: &mut World) {
+    world.resource_scope(|world, mut data: Mut<SpatialInterestData>| {
+        let config = *world.resource::<SpatialInterestConfig>();
+        let mut cells = EntityHashMap::default();
+        let mut clients: HashMap<ClientId, Vec<ViewerState>> = HashMap::default();
+        let mut viewers = world.query::<(Entity, &SpatialViewer, &Pos)>();
+        for (entity, viewer, position) in viewers.iter(world) {
+            let state = data.update_state(&config, &mut cells, entity, position.position());
+            clients
+                .entry(viewer.client_id)
+                .or_default()
+                .push(ViewerState {
+                    state,
+                    radius: viewer.radius,
+                });
+        }
+
+        // bucket the entities by cell
+        let mut buckets: HashMap<IVec3, Vec<(Entity, SpatialState)>> = HashMap::default();
+        let mut entities =
+            world.query_filtered::<(Entity, &Pos, &Replicate<P>), With<SpatialInterest>>();
+        for (entity, position, replicate) in entities.iter(world) {
+            if replicate.replication_mode != ReplicationMode::Room {
+                continue;
+            }
+            let state = data.update_state(&config, &mut cells, entity, position.position());
+            buckets.entry(state.cell).or_default().push((entity, state));
+        }
+
+        // each client only checks the entities in the cells that are close to its viewers
+        let filters = world.resource::<VisibilityFilters>();
+        let mut visible: HashMap<ClientId, EntityHashSet<Entity>> = HashMap::default();
+        for (client_id, viewers) in clients.iter() {
+            let previous = data.visible.get(client_id);
+            let client_visible = visible.entry(*client_id).or_default();
+            for viewer in viewers {
+                let distance = config.view_distance(viewer);
+                for_each_bucket_in_range(&buckets, viewer.state.cell, distance, |bucket| {
+                    for (entity, state) in bucket {
+                        if client_visible.contains(entity) {
+                            continue;
+                        }
+                        let was_visible = previous.map_or(false, |p| p.contains(entity));
+                        if config.is_visible(was_visible, state, viewer)
+                            && filters.is_visible(*client_id, world.entity(*entity))
+                        {
+                            client_visible.insert(*entity);
+                        }
+                    }
+                });
+            }
+        }
+
+        let mut changes = Vec::new();
+        for (client_id, client_visible) in visible.iter() {
+            let previous = data.visible.get(client_id);
+            for entity in client_visible.iter() {
+                if !previous.map_or(false, |p| p.contains(entity)) {
+                    changes.push((*entity, *client_id, true));
+                }
+            }
+        }
+        // the entities that are not visible anymore (including all the entities visible to the clients
+        // that don't have any viewers anymore)
+        for (client_id, previous) in data.visible.iter() {
+            let current = visible.get(client_id);
+            for entity in previous.iter() {
+                if !current.map_or(false, |c| c.contains(entity)) {
+                    changes.push((*entity, *client_id, false));
+                }
+            }
+        }
+        for (entity, client_id, visible) in changes {
+            trace!(?entity, ?client_id, ?visible, "spatial visibility changed");
+            let Some(mut entity_mut) = world.get_entity_mut(entity) else {
+                continue;
+            };
+            if !entity_mut.contains::<SpatialInterest>() {
+                continue;
+            }
+            if let Some(mut replicate) = entity_mut.get_mut::<Replicate<P>>() {
+                ClientVisibility::update(&mut replicate, client_id, visible);
+            }
+        }
+        data.cells = cells;
+        data.visible = visible;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::{default, With};
+    use bevy::utils::Duration;
+
+    use crate::prelude::client::{Confirmed, InterpolationConfig, PredictionConfig, SyncConfig};
+    use crate::prelude::{LinkConditionerConfig, SharedConfig, TickConfig};
+    use crate::tests::protocol::Replicate;
+    use crate::tests::protocol::*;
+    use crate::tests::stepper::{BevyStepper, Step};
+
+    use super::*;
+
+    #[test]
+    fn test_grid_cell_hysteresis() {
+        let config = SpatialInterestConfig {
+            mode: SpatialInterestMode::Grid {
+                cell_size: 10.0,
+                view_distance: 1,
+            },
+            hysteresis: 2.0,
+        };
+        assert_eq!(
+            config.cell(10.0, Vec3::new(5.0, 0.0, 0.0), None),
+            IVec3::ZERO
+        );
+        // within the hysteresis margin: stay in the previous cell
+        assert_eq!(
+            config.cell(10.0, Vec3::new(11.0, 0.0, 0.0), Some(IVec3::ZERO)),
+            IVec3::ZERO
+        );
+        assert_eq!(
+            config.cell(10.0, Vec3::new(13.0, 0.0, 0.0), Some(IVec3::ZERO)),
+            IVec3::X
+        );
+        assert_eq!(
+            config.cell(10.0, Vec3::new(-1.0, 0.0, 0.0), Some(IVec3::ZERO)),
+            IVec3::ZERO
+        );
+        assert_eq!(
+            config.cell(10.0, Vec3::new(-1.0, 0.0, 0.0), None),
+            -IVec3::X
+        );
+    }
+
+    #[test]
+    fn test_radius_visibility() {
+        let frame_duration = Duration::from_millis(10);
+        let tick_duration = Duration::from_millis(10);
+        let shared_config = SharedConfig {
+            tick: TickConfig::new(tick_duration),
+            ..Default::default()
+        };
+        let link_conditioner = LinkConditionerConfig {
+            incoming_latency: Duration::from_millis(0),
+            incoming_jitter: Duration::from_millis(0),
+            incoming_loss: 0.0,
+        };
+        let mut stepper = BevyStepper::new(
+            shared_config,
+            SyncConfig::default().speedup_factor(1.0),
+            PredictionConfig::default(),
+            InterpolationConfig::default(),
+            link_conditioner,
+            frame_duration,
+        );
+        stepper
+            .server_app
+            .add_plugins(SpatialInterestPlugin::<MyProtocol, Transform>::new(
+                SpatialInterestConfig {
+                    mode: SpatialInterestMode::Radius { radius: 10.0 },
+                    hysteresis: 2.0,
+                },
+            ));
+        stepper.init();
+        stepper.server_app.world.spawn((
+            Transform::default(),
+            SpatialViewer::new(ClientId::Netcode(111)),
+        ));
+        let server_entity = stepper
+            .server_app
+            .world
+            .spawn((
+                Component1(0.0),
+                Transform::from_xyz(5.0, 0.0, 0.0),
+                SpatialInterest,
+                Replicate {
+                    replication_mode: ReplicationMode::Room,
+                    ..default()
+                },
+            ))
+            .id();
+
+        let mut move_and_check = |x: f32, visible: bool| {
+            stepper
+                .server_app
+                .world
+                .entity_mut(server_entity)
+                .insert(Transform::from_xyz(x, 0.0, 0.0));
+            stepper.frame_step();
+            stepper.frame_step();
+            let count = stepper
+                .client_app
+                .world
+                .query_filtered::<(), (With<Component1>, With<Confirmed>)>()
+                .iter(&stepper.client_app.world)
+                .count();
+            assert_eq!(count, visible as usize, "x = {x}");
+        };
+        move_and_check(5.0, true);
+        // still visible thanks to the hysteresis
+        move_and_check(11.0, true);
+        move_and_check(13.0, false);
+        // the entity must come back within the radius to be visible again
+        move_and_check(11.0, false);
+        move_and_check(9.0, true);
+    }
+
+    #[test]
+    fn test_grid_visibility() {
+        let frame_duration = Duration::from_millis(10);
+        let tick_duration = Duration::from_millis(10);
+        let shared_config = SharedConfig {
+            tick: TickConfig::new(tick_duration),
+            ..Default::default()
+        };
+        let link_conditioner = LinkConditionerConfig {
+            incoming_latency: Duration::from_millis(0),
+            incoming_jitter: Duration::from_millis(0),
+            incoming_loss: 0.0,
+        };
+        let mut stepper = BevyStepper::new(
+            shared_config,
+            SyncConfig::default().speedup_factor(1.0),
+            PredictionConfig::default(),
+            InterpolationConfig::default(),
+            link_conditioner,
+            frame_duration,
+        );
+        stepper
+            .server_app
+            .add_plugins(SpatialInterestPlugin::<MyProtocol, Transform>::new(
+                SpatialInterestConfig {
+                    mode: SpatialInterestMode::Grid {
+                        cell_size: 10.0,
+                        view_distance: 1,
+                    },
+                    hysteresis: 2.0,
+                },
+            ));
+        stepper.init();
+        stepper.server_app.world.spawn((
+            Transform::default(),
+            SpatialViewer::new(ClientId::Netcode(111)),
+        ));
+        let server_entity = stepper
+            .server_app
+            .world
+            .spawn((
+                Component1(0.0),
+                Transform::from_xyz(15.0, 0.0, 0.0),
+                SpatialInterest,
+                Replicate {
+                    replication_mode: ReplicationMode::Room,
+                    ..default()
+                },
+            ))
+            .id();
+        // entities without the marker are not managed by the plugin
+        stepper.server_app.world.spawn((
+            Component2(0.0),
+            Transform::default(),
+            Replicate {
+                replication_mode: ReplicationMode::Room,
+                ..default()
+            },
+        ));
+
+        let mut move_and_check = |x: f32, visible: bool| {
+            stepper
+                .server_app
+                .world
+                .entity_mut(server_entity)
+                .insert(Transform::from_xyz(x, 0.0, 0.0));
+            stepper.frame_step();
+            stepper.frame_step();
+            let count = stepper
+                .client_app
+                .world
+                .query_filtered::<(), (With<Component1>, With<Confirmed>)>()
+                .iter(&stepper.client_app.world)
+                .count();
+            assert_eq!(count, visible as usize, "x = {x}");
+            assert_eq!(
+                stepper
+                    .client_app
+                    .world
+                    .query_filtered::<(), With<Component2>>()
+                    .iter(&stepper.client_app.world)
+                    .count(),
+                0
+            );
+        };
+        // the entity is in the neighbouring cell of the viewer
+        move_and_check(15.0, true);
+        // still in the neighbouring cell thanks to the hysteresis
+        move_and_check(21.0, true);
+        move_and_check(23.0, false);
+        move_and_check(-5.0, true);
+        move_and_check(-25.0, false);
+    }
+}
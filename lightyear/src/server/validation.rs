@@ -0,0 +1,311 @@
+//! # Validation of client updates
+//!
+//! When clients replicate entities to the server (with `ReplicationConfig::enable_send` on the client), the server
+//! applies whatever the clients send. Validators let the server check the values proposed by the clients before
+//! they are applied, for example to prevent a client from moving its character faster than allowed.
+//!
+//! A validator is a function `Fn(ClientId, EntityRef, &C) -> Validation<C>` registered on the server [`App`] for a
+//! component type `C`. It receives the id of the client that sent the value, the server entity (with its current
+//! components) and the proposed value, and decides what to do with it:
+//! - [`Validation::Accept`]: apply the value sent by the client
+//! - [`Validation::Replace`]: apply another value instead (for example a clamped value), and send it back to the client
+//! - [`Validation::Reject`]: ignore the value sent by the client
+//! - [`Validation::Correct`]: ignore the value sent by the client, and send the current server value back to the client
+//!
+//! The corrections are applied directly on the client entity, even if the client has authority over the entity.
+//!
+//! ```rust,ignore
+//! app.add_component_validator::<MyProtocol, Position>(|_, entity, position| {
+//!     let Some(previous) = entity.get::<Position>() else {
+//!         return Validation::Accept;
+//!     };
+//!     if previous.distance(position) > MAX_SPEED {
+//!         Validation::Correct
+//!     } else {
+//!         Validation::Accept
+//!     }
+//! });
+//! ```
+use bevy::app::App;
+use bevy::prelude::{Component, Entity, EntityRef, Resource};
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::connection::id::ClientId;
+use crate::protocol::component::FromType;
+use crate::protocol::Protocol;
+
+/// What to do with a component value sent by a client
+#[derive(Debug, Clone, PartialEq)]
+pub enum Validation<C> {
+    /// Apply the value sent by the client
+    Accept,
+    /// Apply another value instead of the one sent by the client, and send it back to the client
+    Replace(C),
+    /// Ignore the value sent by the client
+    Reject,
+    /// Ignore the value sent by the client, and send the current server value back to the client
+    Correct,
+}
+
+/// Outcome of the validation of a component sent by a client
+pub(crate) struct ValidationOutcome<C> {
+    /// The component to apply on the server, if any
+    pub(crate) apply: Option<C>,
+    /// The component to send back to the client, if any
+    pub(crate) correction: Option<C>,
+}
+
+/// Type-erased validator for a component of the `ComponentProtocol`
+type ValidateFn<P> = Box<
+    dyn Fn(
+            ClientId,
+            EntityRef,
+            <P as Protocol>::Components,
+        ) -> ValidationOutcome<<P as Protocol>::Components>
+        + Send
+        + Sync,
+>;
+
+/// Resource holding the validators registered on the server, by component kind
+#[derive(Resource)]
+pub struct ComponentValidators<P: Protocol> {
+    validators: HashMap<P::ComponentKinds, ValidateFn<P>>,
+}
+
+impl<P: Protocol> Default for ComponentValidators<P> {
+    fn default() -> Self {
+        Self {
+            validators: HashMap::default(),
+        }
+    }
+}
+
+impl<P: Protocol> ComponentValidators<P> {
+    /// Set the validator for the component `C`. This replaces any existing validator for that component
+    pub fn add<C: Component + Clone>(
+        &mut self,
+        validator: impl Fn(ClientId, EntityRef, &C) -> Validation<C> + Send + Sync + 'static,
+    ) where
+        P::Components: From<C> + TryInto<C>,
+        P::ComponentKinds: FromType<C>,
+    {
+        let kind = <P::ComponentKinds as FromType<C>>::from_type();
+        let validate: ValidateFn<P> = Box::new(move |client_id, entity, component| {
+            let Ok(value) = component.clone().try_into() else {
+                return ValidationOutcome {
+                    apply: Some(component),
+                    correction: None,
+                };
+            };
+            match validator(client_id, entity, &value) {
+                Validation::Accept => ValidationOutcome {
+                    apply: Some(component),
+                    correction: None,
+                },
+                Validation::Replace(value) => {
+                    let component = P::Components::from(value);
+                    ValidationOutcome {
+                        apply: Some(component.clone()),
+                        correction: Some(component),
+                    }
+                }
+                Validation::Reject => ValidationOutcome {
+                    apply: None,
+                    correction: None,
+                },
+                Validation::Correct => ValidationOutcome {
+                    apply: None,
+                    correction: entity.get::<C>().cloned().map(P::Components::from),
+                },
+            }
+        });
+        self.validators.insert(kind, validate);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    /// Validate a component sent by a client for the server entity `entity`
+    pub(crate) fn validate(
+        &self,
+        client_id: ClientId,
+        entity: EntityRef,
+        component: P::Components,
+    ) -> ValidationOutcome<P::Components> {
+        let kind = P::ComponentKinds::from(&component);
+        match self.validators.get(&kind) {
+            Some(validate) => validate(client_id, entity, component),
+            None => ValidationOutcome {
+                apply: Some(component),
+                correction: None,
+            },
+        }
+    }
+}
+
+/// Message sent to a client to overwrite the value of a component that was rejected by a validator
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CorrectionMessage<C> {
+    /// The client entity (the entity is not mapped)
+    pub(crate) entity: Entity,
+    /// The server value of the component; the entities it contains are mapped on the client
+    pub(crate) component: C,
+}
+
+/// Extension trait to register component validators on the server [`App`]
+pub trait AppValidationExt {
+    /// Register a validator for the values of the component `C` sent by the clients
+    fn add_component_validator<P: Protocol, C: Component + Clone>(
+        &mut self,
+        validator: impl Fn(ClientId, EntityRef, &C) -> Validation<C> + Send + Sync + 'static,
+    ) -> &mut Self
+    where
+        P::Components: From<C> + TryInto<C>,
+        P::ComponentKinds: FromType<C>;
+}
+
+impl AppValidationExt for App {
+    fn add_component_validator<P: Protocol, C: Component + Clone>(
+        &mut self,
+        validator: impl Fn(ClientId, EntityRef, &C) -> Validation<C> + Send + Sync + 'static,
+    ) -> &mut Self
+    where
+        P::Components: From<C> + TryInto<C>,
+        P::ComponentKinds: FromType<C>,
+    {
+        self.world
+            .get_resource_or_insert_with(ComponentValidators::<P>::default)
+            .add(validator);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::{Entity, With};
+
+    use crate::tests::protocol::*;
+    use crate::tests::stepper::{BevyStepper, Step};
+
+    use super::*;
+
+    fn step(stepper: &mut BevyStepper) {
+        for _ in 0..5 {
+            stepper.frame_step();
+        }
+    }
+
+    #[test]
+    fn test_component_validation() {
+        // enable client->server replication, for the entities that the client has authority over
+        let mut stepper = BevyStepper::default_with_client_replication(
+            crate::prelude::client::ReplicationConfig {
+                enable_send: true,
+                ..Default::default()
+            },
+        );
+        // negative values are corrected, large values are clamped
+        stepper
+            .server_app
+            .add_component_validator::<MyProtocol, Component1>(|client_id, _, component| {
+                assert_eq!(client_id, ClientId::Netcode(111));
+                if component.0 < 0.0 {
+                    Validation::Correct
+                } else if component.0 > 5.0 {
+                    Validation::Replace(Component1(5.0))
+                } else {
+                    Validation::Accept
+                }
+            });
+        let client_entity = stepper
+            .client_app
+            .world
+            .spawn((Component1(1.0), Replicate::default()))
+            .id();
+        step(&mut stepper);
+        let server_entity = stepper
+            .server_app
+            .world
+            .query_filtered::<Entity, With<Component1>>()
+            .get_single(&stepper.server_app.world)
+            .unwrap();
+        assert_eq!(
+            stepper.server_app.world.get::<Component1>(server_entity),
+            Some(&Component1(1.0))
+        );
+
+        let mut set_and_check = |value: f32, expected: f32| {
+            stepper
+                .client_app
+                .world
+                .entity_mut(client_entity)
+                .insert(Component1(value));
+            step(&mut stepper);
+            assert_eq!(
+                stepper.server_app.world.get::<Component1>(server_entity),
+                Some(&Component1(expected)),
+                "value = {value}"
+            );
+            // the client entity is corrected
+            assert_eq!(
+                stepper.client_app.world.get::<Component1>(client_entity),
+                Some(&Component1(expected)),
+                "value = {value}"
+            );
+        };
+        // accepted
+        set_and_check(2.0, 2.0);
+        // replaced by a clamped value
+        set_and_check(10.0, 5.0);
+        // rejected, and the client receives the server value
+        set_and_check(-1.0, 5.0);
+    }
+
+    /// The entities contained in a correction are mapped to the client entities
+    #[test]
+    fn test_correction_entity_mapping() {
+        let mut stepper = BevyStepper::default_with_client_replication(
+            crate::prelude::client::ReplicationConfig {
+                enable_send: true,
+                ..Default::default()
+            },
+        );
+        // make sure that the client entities are different from the server entities
+        for _ in 0..10 {
+            stepper.client_app.world.spawn_empty();
+        }
+        // an entity replicated from the server to the client
+        let server_target = stepper
+            .server_app
+            .world
+            .spawn((Component1(0.0), Replicate::default()))
+            .id();
+        step(&mut stepper);
+        let client_target = *stepper
+            .client_app
+            .world
+            .resource::<ClientConnectionManager>()
+            .replication_receiver
+            .remote_entity_map
+            .get_local(server_target)
+            .unwrap();
+        assert_ne!(client_target, server_target);
+        stepper
+            .server_app
+            .add_component_validator::<MyProtocol, Component4>(move |_, _, _| {
+                Validation::Replace(Component4(server_target))
+            });
+        let client_entity = stepper
+            .client_app
+            .world
+            .spawn((Component4(Entity::PLACEHOLDER), Replicate::default()))
+            .id();
+        step(&mut stepper);
+        assert_eq!(
+            stepper.client_app.world.get::<Component4>(client_entity),
+            Some(&Component4(client_target))
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! # Visibility filters
+//!
+//! Rules that restrict which entities are replicated to which clients, for visibility rules that
+//! don't fit into room membership (fog-of-war, stealth units only visible to their team, line-of-sight, etc.)
+//!
+//! A filter is a function `Fn(ClientId, EntityRef) -> bool` registered on the server [`App`].
+//! The filters are evaluated every `send_interval` for the entities that use [`ReplicationMode::Room`](crate::prelude::ReplicationMode::Room):
+//! an entity is replicated to a client only if the client can see the entity through the rooms (or through the
+//! [`SpatialInterestPlugin`](crate::server::spatial::SpatialInterestPlugin)) AND if all the filters accept it.
+//! The entity is spawned on the client when it becomes visible, and despawned when it stops being visible.
+//!
+//! ```rust,ignore
+//! app.add_visibility_filter(|client_id, entity| {
+//!     entity
+//!         .get::<Team>()
+//!         .map_or(true, |team| team.members.contains(&client_id))
+//! });
+//! ```
+use bevy::app::App;
+use bevy::prelude::{EntityRef, Resource};
+
+use crate::connection::id::ClientId;
+
+/// Function that returns true if the entity can be replicated to the client
+pub type VisibilityFn = Box<dyn Fn(ClientId, EntityRef) -> bool + Send + Sync>;
+
+/// Resource holding all the visibility filters registered on the server
+#[derive(Resource, Default)]
+pub struct VisibilityFilters {
+    filters: Vec<VisibilityFn>,
+}
+
+impl VisibilityFilters {
+    /// Add a new filter. An entity is visible to a client only if all the filters accept it
+    pub fn add(&mut self, filter: impl Fn(ClientId, EntityRef) -> bool + Send + Sync + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Returns true if all the filters accept replicating the entity to the client
+    pub fn is_visible(&self, client_id: ClientId, entity: EntityRef) -> bool {
+        self.filters.iter().all(|filter| filter(client_id, entity))
+    }
+}
+
+/// Extension trait to register visibility filters on the server [`App`]
+pub trait AppVisibilityExt {
+    /// Register a filter that restricts which entities are replicated to which clients
+    fn add_visibility_filter(
+        &mut self,
+        filter: impl Fn(ClientId, EntityRef) -> bool + Send + Sync + 'static,
+    ) -> &mut Self;
+}
+
+impl AppVisibilityExt for App {
+    fn add_visibility_filter(
+        &mut self,
+        filter: impl Fn(ClientId, EntityRef) -> bool + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(VisibilityFilters::default)
+            .add(filter);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::{default, With};
+
+    use crate::prelude::client::Confirmed;
+    use crate::prelude::server::{RoomId, RoomManager};
+    use crate::prelude::ReplicationMode;
+    use crate::tests::protocol::Replicate;
+    use crate::tests::protocol::*;
+    use crate::tests::stepper::{BevyStepper, Step};
+
+    use super::*;
+
+    #[test]
+    fn test_visibility_filter() {
+        let mut stepper = BevyStepper::default();
+        let client_id = ClientId::Netcode(111);
+        // entities with a large Component1 are hidden
+        stepper.server_app.add_visibility_filter(|_, entity| {
+            entity
+                .get::<Component1>()
+                .map_or(true, |component| component.0 < 5.0)
+        });
+        let server_entity = stepper
+            .server_app
+            .world
+            .spawn((
+                Component1(10.0),
+                Replicate {
+                    replication_mode: ReplicationMode::Room,
+                    ..default()
+                },
+            ))
+            .id();
+        let mut room_manager = stepper.server_app.world.resource_mut::<RoomManager>();
+        room_manager.add_client(client_id, RoomId(0));
+        room_manager.add_entity(server_entity, RoomId(0));
+
+        let mut set_and_check = |value: f32, visible: bool| {
+            stepper
+                .server_app
+                .world
+                .entity_mut(server_entity)
+                .insert(Component1(value));
+            stepper.frame_step();
+            stepper.frame_step();
+            let count = stepper
+                .client_app
+                .world
+                .query_filtered::<(), (With<Component1>, With<Confirmed>)>()
+                .iter(&stepper.client_app.world)
+                .count();
+            assert_eq!(count, visible as usize, "value = {value}");
+        };
+        // the entity shares a room with the client, but is rejected by the filter
+        set_and_check(10.0, false);
+        // gain visibility
+        set_and_check(1.0, true);
+        // lose visibility
+        set_and_check(7.0, false);
+        set_and_check(2.0, true);
+    }
+}
@@ -139,6 +139,50 @@ impl<Ctx> EntityDespawnEvent<Ctx> {
     }
 }
 
+/// Event emitted whenever an entity from the remote world is not visible to us anymore
+/// (for example because it left our rooms). The entity was not despawned on the remote.
+#[derive(Event)]
+pub struct EntityLostVisibilityEvent<Ctx = ()> {
+    entity: Entity,
+    context: Ctx,
+}
+
+impl<Ctx> EntityLostVisibilityEvent<Ctx> {
+    pub fn new(entity: Entity, context: Ctx) -> Self {
+        Self { entity, context }
+    }
+
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    pub fn context(&self) -> &Ctx {
+        &self.context
+    }
+}
+
+/// Event emitted whenever an entity from the remote world stops being replicated to us without being despawned,
+/// and our local entity was converted to a plain local entity
+#[derive(Event)]
+pub struct EntityDetachEvent<Ctx = ()> {
+    entity: Entity,
+    context: Ctx,
+}
+
+impl<Ctx> EntityDetachEvent<Ctx> {
+    pub fn new(entity: Entity, context: Ctx) -> Self {
+        Self { entity, context }
+    }
+
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    pub fn context(&self) -> &Ctx {
+        &self.context
+    }
+}
+
 /// Event emitted whenever we update a component from the remote world
 #[derive(Event)]
 pub struct ComponentUpdateEvent<C: Component, Ctx = ()> {
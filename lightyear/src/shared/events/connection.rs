@@ -27,6 +27,8 @@ pub struct ConnectionEvents<P: Protocol> {
     // replication
     pub spawns: Vec<Entity>,
     pub despawns: Vec<Entity>,
+    pub lost_visibility: Vec<Entity>,
+    pub detaches: Vec<Entity>,
 
     // TODO: [IMPORTANT]: add ticks as well?
     // - should we just return the latest update for a given component/entity, or all of them?
@@ -68,6 +70,8 @@ impl<P: Protocol> ConnectionEvents<P> {
             // replication
             spawns: Vec::new(),
             despawns: Vec::new(),
+            lost_visibility: Vec::new(),
+            detaches: Vec::new(),
             component_inserts: Default::default(),
             component_removes: Default::default(),
             component_updates: Default::default(),
@@ -83,6 +87,8 @@ impl<P: Protocol> ConnectionEvents<P> {
         self.messages.clear();
         self.spawns.clear();
         self.despawns.clear();
+        self.lost_visibility.clear();
+        self.detaches.clear();
         self.component_inserts.clear();
         self.component_removes.clear();
         self.component_updates.clear();
@@ -149,6 +155,26 @@ impl<P: Protocol> ConnectionEvents<P> {
         self.empty = false;
     }
 
+    pub(crate) fn push_lost_visibility(&mut self, entity: Entity) {
+        trace!(?entity, "Received entity lost visibility");
+        #[cfg(feature = "metrics")]
+        {
+            metrics::counter!("entity_lost_visibility").increment(1);
+        }
+        self.lost_visibility.push(entity);
+        self.empty = false;
+    }
+
+    pub(crate) fn push_detach(&mut self, entity: Entity) {
+        trace!(?entity, "Received entity detach");
+        #[cfg(feature = "metrics")]
+        {
+            metrics::counter!("entity_detach").increment(1);
+        }
+        self.detaches.push(entity);
+        self.empty = false;
+    }
+
     pub(crate) fn push_insert_component(
         &mut self,
         entity: Entity,
@@ -325,6 +351,38 @@ impl<P: Protocol> IterEntityDespawnEvent for ConnectionEvents<P> {
     }
 }
 
+pub trait IterEntityLostVisibilityEvent<Ctx: EventContext = ()> {
+    fn into_iter_entity_lost_visibility(&mut self) -> Box<dyn Iterator<Item = (Entity, Ctx)> + '_>;
+    fn has_entity_lost_visibility(&self) -> bool;
+}
+
+impl<P: Protocol> IterEntityLostVisibilityEvent for ConnectionEvents<P> {
+    fn into_iter_entity_lost_visibility(&mut self) -> Box<dyn Iterator<Item = (Entity, ())> + '_> {
+        let lost_visibility = std::mem::take(&mut self.lost_visibility);
+        Box::new(lost_visibility.into_iter().map(|entity| (entity, ())))
+    }
+
+    fn has_entity_lost_visibility(&self) -> bool {
+        !self.lost_visibility.is_empty()
+    }
+}
+
+pub trait IterEntityDetachEvent<Ctx: EventContext = ()> {
+    fn into_iter_entity_detach(&mut self) -> Box<dyn Iterator<Item = (Entity, Ctx)> + '_>;
+    fn has_entity_detach(&self) -> bool;
+}
+
+impl<P: Protocol> IterEntityDetachEvent for ConnectionEvents<P> {
+    fn into_iter_entity_detach(&mut self) -> Box<dyn Iterator<Item = (Entity, ())> + '_> {
+        let detaches = std::mem::take(&mut self.detaches);
+        Box::new(detaches.into_iter().map(|entity| (entity, ())))
+    }
+
+    fn has_entity_detach(&self) -> bool {
+        !self.detaches.is_empty()
+    }
+}
+
 /// Iterate through all the events for a given entity
 pub trait IterComponentUpdateEvent<P: Protocol, Ctx: EventContext = ()> {
     /// Find all the updates of component C
@@ -332,12 +390,27 @@ pub trait IterComponentUpdateEvent<P: Protocol, Ctx: EventContext = ()> {
         &mut self,
     ) -> Box<dyn Iterator<Item = (Entity, Ctx)> + '_>
     where
-        P::ComponentKinds: FromType<C>;
+        P::ComponentKinds: FromType<C>,
+    {
+        self.iter_component_update_by_kind(<P::ComponentKinds as FromType<C>>::from_type())
+    }
 
     /// Is there any update for component C
     fn has_component_update<C: Component>(&self) -> bool
     where
-        P::ComponentKinds: FromType<C>;
+        P::ComponentKinds: FromType<C>,
+    {
+        self.has_component_update_by_kind(<P::ComponentKinds as FromType<C>>::from_type())
+    }
+
+    /// Find all the updates for the component of the given kind
+    fn iter_component_update_by_kind(
+        &mut self,
+        kind: P::ComponentKinds,
+    ) -> Box<dyn Iterator<Item = (Entity, Ctx)> + '_>;
+
+    /// Is there any update for the component of the given kind
+    fn has_component_update_by_kind(&self, kind: P::ComponentKinds) -> bool;
 
     // /// Find all the updates of component C for a given entity
     // fn get_component_update<C: Component>(&self, entity: Entity) -> Option<Ctx>
@@ -346,12 +419,11 @@ pub trait IterComponentUpdateEvent<P: Protocol, Ctx: EventContext = ()> {
 }
 
 impl<P: Protocol> IterComponentUpdateEvent<P> for ConnectionEvents<P> {
-    fn iter_component_update<C: Component>(&mut self) -> Box<dyn Iterator<Item = (Entity, ())> + '_>
-    where
-        P::ComponentKinds: FromType<C>,
-    {
-        let component_kind = <P::ComponentKinds as FromType<C>>::from_type();
-        if let Some(data) = self.component_updates.remove(&component_kind) {
+    fn iter_component_update_by_kind(
+        &mut self,
+        kind: P::ComponentKinds,
+    ) -> Box<dyn Iterator<Item = (Entity, ())> + '_> {
+        if let Some(data) = self.component_updates.remove(&kind) {
             return Box::new(data.into_iter().map(|entity| (entity, ())));
         }
         Box::new(iter::empty())
@@ -364,12 +436,8 @@ impl<P: Protocol> IterComponentUpdateEvent<P> for ConnectionEvents<P> {
         // )
     }
 
-    fn has_component_update<C: Component>(&self) -> bool
-    where
-        P::ComponentKinds: FromType<C>,
-    {
-        let component_kind = <P::ComponentKinds as FromType<C>>::from_type();
-        self.component_updates.contains_key(&component_kind)
+    fn has_component_update_by_kind(&self, kind: P::ComponentKinds) -> bool {
+        self.component_updates.contains_key(&kind)
         // self.components_with_updates.contains(&C::into_kind())
     }
 
@@ -393,31 +461,37 @@ pub trait IterComponentRemoveEvent<P: Protocol, Ctx: EventContext = ()> {
         &mut self,
     ) -> Box<dyn Iterator<Item = (Entity, Ctx)> + '_>
     where
-        P::ComponentKinds: FromType<C>;
+        P::ComponentKinds: FromType<C>,
+    {
+        self.iter_component_remove_by_kind(<P::ComponentKinds as FromType<C>>::from_type())
+    }
     fn has_component_remove<C: Component>(&self) -> bool
     where
-        P::ComponentKinds: FromType<C>;
+        P::ComponentKinds: FromType<C>,
+    {
+        self.has_component_remove_by_kind(<P::ComponentKinds as FromType<C>>::from_type())
+    }
+    fn iter_component_remove_by_kind(
+        &mut self,
+        kind: P::ComponentKinds,
+    ) -> Box<dyn Iterator<Item = (Entity, Ctx)> + '_>;
+    fn has_component_remove_by_kind(&self, kind: P::ComponentKinds) -> bool;
 }
 
 // TODO: move these implementations to client?
 impl<P: Protocol> IterComponentRemoveEvent<P> for ConnectionEvents<P> {
-    fn iter_component_remove<C: Component>(&mut self) -> Box<dyn Iterator<Item = (Entity, ())> + '_>
-    where
-        P::ComponentKinds: FromType<C>,
-    {
-        let component_kind = <P::ComponentKinds as FromType<C>>::from_type();
-        if let Some(data) = self.component_removes.remove(&component_kind) {
+    fn iter_component_remove_by_kind(
+        &mut self,
+        kind: P::ComponentKinds,
+    ) -> Box<dyn Iterator<Item = (Entity, ())> + '_> {
+        if let Some(data) = self.component_removes.remove(&kind) {
             return Box::new(data.into_iter().map(|entity| (entity, ())));
         }
         Box::new(iter::empty())
     }
 
-    fn has_component_remove<C: Component>(&self) -> bool
-    where
-        P::ComponentKinds: FromType<C>,
-    {
-        let component_kind = <P::ComponentKinds as FromType<C>>::from_type();
-        self.component_removes.contains_key(&component_kind)
+    fn has_component_remove_by_kind(&self, kind: P::ComponentKinds) -> bool {
+        self.component_removes.contains_key(&kind)
     }
 }
 
@@ -426,30 +500,36 @@ pub trait IterComponentInsertEvent<P: Protocol, Ctx: EventContext = ()> {
         &mut self,
     ) -> Box<dyn Iterator<Item = (Entity, Ctx)> + '_>
     where
-        P::ComponentKinds: FromType<C>;
+        P::ComponentKinds: FromType<C>,
+    {
+        self.iter_component_insert_by_kind(<P::ComponentKinds as FromType<C>>::from_type())
+    }
     fn has_component_insert<C: Component>(&self) -> bool
     where
-        P::ComponentKinds: FromType<C>;
+        P::ComponentKinds: FromType<C>,
+    {
+        self.has_component_insert_by_kind(<P::ComponentKinds as FromType<C>>::from_type())
+    }
+    fn iter_component_insert_by_kind(
+        &mut self,
+        kind: P::ComponentKinds,
+    ) -> Box<dyn Iterator<Item = (Entity, Ctx)> + '_>;
+    fn has_component_insert_by_kind(&self, kind: P::ComponentKinds) -> bool;
 }
 
 impl<P: Protocol> IterComponentInsertEvent<P> for ConnectionEvents<P> {
-    fn iter_component_insert<C: Component>(&mut self) -> Box<dyn Iterator<Item = (Entity, ())> + '_>
-    where
-        P::ComponentKinds: FromType<C>,
-    {
-        let component_kind = <P::ComponentKinds as FromType<C>>::from_type();
-        if let Some(data) = self.component_inserts.remove(&component_kind) {
+    fn iter_component_insert_by_kind(
+        &mut self,
+        kind: P::ComponentKinds,
+    ) -> Box<dyn Iterator<Item = (Entity, ())> + '_> {
+        if let Some(data) = self.component_inserts.remove(&kind) {
             return Box::new(data.into_iter().map(|entity| (entity, ())));
         }
         Box::new(iter::empty())
     }
 
-    fn has_component_insert<C: Component>(&self) -> bool
-    where
-        P::ComponentKinds: FromType<C>,
-    {
-        let component_kind = <P::ComponentKinds as FromType<C>>::from_type();
-        self.component_inserts.contains_key(&component_kind)
+    fn has_component_insert_by_kind(&self, kind: P::ComponentKinds) -> bool {
+        self.component_inserts.contains_key(&kind)
     }
 }
 
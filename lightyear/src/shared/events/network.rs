@@ -0,0 +1,263 @@
+//! Replicate bevy [`Event`]s over the network
+//!
+//! An event registered with [`AppRegistrationExt::register_network_event`] is sent to the remote every time it is
+//! written with an [`EventWriter`](bevy::prelude::EventWriter), and is emitted on the remote as a regular bevy event
+//! that can be read with an [`EventReader`]:
+//! ```rust,ignore
+//! app.register_network_event::<Explosion, Channel1>(ChannelDirection::ServerToClient, NetworkTarget::All);
+//!
+//! // server
+//! fn explode(mut events: EventWriter<Explosion>) {
+//!     events.send(Explosion { radius: 2.0 });
+//! }
+//!
+//! // client
+//! fn on_explosion(mut events: EventReader<Explosion>) {
+//!     for explosion in events.read() { /* ... */ }
+//! }
+//! ```
+//!
+//! Events that are about a specific entity can be registered with [`AppRegistrationExt::register_entity_network_event`]:
+//! they are only sent to the clients that the entity is replicated to, and the entities they contain are mapped on the remote.
+//!
+//! The events that were received from the remote are not sent back, even if the event is registered as
+//! [`ChannelDirection::Bidirectional`].
+//!
+//! [`AppRegistrationExt::register_network_event`]: crate::protocol::registration::AppRegistrationExt::register_network_event
+//! [`AppRegistrationExt::register_entity_network_event`]: crate::protocol::registration::AppRegistrationExt::register_entity_network_event
+use bevy::ecs::event::EventId;
+use bevy::prelude::{Entity, Event, EventReader, Res, ResMut, Resource};
+use tracing::error;
+
+use crate::channel::builder::ChannelDirection;
+use crate::packet::message::Message;
+use crate::prelude::NetworkTarget;
+use crate::protocol::channel::ChannelKind;
+use crate::protocol::registration::{ProtocolRegistry, RegisteredMessage};
+
+/// How a registered network event is sent
+pub(crate) struct NetworkEventSettings<E> {
+    pub(crate) channel: ChannelKind,
+    pub(crate) direction: ChannelDirection,
+    pub(crate) target: NetworkTarget,
+    /// Returns the entity that the event is tied to
+    pub(crate) entity: Option<fn(&E) -> Entity>,
+}
+
+/// A network event that was written locally and is waiting to be sent
+#[derive(Debug)]
+pub(crate) struct QueuedNetworkEvent {
+    pub(crate) message: RegisteredMessage,
+    pub(crate) channel: ChannelKind,
+    pub(crate) direction: ChannelDirection,
+    pub(crate) target: NetworkTarget,
+    pub(crate) entity: Option<Entity>,
+}
+
+impl QueuedNetworkEvent {
+    /// Returns true if the event can be sent in the given direction
+    pub(crate) fn can_send(&self, direction: &ChannelDirection) -> bool {
+        self.direction == ChannelDirection::Bidirectional || &self.direction == direction
+    }
+}
+
+/// Network events that are waiting to be sent by the connection manager
+#[derive(Resource, Debug, Default)]
+pub(crate) struct NetworkEventQueue(pub(crate) Vec<QueuedNetworkEvent>);
+
+/// Ids of the events that were received from the remote during the current frame
+#[derive(Resource, Debug)]
+pub(crate) struct ReceivedNetworkEvents<E: Event>(Vec<EventId<E>>);
+
+impl<E: Event> Default for ReceivedNetworkEvents<E> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<E: Event> ReceivedNetworkEvents<E> {
+    pub(crate) fn push(&mut self, id: EventId<E>) {
+        self.0.push(id);
+    }
+}
+
+/// Serialize the events that were written during the frame, and add them to the [`NetworkEventQueue`].
+///
+/// This runs every frame, because bevy events are only kept for two frames.
+pub(crate) fn queue_network_events<E: Event + Message>(
+    settings: NetworkEventSettings<E>,
+) -> impl FnMut(
+    EventReader<E>,
+    ResMut<ReceivedNetworkEvents<E>>,
+    Res<ProtocolRegistry>,
+    ResMut<NetworkEventQueue>,
+) {
+    move |mut events, mut received, registry, mut queue| {
+        let Some(net_id) = registry.message_net_id::<E>() else {
+            events.clear();
+            return;
+        };
+        for (event, id) in events.read_with_id() {
+            if received.0.contains(&id) {
+                continue;
+            }
+            let message = match RegisteredMessage::new(net_id, event) {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("error serializing network event: {:?}", e);
+                    continue;
+                }
+            };
+            queue.0.push(QueuedNetworkEvent {
+                message,
+                channel: settings.channel,
+                direction: settings.direction.clone(),
+                target: settings.target.clone(),
+                entity: settings.entity.map(|entity| entity(event)),
+            });
+        }
+        received.0.clear();
+    }
+}
+
+/// Drop the queued network events when there is no connection to send them on, so that the
+/// [`NetworkEventQueue`] does not keep growing while disconnected
+pub(crate) fn clear_network_event_queue(mut queue: ResMut<NetworkEventQueue>) {
+    queue.0.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::entity::{EntityMapper, MapEntities};
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::prelude::*;
+    use bevy::utils::Duration;
+    use serde::{Deserialize, Serialize};
+
+    use crate::prelude::client::*;
+    use crate::prelude::*;
+    use crate::tests::protocol::*;
+    use crate::tests::stepper::{BevyStepper, Step};
+
+    use super::*;
+
+    #[derive(Event, Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct Explosion(u32);
+
+    #[derive(Event, Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct Hit(Entity);
+
+    impl MapEntities for Hit {
+        fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+            self.0 = entity_mapper.map_entity(self.0);
+        }
+    }
+
+    #[derive(Resource, Default)]
+    struct Received {
+        explosions: Vec<Explosion>,
+        hits: Vec<Hit>,
+    }
+
+    fn receive_events(
+        mut explosions: EventReader<Explosion>,
+        mut hits: EventReader<Hit>,
+        mut received: ResMut<Received>,
+    ) {
+        received.explosions.extend(explosions.read().cloned());
+        received.hits.extend(hits.read().cloned());
+    }
+
+    #[test]
+    fn test_network_events() {
+        let frame_duration = Duration::from_millis(10);
+        let tick_duration = Duration::from_millis(10);
+        let shared_config = SharedConfig {
+            tick: TickConfig::new(tick_duration),
+            ..Default::default()
+        };
+        let link_conditioner = LinkConditionerConfig {
+            incoming_latency: Duration::from_millis(0),
+            incoming_jitter: Duration::from_millis(0),
+            incoming_loss: 0.0,
+        };
+        let mut stepper = BevyStepper::new(
+            shared_config,
+            SyncConfig::default().speedup_factor(1.0),
+            PredictionConfig::default(),
+            InterpolationConfig::default(),
+            link_conditioner,
+            frame_duration,
+        );
+        for app in [&mut stepper.server_app, &mut stepper.client_app] {
+            app.register_network_event::<Explosion, Channel1>(
+                ChannelDirection::ServerToClient,
+                NetworkTarget::All,
+            )
+            .register_entity_network_event::<Hit, Channel1>(
+                ChannelDirection::ServerToClient,
+                NetworkTarget::All,
+                |hit| hit.0,
+            );
+        }
+        stepper.client_app.init_resource::<Received>();
+        stepper.client_app.add_systems(Update, receive_events);
+        stepper.init();
+
+        // events written on the server are emitted on the client
+        stepper.server_app.world.send_event(Explosion(1));
+        stepper.frame_step();
+        stepper.frame_step();
+        assert_eq!(
+            stepper.client_app.world.resource::<Received>().explosions,
+            vec![Explosion(1)]
+        );
+
+        // entity events are only sent if the entity is replicated to the client
+        let server_entity = stepper.server_app.world.spawn(Replicate::default()).id();
+        let hidden_entity = stepper
+            .server_app
+            .world
+            .spawn(Replicate {
+                replication_target: NetworkTarget::None,
+                ..default()
+            })
+            .id();
+        stepper.frame_step();
+        stepper.frame_step();
+        stepper.server_app.world.send_event(Hit(server_entity));
+        stepper.server_app.world.send_event(Hit(hidden_entity));
+        stepper.frame_step();
+        stepper.frame_step();
+
+        // the entity is mapped to the client entity
+        let client_entity = *stepper
+            .client_app
+            .world
+            .resource::<ClientConnectionManager>()
+            .replication_receiver
+            .remote_entity_map
+            .get_local(server_entity)
+            .unwrap();
+        assert_eq!(
+            stepper.client_app.world.resource::<Received>().hits,
+            vec![Hit(client_entity)]
+        );
+
+        // the events written while disconnected are not queued
+        stepper
+            .client_app
+            .world
+            .run_system_once(|mut commands: Commands| commands.disconnect_client());
+        for i in 0..5 {
+            stepper.client_app.world.send_event(Explosion(i));
+            stepper.frame_step();
+        }
+        assert!(stepper
+            .client_app
+            .world
+            .resource::<NetworkEventQueue>()
+            .0
+            .is_empty());
+    }
+}
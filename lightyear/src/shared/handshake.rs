@@ -0,0 +1,43 @@
+//! Messages exchanged when a client connects, to agree on the settings of the connection.
+//!
+//! The ack window is negotiated in three steps, so that a peer never reads the header of a packet
+//! with a bigger window than the one that was used to write it:
+//! - the client sends a [`HandshakeMessage::Request`] with the ack window it would like to use
+//! - the server picks the smallest of the requested window and of its own maximum window, starts using it
+//!   and answers with a [`HandshakeMessage::Response`] containing the id of its first packet that uses the window
+//! - the client starts using the window and answers with a [`HandshakeMessage::Confirm`] containing the id of
+//!   its first packet that uses the window
+//!
+//! The client also sends a [`HandshakeMessage::Registry`] with the hash of its
+//! [`ProtocolRegistry`](crate::protocol::registration::ProtocolRegistry); the server disconnects the client
+//! if it doesn't match its own registry.
+use serde::{Deserialize, Serialize};
+
+use crate::packet::header::AckBitfieldSize;
+use crate::packet::packet::PacketId;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum HandshakeMessage {
+    /// Sent by the client when it connects
+    Request {
+        /// Ack window requested by the client
+        ack_bitfield_size: AckBitfieldSize,
+    },
+    /// Sent by the server in response to a [`HandshakeMessage::Request`]
+    Response {
+        /// Ack window used on the connection
+        ack_bitfield_size: AckBitfieldSize,
+        /// Id of the first server packet whose header uses the ack window
+        first_packet_id: PacketId,
+    },
+    /// Sent by the client in response to a [`HandshakeMessage::Response`]
+    Confirm {
+        /// Id of the first client packet whose header uses the ack window
+        first_packet_id: PacketId,
+    },
+    /// Sent by the client when it connects
+    Registry {
+        /// Hash of the types registered at runtime
+        hash: u64,
+    },
+}
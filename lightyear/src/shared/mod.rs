@@ -4,6 +4,8 @@ pub mod config;
 
 pub mod events;
 
+pub mod handshake;
+
 pub mod log;
 
 pub mod ping;
@@ -14,6 +16,8 @@ pub mod replication;
 
 pub mod sets;
 
+pub mod stats;
+
 pub mod tick_manager;
 
 pub mod time_manager;
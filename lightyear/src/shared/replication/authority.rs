@@ -0,0 +1,245 @@
+//! Authority model for replicated entities: which peer is allowed to write to an entity
+use bevy::prelude::{Changed, Commands, Component, Entity, EntityWorldMut, Has, Query, Res, With};
+use bevy::reflect::Reflect;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::client::components::Confirmed;
+use crate::client::connection::ConnectionManager;
+use crate::connection::client::NetClient;
+use crate::connection::id::ClientId;
+use crate::prelude::client::ClientConnection;
+use crate::prelude::{Protocol, ShouldBePredicted};
+use crate::protocol::component::FromType;
+use crate::shared::replication::components::{
+    Replicate, ReplicateRemoveBehaviour, ShouldBeInterpolated,
+};
+
+/// Records which peer is allowed to write to a replicated entity.
+///
+/// The component is owned by the server: it is replicated to the clients like any other component,
+/// and only the server can modify it. To transfer the authority over an entity to a client
+/// (for example for a vehicle that a player enters), insert `Authority::Client(client_id)` on the
+/// server entity; insert `Authority::Server` to take it back.
+///
+/// Replication updates coming from a peer that doesn't have authority over the entity are rejected:
+/// - the server only accepts updates from a client for entities where that client has authority
+/// - a client ignores the updates from the server for the entities it has authority over
+///
+/// Entities without an `Authority` component keep the default behaviour: the peer that spawned them writes to them.
+#[derive(
+    Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, Reflect,
+)]
+pub enum Authority {
+    /// The server has authority over the entity
+    #[default]
+    Server,
+    /// The client with the given id has authority over the entity
+    Client(ClientId),
+}
+
+/// Marker component inserted on the client for the entities that the client currently has authority over.
+///
+/// While this component is present, the entity is replicated from the client to the server, and
+/// the client ignores the replication updates sent by the server for this entity (apart from the
+/// [`Authority`] component itself).
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub struct HasAuthority;
+
+/// Sent by a client along with the spawn of an entity that it received authority over.
+///
+/// It lets the server map the client entity to its own entity instead of spawning a new one.
+// NOTE: we do not map entities for this component, we want to receive the server entity as is
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct AuthorityTransfer {
+    pub(crate) server_entity: Entity,
+}
+
+impl Authority {
+    /// Returns true if the peer `remote` (the peer that sent a replication message) is allowed
+    /// to write the component `kind` to the local `entity`.
+    pub(crate) fn can_write<P: Protocol>(
+        remote: Authority,
+        entity: &EntityWorldMut,
+        kind: P::ComponentKinds,
+    ) -> bool {
+        let is_authority = kind == <P::ComponentKinds as FromType<Authority>>::from_type();
+        match remote {
+            // the server can always update the authority, but cannot write to entities owned by the local client
+            Authority::Server => is_authority || !entity.contains::<HasAuthority>(),
+            // clients can never modify the authority
+            Authority::Client(_) => !is_authority && Self::can_despawn(remote, entity),
+        }
+    }
+
+    /// Returns true if the peer `remote` is allowed to despawn the local `entity`
+    pub(crate) fn can_despawn(remote: Authority, entity: &EntityWorldMut) -> bool {
+        match remote {
+            Authority::Server => true,
+            Authority::Client(_) => entity
+                .get::<Authority>()
+                .map_or(true, |authority| *authority == remote),
+        }
+    }
+}
+
+/// When the server changes the authority of a confirmed entity, start or stop replicating that
+/// entity from the client to the server.
+pub(crate) fn handle_authority_change<P: Protocol>(
+    mut commands: Commands,
+    netclient: Res<ClientConnection>,
+    connection: Res<ConnectionManager<P>>,
+    query: Query<(Entity, &Authority, Has<HasAuthority>), (Changed<Authority>, With<Confirmed>)>,
+) {
+    let local = Authority::Client(netclient.id());
+    for (entity, authority, has_authority) in query.iter() {
+        match (*authority == local, has_authority) {
+            (true, false) => {
+                let Some(server_entity) = connection
+                    .replication_receiver
+                    .remote_entity_map
+                    .get_remote(entity)
+                    .copied()
+                else {
+                    continue;
+                };
+                debug!(?entity, "gained authority over entity");
+                let mut replicate = Replicate::<P> {
+                    // losing the authority must not despawn the server entity
+                    on_remove: Some(ReplicateRemoveBehaviour::Freeze),
+                    ..Default::default()
+                };
+                replicate.disable_component::<Authority>();
+                replicate.disable_component::<ShouldBePredicted>();
+                replicate.disable_component::<ShouldBeInterpolated>();
+                commands.entity(entity).insert((
+                    HasAuthority,
+                    AuthorityTransfer { server_entity },
+                    replicate,
+                ));
+            }
+            (false, true) => {
+                debug!(?entity, "lost authority over entity");
+                // removing `Replicate` stops the replication without despawning the server entity
+                commands
+                    .entity(entity)
+                    .remove::<(HasAuthority, AuthorityTransfer, Replicate<P>)>();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::{Entity, With};
+
+    use crate::prelude::client::Confirmed;
+    use crate::prelude::ClientId;
+    use crate::tests::protocol::*;
+    use crate::tests::stepper::{BevyStepper, Step};
+
+    use super::{Authority, AuthorityTransfer, HasAuthority};
+
+    fn step(stepper: &mut BevyStepper) {
+        for _ in 0..5 {
+            stepper.frame_step();
+        }
+    }
+
+    #[test]
+    fn test_authority_transfer() {
+        // enable client->server replication, for the entities that the client has authority over
+        let mut stepper = BevyStepper::default_with_client_replication(
+            crate::prelude::client::ReplicationConfig {
+                enable_send: true,
+                ..Default::default()
+            },
+        );
+        let client_id = ClientId::Netcode(111);
+        let server_entity = stepper
+            .server_app
+            .world
+            .spawn((Component1(0.0), Authority::Server, Replicate::default()))
+            .id();
+        step(&mut stepper);
+        let client_entity = stepper
+            .client_app
+            .world
+            .query_filtered::<Entity, (With<Component1>, With<Confirmed>)>()
+            .get_single(&stepper.client_app.world)
+            .unwrap();
+
+        // transfer the authority to the client: the client starts replicating the entity
+        stepper
+            .server_app
+            .world
+            .entity_mut(server_entity)
+            .insert(Authority::Client(client_id));
+        step(&mut stepper);
+        assert!(stepper
+            .client_app
+            .world
+            .entity(client_entity)
+            .contains::<HasAuthority>());
+        stepper
+            .client_app
+            .world
+            .entity_mut(client_entity)
+            .insert(Component1(1.0));
+        step(&mut stepper);
+        // the update is applied to the server entity, no new entity is spawned on the server
+        assert_eq!(
+            stepper.server_app.world.get::<Component1>(server_entity),
+            Some(&Component1(1.0))
+        );
+        assert_eq!(
+            stepper
+                .server_app
+                .world
+                .query::<&Component1>()
+                .iter(&stepper.server_app.world)
+                .count(),
+            1
+        );
+
+        // the client ignores server updates while it has authority
+        stepper
+            .server_app
+            .world
+            .entity_mut(server_entity)
+            .insert(Component1(5.0));
+        step(&mut stepper);
+        assert_eq!(
+            stepper.client_app.world.get::<Component1>(client_entity),
+            Some(&Component1(1.0))
+        );
+
+        // take the authority back
+        stepper
+            .server_app
+            .world
+            .entity_mut(server_entity)
+            .insert((Authority::Server, Component1(3.0)));
+        step(&mut stepper);
+        let client_entity_ref = stepper.client_app.world.entity(client_entity);
+        assert!(!client_entity_ref.contains::<HasAuthority>());
+        assert!(!client_entity_ref.contains::<Replicate>());
+        assert_eq!(
+            client_entity_ref.get::<Component1>(),
+            Some(&Component1(3.0))
+        );
+
+        // updates from a client without authority are rejected
+        stepper.client_app.world.entity_mut(client_entity).insert((
+            AuthorityTransfer { server_entity },
+            Component1(10.0),
+            Replicate::default(),
+        ));
+        step(&mut stepper);
+        assert_eq!(
+            stepper.server_app.world.get::<Component1>(server_entity),
+            Some(&Component1(3.0))
+        );
+    }
+}
@@ -3,14 +3,16 @@ use bevy::prelude::{Entity, World};
 
 use crate::_reexport::ReplicationSend;
 use crate::prelude::Protocol;
-use crate::shared::replication::components::Replicate;
+use crate::shared::replication::components::{Replicate, ReplicateRemoveBehaviour};
 
 pub struct RemoveReplicate;
 
 fn remove_replicate<P: Protocol, R: ReplicationSend<P>>(entity: Entity, world: &mut World) {
     let mut sender = world.resource_mut::<R>();
     // remove the entity from the cache of entities that are being replicated
-    // so that if it gets despawned, the despawn won't be replicated
+    // so that if it gets despawned, the despawn won't be replicated.
+    // This also means that the removal of the component is not handled: the remote entity is always frozen,
+    // regardless of `Replicate::on_remove` and of the global `ReplicateRemoveBehaviour`
     sender.get_mut_replicate_component_cache().remove(&entity);
     // remove the replicate component
     if let Some(mut entity) = world.get_entity_mut(entity) {
@@ -18,18 +20,47 @@ fn remove_replicate<P: Protocol, R: ReplicationSend<P>>(entity: Entity, world: &
     }
 }
 
+fn remove_replicate_with<P: Protocol, R: ReplicationSend<P>>(
+    entity: Entity,
+    world: &mut World,
+    behaviour: ReplicateRemoveBehaviour,
+) {
+    let mut sender = world.resource_mut::<R>();
+    // the behaviour is read from the cache when the removal of the component is handled
+    if let Some(replicate) = sender.get_mut_replicate_component_cache().get_mut(&entity) {
+        replicate.on_remove = Some(behaviour);
+    }
+    if let Some(mut entity) = world.get_entity_mut(entity) {
+        entity.remove::<Replicate<P>>();
+    }
+}
+
 pub trait RemoveReplicateCommandsExt<P: Protocol, R: ReplicationSend<P>> {
     /// Remove the replicate component from the entity.
     /// This also makes sure that if you despawn the entity right after, the despawn won't be replicated.
     ///
     /// This can be useful when you want to despawn an entity on the server, but you don't want the despawn to be replicated
     /// immediately to clients (for example because clients are playing a despawn animation)/
+    ///
+    /// The remote entity is always left as is ([`ReplicateRemoveBehaviour::Freeze`]): [`Replicate::on_remove`] and the
+    /// global `on_replicate_remove` setting of the `ReplicationConfig` are ignored.
+    /// Use [`remove_replicate_with`](Self::remove_replicate_with) to apply a different behaviour.
     fn remove_replicate(&mut self);
+
+    /// Remove the replicate component from the entity, and choose what happens to the remote entity
+    /// (this overrides [`Replicate::on_remove`])
+    fn remove_replicate_with(&mut self, behaviour: ReplicateRemoveBehaviour);
 }
 impl<P: Protocol, R: ReplicationSend<P>> RemoveReplicateCommandsExt<P, R> for EntityCommands<'_> {
     fn remove_replicate(&mut self) {
         self.add(remove_replicate::<P, R>);
     }
+
+    fn remove_replicate_with(&mut self, behaviour: ReplicateRemoveBehaviour) {
+        self.add(move |entity: Entity, world: &mut World| {
+            remove_replicate_with::<P, R>(entity, world, behaviour);
+        });
+    }
 }
 
 #[cfg(test)]
@@ -37,7 +68,11 @@ mod tests {
     use bevy::utils::Duration;
 
     use crate::client::sync::SyncConfig;
-    use crate::prelude::client::{InterpolationConfig, PredictionConfig};
+    use bevy::prelude::Events;
+
+    use crate::prelude::client::{
+        Confirmed, EntityDetachEvent, InterpolationConfig, PredictionConfig,
+    };
     use crate::prelude::{LinkConditionerConfig, SharedConfig, TickConfig};
     use crate::tests::protocol::Replicate;
     use crate::tests::protocol::*;
@@ -134,5 +169,95 @@ mod tests {
             .query::<&Component1>()
             .get_single(&stepper.client_app.world)
             .is_ok());
+
+        // the remote entity is frozen even if the entity asks to despawn it when `Replicate` is removed
+        let entity = stepper
+            .server_app
+            .world
+            .spawn((
+                Component2(1.0),
+                Replicate {
+                    on_remove: Some(ReplicateRemoveBehaviour::Despawn),
+                    ..Default::default()
+                },
+            ))
+            .id();
+        stepper.frame_step();
+        stepper.frame_step();
+        remove_replicate::<MyProtocol, ServerConnectionManager>(
+            entity,
+            &mut stepper.server_app.world,
+        );
+        stepper.frame_step();
+        stepper.frame_step();
+        assert!(stepper
+            .client_app
+            .world
+            .query::<&Component2>()
+            .get_single(&stepper.client_app.world)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_replicate_remove_behaviour() {
+        let mut stepper = BevyStepper::default();
+        let spawn = |stepper: &mut BevyStepper, on_remove| {
+            let server_entity = stepper
+                .server_app
+                .world
+                .spawn((
+                    Component1(1.0),
+                    Replicate {
+                        on_remove,
+                        ..Default::default()
+                    },
+                ))
+                .id();
+            stepper.frame_step();
+            stepper.frame_step();
+            let client_entity = *stepper
+                .client_app
+                .world
+                .resource::<ClientConnectionManager>()
+                .replication_receiver
+                .remote_entity_map
+                .get_local(server_entity)
+                .unwrap();
+            stepper
+                .server_app
+                .world
+                .entity_mut(server_entity)
+                .remove::<Replicate>();
+            stepper.frame_step();
+            stepper.frame_step();
+            client_entity
+        };
+
+        // by default, the client entity is kept but doesn't receive updates anymore
+        let client_entity = spawn(&mut stepper, None);
+        assert!(stepper
+            .client_app
+            .world
+            .entity(client_entity)
+            .contains::<Confirmed>());
+
+        let client_entity = spawn(&mut stepper, Some(ReplicateRemoveBehaviour::Despawn));
+        assert!(stepper.client_app.world.get_entity(client_entity).is_none());
+
+        let client_entity = spawn(&mut stepper, Some(ReplicateRemoveBehaviour::Detach));
+        let client_entity_ref = stepper.client_app.world.entity(client_entity);
+        assert!(!client_entity_ref.contains::<Confirmed>());
+        assert_eq!(
+            client_entity_ref.get::<Component1>(),
+            Some(&Component1(1.0))
+        );
+        assert_eq!(
+            stepper
+                .client_app
+                .world
+                .resource::<Events<EntityDetachEvent>>()
+                .len(),
+            1
+        );
     }
 }
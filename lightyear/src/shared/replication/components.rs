@@ -1,8 +1,10 @@
 //! Components used for replication
+use std::num::NonZeroU16;
+
 use bevy::ecs::entity::MapEntities;
 use bevy::ecs::query::QueryFilter;
 use bevy::prelude::{Component, Entity, EntityMapper, Reflect};
-use bevy::utils::{HashMap, HashSet};
+use bevy::utils::{Duration, HashMap, HashSet};
 use serde::{Deserialize, Serialize};
 use tracing::trace;
 
@@ -37,8 +39,15 @@ pub struct Replicate<P: Protocol> {
     #[doc(hidden)]
     pub replication_clients_cache: HashMap<ClientId, ClientVisibility>,
     pub replication_mode: ReplicationMode,
-    // TODO: currently, if the host removes Replicate, then the entity is not removed in the remote
-    //  it just keeps living but doesn't receive any updates. Should we make this configurable?
+    /// What happens to the remote entity when the `Replicate` component is removed from this entity
+    /// (while the entity itself is not despawned).
+    /// If `None`, the default from the `ReplicationConfig` is used.
+    pub on_remove: Option<ReplicateRemoveBehaviour>,
+    /// If set, the entity goes dormant when none of its replicated components changed for this many ticks:
+    /// no updates are gathered for the entity until one of its components changes again.
+    /// (see [`Dormant`](crate::shared::replication::dormancy::Dormant)).
+    /// Values above `i16::MAX` are clamped to `i16::MAX`.
+    pub dormancy: Option<NonZeroU16>,
     pub replication_group: ReplicationGroup,
     /// If true, recursively add `Replicate` and `ParentSync` components to all children to make sure they are replicated
     /// If false, you can still replicate hierarchies, but in a more fine-grained manner. You will have to add the `Replicate`
@@ -49,6 +58,21 @@ pub struct Replicate<P: Protocol> {
     pub per_component_metadata: HashMap<P::ComponentKinds, PerComponentReplicationMetadata>,
 }
 
+/// What happens to the remote entity when the [`Replicate`] component is removed from a local entity
+/// that is not despawned
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Reflect)]
+pub enum ReplicateRemoveBehaviour {
+    /// The remote entity keeps living but doesn't receive any updates anymore
+    #[default]
+    Freeze,
+    /// The remote entity is despawned
+    Despawn,
+    /// The remote entity is converted to a plain local entity: it is not linked to this entity anymore
+    /// (the [`Confirmed`](crate::client::components::Confirmed) component is removed) and an
+    /// `EntityDetachEvent` is emitted on the remote
+    Detach,
+}
+
 /// This lets you specify how to customize the replication behaviour for a given component
 #[derive(Clone, Debug, PartialEq, Reflect)]
 pub struct PerComponentReplicationMetadata {
@@ -62,6 +86,10 @@ pub struct PerComponentReplicationMetadata {
     /// Custom replication target for this component. We will replicate to the intersection of
     /// the entity's replication target and this target
     target: NetworkTarget,
+    /// Minimum interval between two updates of the component. If None, the component is updated
+    /// every time the replication systems run (every `send_interval`).
+    /// A component that changes and then stops changing still gets a final update with its latest value.
+    send_interval: Option<Duration>,
 }
 impl Default for PerComponentReplicationMetadata {
     fn default() -> Self {
@@ -69,21 +97,44 @@ impl Default for PerComponentReplicationMetadata {
             disabled: false,
             replicate_once: false,
             target: NetworkTarget::All,
+            send_interval: None,
         }
     }
 }
 
 impl<P: Protocol> Replicate<P> {
-    pub(crate) fn group_id(&self, entity: Option<Entity>) -> ReplicationGroupId {
+    /// The id of the replication group of the entity
+    pub fn group_id(&self, entity: Option<Entity>) -> ReplicationGroupId {
         self.replication_group.group_id(entity)
     }
 
+    /// The remotes that the entity is currently replicated to
+    pub(crate) fn replicated_to(&self) -> NetworkTarget {
+        match self.replication_mode {
+            ReplicationMode::Room => {
+                let mut target = NetworkTarget::Only(
+                    self.replication_clients_cache
+                        .iter()
+                        .filter(|(_, visibility)| !matches!(visibility, ClientVisibility::Lost))
+                        .map(|(client_id, _)| *client_id)
+                        .collect(),
+                );
+                target.intersection(self.replication_target.clone());
+                target
+            }
+            ReplicationMode::NetworkTarget => self.replication_target.clone(),
+        }
+    }
+
     /// Returns true if we don't want to replicate the component
     pub fn is_disabled<C>(&self) -> bool
     where
         P::ComponentKinds: FromType<C>,
     {
-        let kind = <P::ComponentKinds as FromType<C>>::from_type();
+        self.is_disabled_by_kind(<P::ComponentKinds as FromType<C>>::from_type())
+    }
+
+    pub(crate) fn is_disabled_by_kind(&self, kind: P::ComponentKinds) -> bool {
         self.per_component_metadata
             .get(&kind)
             .is_some_and(|metadata| metadata.disabled)
@@ -95,7 +146,10 @@ impl<P: Protocol> Replicate<P> {
     where
         P::ComponentKinds: FromType<C>,
     {
-        let kind = <P::ComponentKinds as FromType<C>>::from_type();
+        self.is_replicate_once_by_kind(<P::ComponentKinds as FromType<C>>::from_type())
+    }
+
+    pub(crate) fn is_replicate_once_by_kind(&self, kind: P::ComponentKinds) -> bool {
         self.per_component_metadata
             .get(&kind)
             .is_some_and(|metadata| metadata.replicate_once)
@@ -104,11 +158,21 @@ impl<P: Protocol> Replicate<P> {
     /// Replication target for this specific component
     /// This will be the intersection of the provided `entity_target`, and the `target` of the component
     /// if it exists
-    pub fn target<C>(&self, mut entity_target: NetworkTarget) -> NetworkTarget
+    pub fn target<C>(&self, entity_target: NetworkTarget) -> NetworkTarget
     where
         P::ComponentKinds: FromType<C>,
     {
-        let kind = <P::ComponentKinds as FromType<C>>::from_type();
+        self.target_by_kind(
+            <P::ComponentKinds as FromType<C>>::from_type(),
+            entity_target,
+        )
+    }
+
+    pub(crate) fn target_by_kind(
+        &self,
+        kind: P::ComponentKinds,
+        mut entity_target: NetworkTarget,
+    ) -> NetworkTarget {
         match self.per_component_metadata.get(&kind) {
             None => entity_target,
             Some(metadata) => {
@@ -177,6 +241,32 @@ impl<P: Protocol> Replicate<P> {
         }
     }
 
+    /// Limit the rate at which the updates of a component are sent.
+    /// For example `Some(Duration::from_millis(200))` replicates the component's updates at most at 5Hz
+    pub fn set_send_interval<C>(&mut self, send_interval: Option<Duration>)
+    where
+        P::ComponentKinds: FromType<C>,
+    {
+        let kind = <P::ComponentKinds as FromType<C>>::from_type();
+        self.per_component_metadata
+            .entry(kind)
+            .or_default()
+            .send_interval = send_interval;
+        // if we are back at the default, remove the entry
+        if self.per_component_metadata.get(&kind).unwrap()
+            == &PerComponentReplicationMetadata::default()
+        {
+            self.per_component_metadata.remove(&kind);
+        }
+    }
+
+    /// Minimum interval between two updates of the component
+    pub(crate) fn send_interval_by_kind(&self, kind: P::ComponentKinds) -> Option<Duration> {
+        self.per_component_metadata
+            .get(&kind)
+            .and_then(|metadata| metadata.send_interval)
+    }
+
     pub fn add_target<C>(&mut self, target: NetworkTarget)
     where
         P::ComponentKinds: FromType<C>,
@@ -192,7 +282,7 @@ impl<P: Protocol> Replicate<P> {
     }
 }
 
-#[derive(Debug, Default, Copy, Clone, PartialEq, Reflect)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize, Reflect)]
 pub enum ReplicationGroupIdBuilder {
     // the group id is the entity id
     #[default]
@@ -204,7 +294,7 @@ pub enum ReplicationGroupIdBuilder {
     Group(u64),
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Reflect)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, Reflect)]
 pub struct ReplicationGroup {
     id_builder: ReplicationGroupIdBuilder,
     /// the priority of the accumulation group
@@ -263,7 +353,7 @@ impl ReplicationGroup {
 #[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Reflect)]
 pub struct ReplicationGroupId(pub u64);
 
-#[derive(Clone, Copy, Default, Debug, PartialEq, Reflect)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Serialize, Deserialize, Reflect)]
 pub enum ReplicationMode {
     /// We will replicate this entity only to clients that are in the same room as the entity
     /// (the client still needs to be included in the [`NetworkTarget`], the room is simply an additional constraint)
@@ -282,6 +372,8 @@ impl<P: Protocol> Default for Replicate<P> {
             interpolation_target: NetworkTarget::None,
             replication_clients_cache: HashMap::new(),
             replication_mode: ReplicationMode::default(),
+            on_remove: None,
+            dormancy: None,
             replication_group: Default::default(),
             replicate_hierarchy: true,
             per_component_metadata: HashMap::default(),
@@ -0,0 +1,576 @@
+//! Delta compression of component updates.
+//!
+//! For components that opt into delta compression (with `#[protocol(delta)]` in the `component_protocol`),
+//! the sender keeps track of the last value of the component that was acked by the remote. Instead of sending the
+//! full value in every update, it sends a [`ComponentDelta`]: the bytes of the encoded value that changed compared
+//! to the acked value.
+//! The receiver keeps the values it received recently, so that it can rebuild the full value from the delta.
+//!
+//! If no value was acked yet (or if the delta would not be smaller than the full value), the full value is sent.
+use std::collections::VecDeque;
+
+use bevy::ecs::entity::EntityHash;
+use bevy::prelude::Entity;
+use bevy::utils::{hashbrown, HashMap};
+use serde::{Deserialize, Serialize};
+use tracing::{error, trace, warn};
+
+use crate::packet::limits::DecodeLimits;
+use crate::packet::message::MessageId;
+use crate::protocol::component::{
+    ComponentKindBehaviour, ComponentProtocol, ComponentProtocolKind,
+};
+use crate::protocol::BitSerializable;
+use crate::serialize::reader::ReadBuffer;
+use crate::serialize::wordbuffer::reader::ReadWordBuffer;
+use crate::serialize::wordbuffer::writer::WriteWordBuffer;
+use crate::serialize::writer::WriteBuffer;
+use crate::shared::replication::components::ReplicationGroupId;
+use crate::shared::replication::EntityUpdatesMessage;
+use crate::shared::tick_manager::Tick;
+
+type EntityHashMap<K, V> = hashbrown::HashMap<K, V, EntityHash>;
+
+/// Two changed byte runs that are separated by fewer unchanged bytes than this are merged in a single patch
+/// (every patch has an overhead because we need to write its offset and length)
+const MIN_PATCH_GAP: usize = 4;
+
+/// Messages that are not acked after this number of ticks are considered lost
+const MAX_UNACKED_TICKS: i16 = 256;
+
+/// Maximum number of values that the receiver keeps for each component of each entity
+const MAX_RECEIVED_VALUES: usize = 64;
+
+/// The sender only computes a delta against a base value if fewer than this number of values of the component
+/// were sent after it, so that the receiver still has the base value.
+/// (the margin with [`MAX_RECEIVED_VALUES`] is for the more recent values that could be received before the delta)
+const MAX_VALUES_SINCE_BASE: u64 = MAX_RECEIVED_VALUES as u64 / 2;
+
+/// Update of a component, written as a diff against a previous value of the component that the remote has received
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ComponentDelta<K> {
+    pub(crate) kind: K,
+    /// Tick of the update message that contained the value that the diff was computed against
+    pub(crate) base_tick: Tick,
+    /// Length of the encoded new value
+    pub(crate) len: u32,
+    /// Runs of bytes of the encoded new value that are different from the base value: (offset, bytes)
+    pub(crate) patches: Vec<(u32, Vec<u8>)>,
+}
+
+/// Encode a component into the bytes that are used to compute the diffs
+pub(crate) fn encode_component<C: BitSerializable>(component: &C) -> anyhow::Result<Vec<u8>> {
+    let mut writer = WriteWordBuffer::with_capacity(64);
+    component.encode(&mut writer)?;
+    Ok(writer.finish_write().to_vec())
+}
+
+/// Decode a component from the bytes rebuilt from a diff
+pub(crate) fn decode_component<C: BitSerializable>(bytes: &[u8]) -> anyhow::Result<C> {
+    let mut reader = ReadWordBuffer::start_read(bytes);
+    C::decode(&mut reader)
+}
+
+/// Compute the runs of bytes of `new` that differ from `base`
+fn diff(base: &[u8], new: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let mut patches: Vec<(u32, Vec<u8>)> = vec![];
+    // end of the last patch
+    let mut end = 0;
+    for (i, byte) in new.iter().enumerate() {
+        if base.get(i) == Some(byte) {
+            continue;
+        }
+        match patches.last_mut() {
+            // the gap since the last patch is small: extend the last patch
+            Some((_, bytes)) if i - end < MIN_PATCH_GAP => bytes.extend_from_slice(&new[end..=i]),
+            _ => patches.push((i as u32, vec![*byte])),
+        }
+        end = i + 1;
+    }
+    patches
+}
+
+/// Rebuild the new value from the base value and the patches.
+///
+/// Returns None if the patches are not valid for the base value, or if the new value would be bigger than `max_len`.
+fn apply(base: &[u8], len: u32, patches: &[(u32, Vec<u8>)], max_len: usize) -> Option<Vec<u8>> {
+    let len = len as usize;
+    // the length is sent by the remote: check it before allocating.
+    // Every byte past the end of the base value must be written by a patch.
+    let patches_len = patches
+        .iter()
+        .try_fold(0usize, |acc, (_, patch)| acc.checked_add(patch.len()))?;
+    if len > max_len || len > base.len().saturating_add(patches_len) {
+        return None;
+    }
+    let mut bytes = base.to_vec();
+    bytes.resize(len, 0);
+    for (offset, patch) in patches {
+        let start = *offset as usize;
+        let end = start.checked_add(patch.len())?;
+        bytes.get_mut(start..end)?.copy_from_slice(patch);
+    }
+    Some(bytes)
+}
+
+/// Value of a component included in an update message, with the number of values of the component
+/// that were buffered before it (included)
+type SentValue<K> = (Entity, K, u64, Vec<u8>);
+
+/// Sender side of the delta compression, for a single remote peer
+#[derive(Debug)]
+pub(crate) struct DeltaSender<K> {
+    /// Number of values of each component that were buffered for an update message
+    buffered: EntityHashMap<Entity, HashMap<K, u64>>,
+    /// Last value of each component that was acked by the remote, with the tick of the update message that contained it
+    /// and its index in the buffered values
+    acked: EntityHashMap<Entity, HashMap<K, (Tick, u64, Vec<u8>)>>,
+    /// Values contained in the update messages that were sent but not acked yet
+    sent: HashMap<MessageId, (Tick, Vec<SentValue<K>>)>,
+    /// Values contained in the update messages that are being written, for each replication group
+    pending: EntityHashMap<ReplicationGroupId, Vec<SentValue<K>>>,
+    /// Values contained in the update messages that were finalized (but don't have a message id yet)
+    finalized: EntityHashMap<ReplicationGroupId, (Tick, Vec<SentValue<K>>)>,
+}
+
+impl<K> Default for DeltaSender<K> {
+    fn default() -> Self {
+        Self {
+            buffered: EntityHashMap::default(),
+            acked: EntityHashMap::default(),
+            sent: HashMap::default(),
+            pending: EntityHashMap::default(),
+            finalized: EntityHashMap::default(),
+        }
+    }
+}
+
+impl<K: Copy + Eq + std::hash::Hash> DeltaSender<K> {
+    /// Compute the delta of the encoded component against the last value acked by the remote.
+    ///
+    /// Returns None if no value was acked yet, if the acked value might not be kept by the receiver anymore,
+    /// or if the delta is not smaller than the full value.
+    pub(crate) fn delta(&self, entity: Entity, kind: K, bytes: &[u8]) -> Option<ComponentDelta<K>> {
+        let (base_tick, base_index, base) = self.acked.get(&entity)?.get(&kind)?;
+        let buffered = self.buffered.get(&entity)?.get(&kind)?;
+        // the receiver only keeps a limited number of values: send the full value instead
+        if buffered - base_index >= MAX_VALUES_SINCE_BASE {
+            return None;
+        }
+        let patches = diff(base, bytes);
+        // rough estimate of the size of the delta (the offset and length of each patch take a few bytes)
+        let delta_size = patches
+            .iter()
+            .map(|(_, patch)| patch.len() + 4)
+            .sum::<usize>()
+            + 4;
+        if delta_size >= bytes.len() {
+            return None;
+        }
+        Some(ComponentDelta {
+            kind,
+            base_tick: *base_tick,
+            len: bytes.len() as u32,
+            patches,
+        })
+    }
+
+    /// Keep track of the value of a component that is included in an update message for the group
+    pub(crate) fn buffer_value(
+        &mut self,
+        group_id: ReplicationGroupId,
+        entity: Entity,
+        kind: K,
+        bytes: Vec<u8>,
+    ) {
+        let index = self
+            .buffered
+            .entry(entity)
+            .or_default()
+            .entry(kind)
+            .or_default();
+        *index += 1;
+        self.pending
+            .entry(group_id)
+            .or_default()
+            .push((entity, kind, *index, bytes));
+    }
+
+    /// The update message for the group was written at the given tick
+    pub(crate) fn finalize(&mut self, group_id: ReplicationGroupId, tick: Tick) {
+        if let Some(values) = self.pending.remove(&group_id) {
+            self.finalized.insert(group_id, (tick, values));
+        }
+    }
+
+    /// The values included in messages that are not update messages cannot be used as base values
+    pub(crate) fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    /// The update message for the group was buffered with the given message id
+    pub(crate) fn message_buffered(&mut self, message_id: MessageId, group_id: ReplicationGroupId) {
+        let Some((tick, values)) = self.finalized.remove(&group_id) else {
+            return;
+        };
+        // forget about the messages that were probably lost
+        self.sent
+            .retain(|_, (sent_tick, _)| tick - *sent_tick < MAX_UNACKED_TICKS);
+        self.sent.insert(message_id, (tick, values));
+    }
+
+    /// The update message was received by the remote: its values can be used as base values
+    pub(crate) fn message_acked(&mut self, message_id: MessageId) {
+        let Some((tick, values)) = self.sent.remove(&message_id) else {
+            return;
+        };
+        for (entity, kind, index, bytes) in values {
+            let acked = self.acked.entry(entity).or_default();
+            // only keep the most recent acked value
+            if acked
+                .get(&kind)
+                .map_or(true, |(acked_tick, _, _)| tick > *acked_tick)
+            {
+                trace!(?entity, ?tick, "new acked value for delta compression");
+                acked.insert(kind, (tick, index, bytes));
+            }
+        }
+    }
+
+    /// The entity is not replicated anymore
+    pub(crate) fn remove_entity(&mut self, entity: Entity) {
+        self.buffered.remove(&entity);
+        self.acked.remove(&entity);
+    }
+}
+
+/// Receiver side of the delta compression, for a single remote peer
+#[derive(Debug)]
+pub(crate) struct DeltaReceiver<K> {
+    /// Values of the components received recently for each remote entity, with the remote tick of the update message
+    received: EntityHashMap<Entity, HashMap<K, VecDeque<(Tick, Vec<u8>)>>>,
+    /// Limits applied when decoding the values rebuilt from the deltas
+    pub(crate) decode_limits: DecodeLimits,
+}
+
+impl<K> Default for DeltaReceiver<K> {
+    fn default() -> Self {
+        Self {
+            received: EntityHashMap::default(),
+            decode_limits: DecodeLimits::default(),
+        }
+    }
+}
+
+impl<K: Copy + Eq + std::hash::Hash> DeltaReceiver<K> {
+    /// Keep a value of a component received in an update message, so that it can be used as a base value
+    pub(crate) fn store(&mut self, entity: Entity, kind: K, tick: Tick, bytes: Vec<u8>) {
+        let values = self
+            .received
+            .entry(entity)
+            .or_default()
+            .entry(kind)
+            .or_default();
+        if values.iter().any(|(t, _)| *t == tick) {
+            return;
+        }
+        values.push_back((tick, bytes));
+        if values.len() > MAX_RECEIVED_VALUES {
+            // the updates can be received out of order: evict the oldest value
+            if let Some(oldest) = values
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (t, _))| *t)
+                .map(|(i, _)| i)
+            {
+                values.remove(oldest);
+            }
+        }
+    }
+
+    /// Rebuild the encoded value of a component from a delta.
+    ///
+    /// Returns None if we don't have the base value of the delta, or if the delta is invalid.
+    ///
+    /// The values are not pruned here: the updates can be received out of order, so a delta against an older
+    /// base value can still arrive later.
+    pub(crate) fn resolve(&self, entity: Entity, delta: &ComponentDelta<K>) -> Option<Vec<u8>> {
+        let values = self.received.get(&entity)?.get(&delta.kind)?;
+        let (_, base) = values.iter().find(|(tick, _)| *tick == delta.base_tick)?;
+        apply(
+            base,
+            delta.len,
+            &delta.patches,
+            self.decode_limits.max_message_size,
+        )
+    }
+
+    /// The entity was despawned
+    pub(crate) fn remove_entity(&mut self, entity: Entity) {
+        self.received.remove(&entity);
+    }
+}
+
+impl<K: ComponentProtocolKind> DeltaReceiver<K> {
+    /// Replace the delta updates of an update message received at `tick` with the full component values,
+    /// and keep the values of the delta-compressed components so that they can be used as base values.
+    ///
+    /// The delta updates whose base value is unknown are dropped.
+    pub(crate) fn resolve_updates<C: ComponentProtocol>(
+        &mut self,
+        message: &mut EntityUpdatesMessage<C, K>,
+        tick: Tick,
+    ) where
+        for<'a> K: From<&'a C>,
+    {
+        for (entity, components) in message.updates.iter() {
+            for component in components {
+                let kind = K::from(component);
+                if !kind.delta_compression() {
+                    continue;
+                }
+                match encode_component(component) {
+                    Ok(bytes) => self.store(*entity, kind, tick, bytes),
+                    Err(e) => error!(
+                        ?kind,
+                        "could not encode component for delta compression: {:?}", e
+                    ),
+                }
+            }
+        }
+        for (entity, deltas) in std::mem::take(&mut message.delta_updates) {
+            let mut components = vec![];
+            for delta in deltas {
+                let Some(bytes) = self.resolve(entity, &delta) else {
+                    warn!(?entity, kind = ?delta.kind, base_tick = ?delta.base_tick, "could not find the base value of a delta update");
+                    continue;
+                };
+                let decoded = self.decode_limits.decode(|| decode_component::<C>(&bytes));
+                // the message will be acked, so the sender can use this value as a base value even if
+                // we could not decode it
+                self.store(entity, delta.kind, tick, bytes);
+                match decoded {
+                    Ok(component) => components.push(component),
+                    Err(e) => error!(kind = ?delta.kind, "could not decode delta update: {:?}", e),
+                }
+            }
+            if components.is_empty() {
+                continue;
+            }
+            match message.updates.iter_mut().find(|(e, _)| *e == entity) {
+                Some((_, updates)) => updates.extend(components),
+                None => message.updates.push((entity, components)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::With;
+
+    use crate::_reexport::EntityUpdatesChannel;
+    use crate::prelude::ClientId;
+    use crate::tests::protocol::*;
+    use crate::tests::stepper::{BevyStepper, Step};
+
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let base = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+        let mut new = base.clone();
+        new[1] = 100;
+        new[3] = 100;
+        new[10] = 100;
+        new.push(12);
+        let patches = diff(&base, &new);
+        // close changes are merged in a single patch
+        assert_eq!(
+            patches,
+            vec![(1, vec![100, 2, 100]), (10, vec![100, 11, 12])]
+        );
+        assert_eq!(apply(&base, new.len() as u32, &patches, 100), Some(new));
+
+        // the new value is shorter
+        let new = vec![0, 1, 5];
+        let patches = diff(&base, &new);
+        assert_eq!(apply(&base, new.len() as u32, &patches, 100), Some(new));
+
+        // invalid patches
+        assert_eq!(apply(&base, 2, &[(1, vec![1, 2])], 100), None);
+        // the new value is bigger than the maximum size
+        assert_eq!(apply(&base, 13, &[(10, vec![1, 2, 3])], 12), None);
+        // the length is bigger than what the base value and the patches can fill
+        assert_eq!(
+            apply(&base, u32::MAX, &[(10, vec![1, 2, 3])], usize::MAX),
+            None
+        );
+    }
+
+    #[test]
+    fn test_delta_sender_receiver() {
+        let mut sender = DeltaSender::<u8>::default();
+        let mut receiver = DeltaReceiver::<u8>::default();
+        let entity = Entity::from_raw(0);
+        let group_id = ReplicationGroupId(0);
+        let value_1 = vec![1; 20];
+        let mut value_2 = value_1.clone();
+        value_2[5] = 2;
+
+        // no value was acked yet: we send the full value
+        assert_eq!(sender.delta(entity, 0, &value_1), None);
+        sender.buffer_value(group_id, entity, 0, value_1.clone());
+        sender.finalize(group_id, Tick(1));
+        sender.message_buffered(MessageId(0), group_id);
+        receiver.store(entity, 0, Tick(1), value_1.clone());
+
+        // the message is still not acked
+        assert_eq!(sender.delta(entity, 0, &value_2), None);
+        sender.message_acked(MessageId(0));
+        let delta = sender.delta(entity, 0, &value_2).unwrap();
+        assert_eq!(delta.base_tick, Tick(1));
+        assert_eq!(delta.patches, vec![(5, vec![2])]);
+        assert_eq!(receiver.resolve(entity, &delta), Some(value_2.clone()));
+
+        // the receiver doesn't have the base value
+        receiver.remove_entity(entity);
+        assert_eq!(receiver.resolve(entity, &delta), None);
+    }
+
+    #[test]
+    fn test_delta_reordered_updates() {
+        let mut sender = DeltaSender::<u8>::default();
+        let mut receiver = DeltaReceiver::<u8>::default();
+        let entity = Entity::from_raw(0);
+        let group_id = ReplicationGroupId(0);
+        let value = |i: u8| {
+            let mut value = vec![0; 20];
+            value[0] = i;
+            value
+        };
+        // send the value in an update message written at the tick `i` with the message id `i`
+        let send = |sender: &mut DeltaSender<u8>, i: u8| {
+            let delta = sender.delta(entity, 0, &value(i));
+            sender.buffer_value(group_id, entity, 0, value(i));
+            sender.finalize(group_id, Tick(i as u16));
+            sender.message_buffered(MessageId(i as u16), group_id);
+            delta
+        };
+        // receive the update message like `resolve_updates` does
+        let receive =
+            |receiver: &mut DeltaReceiver<u8>, i: u8, delta: Option<ComponentDelta<u8>>| {
+                let bytes = match delta {
+                    Some(delta) => receiver.resolve(entity, &delta)?,
+                    None => value(i),
+                };
+                receiver.store(entity, 0, Tick(i as u16), bytes.clone());
+                Some(bytes)
+            };
+
+        let update_1 = send(&mut sender, 1);
+        assert_eq!(receive(&mut receiver, 1, update_1), Some(value(1)));
+        sender.message_acked(MessageId(1));
+
+        // the updates 2 and 3 are computed against the value 1
+        let update_2 = send(&mut sender, 2);
+        let update_3 = send(&mut sender, 3);
+        assert_eq!(update_3.as_ref().unwrap().base_tick, Tick(1));
+        assert_eq!(receive(&mut receiver, 2, update_2), Some(value(2)));
+        sender.message_acked(MessageId(2));
+
+        // the update 4 is computed against the value 2, and is received before the update 3
+        let update_4 = send(&mut sender, 4);
+        assert_eq!(update_4.as_ref().unwrap().base_tick, Tick(2));
+        assert_eq!(receive(&mut receiver, 4, update_4), Some(value(4)));
+        // we can still resolve the update 3 against the value 1
+        assert_eq!(receive(&mut receiver, 3, update_3), Some(value(3)));
+        sender.message_acked(MessageId(3));
+
+        // the next updates are computed against the value 3
+        let update_5 = send(&mut sender, 5);
+        assert_eq!(update_5.as_ref().unwrap().base_tick, Tick(3));
+        assert_eq!(receive(&mut receiver, 5, update_5), Some(value(5)));
+    }
+
+    #[test]
+    fn test_delta_sender_full_value_fallback() {
+        let mut sender = DeltaSender::<u8>::default();
+        let entity = Entity::from_raw(0);
+        let group_id = ReplicationGroupId(0);
+        let value = vec![1; 20];
+
+        sender.buffer_value(group_id, entity, 0, value.clone());
+        sender.finalize(group_id, Tick(0));
+        sender.message_buffered(MessageId(0), group_id);
+        sender.message_acked(MessageId(0));
+        assert!(sender.delta(entity, 0, &value).is_some());
+
+        // too many values were sent since the acked value: the receiver might not have it anymore
+        for i in 1..=MAX_VALUES_SINCE_BASE {
+            sender.buffer_value(group_id, entity, 0, value.clone());
+            sender.finalize(group_id, Tick(i as u16));
+            sender.message_buffered(MessageId(i as u16), group_id);
+        }
+        assert_eq!(sender.delta(entity, 0, &value), None);
+
+        // a more recent value is acked
+        sender.message_acked(MessageId(MAX_VALUES_SINCE_BASE as u16));
+        assert!(sender.delta(entity, 0, &value).is_some());
+    }
+
+    #[test]
+    fn test_replicate_delta_component() {
+        let mut stepper = BevyStepper::default();
+        let mut value: Vec<u32> = (0..100).collect();
+        let server_entity = stepper
+            .server_app
+            .world
+            .spawn((Component5(value.clone()), Replicate::default()))
+            .id();
+        for _ in 0..10 {
+            stepper.frame_step();
+        }
+        let client_component = |stepper: &mut BevyStepper| {
+            stepper
+                .client_app
+                .world
+                .query_filtered::<&Component5, With<Component5>>()
+                .get_single(&stepper.client_app.world)
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(client_component(&mut stepper), Component5(value.clone()));
+
+        let update_bytes_sent = |stepper: &BevyStepper| {
+            stepper
+                .server_app
+                .world
+                .resource::<ServerConnectionManager>()
+                .connection_stats(ClientId::Netcode(111))
+                .unwrap()
+                .channel::<EntityUpdatesChannel>()
+                .map_or(0, |stats| stats.bytes_sent)
+        };
+        let full_size =
+            encode_component(&MyComponentsProtocol::Component5(Component5(value.clone())))
+                .unwrap()
+                .len() as u64;
+
+        // the updates are sent as deltas against the last value acked by the client
+        for i in 0..5 {
+            value[10 * i] = 1000;
+            let bytes_sent = update_bytes_sent(&stepper);
+            stepper
+                .server_app
+                .world
+                .entity_mut(server_entity)
+                .insert(Component5(value.clone()));
+            stepper.frame_step();
+            stepper.frame_step();
+            assert_eq!(client_component(&mut stepper), Component5(value.clone()));
+            // the update message is much smaller than the full value
+            assert!(update_bytes_sent(&stepper) - bytes_sent < full_size / 2);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Dormancy: stop scanning and sending updates for replicated entities that are idle
+//!
+//! An entity whose [`Replicate::dormancy`] is set goes dormant when none of its replicated components changed
+//! for that many ticks, and once the latest changes are guaranteed to be received by the remote (they were sent
+//! in a reliable message, or in an update that was acked). Dormant entities are skipped by the systems that gather
+//! component updates, and wake up automatically the next time one of their replicated components changes.
+//!
+//! You can also control the dormancy explicitly by inserting or removing the [`Dormant`] component.
+//!
+//! The activity is tracked with bevy's change detection: every frame, for each replicated component, the change
+//! ticks of the entities that have that component and a [`Replicate::dormancy`] are checked. The entities that are
+//! awake and the dormant entities are in different archetypes, so the two cases are handled by separate queries.
+//! This check is much cheaper than gathering the updates of an entity (no cloning or serialization), which is the
+//! cost that dormancy saves.
+use bevy::ecs::component::Tick as BevyTick;
+use bevy::ecs::system::SystemChangeTick;
+use bevy::prelude::{
+    Added, Changed, Commands, Component, DetectChanges, Entity, EntityRef, Query, Ref, Reflect,
+    RemovedComponents, Res, ResMut, With, Without,
+};
+use tracing::{error, trace};
+
+use crate::prelude::{Protocol, Tick, TickManager};
+use crate::protocol::registration::ProtocolRegistry;
+use crate::shared::replication::components::Replicate;
+use crate::shared::replication::ReplicationSend;
+
+/// Marker component for dormant entities.
+///
+/// - on the sending side, no updates are gathered for a dormant entity. Remove the component to wake the entity up.
+/// - on the receiving side, the component is added to the entities that are dormant on the remote, so that your
+///   systems can skip them: they won't receive any updates until they wake up.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub struct Dormant;
+
+/// Keeps track of the last tick where a replicated component of the entity changed
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ReplicationActivity {
+    pub(crate) last_change: Tick,
+    /// Bevy change tick of the last change, used to check if the change was received by the remote
+    pub(crate) last_change_bevy_tick: BevyTick,
+}
+
+impl ReplicationActivity {
+    fn record_change(&mut self, tick: Tick, bevy_tick: BevyTick) {
+        self.last_change = tick;
+        self.last_change_bevy_tick = bevy_tick;
+    }
+}
+
+/// Start tracking the activity of the entities that can go dormant
+pub(crate) fn add_replication_activity<P: Protocol>(
+    mut commands: Commands,
+    system_bevy_ticks: SystemChangeTick,
+    tick_manager: Res<TickManager>,
+    query: Query<(Entity, &Replicate<P>), Without<ReplicationActivity>>,
+) {
+    for (entity, replicate) in query.iter() {
+        if replicate.dormancy.is_some() {
+            commands.entity(entity).insert(ReplicationActivity {
+                last_change: tick_manager.tick(),
+                last_change_bevy_tick: system_bevy_ticks.this_run(),
+            });
+        }
+    }
+}
+
+/// Put to sleep the entities that have been idle for long enough, and wake up dormant entities
+/// that need to send their components (new clients, the replication settings changed, etc.)
+pub(crate) fn update_dormancy<P: Protocol, R: ReplicationSend<P>>(
+    mut commands: Commands,
+    system_bevy_ticks: SystemChangeTick,
+    tick_manager: Res<TickManager>,
+    sender: Res<R>,
+    awake: Query<(Entity, &Replicate<P>, &ReplicationActivity), Without<Dormant>>,
+    dormant: Query<(Entity, Ref<Replicate<P>>), With<Dormant>>,
+) {
+    let tick = tick_manager.tick();
+    for (entity, replicate) in dormant.iter() {
+        // the components of the entity need to be sent to the newly connected clients, or to the
+        // clients that just gained visibility of the entity
+        if !sender.full_sync_clients(entity).is_empty() || replicate.is_changed() {
+            trace!(?entity, "waking up dormant entity");
+            commands.entity(entity).remove::<Dormant>();
+        }
+    }
+    for (entity, replicate, activity) in awake.iter() {
+        let Some(dormancy) = replicate.dormancy else {
+            continue;
+        };
+        // the difference between two ticks is at most i16::MAX
+        let dormancy = i16::try_from(dormancy.get()).unwrap_or(i16::MAX);
+        // the entity must not go to sleep before its latest changes are received: the updates of dormant
+        // entities are not sent anymore, so a lost update would never be sent again
+        if tick - activity.last_change >= dormancy
+            && sender.changes_delivered(
+                entity,
+                replicate,
+                activity.last_change_bevy_tick,
+                system_bevy_ticks.this_run(),
+            )
+        {
+            trace!(?entity, "entity goes dormant");
+            commands.entity(entity).insert(Dormant);
+        }
+    }
+}
+
+/// Keep track of the last time a replicated component of an awake entity changed
+pub(crate) fn track_activity<C: Component>(
+    tick_manager: Res<TickManager>,
+    mut query: Query<(Ref<C>, &mut ReplicationActivity), (Changed<C>, Without<Dormant>)>,
+) {
+    for (component, mut activity) in query.iter_mut() {
+        activity.record_change(tick_manager.tick(), component.last_changed());
+    }
+}
+
+/// Wake up the dormant entities when one of their replicated components changes
+pub(crate) fn wake_up_on_change<C: Component>(
+    mut commands: Commands,
+    tick_manager: Res<TickManager>,
+    mut query: Query<(Entity, Ref<C>, &mut ReplicationActivity), (Changed<C>, With<Dormant>)>,
+) {
+    for (entity, component, mut activity) in query.iter_mut() {
+        trace!(?entity, "waking up dormant entity after a component change");
+        activity.record_change(tick_manager.tick(), component.last_changed());
+        commands.entity(entity).remove::<Dormant>();
+    }
+}
+
+/// Same as [`track_activity`] and [`wake_up_on_change`], for the components that were registered at runtime
+pub(crate) fn track_registered_activity(
+    mut commands: Commands,
+    system_bevy_ticks: SystemChangeTick,
+    tick_manager: Res<TickManager>,
+    registry: Option<Res<ProtocolRegistry>>,
+    query: Query<(Entity, EntityRef), With<ReplicationActivity>>,
+) {
+    let Some(registry) = registry else {
+        return;
+    };
+    if registry.components().is_empty() {
+        return;
+    }
+    let last_run = system_bevy_ticks.last_run();
+    let this_run = system_bevy_ticks.this_run();
+    let tick = tick_manager.tick();
+    for (entity, entity_ref) in query.iter() {
+        let changed = registry.components().iter().any(|registration| {
+            entity_ref
+                .get_change_ticks_by_id(registration.component_id)
+                .map_or(false, |ticks| ticks.is_changed(last_run, this_run))
+        });
+        if !changed {
+            continue;
+        }
+        if entity_ref.contains::<Dormant>() {
+            trace!(?entity, "waking up dormant entity after a component change");
+            commands.entity(entity).remove::<Dormant>();
+        }
+        // the change happened before this system ran, so `this_run` is a conservative change tick
+        commands.entity(entity).insert(ReplicationActivity {
+            last_change: tick,
+            last_change_bevy_tick: this_run,
+        });
+    }
+}
+
+/// Notify the remote when an entity goes dormant or wakes up
+pub(crate) fn send_dormancy_changes<P: Protocol, R: ReplicationSend<P>>(
+    system_bevy_ticks: SystemChangeTick,
+    tick_manager: Res<TickManager>,
+    mut sender: ResMut<R>,
+    added: Query<(Entity, &Replicate<P>), Added<Dormant>>,
+    mut removed: RemovedComponents<Dormant>,
+    mut query: Query<(&Replicate<P>, Option<&mut ReplicationActivity>), Without<Dormant>>,
+) {
+    for (entity, replicate) in added.iter() {
+        let _ = sender
+            .prepare_entity_dormancy(
+                entity,
+                replicate,
+                replicate.replicated_to(),
+                true,
+                system_bevy_ticks.this_run(),
+            )
+            .map_err(|e| {
+                error!("error sending entity dormancy: {:?}", e);
+            });
+    }
+    for entity in removed.read() {
+        let Ok((replicate, activity)) = query.get_mut(entity) else {
+            continue;
+        };
+        // the entity should not go dormant again right away
+        if let Some(mut activity) = activity {
+            activity.record_change(tick_manager.tick(), system_bevy_ticks.this_run());
+        }
+        let _ = sender
+            .prepare_entity_dormancy(
+                entity,
+                replicate,
+                replicate.replicated_to(),
+                false,
+                system_bevy_ticks.this_run(),
+            )
+            .map_err(|e| {
+                error!("error sending entity wake up: {:?}", e);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU16;
+
+    use bevy::prelude::{default, IntoSystemConfigs, PostUpdate, Resource};
+
+    use crate::shared::sets::{InternalReplicationSet, ServerMarker};
+    use crate::tests::protocol::Replicate;
+    use crate::tests::protocol::*;
+    use crate::tests::stepper::{BevyStepper, Step};
+
+    use super::*;
+
+    #[test]
+    fn test_dormancy() {
+        let mut stepper = BevyStepper::default();
+        let server_entity = stepper
+            .server_app
+            .world
+            .spawn((
+                Component1(0.0),
+                Replicate {
+                    dormancy: NonZeroU16::new(5),
+                    ..default()
+                },
+            ))
+            .id();
+        stepper.frame_step();
+        stepper.frame_step();
+        let client_entity = *stepper
+            .client_app
+            .world
+            .resource::<ClientConnectionManager>()
+            .replication_receiver
+            .remote_entity_map
+            .get_local(server_entity)
+            .unwrap();
+        assert!(!stepper
+            .server_app
+            .world
+            .entity(server_entity)
+            .contains::<Dormant>());
+
+        // the entity goes dormant after being idle for long enough
+        for _ in 0..10 {
+            stepper.frame_step();
+        }
+        assert!(stepper
+            .server_app
+            .world
+            .entity(server_entity)
+            .contains::<Dormant>());
+        assert!(stepper
+            .client_app
+            .world
+            .entity(client_entity)
+            .contains::<Dormant>());
+
+        // a component change wakes the entity up, and the update is replicated
+        stepper
+            .server_app
+            .world
+            .entity_mut(server_entity)
+            .insert(Component1(1.0));
+        stepper.frame_step();
+        stepper.frame_step();
+        assert!(!stepper
+            .server_app
+            .world
+            .entity(server_entity)
+            .contains::<Dormant>());
+        let client_entity_ref = stepper.client_app.world.entity(client_entity);
+        assert!(!client_entity_ref.contains::<Dormant>());
+        assert_eq!(
+            client_entity_ref.get::<Component1>(),
+            Some(&Component1(1.0))
+        );
+    }
+
+    /// Pretend that none of the replication messages were received by the clients
+    #[derive(Resource)]
+    struct ForgetDeliveries(bool);
+
+    fn forget_deliveries(
+        forget: Res<ForgetDeliveries>,
+        mut manager: ResMut<ServerConnectionManager>,
+    ) {
+        if !forget.0 {
+            return;
+        }
+        for connection in manager.connections.values_mut() {
+            for channel in connection.replication_sender.group_channels.values_mut() {
+                channel.last_action_bevy_tick = None;
+                channel.collect_changes_since_this_tick = None;
+            }
+        }
+    }
+
+    #[test]
+    fn test_dormancy_waits_for_delivery() {
+        let mut stepper = BevyStepper::default();
+        stepper
+            .server_app
+            .insert_resource(ForgetDeliveries(true))
+            .add_systems(
+                PostUpdate,
+                forget_deliveries.before(InternalReplicationSet::<ServerMarker>::HandleDormancy),
+            );
+        let server_entity = stepper
+            .server_app
+            .world
+            .spawn((
+                Component1(0.0),
+                Replicate {
+                    dormancy: NonZeroU16::new(5),
+                    ..default()
+                },
+            ))
+            .id();
+        // the changes are not received by the client: the entity must stay awake
+        for _ in 0..20 {
+            stepper.frame_step();
+        }
+        assert!(!stepper
+            .server_app
+            .world
+            .entity(server_entity)
+            .contains::<Dormant>());
+
+        // once the changes are received, the entity can go dormant
+        stepper
+            .server_app
+            .world
+            .resource_mut::<ForgetDeliveries>()
+            .0 = false;
+        for _ in 0..10 {
+            stepper.frame_step();
+        }
+        assert!(stepper
+            .server_app
+            .world
+            .entity(server_entity)
+            .contains::<Dormant>());
+    }
+}
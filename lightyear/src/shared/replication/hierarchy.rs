@@ -1,4 +1,10 @@
 //! This module is responsible for making sure that parent-children hierarchies are replicated correctly.
+//!
+//! The parent of each entity is replicated with [`ParentSync`], and the order of the children of an entity
+//! is replicated with [`ChildrenSync`]. On the receiving side, all the hierarchy changes received during a frame are
+//! applied together (first the parents, then the order of the children), so that the intermediate states of a
+//! reparenting are not visible. When the entities of a hierarchy are in the same [`ReplicationGroup`] (which is the
+//! case when using `replicate_hierarchy`), their changes are always received in the same frame.
 use bevy::ecs::entity::MapEntities;
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -18,6 +24,12 @@ use crate::shared::sets::{InternalMainSet, InternalReplicationSet};
 #[derive(Component, Default, Reflect, Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
 pub struct ParentSync(Option<Entity>);
 
+impl ParentSync {
+    pub(crate) fn parent(&self) -> Option<Entity> {
+        self.0
+    }
+}
+
 impl MapEntities for ParentSync {
     fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
         if let Some(entity) = &mut self.0 {
@@ -26,6 +38,18 @@ impl MapEntities for ParentSync {
     }
 }
 
+/// This component is added automatically to the replicated entities that have replicated children,
+/// to replicate the order of their children.
+///
+/// Only the children that are replicated with [`ParentSync`] are included; on the receiving side, the children that
+/// are not part of the list are kept after the replicated children.
+///
+/// The list is not mapped on the receiving side: it keeps the entities of the sender, so that the children that
+/// are received after their parent's `ChildrenSync` (for example because they are in another replication group)
+/// are still sorted correctly.
+#[derive(Component, Default, Reflect, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ChildrenSync(Vec<Entity>);
+
 pub struct HierarchySendPlugin<P, R> {
     _marker: std::marker::PhantomData<(P, R)>,
 }
@@ -79,18 +103,54 @@ impl<P: Protocol, R: ReplicationSend<P>> HierarchySendPlugin<P, R> {
         }
     }
 
-    /// Update ParentSync if the parent has been removed
+    /// Update ChildrenSync if the children of the entity changed
+    ///
+    /// This only runs on the sending side
+    fn update_children_sync(
+        mut commands: Commands,
+        mut query: Query<(Entity, Ref<Children>, Option<&mut ChildrenSync>), With<Replicate<P>>>,
+        replicated_children: Query<(), (With<ParentSync>, With<Replicate<P>>)>,
+    ) {
+        for (entity, children, children_sync) in query.iter_mut() {
+            if !children.is_changed() && children_sync.is_some() {
+                continue;
+            }
+            let replicated = children
+                .iter()
+                .copied()
+                .filter(|child| replicated_children.contains(*child))
+                .collect::<Vec<_>>();
+            match children_sync {
+                Some(mut children_sync) => {
+                    children_sync.set_if_neq(ChildrenSync(replicated));
+                }
+                None if !replicated.is_empty() => {
+                    commands.entity(entity).insert(ChildrenSync(replicated));
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Update ParentSync if the parent has been removed, and ChildrenSync if all the children have been removed
     ///
     /// This only runs on the sending side
     fn removal_system(
         mut removed_parents: RemovedComponents<Parent>,
+        mut removed_children: RemovedComponents<Children>,
         mut hierarchy: Query<&mut ParentSync, With<Replicate<P>>>,
+        mut children_hierarchy: Query<&mut ChildrenSync, With<Replicate<P>>>,
     ) {
         for entity in removed_parents.read() {
             if let Ok(mut parent_sync) = hierarchy.get_mut(entity) {
                 parent_sync.0 = None;
             }
         }
+        for entity in removed_children.read() {
+            if let Ok(mut children_sync) = children_hierarchy.get_mut(entity) {
+                children_sync.set_if_neq(ChildrenSync::default());
+            }
+        }
     }
 }
 
@@ -99,7 +159,12 @@ impl<P: Protocol, R: ReplicationSend<P>> Plugin for HierarchySendPlugin<P, R> {
         app.add_systems(
             PostUpdate,
             (
-                (Self::propagate_replicate, Self::update_parent_sync).chain(),
+                (
+                    Self::propagate_replicate,
+                    Self::update_parent_sync,
+                    Self::update_children_sync,
+                )
+                    .chain(),
                 Self::removal_system,
             )
                 // we don't need to run these every frame, only every send_interval
@@ -148,18 +213,54 @@ impl<P: Protocol, R: ReplicationSend<P>> HierarchyReceivePlugin<P, R> {
             }
         }
     }
+
+    /// Sort the children of an entity according to ChildrenSync.
+    /// This runs after the parents have been updated, and also when the children change (a child can be
+    /// received after the ChildrenSync of its parent)
+    ///
+    /// This only runs on the receiving side
+    fn update_children_order(
+        connection_manager: Res<R>,
+        mut hierarchy: Query<(Ref<ChildrenSync>, &mut Children), Without<Replicate<P>>>,
+    ) {
+        for (children_sync, mut children) in hierarchy.iter_mut() {
+            if !children_sync.is_changed() && !children.is_changed() {
+                continue;
+            }
+            // ChildrenSync contains the remote entities of the children
+            let position = |child: &Entity| {
+                connection_manager
+                    .remote_entity(*child)
+                    .and_then(|remote| children_sync.0.iter().position(|c| *c == remote))
+                    .unwrap_or(usize::MAX)
+            };
+            // only trigger change detection if the order actually changes
+            if children
+                .windows(2)
+                .all(|pair| position(&pair[0]) <= position(&pair[1]))
+            {
+                continue;
+            }
+            trace!(?children_sync, "Update the order of the children");
+            children.sort_by_key(position);
+        }
+    }
 }
 
 impl<P: Protocol, R: ReplicationSend<P>> Plugin for HierarchyReceivePlugin<P, R> {
     fn build(&self, app: &mut App) {
         // REFLECTION
-        app.register_type::<ParentSync>();
+        app.register_type::<ParentSync>()
+            .register_type::<ChildrenSync>();
 
         // TODO: does this work for client replication? (client replicating to other clients via the server?)
-        // when we receive a ParentSync update from the remote, update the hierarchy
+        // when we receive a ParentSync or ChildrenSync update from the remote, update the hierarchy
+        // (all the hierarchy changes of the frame are applied together)
         app.add_systems(
             PreUpdate,
-            Self::update_parent.after(InternalMainSet::<R::SetMarker>::Receive),
+            (Self::update_parent, Self::update_children_order)
+                .chain()
+                .after(InternalMainSet::<R::SetMarker>::Receive),
         );
     }
 }
@@ -168,10 +269,11 @@ impl<P: Protocol, R: ReplicationSend<P>> Plugin for HierarchyReceivePlugin<P, R>
 mod tests {
     use std::ops::Deref;
 
-    use bevy::hierarchy::{BuildWorldChildren, Children, Parent};
+    use bevy::hierarchy::{BuildWorldChildren, Children, DespawnRecursiveExt, Parent};
     use bevy::prelude::{default, Entity, With};
 
-    use crate::prelude::ReplicationGroup;
+    use crate::prelude::server::{RoomId, RoomManager};
+    use crate::prelude::{ClientId, ReplicationGroup, ReplicationMode};
     use crate::shared::replication::hierarchy::ParentSync;
     use crate::tests::protocol::*;
     use crate::tests::stepper::{BevyStepper, Step};
@@ -353,4 +455,173 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn test_children_order() {
+        let mut stepper = BevyStepper::default();
+        let children = [
+            stepper.server_app.world.spawn(Component2(1.0)).id(),
+            stepper.server_app.world.spawn(Component2(2.0)).id(),
+            stepper.server_app.world.spawn(Component2(3.0)).id(),
+        ];
+        let parent = stepper
+            .server_app
+            .world
+            .spawn((Component1(0.0), Replicate::default()))
+            .push_children(&children)
+            .id();
+        stepper.frame_step();
+        stepper.frame_step();
+
+        let client_children = |stepper: &BevyStepper| {
+            let manager = stepper
+                .client_app
+                .world
+                .resource::<ClientConnectionManager>();
+            let map = &manager.replication_receiver.remote_entity_map;
+            let client_parent = *map.get_local(parent).unwrap();
+            stepper
+                .client_app
+                .world
+                .get::<Children>(client_parent)
+                .unwrap()
+                .iter()
+                .map(|child| *map.get_remote(*child).unwrap())
+                .collect::<Vec<_>>()
+        };
+        // 1. the order of the children is replicated
+        assert_eq!(client_children(&stepper), children.to_vec());
+
+        // 2. a change in the order of the children is replicated
+        stepper
+            .server_app
+            .world
+            .entity_mut(parent)
+            .insert_children(0, &[children[2]]);
+        stepper.frame_step();
+        stepper.frame_step();
+        assert_eq!(
+            client_children(&stepper),
+            vec![children[2], children[0], children[1]]
+        );
+    }
+
+    /// The children in other replication groups can be received after the ChildrenSync of their parent
+    #[test]
+    fn test_children_order_late_children() {
+        let mut stepper = BevyStepper::default();
+        // make sure that the client entities are different from the server entities
+        for _ in 0..10 {
+            stepper.client_app.world.spawn_empty();
+        }
+        // the children are not visible to the client yet
+        stepper
+            .server_app
+            .world
+            .resource_mut::<RoomManager>()
+            .add_client(ClientId::Netcode(111), RoomId(0));
+        let children = [1.0, 2.0, 3.0].map(|i| {
+            stepper
+                .server_app
+                .world
+                .spawn((
+                    Component2(i),
+                    Replicate {
+                        replication_mode: ReplicationMode::Room,
+                        ..default()
+                    },
+                    ParentSync::default(),
+                ))
+                .id()
+        });
+        let parent = stepper
+            .server_app
+            .world
+            .spawn((
+                Component1(0.0),
+                Replicate {
+                    replicate_hierarchy: false,
+                    ..default()
+                },
+            ))
+            .push_children(&children)
+            .id();
+        // make sure that the ChildrenSync of the parent is not sent again when the children are received
+        for _ in 0..10 {
+            stepper.frame_step();
+        }
+
+        // the children are received one by one, in the reverse order
+        for child in children.iter().rev() {
+            stepper
+                .server_app
+                .world
+                .resource_mut::<RoomManager>()
+                .add_entity(*child, RoomId(0));
+            stepper.frame_step();
+            stepper.frame_step();
+        }
+        let manager = stepper
+            .client_app
+            .world
+            .resource::<ClientConnectionManager>();
+        let map = &manager.replication_receiver.remote_entity_map;
+        let client_parent = *map.get_local(parent).unwrap();
+        let client_children = stepper
+            .client_app
+            .world
+            .get::<Children>(client_parent)
+            .unwrap()
+            .iter()
+            .map(|child| *map.get_remote(*child).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(client_children, children.to_vec());
+    }
+
+    #[test]
+    fn test_despawn_subtree() {
+        let (mut stepper, grandparent, parent, _) = setup_hierarchy();
+        stepper
+            .server_app
+            .world
+            .entity_mut(grandparent)
+            .insert(Replicate {
+                replicate_hierarchy: false,
+                ..default()
+            });
+        stepper.frame_step();
+        stepper.frame_step();
+        // the parent is replicated in a different group than the grandparent
+        stepper
+            .server_app
+            .world
+            .entity_mut(parent)
+            .insert((Replicate::default(), ParentSync::default()));
+        stepper.frame_step();
+        stepper.frame_step();
+        let client_parent = stepper
+            .client_app
+            .world
+            .query_filtered::<Entity, With<Component2>>()
+            .get_single(&stepper.client_app.world)
+            .unwrap();
+
+        // despawning the root despawns the whole subtree on the receiver side, and the replicated descendants
+        // stop being tracked
+        stepper
+            .server_app
+            .world
+            .entity_mut(grandparent)
+            .despawn_recursive();
+        stepper.frame_step();
+        stepper.frame_step();
+        assert!(stepper.client_app.world.get_entity(client_parent).is_none());
+        let manager = stepper
+            .client_app
+            .world
+            .resource::<ClientConnectionManager>();
+        let map = &manager.replication_receiver.remote_entity_map;
+        assert!(map.get_local(grandparent).is_none());
+        assert!(map.get_local(parent).is_none());
+    }
 }
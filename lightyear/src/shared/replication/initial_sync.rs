@@ -0,0 +1,525 @@
+//! Initial sync: stream the replicated world to newly connected clients in chunks
+//!
+//! By default, all the replicated entities are sent to a client as soon as it connects, which can saturate
+//! the link when the world contains a lot of entities. With an [`InitialSyncConfig`], the entities are instead
+//! sent in chunks of [`InitialSyncConfig::entities_per_send`] entities every send interval, starting with the
+//! entities that have the highest priority for the client.
+//! The replication messages for the entities that were not sent yet are held back until the entity is sent.
+//!
+//! Entities in [`ReplicationMode::Room`](crate::prelude::ReplicationMode::Room) are part of the initial sync if they
+//! are visible to the client when it starts; the ones that become visible later are sent right away.
+//!
+//! The entity actions messages that are sent to a client during its initial sync are compressed with LZ4 and sent
+//! together in a single [`InitialSyncBatchMessage`] every send interval, because the spawns of many similar entities
+//! compress well. The client decompresses the batch and handles the replication messages it contains as usual.
+//!
+//! The client is told how many entities are part of its initial sync, and emits the
+//! [`InitialSyncProgress`](crate::client::events::InitialSyncProgress) and
+//! [`InitialSyncComplete`](crate::client::events::InitialSyncComplete) events as it receives them.
+use std::hash::Hash;
+
+use anyhow::Context;
+use bevy::ecs::entity::EntityHashSet;
+use bevy::prelude::Entity;
+use bevy::utils::HashMap;
+use bitcode::{Decode, Encode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::client::events::InitialSyncProgress;
+use crate::connection::id::ClientId;
+use crate::packet::limits::DecodeLimits;
+use crate::serialize::reader::ReadBuffer;
+use crate::serialize::wordbuffer::reader::ReadWordBuffer;
+use crate::serialize::wordbuffer::writer::WriteWordBuffer;
+use crate::serialize::writer::WriteBuffer;
+use crate::shared::replication::ReplicationMessage;
+
+/// Configuration of the initial sync of the newly connected clients
+#[derive(Clone, Debug, PartialEq)]
+pub struct InitialSyncConfig {
+    /// Maximum number of entities that are sent to a client that is waiting for its initial sync, every send interval
+    pub entities_per_send: usize,
+}
+
+impl Default for InitialSyncConfig {
+    fn default() -> Self {
+        Self {
+            entities_per_send: 100,
+        }
+    }
+}
+
+/// Message sent to a client that is waiting for its initial sync, with the number of entities that are part of it
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct InitialSyncMessage {
+    pub(crate) total: u32,
+}
+
+/// Replication messages sent to a client during its initial sync, compressed with LZ4
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct InitialSyncBatchMessage {
+    /// The serialized messages, compressed with the uncompressed size prepended
+    pub(crate) bytes: Vec<u8>,
+}
+
+impl InitialSyncBatchMessage {
+    pub(crate) fn compress<C: Serialize, K: Serialize + Hash + Eq>(
+        messages: &[ReplicationMessage<C, K>],
+    ) -> anyhow::Result<Self> {
+        let mut writer = WriteWordBuffer::with_capacity(1024);
+        writer.serialize(messages)?;
+        Ok(Self {
+            bytes: lz4_flex::compress_prepend_size(writer.finish_write()),
+        })
+    }
+
+    /// Decompress the replication messages.
+    ///
+    /// The size of the decompressed messages is bounded by [`DecodeLimits::max_message_size`]
+    pub(crate) fn decompress<C: DeserializeOwned, K: DeserializeOwned + Hash + Eq>(
+        &self,
+        decode_limits: &DecodeLimits,
+    ) -> anyhow::Result<Vec<ReplicationMessage<C, K>>> {
+        // the size is sent by the remote: check it before allocating
+        let (size, _) = lz4_flex::block::uncompressed_size(&self.bytes)
+            .context("invalid initial sync batch")?;
+        anyhow::ensure!(
+            size <= decode_limits.max_message_size,
+            "the initial sync batch is too big: {size} bytes (max {})",
+            decode_limits.max_message_size
+        );
+        let bytes = lz4_flex::decompress_size_prepended(&self.bytes)
+            .context("could not decompress the initial sync batch")?;
+        let mut reader = ReadWordBuffer::start_read(&bytes);
+        decode_limits.decode(|| reader.deserialize())
+    }
+}
+
+/// State of the initial sync of a client, on the sender side
+#[derive(Debug, Default)]
+struct ClientSync {
+    /// Entities that still need to be sent, sorted by increasing priority
+    pending: Vec<Entity>,
+    /// Same entities as `pending`, for fast lookups
+    pending_set: EntityHashSet,
+    /// Entities that are sent during the current send
+    batch: EntityHashSet,
+    /// Number of entities that were sent so far, including the current batch
+    sent: u32,
+    /// Last number of entities that we announced to the client
+    announced_total: Option<u32>,
+}
+
+/// Keeps track of the clients that are waiting for their initial sync
+#[derive(Debug)]
+pub(crate) struct InitialSyncSender {
+    config: InitialSyncConfig,
+    clients: HashMap<ClientId, ClientSync>,
+}
+
+impl InitialSyncSender {
+    pub(crate) fn new(config: InitialSyncConfig) -> Self {
+        Self {
+            config,
+            clients: HashMap::default(),
+        }
+    }
+
+    /// Start the initial sync of a client, with the list of entities to send and their priority
+    pub(crate) fn start(&mut self, client_id: ClientId, mut entities: Vec<(Entity, f32)>) {
+        // sort by increasing priority, so that we can pop the entities with the highest priority
+        entities.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        let pending = entities
+            .into_iter()
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+        let pending_set = pending.iter().copied().collect();
+        self.clients.insert(
+            client_id,
+            ClientSync {
+                pending,
+                pending_set,
+                ..Default::default()
+            },
+        );
+    }
+
+    pub(crate) fn remove_client(&mut self, client_id: ClientId) {
+        self.clients.remove(&client_id);
+    }
+
+    /// Select the entities that are sent to each client during the current send.
+    ///
+    /// `should_send` returns false for the entities that cannot be sent to the client anymore (for example because
+    /// they were despawned). Returns the clients that need to be notified of the number of entities in their initial sync.
+    pub(crate) fn next_batch(
+        &mut self,
+        should_send: impl Fn(ClientId, Entity) -> bool,
+    ) -> Vec<(ClientId, u32)> {
+        let mut announcements = vec![];
+        for (client_id, sync) in self.clients.iter_mut() {
+            sync.pending
+                .retain(|entity| should_send(*client_id, *entity));
+            let count = sync.pending.len().min(self.config.entities_per_send);
+            sync.batch = sync
+                .pending
+                .split_off(sync.pending.len() - count)
+                .into_iter()
+                .collect();
+            sync.pending_set = sync.pending.iter().copied().collect();
+            sync.sent += count as u32;
+            let total = sync.sent + sync.pending.len() as u32;
+            if sync.announced_total != Some(total) {
+                sync.announced_total = Some(total);
+                announcements.push((*client_id, total));
+            }
+        }
+        announcements
+    }
+
+    /// Clear the current batches, and stop tracking the clients whose initial sync is over
+    pub(crate) fn finish_send(&mut self) {
+        self.clients.retain(|_, sync| {
+            sync.batch.clear();
+            !sync.pending.is_empty()
+        });
+    }
+
+    /// Returns true if the entity is sent to the client as part of its initial sync during the current send
+    pub(crate) fn is_in_batch(&self, client_id: ClientId, entity: Entity) -> bool {
+        self.clients
+            .get(&client_id)
+            .map_or(false, |sync| sync.batch.contains(&entity))
+    }
+
+    /// Returns true if entities are sent to the client as part of its initial sync during the current send
+    pub(crate) fn has_batch(&self, client_id: ClientId) -> bool {
+        self.clients
+            .get(&client_id)
+            .map_or(false, |sync| !sync.batch.is_empty())
+    }
+
+    /// Clients for which the entity is part of the current batch
+    pub(crate) fn batch_clients(&self, entity: Entity) -> Vec<ClientId> {
+        self.clients
+            .iter()
+            .filter(|(_, sync)| sync.batch.contains(&entity))
+            .map(|(client_id, _)| *client_id)
+            .collect()
+    }
+
+    /// Clients that are still waiting to receive the entity
+    pub(crate) fn pending_clients(&self, entity: Entity) -> Vec<ClientId> {
+        self.clients
+            .iter()
+            .filter(|(_, sync)| sync.pending_set.contains(&entity))
+            .map(|(client_id, _)| *client_id)
+            .collect()
+    }
+}
+
+/// State of the initial sync on the receiver side
+#[derive(Debug, Default)]
+pub(crate) struct InitialSyncReceiver {
+    received: u32,
+    total: Option<u32>,
+    /// True if the progress changed since the last time it was read
+    changed: bool,
+}
+
+impl InitialSyncReceiver {
+    pub(crate) fn recv_message(&mut self, message: InitialSyncMessage) {
+        // the messages can arrive out of order, but the total can only decrease
+        // (entities that were despawned before being sent are not part of the initial sync anymore)
+        self.total = Some(
+            self.total
+                .map_or(message.total, |total| total.min(message.total)),
+        );
+        self.changed = true;
+    }
+
+    pub(crate) fn recv_entity(&mut self) {
+        self.received += 1;
+        self.changed = true;
+    }
+
+    /// Returns the progress of the initial sync if it changed since the last call
+    pub(crate) fn take_progress(&mut self) -> Option<InitialSyncProgress> {
+        let total = self.total?;
+        if !std::mem::take(&mut self.changed) {
+            return None;
+        }
+        Some(InitialSyncProgress {
+            received: self.received.min(total),
+            total,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::{default, EventReader, ResMut, Resource, Update};
+    use bevy::utils::Duration;
+
+    use crate::client::events::InitialSyncComplete;
+    use crate::client::sync::SyncConfig;
+    use crate::packet::message::MessageId;
+    use crate::prelude::client::{InterpolationConfig, PredictionConfig};
+    use crate::prelude::server::{ConnectEvent, RoomId, RoomManager};
+    use crate::prelude::{
+        LinkConditionerConfig, ReplicationGroup, ReplicationMode, SharedConfig, TickConfig,
+    };
+    use crate::protocol::Protocol;
+    use crate::server::config::ServerConfig;
+    use crate::shared::replication::components::ReplicationGroupId;
+    use crate::shared::replication::{EntityActionMessage, EntityActions, ReplicationMessageData};
+    use crate::tests::protocol::Replicate;
+    use crate::tests::protocol::*;
+    use crate::tests::stepper::{BevyStepper, Step};
+
+    use super::*;
+
+    #[test]
+    fn test_initial_sync_batches() {
+        let client_id = ClientId::Netcode(1);
+        let mut sender = InitialSyncSender::new(InitialSyncConfig {
+            entities_per_send: 2,
+        });
+        let entities = (0..5).map(Entity::from_raw).collect::<Vec<_>>();
+        sender.start(
+            client_id,
+            entities
+                .iter()
+                .enumerate()
+                .map(|(i, entity)| (*entity, i as f32))
+                .collect(),
+        );
+
+        // the entities with the highest priority are sent first
+        assert_eq!(sender.next_batch(|_, _| true), vec![(client_id, 5)]);
+        assert!(sender.is_in_batch(client_id, entities[4]));
+        assert!(sender.is_in_batch(client_id, entities[3]));
+        assert_eq!(sender.pending_clients(entities[0]), vec![client_id]);
+        assert!(sender.pending_clients(entities[4]).is_empty());
+        sender.finish_send();
+
+        // entities that cannot be sent anymore are removed from the total
+        assert_eq!(
+            sender.next_batch(|_, entity| entity != entities[0]),
+            vec![(client_id, 4)]
+        );
+        assert_eq!(sender.batch_clients(entities[2]), vec![client_id]);
+        assert_eq!(sender.batch_clients(entities[1]), vec![client_id]);
+        sender.finish_send();
+        assert!(sender.clients.is_empty());
+
+        let mut receiver = InitialSyncReceiver::default();
+        receiver.recv_entity();
+        assert_eq!(receiver.take_progress(), None);
+        receiver.recv_message(InitialSyncMessage { total: 5 });
+        receiver.recv_message(InitialSyncMessage { total: 4 });
+        assert_eq!(
+            receiver.take_progress(),
+            Some(InitialSyncProgress {
+                received: 1,
+                total: 4
+            })
+        );
+        assert_eq!(receiver.take_progress(), None);
+    }
+
+    #[test]
+    fn test_initial_sync_batch_compression() {
+        type Kinds = <MyProtocol as Protocol>::ComponentKinds;
+        let messages = (0..100)
+            .map(|i| ReplicationMessage {
+                group_id: ReplicationGroupId(i),
+                data: ReplicationMessageData::Actions(EntityActionMessage {
+                    sequence_id: MessageId(0),
+                    actions: vec![(
+                        Entity::from_raw(i as u32),
+                        EntityActions {
+                            spawn: true,
+                            initial_sync: true,
+                            insert: vec![Component1(1.0).into(), Component2(2.0).into()],
+                            ..default()
+                        },
+                    )],
+                }),
+            })
+            .collect::<Vec<ReplicationMessage<MyComponentsProtocol, Kinds>>>();
+        let batch = InitialSyncBatchMessage::compress(&messages).unwrap();
+        let mut writer = WriteWordBuffer::with_capacity(1024);
+        writer.serialize(&messages).unwrap();
+        let uncompressed_size = writer.finish_write().len();
+        // the spawns of similar entities compress well
+        assert!(batch.bytes.len() < uncompressed_size / 2);
+        assert_eq!(
+            batch
+                .decompress::<MyComponentsProtocol, Kinds>(&DecodeLimits::default())
+                .unwrap(),
+            messages
+        );
+
+        // the decompressed size is bounded by the decode limits
+        let decode_limits = DecodeLimits::default().with_max_message_size(uncompressed_size - 1);
+        assert!(batch
+            .decompress::<MyComponentsProtocol, Kinds>(&decode_limits)
+            .is_err());
+    }
+
+    #[derive(Resource, Default)]
+    struct CompleteEvents(usize);
+
+    /// Stepper where the server sends the initial sync in chunks of 2 entities; the client is not connected yet
+    fn initial_sync_stepper() -> BevyStepper {
+        let tick_duration = Duration::from_millis(10);
+        let frame_duration = Duration::from_millis(10);
+        let shared_config = SharedConfig {
+            tick: TickConfig::new(tick_duration),
+            ..Default::default()
+        };
+        let link_conditioner = LinkConditionerConfig {
+            incoming_latency: Duration::from_millis(0),
+            incoming_jitter: Duration::from_millis(0),
+            incoming_loss: 0.0,
+        };
+        let mut stepper = BevyStepper::new(
+            shared_config,
+            SyncConfig::default().speedup_factor(1.0),
+            PredictionConfig::default().disable(false),
+            InterpolationConfig::default(),
+            link_conditioner,
+            frame_duration,
+        );
+        stepper
+            .server_app
+            .world
+            .resource_mut::<ServerConfig>()
+            .replication
+            .initial_sync = Some(InitialSyncConfig {
+            entities_per_send: 2,
+        });
+        stepper
+    }
+
+    #[test]
+    fn test_initial_sync() {
+        // connect with the initial sync enabled, with some entities already in the world
+        let mut stepper = initial_sync_stepper();
+        let server_entities = (0..5)
+            .map(|i| {
+                stepper
+                    .server_app
+                    .world
+                    .spawn((
+                        Component1(i as f32),
+                        Replicate {
+                            replication_group: ReplicationGroup::default().set_priority(i as f32),
+                            ..default()
+                        },
+                    ))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        stepper.client_app.init_resource::<CompleteEvents>();
+        stepper.client_app.add_systems(
+            Update,
+            |mut events: EventReader<InitialSyncComplete>, mut count: ResMut<CompleteEvents>| {
+                count.0 += events.read().count();
+            },
+        );
+        stepper.init();
+        for _ in 0..10 {
+            stepper.frame_step();
+        }
+
+        let manager = stepper
+            .client_app
+            .world
+            .resource::<ClientConnectionManager>();
+        for server_entity in server_entities {
+            assert!(manager
+                .replication_receiver
+                .remote_entity_map
+                .get_local(server_entity)
+                .is_some());
+        }
+        assert_eq!(manager.replication_receiver.initial_sync.received, 5);
+        assert_eq!(manager.replication_receiver.initial_sync.total, Some(5));
+        assert_eq!(stepper.client_app.world.resource::<CompleteEvents>().0, 1);
+    }
+
+    /// The entities in rooms that are visible to the client when it connects are part of its initial sync
+    #[test]
+    fn test_initial_sync_rooms() {
+        let mut stepper = initial_sync_stepper();
+        // the entities in room 0 are visible to the client, the entities in room 1 are not
+        let server_entities = (0..5)
+            .map(|i| {
+                let entity = stepper
+                    .server_app
+                    .world
+                    .spawn((
+                        Component1(i as f32),
+                        Replicate {
+                            replication_mode: ReplicationMode::Room,
+                            ..default()
+                        },
+                    ))
+                    .id();
+                stepper
+                    .server_app
+                    .world
+                    .resource_mut::<RoomManager>()
+                    .add_entity(entity, RoomId(i % 2));
+                entity
+            })
+            .collect::<Vec<_>>();
+        stepper.server_app.add_systems(
+            Update,
+            |mut events: EventReader<ConnectEvent>, mut room_manager: ResMut<RoomManager>| {
+                for event in events.read() {
+                    room_manager.add_client(*event.context(), RoomId(0));
+                }
+            },
+        );
+        stepper.client_app.init_resource::<CompleteEvents>();
+        stepper.client_app.add_systems(
+            Update,
+            |mut events: EventReader<InitialSyncComplete>, mut count: ResMut<CompleteEvents>| {
+                count.0 += events.read().count();
+            },
+        );
+        stepper.init();
+        for _ in 0..10 {
+            stepper.frame_step();
+        }
+
+        let manager = stepper
+            .client_app
+            .world
+            .resource::<ClientConnectionManager>();
+        for (i, server_entity) in server_entities.into_iter().enumerate() {
+            let local_entity = manager
+                .replication_receiver
+                .remote_entity_map
+                .get_local(server_entity)
+                .copied();
+            if i % 2 == 0 {
+                let local_entity =
+                    local_entity.expect("the entity in the room should be replicated");
+                assert_eq!(
+                    stepper.client_app.world.get::<Component1>(local_entity),
+                    Some(&Component1(i as f32))
+                );
+            } else {
+                assert!(local_entity.is_none());
+            }
+        }
+        assert_eq!(manager.replication_receiver.initial_sync.received, 3);
+        assert_eq!(manager.replication_receiver.initial_sync.total, Some(3));
+        assert_eq!(stepper.client_app.world.resource::<CompleteEvents>().0, 1);
+    }
+}
@@ -11,22 +11,30 @@ use bevy::utils::HashSet;
 use serde::{Deserialize, Serialize};
 
 use crate::_reexport::{ComponentProtocol, ComponentProtocolKind};
-use crate::channel::builder::Channel;
+use crate::channel::builder::{Channel, ChannelDirection};
 use crate::connection::id::ClientId;
 use crate::packet::message::MessageId;
 use crate::prelude::{NetworkTarget, Tick};
 use crate::protocol::{EventContext, Protocol};
-use crate::shared::replication::components::{Replicate, ReplicationGroupId};
+use crate::shared::replication::components::{
+    Replicate, ReplicateRemoveBehaviour, ReplicationGroupId,
+};
+use crate::shared::replication::delta::ComponentDelta;
 
+pub mod authority;
 pub mod components;
+pub mod dormancy;
 
 mod commands;
+pub(crate) mod delta;
 pub mod entity_map;
 pub(crate) mod hierarchy;
+pub mod initial_sync;
 pub(crate) mod plugin;
 pub(crate) mod receive;
 pub(crate) mod resources;
 pub(crate) mod send;
+pub mod state;
 pub mod systems;
 
 // // NOTE: cannot add trait bounds on C: ComponentProtocol and K: ComponentProtocolKind because of https://github.com/serde-rs/serde/issues/1296
@@ -51,6 +59,15 @@ pub mod systems;
 pub struct EntityActions<C, K: Hash + Eq> {
     pub(crate) spawn: bool,
     pub(crate) despawn: bool,
+    /// The entity is not visible to the remote anymore (for example it left the remote's rooms),
+    /// but it was not despawned
+    pub(crate) lost_visibility: bool,
+    /// The entity stopped being replicated, and the remote entity should be converted to a plain local entity
+    pub(crate) detach: bool,
+    /// The entity went dormant (`Some(true)`) or woke up (`Some(false)`)
+    pub(crate) dormant: Option<bool>,
+    /// The entity spawn is part of the initial sync of the remote
+    pub(crate) initial_sync: bool,
     // Cannot use HashSet because we would need ComponentProtocol to implement Hash + Eq
     pub(crate) insert: Vec<C>,
     pub(crate) remove: HashSet<K>,
@@ -63,6 +80,10 @@ impl<C, K: Hash + Eq> Default for EntityActions<C, K> {
         Self {
             spawn: false,
             despawn: false,
+            lost_visibility: false,
+            detach: false,
+            dormant: None,
+            initial_sync: false,
             insert: Vec::new(),
             remove: HashSet::new(),
             updates: Vec::new(),
@@ -80,12 +101,14 @@ pub struct EntityActionMessage<C, K: Hash + Eq> {
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
-pub struct EntityUpdatesMessage<C> {
+pub struct EntityUpdatesMessage<C, K> {
     /// The last tick for which we sent an EntityActionsMessage for this group
     /// We set this to None after a certain amount of time without any new Actions, to signify on the receiver side
     /// that there is no ordering constraint with respect to Actions for this group (i.e. the Update can be applied immediately)
     last_action_tick: Option<Tick>,
     pub(crate) updates: Vec<(Entity, Vec<C>)>,
+    /// Updates of the delta-compressed components, written as a diff against a value acked by the remote
+    pub(crate) delta_updates: Vec<(Entity, Vec<ComponentDelta<K>>)>,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
@@ -93,7 +116,7 @@ pub enum ReplicationMessageData<C, K: Hash + Eq> {
     /// All the entity actions (Spawn/despawn/inserts/removals) for a given group
     Actions(EntityActionMessage<C, K>),
     /// All the entity updates for a given group
-    Updates(EntityUpdatesMessage<C>),
+    Updates(EntityUpdatesMessage<C, K>),
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
@@ -115,6 +138,10 @@ pub trait ReplicationSend<P: Protocol>: Resource {
     /// (in the client and the server replication plugins)
     type SetMarker: Debug + Hash + Send + Sync + Eq + Clone;
 
+    /// Direction in which the replication data is sent
+    /// (only the registered components that can be sent in this direction are replicated)
+    const DIRECTION: ChannelDirection;
+
     /// Set the priority for a given replication group, for a given client
     /// This IS the client-facing API that users must use to update the priorities for a given client.
     ///
@@ -130,6 +157,21 @@ pub trait ReplicationSend<P: Protocol>: Resource {
     /// (this is used to send the initial state of the world to new clients)
     fn new_connected_clients(&self) -> Vec<ClientId>;
 
+    /// Return the list of clients that need to receive the full state of the entity during this send:
+    /// the newly connected clients, or the clients for which the entity is part of the current
+    /// chunk of their initial sync
+    fn full_sync_clients(&self, _entity: Entity) -> Vec<ClientId> {
+        self.new_connected_clients()
+    }
+
+    /// Return the list of clients for which the entity is part of the current chunk of their initial sync
+    fn initial_sync_clients(&self, _entity: Entity) -> Vec<ClientId> {
+        vec![]
+    }
+
+    /// Return the entity of the remote peer that the local entity was replicated from
+    fn remote_entity(&self, local_entity: Entity) -> Option<Entity>;
+
     fn prepare_entity_spawn(
         &mut self,
         entity: Entity,
@@ -146,6 +188,44 @@ pub trait ReplicationSend<P: Protocol>: Resource {
         system_current_tick: BevyTick,
     ) -> Result<()>;
 
+    /// The entity is not visible to the remote anymore, but was not despawned
+    fn prepare_entity_lost_visibility(
+        &mut self,
+        entity: Entity,
+        replicate: &Replicate<P>,
+        target: NetworkTarget,
+        system_current_tick: BevyTick,
+    ) -> Result<()>;
+
+    /// The entity stopped being replicated: the remote entity should be converted to a plain local entity
+    fn prepare_entity_detach(
+        &mut self,
+        entity: Entity,
+        replicate: &Replicate<P>,
+        target: NetworkTarget,
+        system_current_tick: BevyTick,
+    ) -> Result<()>;
+
+    /// Returns true if the changes of the entity that happened at `change_tick` will be received by every remote
+    /// that the entity is replicated to (they were sent reliably, or in an update that was acked)
+    fn changes_delivered(
+        &self,
+        entity: Entity,
+        replicate: &Replicate<P>,
+        change_tick: BevyTick,
+        current_tick: BevyTick,
+    ) -> bool;
+
+    /// The entity went dormant or woke up
+    fn prepare_entity_dormancy(
+        &mut self,
+        entity: Entity,
+        replicate: &Replicate<P>,
+        target: NetworkTarget,
+        dormant: bool,
+        system_current_tick: BevyTick,
+    ) -> Result<()>;
+
     fn prepare_component_insert(
         &mut self,
         entity: Entity,
@@ -190,6 +270,10 @@ pub trait ReplicationSend<P: Protocol>: Resource {
 
     fn get_mut_replicate_component_cache(&mut self) -> &mut EntityHashMap<Replicate<P>>;
 
+    /// What happens to the remote entity when `Replicate` is removed from an entity that
+    /// doesn't specify a [`ReplicateRemoveBehaviour`]
+    fn replicate_remove_behaviour(&self) -> ReplicateRemoveBehaviour;
+
     /// Do some regular cleanup on the internals of replication
     /// - account for tick wrapping by resetting some internal ticks for each replication group
     fn cleanup(&mut self, tick: Tick);
@@ -204,6 +288,56 @@ mod tests {
     use crate::tests::protocol::*;
     use crate::tests::stepper::{BevyStepper, Step};
 
+    // A component with a send_interval is updated at most once per interval,
+    // but still gets the final update when it stops changing
+    #[test]
+    fn test_component_send_interval() {
+        let mut stepper = BevyStepper::default();
+        let mut replicate = Replicate::default();
+        replicate.set_send_interval::<Component1>(Some(Duration::from_millis(100)));
+        let server_entity = stepper
+            .server_app
+            .world
+            .spawn((Component1(0.0), Component2(0.0), replicate))
+            .id();
+        stepper.frame_step();
+        stepper.frame_step();
+        let client_entity = *stepper
+            .client_app
+            .world
+            .resource::<ClientConnectionManager>()
+            .replication_receiver
+            .remote_entity_map
+            .get_local(server_entity)
+            .unwrap();
+
+        stepper
+            .server_app
+            .world
+            .entity_mut(server_entity)
+            .insert((Component1(1.0), Component2(1.0)));
+        stepper.frame_step();
+        stepper.frame_step();
+        // the component without a send_interval is updated right away
+        assert_eq!(
+            stepper.client_app.world.get::<Component2>(client_entity),
+            Some(&Component2(1.0))
+        );
+        assert_eq!(
+            stepper.client_app.world.get::<Component1>(client_entity),
+            Some(&Component1(0.0))
+        );
+
+        // the component stopped changing: its latest value is still sent
+        for _ in 0..10 {
+            stepper.frame_step();
+        }
+        assert_eq!(
+            stepper.client_app.world.get::<Component1>(client_entity),
+            Some(&Component1(1.0))
+        );
+    }
+
     // An entity gets replicated from server to client,
     // then a component gets removed from that entity on server,
     // that component should also removed on client as well.
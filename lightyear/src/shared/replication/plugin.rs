@@ -10,6 +10,7 @@ use crate::prelude::{
 use crate::shared::replication::components::{
     PerComponentReplicationMetadata, Replicate, ReplicationGroupId, ReplicationGroupIdBuilder,
 };
+use crate::shared::replication::dormancy::Dormant;
 use crate::shared::replication::entity_map::{InterpolatedEntityMap, PredictedEntityMap};
 use crate::shared::replication::hierarchy::{HierarchyReceivePlugin, HierarchySendPlugin};
 use crate::shared::replication::resources::{
@@ -53,6 +54,7 @@ impl<P: Protocol, R: ReplicationSend<P>> Plugin for ReplicationPlugin<P, R> {
             .register_type::<ShouldBeInterpolated>()
             .register_type::<PrePredicted>()
             .register_type::<ShouldBePredicted>()
+            .register_type::<Dormant>()
             .register_type::<RemoteEntityMap>()
             .register_type::<PredictedEntityMap>()
             .register_type::<InterpolatedEntityMap>();
@@ -81,8 +83,13 @@ impl<P: Protocol, R: ReplicationSend<P>> Plugin for ReplicationPlugin<P, R> {
                         InternalReplicationSet::<R::SetMarker>::SendResourceUpdates,
                         InternalReplicationSet::<R::SetMarker>::SendComponentUpdates,
                         InternalReplicationSet::<R::SetMarker>::SendDespawnsAndRemovals,
+                        InternalReplicationSet::<R::SetMarker>::HandleDormancy,
                     )
                         .in_set(InternalReplicationSet::<R::SetMarker>::All),
+                    InternalReplicationSet::<R::SetMarker>::HandleDormancy
+                        .before(InternalReplicationSet::<R::SetMarker>::SendEntityUpdates),
+                    InternalReplicationSet::<R::SetMarker>::HandleDormancy
+                        .before(InternalReplicationSet::<R::SetMarker>::SendComponentUpdates),
                     (
                         InternalReplicationSet::<R::SetMarker>::SendEntityUpdates,
                         InternalReplicationSet::<R::SetMarker>::SendResourceUpdates,
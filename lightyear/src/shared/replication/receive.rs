@@ -4,20 +4,28 @@ use std::iter::Extend;
 
 use anyhow::Context;
 use bevy::ecs::entity::{EntityHash, MapEntities};
-use bevy::prelude::{DespawnRecursiveExt, Entity, World};
+use bevy::prelude::{Children, DespawnRecursiveExt, Entity, EntityRef, EntityWorldMut, World};
 use bevy::reflect::Reflect;
 use bevy::utils::HashSet;
 use tracing::{debug, error, info, trace, trace_span, warn};
 
+use crate::client::replication::{LostVisibility, LostVisibilityBehaviour};
+use crate::packet::limits::DecodeLimits;
 use crate::packet::message::MessageId;
 use crate::prelude::client::Confirmed;
 use crate::prelude::Tick;
 use crate::protocol::component::ComponentProtocol;
+use crate::protocol::component::FromType;
 use crate::protocol::component::{ComponentBehaviour, ComponentKindBehaviour};
 use crate::protocol::Protocol;
+use crate::server::validation::{ComponentValidators, CorrectionMessage};
 use crate::shared::events::connection::ConnectionEvents;
+use crate::shared::replication::authority::{Authority, AuthorityTransfer};
 use crate::shared::replication::components::ReplicationGroupId;
+use crate::shared::replication::dormancy::Dormant;
+use crate::shared::replication::initial_sync::{InitialSyncBatchMessage, InitialSyncReceiver};
 
+use super::delta::DeltaReceiver;
 use super::entity_map::RemoteEntityMap;
 use super::{
     EntityActionMessage, EntityUpdatesMessage, ReplicationMessage, ReplicationMessageData,
@@ -28,6 +36,12 @@ type EntityHashMap<K, V> = hashbrown::HashMap<K, V, EntityHash>;
 type EntityHashSet<K> = hashbrown::HashSet<K, EntityHash>;
 
 pub(crate) struct ReplicationReceiver<P: Protocol> {
+    /// The peer that sends us the replication messages. Used to reject the writes to entities
+    /// that the peer doesn't have [`Authority`] over
+    remote_peer: Authority,
+    /// What to do with the entities that are not visible to us anymore
+    lost_visibility: LostVisibilityBehaviour,
+
     /// Map between local and remote entities. (used mostly on client because it's when we receive entity updates)
     pub remote_entity_map: RemoteEntityMap,
 
@@ -37,17 +51,53 @@ pub(crate) struct ReplicationReceiver<P: Protocol> {
     // BOTH
     /// Buffer to so that we have an ordered receiver per group
     pub group_channels: EntityHashMap<ReplicationGroupId, GroupChannel<P>>,
+
+    /// Keeps the recently received values of the delta-compressed components, to rebuild the delta updates
+    delta_receiver: DeltaReceiver<P::ComponentKinds>,
+    /// Progress of the initial sync of the world
+    pub(crate) initial_sync: InitialSyncReceiver,
+    /// Corrections produced by the server validators, to send back to the remote
+    pub(crate) corrections: Vec<CorrectionMessage<P::Components>>,
 }
 
 impl<P: Protocol> ReplicationReceiver<P> {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(remote_peer: Authority) -> Self {
         Self {
+            remote_peer,
+            lost_visibility: LostVisibilityBehaviour::default(),
             // RECEIVE
             remote_entity_map: RemoteEntityMap::default(),
             remote_entity_to_group: Default::default(),
             // BOTH
             group_channels: Default::default(),
+            delta_receiver: DeltaReceiver::default(),
+            initial_sync: InitialSyncReceiver::default(),
+            corrections: Vec::new(),
+        }
+    }
+
+    pub(crate) fn with_lost_visibility(mut self, lost_visibility: LostVisibilityBehaviour) -> Self {
+        self.lost_visibility = lost_visibility;
+        self
+    }
+
+    pub(crate) fn with_decode_limits(mut self, decode_limits: DecodeLimits) -> Self {
+        self.delta_receiver.decode_limits = decode_limits;
+        self
+    }
+
+    /// Recv a batch of compressed replication messages sent during the initial sync, and buffer them
+    pub(crate) fn recv_initial_sync_batch(
+        &mut self,
+        batch: InitialSyncBatchMessage,
+        remote_tick: Tick,
+    ) -> anyhow::Result<()> {
+        let messages = batch.decompress(&self.delta_receiver.decode_limits)?;
+        trace!(len = messages.len(), "Received initial sync batch");
+        for message in messages {
+            self.recv_message(message, remote_tick);
         }
+        Ok(())
     }
 
     /// Recv a new replication message and buffer it
@@ -79,7 +129,11 @@ impl<P: Protocol> ReplicationReceiver<P> {
                     .actions_recv_message_buffer
                     .insert(m.sequence_id, (remote_tick, m));
             }
-            ReplicationMessageData::Updates(m) => {
+            ReplicationMessageData::Updates(mut m) => {
+                // rebuild the full value of the delta-compressed components.
+                // (we do this even if the update is too old to be applied, because the sender could use
+                // the values it contains as base values for future delta updates)
+                self.delta_receiver.resolve_updates(&mut m, remote_tick);
                 // NOTE: this is valid instead after tick wrapping because we keep clamping the latest_tick values
                 //  for each channel
                 // if we have already applied a more recent update for this group, no need to keep this one
@@ -183,6 +237,164 @@ impl<P: Protocol> ReplicationReceiver<P> {
 ///
 /// - all component inserts/removes/updates for an entity to be grouped together in a single message
 impl<P: Protocol> ReplicationReceiver<P> {
+    /// The remote entity is not visible to us anymore, but was not despawned on the remote
+    fn lost_visibility(
+        &mut self,
+        world: &mut World,
+        remote_entity: Entity,
+        group_id: ReplicationGroupId,
+        events: &mut ConnectionEvents<P>,
+    ) {
+        // the entity will be sent again with all its components if it becomes visible again
+        self.delta_receiver.remove_entity(remote_entity);
+        if let Some(group) = self.group_channels.get_mut(&group_id) {
+            group.remote_entities.remove(&remote_entity);
+        }
+        let Some(local_entity) = self.remote_entity_map.get_local(remote_entity).copied() else {
+            error!("Received lost visibility for an entity that does not exist");
+            return;
+        };
+        match self.lost_visibility {
+            LostVisibilityBehaviour::Despawn => {
+                self.remote_entity_map.remove_by_remote(remote_entity);
+                self.remote_entity_to_group.remove(&remote_entity);
+                if let Some(entity_mut) = world.get_entity_mut(local_entity) {
+                    entity_mut.despawn_recursive();
+                }
+            }
+            LostVisibilityBehaviour::Keep => {
+                // keep the mapping, so that we re-use the same entity if it becomes visible again
+                if let Some(mut entity_mut) = world.get_entity_mut(local_entity) {
+                    entity_mut.insert(LostVisibility);
+                }
+            }
+        }
+        events.push_lost_visibility(local_entity);
+    }
+
+    /// Despawn a local entity along with all its descendants, as a single action.
+    ///
+    /// The replicated descendants stop being tracked immediately, even if their own despawns are received later
+    /// (for example if they are in a different replication group)
+    fn despawn_subtree(
+        &mut self,
+        world: &mut World,
+        local_entity: Entity,
+        events: &mut ConnectionEvents<P>,
+    ) {
+        let mut descendants = vec![];
+        let mut stack = vec![local_entity];
+        while let Some(entity) = stack.pop() {
+            if let Some(children) = world.get::<Children>(entity) {
+                stack.extend(children.iter().copied());
+                descendants.extend(children.iter().copied());
+            }
+        }
+        for descendant in descendants {
+            let Some(remote_entity) = self.remote_entity_map.get_remote(descendant).copied() else {
+                continue;
+            };
+            self.remote_entity_map.remove_by_remote(remote_entity);
+            if let Some(group_id) = self.remote_entity_to_group.remove(&remote_entity) {
+                if let Some(group) = self.group_channels.get_mut(&group_id) {
+                    group.remote_entities.remove(&remote_entity);
+                }
+            }
+            self.delta_receiver.remove_entity(remote_entity);
+            events.push_despawn(descendant);
+        }
+        if let Some(entity_mut) = world.get_entity_mut(local_entity) {
+            entity_mut.despawn_recursive();
+        }
+        events.push_despawn(local_entity);
+    }
+
+    /// The remote entity stopped being replicated without being despawned: convert the local entity
+    /// to a plain local entity
+    fn detach(
+        &mut self,
+        world: &mut World,
+        remote_entity: Entity,
+        group_id: ReplicationGroupId,
+        events: &mut ConnectionEvents<P>,
+    ) {
+        self.delta_receiver.remove_entity(remote_entity);
+        if let Some(group) = self.group_channels.get_mut(&group_id) {
+            group.remote_entities.remove(&remote_entity);
+        }
+        self.remote_entity_to_group.remove(&remote_entity);
+        let Some(local_entity) = self.remote_entity_map.remove_by_remote(remote_entity) else {
+            error!("Received detach for an entity that does not exist");
+            return;
+        };
+        if let Some(mut entity_mut) = world.get_entity_mut(local_entity) {
+            entity_mut.remove::<Confirmed>();
+        }
+        events.push_detach(local_entity);
+    }
+
+    /// Run the server [`ComponentValidators`] on a component sent by a client.
+    /// Returns the component to apply, or None if it was rejected
+    fn validate(
+        &mut self,
+        entity: &EntityWorldMut,
+        remote_entity: Entity,
+        component: P::Components,
+    ) -> Option<P::Components> {
+        let Authority::Client(client_id) = self.remote_peer else {
+            return Some(component);
+        };
+        let Some(validators) = entity.world().get_resource::<ComponentValidators<P>>() else {
+            return Some(component);
+        };
+        let outcome = validators.validate(client_id, EntityRef::from(entity), component);
+        if let Some(component) = outcome.correction {
+            self.corrections.push(CorrectionMessage {
+                entity: remote_entity,
+                component,
+            });
+        }
+        if outcome.apply.is_none() {
+            debug!(?remote_entity, "Component rejected by the validator");
+        }
+        outcome.apply
+    }
+
+    /// Remove the [`AuthorityTransfer`] component from the list of inserted components, if there is one
+    fn take_authority_transfer(insert: &mut Vec<P::Components>) -> Option<AuthorityTransfer> {
+        let kind = <P::ComponentKinds as FromType<AuthorityTransfer>>::from_type();
+        let index = insert
+            .iter()
+            .position(|c| P::ComponentKinds::from(c) == kind)?;
+        insert.remove(index).try_into().ok()
+    }
+
+    /// A client that received authority over one of our entities starts replicating its own copy of the entity.
+    /// Map the client entity to our entity, if the client does have authority over it.
+    fn accept_authority_transfer(
+        &mut self,
+        world: &World,
+        remote_entity: Entity,
+        transfer: AuthorityTransfer,
+    ) -> bool {
+        let local_entity = transfer.server_entity;
+        let has_authority = matches!(
+            world.get::<Authority>(local_entity),
+            Some(authority) if *authority == self.remote_peer
+        );
+        if !has_authority {
+            warn!(
+                ?remote_entity,
+                ?local_entity,
+                "Rejected authority transfer from a peer without authority"
+            );
+            return false;
+        }
+        debug!(?remote_entity, ?local_entity, "Received authority transfer");
+        self.remote_entity_map.insert(remote_entity, local_entity);
+        true
+    }
+
     // TODO: how can I emit metrics here that contain the channel kind?
     //  use a OnceCell that gets set with the channel name mapping when the protocol is finalized?
     //  the other option is to have wrappers in Connection, but that's pretty ugly
@@ -200,24 +412,47 @@ impl<P: Protocol> ReplicationReceiver<P> {
     ) {
         let _span = trace_span!("Apply received replication message to world").entered();
         match replication {
-            ReplicationMessageData::Actions(m) => {
+            ReplicationMessageData::Actions(mut m) => {
                 debug!(?tick, ?m, "Received replication actions");
                 // NOTE: order matters here, because some components can depend on other entities.
                 // These components could even form a cycle, for example A.HasWeapon(B) and B.HasHolder(A)
                 // Our solution is to first handle spawn for all entities separately.
-                for (entity, actions) in m.actions.iter() {
+                let mut rejected = EntityHashSet::default();
+                for (entity, actions) in m.actions.iter_mut() {
                     debug!(remote_entity = ?entity, "Received entity actions");
                     assert!(!(actions.spawn && actions.despawn));
                     // spawn
                     if actions.spawn {
+                        if actions.initial_sync {
+                            self.initial_sync.recv_entity();
+                        }
+                        // the remote client received authority over one of our entities: map its entity to ours
+                        if let Some(transfer) = Self::take_authority_transfer(&mut actions.insert) {
+                            if self.accept_authority_transfer(world, *entity, transfer) {
+                                self.remote_entity_to_group.insert(*entity, group_id);
+                            } else {
+                                rejected.insert(*entity);
+                            }
+                            continue;
+                        }
                         self.remote_entity_to_group.insert(*entity, group_id);
-                        if let Some(local_entity) = self.remote_entity_map.get_local(*entity) {
-                            if world.get_entity(*local_entity).is_some() {
-                                warn!("Received spawn for an entity that already exists");
+                        if let Some(local_entity) =
+                            self.remote_entity_map.get_local(*entity).copied()
+                        {
+                            if let Some(mut local_entity_mut) = world.get_entity_mut(local_entity) {
+                                if local_entity_mut.contains::<LostVisibility>() {
+                                    // the entity is visible again: re-use the entity that we kept
+                                    debug!(remote_entity = ?entity, "Entity regained visibility");
+                                    local_entity_mut.remove::<LostVisibility>();
+                                } else {
+                                    warn!("Received spawn for an entity that already exists");
+                                }
                                 continue;
                             }
-                            warn!("Received spawn for an entity that is already in our entity mapping! Not spawning");
-                            continue;
+                            // the local entity was despawned (for example an entity that lost visibility was
+                            // cleaned up), so we spawn a new one
+                            debug!("Received spawn for an entity that is already in our entity mapping but doesn't exist anymore");
+                            self.remote_entity_map.remove_by_remote(*entity);
                         }
                         // TODO: optimization: spawn the bundle of insert components
                         // we spawn every replicated entity with the `Confirmed` component
@@ -236,27 +471,51 @@ impl<P: Protocol> ReplicationReceiver<P> {
 
                 for (entity, actions) in m.actions.into_iter() {
                     debug!(remote_entity = ?entity, "Received entity actions");
+                    if rejected.contains(&entity) {
+                        continue;
+                    }
 
                     // despawn
                     if actions.despawn {
                         debug!(remote_entity = ?entity, "Received entity despawn");
+                        if let Ok(local_entity_mut) =
+                            self.remote_entity_map.get_by_remote(world, entity)
+                        {
+                            if !Authority::can_despawn(self.remote_peer, &local_entity_mut) {
+                                warn!(remote_entity = ?entity, "Rejected despawn from a peer without authority");
+                                continue;
+                            }
+                        }
                         if let Some(local_entity) = self.remote_entity_map.remove_by_remote(entity)
                         {
                             if let Some(group) = self.group_channels.get_mut(&group_id) {
                                 group.remote_entities.remove(&entity);
                             }
-                            // TODO: we despawn all children as well right now, but that might not be what we want?
-                            if let Some(entity_mut) = world.get_entity_mut(local_entity) {
-                                entity_mut.despawn_recursive();
-                            }
-                            events.push_despawn(local_entity);
                             self.remote_entity_to_group.remove(&entity);
+                            self.delta_receiver.remove_entity(entity);
+                            self.despawn_subtree(world, local_entity, events);
                         } else {
-                            error!("Received despawn for an entity that does not exist")
+                            // the entity can already have been despawned as part of the subtree of
+                            // one of its ancestors
+                            debug!(remote_entity = ?entity, "Received despawn for an entity that does not exist")
                         }
                         continue;
                     }
 
+                    // lost visibility
+                    if actions.lost_visibility {
+                        debug!(remote_entity = ?entity, "Received entity lost visibility");
+                        self.lost_visibility(world, entity, group_id, events);
+                        continue;
+                    }
+
+                    // detach
+                    if actions.detach {
+                        debug!(remote_entity = ?entity, "Received entity detach");
+                        self.detach(world, entity, group_id, events);
+                        continue;
+                    }
+
                     // safety: we know by this point that the entity exists
                     let Ok(mut local_entity_mut) =
                         self.remote_entity_map.get_by_remote(world, entity)
@@ -265,6 +524,19 @@ impl<P: Protocol> ReplicationReceiver<P> {
                         continue;
                     };
 
+                    // dormancy
+                    match actions.dormant {
+                        Some(true) => {
+                            trace!(remote_entity = ?entity, "Entity went dormant");
+                            local_entity_mut.insert(Dormant);
+                        }
+                        Some(false) => {
+                            trace!(remote_entity = ?entity, "Entity woke up");
+                            local_entity_mut.remove::<Dormant>();
+                        }
+                        None => {}
+                    }
+
                     // inserts
                     let kinds = actions
                         .insert
@@ -273,8 +545,20 @@ impl<P: Protocol> ReplicationReceiver<P> {
                         .collect::<HashSet<P::ComponentKinds>>();
                     debug!(remote_entity = ?entity, ?kinds, "Received InsertComponent");
                     for mut component in actions.insert {
+                        if !Authority::can_write::<P>(
+                            self.remote_peer,
+                            &local_entity_mut,
+                            (&component).into(),
+                        ) {
+                            debug!(remote_entity = ?entity, "Rejected insert from a peer without authority");
+                            continue;
+                        }
                         // map any entities inside the component
                         component.map_entities(&mut self.remote_entity_map);
+                        let Some(component) = self.validate(&local_entity_mut, entity, component)
+                        else {
+                            continue;
+                        };
                         // TODO: figure out what to do with tick here
                         events.push_insert_component(
                             local_entity_mut.id(),
@@ -296,6 +580,10 @@ impl<P: Protocol> ReplicationReceiver<P> {
                     // removals
                     trace!(remote_entity = ?entity, ?actions.remove, "Received RemoveComponent");
                     for kind in actions.remove {
+                        if !Authority::can_write::<P>(self.remote_peer, &local_entity_mut, kind) {
+                            debug!(remote_entity = ?entity, "Rejected removal from a peer without authority");
+                            continue;
+                        }
                         events.push_remove_component(local_entity_mut.id(), kind, Tick(0));
                         kind.remove(&mut local_entity_mut);
                     }
@@ -310,8 +598,20 @@ impl<P: Protocol> ReplicationReceiver<P> {
                         .collect::<Vec<P::ComponentKinds>>();
                     debug!(remote_entity = ?entity, ?kinds, "Received UpdateComponent");
                     for mut component in actions.updates {
+                        if !Authority::can_write::<P>(
+                            self.remote_peer,
+                            &local_entity_mut,
+                            (&component).into(),
+                        ) {
+                            debug!(remote_entity = ?entity, "Rejected update from a peer without authority");
+                            continue;
+                        }
                         // map any entities inside the component
                         component.map_entities(&mut self.remote_entity_map);
+                        let Some(component) = self.validate(&local_entity_mut, entity, component)
+                        else {
+                            continue;
+                        };
                         events.push_update_component(
                             local_entity_mut.id(),
                             (&component).into(),
@@ -330,8 +630,20 @@ impl<P: Protocol> ReplicationReceiver<P> {
                         self.remote_entity_map.get_by_remote(world, entity)
                     {
                         for mut component in components {
+                            if !Authority::can_write::<P>(
+                                self.remote_peer,
+                                &local_entity,
+                                (&component).into(),
+                            ) {
+                                debug!(remote_entity = ?entity, "Rejected update from a peer without authority");
+                                continue;
+                            }
                             // map any entities inside the component
                             component.map_entities(&mut self.remote_entity_map);
+                            let Some(component) = self.validate(&local_entity, entity, component)
+                            else {
+                                continue;
+                            };
                             events.push_update_component(
                                 local_entity.id(),
                                 (&component).into(),
@@ -385,10 +697,10 @@ pub struct GroupChannel<P: Protocol> {
     // the first tick is the last_action_tick (we can only apply the update if the last action tick has been reached)
     // the second tick is the update's server tick when it was sent
     pub buffered_updates_with_last_action_tick:
-        BTreeMap<Tick, BTreeMap<Tick, EntityUpdatesMessage<P::Components>>>,
+        BTreeMap<Tick, BTreeMap<Tick, EntityUpdatesMessage<P::Components, P::ComponentKinds>>>,
     // updates for which there is no condition on the last_action_tick: we can apply them immediately
     pub buffered_updates_without_last_action_tick:
-        BTreeMap<Tick, EntityUpdatesMessage<P::Components>>,
+        BTreeMap<Tick, EntityUpdatesMessage<P::Components, P::ComponentKinds>>,
     /// remote tick of the latest update/action that we applied to the local group
     pub latest_tick: Option<Tick>,
 }
@@ -439,7 +751,9 @@ impl<P: Protocol> GroupChannel<P> {
         Some(message)
     }
 
-    fn read_buffered_updates(&mut self) -> Vec<(Tick, EntityUpdatesMessage<P::Components>)> {
+    fn read_buffered_updates(
+        &mut self,
+    ) -> Vec<(Tick, EntityUpdatesMessage<P::Components, P::ComponentKinds>)> {
         // if we haven't applied any actions (latest_tick is None) we cannot apply any updates
         let Some(latest_tick) = self.latest_tick else {
             return vec![];
@@ -506,14 +820,139 @@ impl<P: Protocol> GroupChannel<P> {
 
 #[cfg(test)]
 mod tests {
+    use bevy::prelude::{default, Events, With};
+
+    use crate::client::connection::ConnectionManager;
+    use crate::client::events::EntityLostVisibilityEvent;
+    use crate::prelude::server::{RoomId, RoomManager};
+    use crate::prelude::{client, ClientId, ReplicationMode};
+    use crate::tests::protocol::Replicate;
     use crate::tests::protocol::*;
+    use crate::tests::stepper::{BevyStepper, Step};
 
     use super::*;
 
+    #[test]
+    fn test_lost_visibility_keep() {
+        let mut stepper = BevyStepper::default_with_client_replication(client::ReplicationConfig {
+            lost_visibility: LostVisibilityBehaviour::Keep,
+            ..default()
+        });
+        let client_id = ClientId::Netcode(111);
+        let room_id = RoomId(0);
+        let server_entity = stepper
+            .server_app
+            .world
+            .spawn((
+                Component1(1.0),
+                Replicate {
+                    replication_mode: ReplicationMode::Room,
+                    ..default()
+                },
+            ))
+            .id();
+        let mut room_manager = stepper.server_app.world.resource_mut::<RoomManager>();
+        room_manager.add_client(client_id, room_id);
+        room_manager.add_entity(server_entity, room_id);
+        stepper.frame_step();
+        stepper.frame_step();
+        let client_entity = stepper
+            .client_app
+            .world
+            .query_filtered::<Entity, (With<Component1>, With<Confirmed>)>()
+            .get_single(&stepper.client_app.world)
+            .unwrap();
+
+        // lose visibility: the entity is kept on the client, with a marker
+        stepper
+            .server_app
+            .world
+            .resource_mut::<RoomManager>()
+            .remove_client(client_id, room_id);
+        stepper.frame_step();
+        stepper.frame_step();
+        assert_eq!(
+            stepper
+                .client_app
+                .world
+                .resource::<Events<EntityLostVisibilityEvent>>()
+                .len(),
+            1
+        );
+        assert!(stepper
+            .client_app
+            .world
+            .entity(client_entity)
+            .contains::<LostVisibility>());
+
+        // updates are not received while the entity is not visible
+        stepper
+            .server_app
+            .world
+            .entity_mut(server_entity)
+            .insert(Component1(2.0));
+        stepper.frame_step();
+        stepper.frame_step();
+        assert_eq!(
+            stepper.client_app.world.get::<Component1>(client_entity),
+            Some(&Component1(1.0))
+        );
+
+        // regain visibility: the same entity is re-used
+        stepper
+            .server_app
+            .world
+            .resource_mut::<RoomManager>()
+            .add_client(client_id, room_id);
+        stepper.frame_step();
+        stepper.frame_step();
+        let client_entity_ref = stepper.client_app.world.entity(client_entity);
+        assert!(!client_entity_ref.contains::<LostVisibility>());
+        assert_eq!(
+            client_entity_ref.get::<Component1>(),
+            Some(&Component1(2.0))
+        );
+        assert_eq!(
+            stepper
+                .client_app
+                .world
+                .query_filtered::<(), With<Confirmed>>()
+                .iter(&stepper.client_app.world)
+                .count(),
+            1
+        );
+
+        // the entity is despawned on the server while it is not visible: the kept entity is despawned too
+        stepper
+            .server_app
+            .world
+            .resource_mut::<RoomManager>()
+            .remove_client(client_id, room_id);
+        stepper.frame_step();
+        stepper.frame_step();
+        assert!(stepper
+            .client_app
+            .world
+            .entity(client_entity)
+            .contains::<LostVisibility>());
+        stepper.server_app.world.despawn(server_entity);
+        stepper.frame_step();
+        stepper.frame_step();
+        assert!(stepper.client_app.world.get_entity(client_entity).is_none());
+        assert!(stepper
+            .client_app
+            .world
+            .resource::<ConnectionManager<MyProtocol>>()
+            .replication_receiver
+            .remote_entity_map
+            .get_local(server_entity)
+            .is_none());
+    }
+
     #[allow(clippy::get_first)]
     #[test]
     fn test_recv_replication_messages() {
-        let mut manager = ReplicationReceiver::<MyProtocol>::new();
+        let mut manager = ReplicationReceiver::<MyProtocol>::new(Authority::Server);
 
         let group_id = ReplicationGroupId(0);
         // recv an actions message that is too old: should be ignored
@@ -568,6 +1007,7 @@ mod tests {
                 data: ReplicationMessageData::Updates(EntityUpdatesMessage {
                     last_action_tick: Some(Tick(0)),
                     updates: Default::default(),
+                    delta_updates: Default::default(),
                 }),
             },
             Tick(1),
@@ -589,6 +1029,7 @@ mod tests {
                 data: ReplicationMessageData::Updates(EntityUpdatesMessage {
                     last_action_tick: Some(Tick(2)),
                     updates: Default::default(),
+                    delta_updates: Default::default(),
                 }),
             },
             Tick(4),
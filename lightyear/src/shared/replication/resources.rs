@@ -1,16 +1,18 @@
 //! Module to handle the replication of bevy [`Resource`]s
 
 use crate::_reexport::{ComponentProtocol, ReplicationSend};
-use crate::prelude::{Message, Protocol};
+use crate::prelude::{ClientId, Message, NetworkTarget, Protocol};
 use crate::shared::replication::components::Replicate;
 use crate::shared::sets::{InternalMainSet, InternalReplicationSet};
 use async_compat::CompatExt;
 use bevy::app::App;
 use bevy::ecs::system::Command;
 use bevy::prelude::{
-    Commands, Component, DetectChanges, Entity, IntoSystemConfigs, IntoSystemSetConfigs, Plugin,
-    PostUpdate, PreUpdate, Query, Ref, Res, ResMut, Resource, SystemSet, With, World,
+    Commands, Component, DetectChanges, DetectChangesMut, Entity, Event, EventWriter,
+    IntoSystemConfigs, IntoSystemSetConfigs, Local, Plugin, PostUpdate, PreUpdate, Query, Ref, Res,
+    ResMut, Resource, SystemSet, With, Without, World,
 };
+use bevy::utils::{HashMap, HashSet};
 use serde::{Deserialize, Serialize};
 use std::marker::PhantomData;
 use tracing::error;
@@ -29,9 +31,15 @@ mod command {
                 .query_filtered::<Entity, With<ReplicateResource<R>>>()
                 .get_single(world)
             {
-                world.entity_mut(entity).insert(self.replicate);
+                world
+                    .entity_mut(entity)
+                    .insert((self.replicate, ReplicateResourceEntity));
             } else {
-                world.spawn((ReplicateResource::<R>::default(), self.replicate));
+                world.spawn((
+                    ReplicateResource::<R>::default(),
+                    self.replicate,
+                    ReplicateResourceEntity,
+                ));
             }
         }
     }
@@ -41,6 +49,7 @@ mod command {
         /// Start replicating a resource to remote clients.
         ///
         /// Any change to the resource will be replicated to the clients.
+        /// The resource is only replicated to the clients in the `replication_target` of the [`Replicate`].
         // TODO: we use `Replicate<P>` as argument instead of the simpler `NetworkTarget`
         //  because it helps with type-inference when calling this method.
         //  We can switch to `NetworkTarget` if we remove the `P` bound of `Replicate`.
@@ -105,8 +114,94 @@ impl<R> Default for ReplicateResource<R> {
     }
 }
 
+/// Resource that holds a different value of the resource `R` for each client.
+///
+/// Each value is only replicated to its client, where it is inserted as the resource `R`.
+/// Removing the value of a client removes the resource on that client.
+/// The value of a client is removed when it disconnects.
+/// The resource `R` should not also be replicated with [`ReplicateResourceExt::replicate_resource`], because
+/// a client can only receive one value for a given resource.
+#[derive(Resource, Debug)]
+pub struct PerClientResource<R> {
+    values: HashMap<ClientId, R>,
+    /// Clients whose value was inserted or modified since the last send
+    changed: HashSet<ClientId>,
+    /// Clients whose value was removed since the last send
+    removed: HashSet<ClientId>,
+}
+
+impl<R> Default for PerClientResource<R> {
+    fn default() -> Self {
+        Self {
+            values: HashMap::default(),
+            changed: HashSet::default(),
+            removed: HashSet::default(),
+        }
+    }
+}
+
+impl<R> PerClientResource<R> {
+    /// Get the value of the resource for a client
+    pub fn get(&self, client_id: ClientId) -> Option<&R> {
+        self.values.get(&client_id)
+    }
+
+    /// Get a mutable reference to the value of the resource for a client.
+    ///
+    /// The value is replicated again to the client, even if it is not actually modified
+    pub fn get_mut(&mut self, client_id: ClientId) -> Option<&mut R> {
+        let value = self.values.get_mut(&client_id)?;
+        self.changed.insert(client_id);
+        Some(value)
+    }
+
+    /// Set the value of the resource for a client
+    pub fn insert(&mut self, client_id: ClientId, value: R) -> Option<R> {
+        self.removed.remove(&client_id);
+        self.changed.insert(client_id);
+        self.values.insert(client_id, value)
+    }
+
+    /// Remove the value of the resource for a client, which removes the resource on the client
+    pub fn remove(&mut self, client_id: ClientId) -> Option<R> {
+        self.changed.remove(&client_id);
+        self.removed.insert(client_id);
+        self.values.remove(&client_id)
+    }
+
+    /// Iterate through the values of the resource for each client
+    pub fn iter(&self) -> impl Iterator<Item = (&ClientId, &R)> {
+        self.values.iter()
+    }
+}
+
+/// Marker for the entities that were spawned to replicate a resource
+#[derive(Component, Debug)]
+pub(crate) struct ReplicateResourceEntity;
+
+/// Marker for the entities that replicate the value of a [`PerClientResource`] to a single client
+#[derive(Component, Debug)]
+pub(crate) struct PerClientResourceEntity;
+
+/// Event emitted on the receiver when a replicated resource is removed, either because it was removed
+/// on the sender or because the resource is not replicated to this peer anymore
+#[derive(Event, Debug)]
+pub struct ResourceRemoveEvent<R> {
+    _marker: PhantomData<R>,
+}
+
+impl<R> Default for ResourceRemoveEvent<R> {
+    fn default() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
 pub(crate) mod send {
     use super::*;
+    use crate::server::events::DisconnectEvent;
+    use bevy::prelude::{resource_exists, EventReader, Events};
     pub(crate) struct ResourceSendPlugin<P, R> {
         _marker: PhantomData<(P, R)>,
     }
@@ -137,14 +232,25 @@ pub(crate) mod send {
     ) {
         app.add_systems(
             PostUpdate,
-            copy_send_resource::<P, R>
+            (
+                copy_send_resource::<P, R>,
+                (
+                    remove_disconnected_clients::<R>
+                        .run_if(resource_exists::<Events<DisconnectEvent>>),
+                    copy_send_per_client_resource::<P, R>,
+                )
+                    .chain(),
+            )
                 .in_set(InternalReplicationSet::<S::SetMarker>::SendResourceUpdates),
         );
     }
 
     fn copy_send_resource<P: Protocol, R: Resource + Clone>(
         resource: Option<Res<R>>,
-        mut replicating_entity: Query<&mut ReplicateResource<R>, With<Replicate<P>>>,
+        mut replicating_entity: Query<
+            &mut ReplicateResource<R>,
+            (With<Replicate<P>>, Without<PerClientResourceEntity>),
+        >,
     ) {
         if replicating_entity.iter().len() > 1 {
             error!(
@@ -172,6 +278,71 @@ pub(crate) mod send {
             }
         }
     }
+
+    /// Remove the value of the clients that disconnected, so that their replicating entity gets despawned
+    fn remove_disconnected_clients<R: Resource>(
+        mut events: EventReader<DisconnectEvent>,
+        resource: Option<ResMut<PerClientResource<R>>>,
+    ) {
+        let Some(mut resource) = resource else {
+            events.clear();
+            return;
+        };
+        for event in events.read() {
+            let client_id = *event.context();
+            if resource.values.contains_key(&client_id) {
+                resource.remove(client_id);
+            }
+        }
+    }
+
+    /// Each client value of a [`PerClientResource`] is replicated via its own entity, which is only replicated to that client
+    fn copy_send_per_client_resource<P: Protocol, R: Resource + Clone>(
+        mut commands: Commands,
+        resource: Option<ResMut<PerClientResource<R>>>,
+        mut entities: Local<HashMap<ClientId, Entity>>,
+        mut replicating_entity: Query<&mut ReplicateResource<R>, With<PerClientResourceEntity>>,
+    ) {
+        let Some(mut resource) = resource else {
+            return;
+        };
+        if !resource.is_changed() {
+            return;
+        }
+        let resource = resource.bypass_change_detection();
+        for client_id in resource.removed.drain() {
+            // despawning the entity removes the resource on the client
+            if let Some(entity) = entities.remove(&client_id) {
+                commands.entity(entity).despawn();
+            }
+        }
+        for client_id in resource.changed.drain() {
+            let Some(value) = resource.values.get(&client_id) else {
+                continue;
+            };
+            if let Some(mut replicating_entity) = entities
+                .get(&client_id)
+                .and_then(|entity| replicating_entity.get_mut(*entity).ok())
+            {
+                replicating_entity.resource = Some(value.clone());
+                continue;
+            }
+            let entity = commands
+                .spawn((
+                    ReplicateResource {
+                        resource: Some(value.clone()),
+                    },
+                    Replicate::<P> {
+                        replication_target: NetworkTarget::Single(client_id),
+                        ..Default::default()
+                    },
+                    PerClientResourceEntity,
+                    ReplicateResourceEntity,
+                ))
+                .id();
+            entities.insert(client_id, entity);
+        }
+    }
 }
 
 pub(crate) mod receive {
@@ -203,9 +374,11 @@ pub(crate) mod receive {
     pub fn add_resource_receive_systems<P: Protocol, S: ReplicationSend<P>, R: Resource + Clone>(
         app: &mut App,
     ) {
+        app.add_event::<ResourceRemoveEvent<R>>();
         app.add_systems(
             PreUpdate,
             (copy_receive_resource::<R>, handle_despawned_entity::<R>)
+                .chain()
                 .in_set(InternalReplicationSet::<S::SetMarker>::ReceiveResourceUpdates),
         );
     }
@@ -214,6 +387,7 @@ pub(crate) mod receive {
         mut commands: Commands,
         replicating_entity: Query<Ref<ReplicateResource<R>>>,
         resource: Option<ResMut<R>>,
+        mut remove_events: EventWriter<ResourceRemoveEvent<R>>,
     ) {
         if replicating_entity.iter().len() > 1 {
             error!(
@@ -232,6 +406,7 @@ pub(crate) mod receive {
                     }
                 } else if let Some(resource) = resource {
                     commands.remove_resource::<R>();
+                    remove_events.send(ResourceRemoveEvent::default());
                 }
             }
         }
@@ -242,22 +417,31 @@ pub(crate) mod receive {
     fn handle_despawned_entity<R: Resource + Clone>(
         mut commands: Commands,
         mut despawned: RemovedComponents<ReplicateResource<R>>,
+        resource: Option<Res<R>>,
+        mut remove_events: EventWriter<ResourceRemoveEvent<R>>,
     ) {
-        for despawned_entity in despawned.read() {
+        if despawned.read().count() > 0 && resource.is_some() {
             commands.remove_resource::<R>();
+            remove_events.send(ResourceRemoveEvent::default());
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{ReplicateResource, StopReplicateResourceExt};
-    use crate::prelude::client::NetworkingState;
-    use crate::prelude::NetworkTarget;
+    use super::{
+        PerClientResource, PerClientResourceEntity, ReplicateResource, ResourceRemoveEvent,
+        StopReplicateResourceExt,
+    };
+    use crate::prelude::client::{ClientCommands, NetworkingState};
+    use crate::prelude::{ClientId, NetworkTarget};
     use crate::shared::replication::resources::ReplicateResourceExt;
     use crate::tests::protocol::{Component1, MyProtocol, Replicate, Resource1};
     use crate::tests::stepper::{BevyStepper, Step};
-    use bevy::prelude::{Commands, Entity, OnEnter, With};
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::prelude::{
+        Commands, Entity, EventReader, OnEnter, PreUpdate, ResMut, Resource, With,
+    };
 
     #[test]
     fn test_resource_replication_manually() {
@@ -537,4 +721,115 @@ mod tests {
         // check that the deletion hasn't been replicated
         assert_eq!(stepper.client_app.world.resource::<Resource1>().0, 3.0);
     }
+
+    #[test]
+    fn test_resource_replication_target() {
+        let mut stepper = BevyStepper::default();
+        let start_replicate_system =
+            stepper
+                .server_app
+                .world
+                .register_system(|mut commands: Commands| {
+                    commands.replicate_resource::<Resource1>(Replicate {
+                        replication_target: NetworkTarget::AllExceptSingle(ClientId::Netcode(111)),
+                        ..Default::default()
+                    });
+                });
+        stepper.server_app.world.insert_resource(Resource1(1.0));
+        let _ = stepper.server_app.world.run_system(start_replicate_system);
+        stepper.frame_step();
+        stepper.frame_step();
+
+        // the resource is not replicated to clients outside of the target
+        assert!(stepper
+            .client_app
+            .world
+            .get_resource::<Resource1>()
+            .is_none());
+    }
+
+    #[derive(Resource, Default)]
+    struct RemoveEvents(usize);
+
+    #[test]
+    fn test_per_client_resource() {
+        let mut stepper = BevyStepper::default();
+        stepper.client_app.init_resource::<RemoveEvents>();
+        stepper.client_app.add_systems(
+            PreUpdate,
+            |mut events: EventReader<ResourceRemoveEvent<Resource1>>,
+             mut count: ResMut<RemoveEvents>| {
+                count.0 += events.read().count();
+            },
+        );
+
+        // each client only receives its own value
+        let mut resource = PerClientResource::<Resource1>::default();
+        resource.insert(ClientId::Netcode(111), Resource1(1.0));
+        resource.insert(ClientId::Netcode(222), Resource1(2.0));
+        stepper.server_app.world.insert_resource(resource);
+        stepper.frame_step();
+        stepper.frame_step();
+        assert_eq!(stepper.client_app.world.resource::<Resource1>().0, 1.0);
+
+        // updates are replicated
+        stepper
+            .server_app
+            .world
+            .resource_mut::<PerClientResource<Resource1>>()
+            .get_mut(ClientId::Netcode(111))
+            .unwrap()
+            .0 = 3.0;
+        stepper.frame_step();
+        stepper.frame_step();
+        assert_eq!(stepper.client_app.world.resource::<Resource1>().0, 3.0);
+
+        // removing the value of the client removes the resource on the client
+        stepper
+            .server_app
+            .world
+            .resource_mut::<PerClientResource<Resource1>>()
+            .remove(ClientId::Netcode(111));
+        stepper.frame_step();
+        stepper.frame_step();
+        stepper.frame_step();
+        assert!(stepper
+            .client_app
+            .world
+            .get_resource::<Resource1>()
+            .is_none());
+        assert_eq!(stepper.client_app.world.resource::<RemoveEvents>().0, 1);
+
+        // the value and the replicating entity of a client are removed when it disconnects
+        stepper
+            .server_app
+            .world
+            .resource_mut::<PerClientResource<Resource1>>()
+            .insert(ClientId::Netcode(111), Resource1(4.0));
+        stepper.frame_step();
+        stepper.frame_step();
+        assert_eq!(stepper.client_app.world.resource::<Resource1>().0, 4.0);
+        stepper
+            .client_app
+            .world
+            .run_system_once(|mut commands: Commands| commands.disconnect_client());
+        stepper.frame_step();
+        stepper.frame_step();
+        assert!(stepper
+            .server_app
+            .world
+            .resource::<PerClientResource<Resource1>>()
+            .get(ClientId::Netcode(111))
+            .is_none());
+        // only the entity that replicates the value of the other client remains
+        assert_eq!(
+            stepper
+                .server_app
+                .world
+                .query_filtered::<Entity, With<PerClientResourceEntity>>()
+                .iter(&stepper.server_app.world)
+                .count(),
+            1
+        );
+    }
 }
@@ -19,6 +19,7 @@ use crate::protocol::component::{ComponentBehaviour, ComponentKindBehaviour};
 use crate::protocol::Protocol;
 use crate::shared::replication::components::{Replicate, ReplicationGroupId};
 
+use super::delta::{encode_component, ComponentDelta, DeltaSender};
 use super::{EntityActionMessage, EntityActions, EntityUpdatesMessage, ReplicationMessageData};
 
 type EntityHashMap<K, V> = hashbrown::HashMap<K, V, EntityHash>;
@@ -46,6 +47,12 @@ pub(crate) struct ReplicationSender<P: Protocol> {
     >,
     pub pending_updates:
         EntityHashMap<ReplicationGroupId, EntityHashMap<Entity, Vec<P::Components>>>,
+    /// Updates of the delta-compressed components. We also keep the full component in case the update
+    /// ends up being sent in an EntityActionMessage
+    pub pending_delta_updates: EntityHashMap<
+        ReplicationGroupId,
+        EntityHashMap<Entity, Vec<(P::Components, ComponentDelta<P::ComponentKinds>)>>,
+    >,
     // Set of unique components for each entity, to avoid sending multiple updates/inserts for the same component
     pub pending_unique_components:
         EntityHashMap<ReplicationGroupId, EntityHashMap<Entity, HashSet<P::ComponentKinds>>>,
@@ -53,6 +60,9 @@ pub(crate) struct ReplicationSender<P: Protocol> {
     /// Buffer to so that we have an ordered receiver per group
     pub group_channels: EntityHashMap<ReplicationGroupId, GroupChannel>,
 
+    /// Keeps track of the component values acked by the remote, to compute delta updates
+    pub(crate) delta_sender: DeltaSender<P::ComponentKinds>,
+
     // PRIORITY
     /// Get notified whenever a message for a given ReplicationGroup was actually sent
     /// (sometimes they might not be sent because of bandwidth constraints
@@ -71,8 +81,10 @@ impl<P: Protocol> ReplicationSender<P> {
             updates_message_id_to_group_id: Default::default(),
             pending_actions: EntityHashMap::default(),
             pending_updates: EntityHashMap::default(),
+            pending_delta_updates: EntityHashMap::default(),
             pending_unique_components: EntityHashMap::default(),
             group_channels: Default::default(),
+            delta_sender: DeltaSender::default(),
             // PRIORITY
             message_send_receiver,
         }
@@ -108,19 +120,45 @@ impl<P: Protocol> ReplicationSender<P> {
         // TODO: don't accumulate priority if priority is not enabled
         // then accumulate the priority for all replication groups
         self.group_channels.values_mut().for_each(|channel| {
+            let priority = channel.priority();
             channel.accumulated_priority = channel
                 .accumulated_priority
-                .map_or(Some(channel.base_priority), |acc| {
-                    Some(acc + channel.base_priority)
-                });
+                .map_or(Some(priority), |acc| Some(acc + priority));
         });
     }
 
+    /// Keep track of the update message that was buffered for a group, so that we can handle receiving an ACK
+    /// for that message_id later
+    pub(crate) fn updates_message_buffered(
+        &mut self,
+        message_id: MessageId,
+        group_id: ReplicationGroupId,
+        bevy_tick: BevyTick,
+    ) {
+        self.updates_message_id_to_group_id
+            .insert(message_id, (group_id, bevy_tick));
+        self.delta_sender.message_buffered(message_id, group_id);
+    }
+
+    /// Returns true if the changes of the group that happened at `change_tick` will be received by the remote
+    pub(crate) fn is_delivered(
+        &self,
+        group_id: ReplicationGroupId,
+        change_tick: BevyTick,
+        current_tick: BevyTick,
+    ) -> bool {
+        self.group_channels.get(&group_id).map_or(false, |channel| {
+            channel.is_delivered(change_tick, current_tick)
+        })
+    }
+
     // TODO: call this in a system after receive
     /// We call this after the Receive SystemSet; to update the bevy_tick at which we received entity updates for each group
     pub(crate) fn recv_update_acks(&mut self) {
         // TODO: handle errors that are not channel::isEmpty
         while let Ok(message_id) = self.updates_ack_tracker.try_recv() {
+            // the component values of the message can now be used to compute delta updates
+            self.delta_sender.message_acked(message_id);
             // remember to remove the entry from the map to avoid memory leakage
             if let Some((group_id, bevy_tick)) =
                 self.updates_message_id_to_group_id.remove(&message_id)
@@ -143,13 +181,39 @@ impl<P: Protocol> ReplicationSender<P> {
 ///
 /// - all component inserts/removes/updates for an entity to be grouped together in a single message
 impl<P: Protocol> ReplicationSender<P> {
+    /// Update the priority multiplier for a given group
+    ///
+    /// The multiplier must be finite and non-negative.
+    pub(crate) fn update_priority_multiplier(
+        &mut self,
+        group_id: ReplicationGroupId,
+        multiplier: f32,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            multiplier.is_finite() && multiplier >= 0.0,
+            "invalid priority multiplier: {multiplier}"
+        );
+        self.group_channels
+            .entry(group_id)
+            .or_default()
+            .priority_multiplier = multiplier;
+        Ok(())
+    }
+
+    /// The priority multiplier of a given group
+    pub(crate) fn priority_multiplier(&self, group_id: ReplicationGroupId) -> f32 {
+        self.group_channels
+            .get(&group_id)
+            .map_or(1.0, |channel| channel.priority_multiplier)
+    }
+
     /// Update the base priority for a given group
     pub(crate) fn update_base_priority(&mut self, group_id: ReplicationGroupId, priority: f32) {
         let channel = self.group_channels.entry(group_id).or_default();
         channel.base_priority = priority;
         // if we already have an accumulated priority, don't override it
         if channel.accumulated_priority.is_none() {
-            channel.accumulated_priority = Some(priority);
+            channel.accumulated_priority = Some(channel.priority());
         }
     }
 
@@ -169,7 +233,22 @@ impl<P: Protocol> ReplicationSender<P> {
         actions.spawn = true;
     }
 
+    /// Mark the entity spawn as being part of the initial sync of the remote
+    pub(crate) fn prepare_entity_initial_sync(
+        &mut self,
+        entity: Entity,
+        group_id: ReplicationGroupId,
+    ) {
+        self.pending_actions
+            .entry(group_id)
+            .or_default()
+            .entry(entity)
+            .or_default()
+            .initial_sync = true;
+    }
+
     pub(crate) fn prepare_entity_despawn(&mut self, entity: Entity, group_id: ReplicationGroupId) {
+        self.delta_sender.remove_entity(entity);
         self.pending_actions
             .entry(group_id)
             .or_default()
@@ -178,6 +257,45 @@ impl<P: Protocol> ReplicationSender<P> {
             .despawn = true;
     }
 
+    pub(crate) fn prepare_entity_lost_visibility(
+        &mut self,
+        entity: Entity,
+        group_id: ReplicationGroupId,
+    ) {
+        // the entity will be spawned again with all its components if it becomes visible again
+        self.delta_sender.remove_entity(entity);
+        self.pending_actions
+            .entry(group_id)
+            .or_default()
+            .entry(entity)
+            .or_default()
+            .lost_visibility = true;
+    }
+
+    pub(crate) fn prepare_entity_detach(&mut self, entity: Entity, group_id: ReplicationGroupId) {
+        self.delta_sender.remove_entity(entity);
+        self.pending_actions
+            .entry(group_id)
+            .or_default()
+            .entry(entity)
+            .or_default()
+            .detach = true;
+    }
+
+    pub(crate) fn prepare_entity_dormancy(
+        &mut self,
+        entity: Entity,
+        group_id: ReplicationGroupId,
+        dormant: bool,
+    ) {
+        self.pending_actions
+            .entry(group_id)
+            .or_default()
+            .entry(entity)
+            .or_default()
+            .dormant = Some(dormant);
+    }
+
     // we want to send all component inserts that happen together for the same entity in a single message
     // (because otherwise the inserts might be received at different packets/ticks by the remote, and
     // the remote might expect the components insert to be received at the same time)
@@ -275,6 +393,34 @@ impl<P: Protocol> ReplicationSender<P> {
             return;
         }
         trace!(?kind, "Inserting pending update!");
+        if kind.delta_compression() {
+            match encode_component(&component) {
+                Ok(bytes) => {
+                    let delta = self.delta_sender.delta(entity, kind, &bytes);
+                    self.delta_sender
+                        .buffer_value(group_id, entity, kind, bytes);
+                    if let Some(delta) = delta {
+                        self.pending_delta_updates
+                            .entry(group_id)
+                            .or_default()
+                            .entry(entity)
+                            .or_default()
+                            .push((component, delta));
+                        self.pending_unique_components
+                            .entry(group_id)
+                            .or_default()
+                            .entry(entity)
+                            .or_default()
+                            .insert(kind);
+                        return;
+                    }
+                }
+                Err(e) => error!(
+                    ?kind,
+                    "could not encode component for delta compression: {:?}", e
+                ),
+            }
+        }
         self.pending_updates
             .entry(group_id)
             .or_default()
@@ -314,10 +460,18 @@ impl<P: Protocol> ReplicationSender<P> {
                         .extend(components.into_iter());
                 }
             }
+            // the actions message is not acked, so we send the full value of the delta-compressed components
+            if let Some(updates) = self.pending_delta_updates.remove(&group_id) {
+                for (entity, components) in updates {
+                    actions
+                        .entry(entity)
+                        .or_default()
+                        .updates
+                        .extend(components.into_iter().map(|(component, _)| component));
+                }
+            }
             let channel = self.group_channels.entry(group_id).or_default();
-            let priority = channel
-                .accumulated_priority
-                .unwrap_or(channel.base_priority);
+            let priority = channel.accumulated_priority.unwrap_or(channel.priority());
             let message_id = channel.actions_next_send_message_id;
             channel.actions_next_send_message_id += 1;
             channel.last_action_tick = Some(tick);
@@ -334,12 +488,22 @@ impl<P: Protocol> ReplicationSender<P> {
             debug!("final action messages to send: {:?}", messages);
         }
         // send the remaining updates
-        for (group_id, updates) in self.pending_updates.drain() {
+        let update_group_ids = self
+            .pending_updates
+            .keys()
+            .chain(self.pending_delta_updates.keys())
+            .copied()
+            .collect::<EntityHashSet<_>>();
+        for group_id in update_group_ids {
+            let updates = self.pending_updates.remove(&group_id).unwrap_or_default();
+            let delta_updates = self
+                .pending_delta_updates
+                .remove(&group_id)
+                .unwrap_or_default();
             trace!(?group_id, "pending updates: {:?}", updates);
             let channel = self.group_channels.entry(group_id).or_default();
-            let priority = channel
-                .accumulated_priority
-                .unwrap_or(channel.base_priority);
+            let priority = channel.accumulated_priority.unwrap_or(channel.priority());
+            self.delta_sender.finalize(group_id, tick);
             messages.push((
                 ChannelKind::of::<EntityUpdatesChannel>(),
                 group_id,
@@ -348,6 +512,15 @@ impl<P: Protocol> ReplicationSender<P> {
                     last_action_tick: channel.last_action_tick,
                     // TODO: maybe we can just send the HashMap directly?
                     updates: Vec::from_iter(updates.into_iter()),
+                    delta_updates: delta_updates
+                        .into_iter()
+                        .map(|(entity, components)| {
+                            (
+                                entity,
+                                components.into_iter().map(|(_, delta)| delta).collect(),
+                            )
+                        })
+                        .collect(),
                 }),
                 priority,
             ));
@@ -359,6 +532,7 @@ impl<P: Protocol> ReplicationSender<P> {
 
         // clear send buffers
         self.pending_unique_components.clear();
+        self.delta_sender.clear_pending();
         messages
     }
 }
@@ -373,12 +547,18 @@ pub struct GroupChannel {
     pub collect_changes_since_this_tick: Option<BevyTick>,
     // last tick for which we sent an action message
     pub last_action_tick: Option<Tick>,
+    /// Bevy tick at which we last buffered an action message for this group.
+    /// Action messages are reliable, so every change that happened before this tick will be received by the remote
+    pub last_action_bevy_tick: Option<BevyTick>,
 
     /// The priority to send the replication group.
     /// This will be reset to base_priority every time we send network updates, unless we couldn't send a message
     /// for this group because of the bandwidth cap, in which case it will be accumulated.
     pub accumulated_priority: Option<f32>,
     pub base_priority: f32,
+    /// Multiplier applied to the base priority for this specific remote
+    /// (for example to update the entities that are close to a client more often)
+    pub priority_multiplier: f32,
 }
 
 impl Default for GroupChannel {
@@ -386,14 +566,20 @@ impl Default for GroupChannel {
         Self {
             actions_next_send_message_id: MessageId(0),
             last_action_tick: None,
+            last_action_bevy_tick: None,
             accumulated_priority: None,
             collect_changes_since_this_tick: None,
             base_priority: 1.0,
+            priority_multiplier: 1.0,
         }
     }
 }
 
 impl GroupChannel {
+    /// The priority that is added to the accumulated priority every time we try to send replication messages
+    pub(crate) fn priority(&self) -> f32 {
+        self.base_priority * self.priority_multiplier
+    }
     /// Update the bevy_tick at which we received entity updates for this group
     /// (we will only collect updates since this tick)
     pub(crate) fn update_collect_changes_since_this_tick(&mut self, bevy_tick: BevyTick) {
@@ -406,6 +592,18 @@ impl GroupChannel {
         self.collect_changes_since_this_tick = Some(bevy_tick);
         // }
     }
+
+    /// Returns true if the changes that happened at `change_tick` will be received by the remote:
+    /// they were sent in a (reliable) action message, or in an update message that was acked
+    pub(crate) fn is_delivered(&self, change_tick: BevyTick, current_tick: BevyTick) -> bool {
+        [
+            self.collect_changes_since_this_tick,
+            self.last_action_bevy_tick,
+        ]
+        .into_iter()
+        .flatten()
+        .any(|tick| !change_tick.is_newer_than(tick, current_tick))
+    }
 }
 
 #[cfg(test)]
@@ -488,6 +686,10 @@ mod tests {
                     EntityActions {
                         spawn: true,
                         despawn: false,
+                        lost_visibility: false,
+                        detach: false,
+                        dormant: None,
+                        initial_sync: false,
                         insert: vec![MyComponentsProtocol::Component1(Component1(1.0))],
                         remove: HashSet::from_iter(vec![MyComponentsProtocolKind::Component2]),
                         updates: vec![MyComponentsProtocol::Component3(Component3(3.0))],
@@ -498,6 +700,10 @@ mod tests {
                     EntityActions {
                         spawn: false,
                         despawn: false,
+                        lost_visibility: false,
+                        detach: false,
+                        dormant: None,
+                        initial_sync: false,
                         insert: vec![],
                         remove: HashSet::default(),
                         updates: vec![MyComponentsProtocol::Component2(Component2(4.0))],
@@ -518,6 +724,7 @@ mod tests {
                         entity_3,
                         vec![MyComponentsProtocol::Component3(Component3(5.0))]
                     )],
+                    delta_updates: vec![],
                 }),
                 1.0
             )
@@ -539,4 +746,50 @@ mod tests {
             Some(Tick(2))
         );
     }
+
+    #[test]
+    fn test_priority_multiplier() {
+        let (_, receiver) = crossbeam_channel::unbounded();
+        let mut manager = ReplicationSender::<MyProtocol>::new(receiver.clone(), receiver);
+        let group_1 = ReplicationGroupId(0);
+        let group_2 = ReplicationGroupId(1);
+        manager.update_priority_multiplier(group_1, 3.0).unwrap();
+        // invalid multipliers are rejected
+        assert!(manager
+            .update_priority_multiplier(group_1, f32::NAN)
+            .is_err());
+        assert!(manager
+            .update_priority_multiplier(group_1, f32::INFINITY)
+            .is_err());
+        assert!(manager.update_priority_multiplier(group_1, -1.0).is_err());
+        manager.update_base_priority(group_1, 2.0);
+        manager.update_base_priority(group_2, 2.0);
+        assert_eq!(
+            manager
+                .group_channels
+                .get(&group_1)
+                .unwrap()
+                .accumulated_priority,
+            Some(6.0)
+        );
+
+        // the priority accumulates faster for the group with a higher multiplier
+        manager.recv_send_notification();
+        assert_eq!(
+            manager
+                .group_channels
+                .get(&group_1)
+                .unwrap()
+                .accumulated_priority,
+            Some(12.0)
+        );
+        assert_eq!(
+            manager
+                .group_channels
+                .get(&group_2)
+                .unwrap()
+                .accumulated_priority,
+            Some(4.0)
+        );
+    }
 }
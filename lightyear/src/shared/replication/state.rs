@@ -0,0 +1,225 @@
+//! Module to replicate bevy [`States`] from the server to the clients
+//!
+//! The current value of the state is copied into a [`ReplicatedState`] resource, which is replicated with the
+//! regular [resource replication](crate::shared::replication::resources). The [`ReplicateResource`] for that resource
+//! must be added to the `ComponentProtocol`:
+//! ```rust,ignore
+//! #[component_protocol(protocol = "MyProtocol")]
+//! pub enum Components {
+//!     GamePhase(ReplicateResource<ReplicatedState<GamePhase>>),
+//! }
+//! ```
+//! When the client receives a new value, it sets [`NextState`], so the `OnExit`/`OnEnter` schedules run on the
+//! client like for a local state transition.
+use std::marker::PhantomData;
+
+use bevy::app::{App, Plugin, PostUpdate, PreUpdate, Startup};
+use bevy::prelude::{
+    resource_exists, Commands, Condition, DetectChanges, IntoSystemConfigs, NextState, Res, ResMut,
+    Resource, State, States,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::client::connection::ConnectionManager as ClientConnectionManager;
+use crate::prelude::{NetworkTarget, Protocol, ReplicateResourceExt};
+use crate::server::connection::ConnectionManager as ServerConnectionManager;
+use crate::shared::replication::components::Replicate;
+use crate::shared::sets::{ClientMarker, InternalReplicationSet, ServerMarker};
+
+/// Resource that holds the current value of a replicated [`State`]
+#[derive(Resource, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ReplicatedState<S>(pub S);
+
+/// Plugin that replicates the [`State<S>`] of the server to the clients.
+///
+/// The plugin must be added to both the client and the server apps, and the state `S` must be initialized on both.
+pub struct ReplicateStatePlugin<P, S> {
+    target: NetworkTarget,
+    _marker: PhantomData<(P, S)>,
+}
+
+impl<P, S> ReplicateStatePlugin<P, S> {
+    /// Only replicate the state to the clients in the [`NetworkTarget`]
+    pub fn new(target: NetworkTarget) -> Self {
+        Self {
+            target,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<P, S> Default for ReplicateStatePlugin<P, S> {
+    fn default() -> Self {
+        Self::new(NetworkTarget::All)
+    }
+}
+
+impl<P: Protocol, S: States + Serialize + DeserializeOwned> Plugin for ReplicateStatePlugin<P, S> {
+    fn build(&self, app: &mut App) {
+        let target = self.target.clone();
+        app.add_systems(
+            Startup,
+            (move |mut commands: Commands| {
+                commands.replicate_resource::<ReplicatedState<S>>(Replicate::<P> {
+                    replication_target: target.clone(),
+                    ..Default::default()
+                });
+            })
+            .run_if(resource_exists::<ServerConnectionManager<P>>),
+        );
+        // only the server sends the state
+        app.add_systems(
+            PostUpdate,
+            copy_state::<S>
+                .run_if(
+                    resource_exists::<State<S>>
+                        .and_then(resource_exists::<ServerConnectionManager<P>>),
+                )
+                .before(InternalReplicationSet::<ServerMarker>::SendResourceUpdates),
+        );
+        // only the client applies the state it received
+        app.add_systems(
+            PreUpdate,
+            apply_replicated_state::<S>
+                .run_if(
+                    resource_exists::<State<S>>
+                        .and_then(resource_exists::<ReplicatedState<S>>)
+                        .and_then(resource_exists::<ClientConnectionManager<P>>),
+                )
+                .after(InternalReplicationSet::<ClientMarker>::ReceiveResourceUpdates),
+        );
+    }
+}
+
+/// Copy the current value of the state to the [`ReplicatedState`] resource
+fn copy_state<S: States>(
+    mut commands: Commands,
+    state: Res<State<S>>,
+    replicated: Option<ResMut<ReplicatedState<S>>>,
+) {
+    match replicated {
+        Some(mut replicated) => {
+            if replicated.0 != *state.get() {
+                replicated.0 = state.get().clone();
+            }
+        }
+        None => commands.insert_resource(ReplicatedState(state.get().clone())),
+    }
+}
+
+/// Transition to the state that was received from the server
+fn apply_replicated_state<S: States>(
+    replicated: Res<ReplicatedState<S>>,
+    state: Res<State<S>>,
+    mut next_state: ResMut<NextState<S>>,
+) {
+    if replicated.is_changed() && replicated.0 != *state.get() {
+        next_state.set(replicated.0.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::{in_state, OnEnter, OnExit, ResMut, Resource, Update};
+    use bevy::utils::Duration;
+
+    use crate::prelude::client::*;
+    use crate::prelude::*;
+    use crate::tests::protocol::*;
+    use crate::tests::stepper::{BevyStepper, Step};
+
+    use super::*;
+
+    #[derive(Resource, Default)]
+    struct Transitions(Vec<&'static str>);
+
+    #[test]
+    fn test_state_replication() {
+        let frame_duration = Duration::from_millis(10);
+        let tick_duration = Duration::from_millis(10);
+        let shared_config = SharedConfig {
+            tick: TickConfig::new(tick_duration),
+            ..Default::default()
+        };
+        let link_conditioner = LinkConditionerConfig {
+            incoming_latency: Duration::from_millis(0),
+            incoming_jitter: Duration::from_millis(0),
+            incoming_loss: 0.0,
+        };
+        let mut stepper = BevyStepper::new(
+            shared_config,
+            SyncConfig::default().speedup_factor(1.0),
+            PredictionConfig::default(),
+            InterpolationConfig::default(),
+            link_conditioner,
+            frame_duration,
+        );
+        for app in [&mut stepper.server_app, &mut stepper.client_app] {
+            app.init_state::<State1>()
+                .add_plugins(ReplicateStatePlugin::<MyProtocol, State1>::default());
+        }
+        stepper.client_app.init_resource::<Transitions>();
+        stepper
+            .client_app
+            .add_systems(
+                OnExit(State1::Lobby),
+                |mut transitions: ResMut<Transitions>| transitions.0.push("exit lobby"),
+            )
+            .add_systems(
+                OnEnter(State1::InGame),
+                |mut transitions: ResMut<Transitions>| transitions.0.push("enter game"),
+            )
+            .add_systems(
+                Update,
+                (|mut transitions: ResMut<Transitions>| transitions.0.push("in game"))
+                    .run_if(in_state(State1::InGame)),
+            );
+        stepper.init();
+        stepper.frame_step();
+        stepper.frame_step();
+        assert_eq!(
+            stepper
+                .client_app
+                .world
+                .resource::<ReplicatedState<State1>>(),
+            &ReplicatedState(State1::Lobby)
+        );
+
+        // the state transition is replicated, and the client runs the OnExit/OnEnter schedules
+        stepper
+            .server_app
+            .world
+            .resource_mut::<NextState<State1>>()
+            .set(State1::InGame);
+        stepper.frame_step();
+        stepper.frame_step();
+        assert_eq!(
+            stepper.client_app.world.resource::<State<State1>>().get(),
+            &State1::InGame
+        );
+        assert_eq!(
+            stepper.client_app.world.resource::<Transitions>().0[..3],
+            ["exit lobby", "enter game", "in game"]
+        );
+
+        // a local state transition on the client is not copied into the replicated state
+        stepper
+            .client_app
+            .world
+            .resource_mut::<NextState<State1>>()
+            .set(State1::Lobby);
+        stepper.frame_step();
+        assert_eq!(
+            stepper.client_app.world.resource::<State<State1>>().get(),
+            &State1::Lobby
+        );
+        assert_eq!(
+            stepper
+                .client_app
+                .world
+                .resource::<ReplicatedState<State1>>(),
+            &ReplicatedState(State1::InGame)
+        );
+    }
+}
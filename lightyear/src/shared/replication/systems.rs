@@ -2,28 +2,44 @@
 use std::any::TypeId;
 use std::ops::Deref;
 
-use bevy::ecs::entity::Entities;
+use bevy::ecs::component::{ComponentId, Tick as BevyTick};
+use bevy::ecs::entity::{Entities, EntityHashMap};
+use bevy::ecs::event::ManualEventReader;
+use bevy::ecs::query::QueryState;
+use bevy::ecs::removal_detection::RemovedComponentEntity;
 use bevy::ecs::system::SystemChangeTick;
 use bevy::prelude::{
-    Added, App, Commands, Component, DetectChanges, Entity, IntoSystemConfigs, PostUpdate,
-    PreUpdate, Query, Ref, RemovedComponents, Res, ResMut, With, Without,
+    Added, App, Changed, Commands, Component, DetectChanges, Entity, EntityRef, Has,
+    IntoSystemConfigs, Local, Mut, PostUpdate, PreUpdate, Query, Ref, RemovedComponents, Res,
+    ResMut, With, Without, World,
 };
+use bevy::utils::{Duration, HashMap};
 use tracing::{debug, error, info, trace, warn};
 
 use crate::_reexport::FromType;
-use crate::prelude::{NetworkTarget, TickManager};
+use crate::prelude::{NetworkTarget, TickManager, TimeManager};
+use crate::protocol::component::ComponentProtocolKind;
+use crate::protocol::registration::{ProtocolRegistry, RegisteredComponent};
 use crate::protocol::Protocol;
 use crate::server::replication::ServerReplicationSet;
 use crate::server::room::ClientVisibility;
-use crate::shared::replication::components::{DespawnTracker, Replicate, ReplicationMode};
+use crate::shared::replication::components::{
+    DespawnTracker, Replicate, ReplicateRemoveBehaviour, ReplicationMode,
+};
+use crate::shared::replication::dormancy::{
+    add_replication_activity, send_dormancy_changes, track_activity, track_registered_activity,
+    update_dormancy, wake_up_on_change, Dormant,
+};
 use crate::shared::replication::ReplicationSend;
 use crate::shared::sets::{InternalMainSet, InternalReplicationSet};
 
 // TODO: run these systems only if there is at least 1 remote connected!!! (so we don't burn CPU when there are no connections)
 
 /// For every entity that removes their Replicate component but are not despawned, remove the component
-/// from our replicate cache (so that the entity's despawns are no longer replicated)
+/// from our replicate cache (so that the entity's despawns are no longer replicated), and apply the
+/// [`ReplicateRemoveBehaviour`] to the remote entity
 fn handle_replicate_remove<P: Protocol, R: ReplicationSend<P>>(
+    system_bevy_ticks: SystemChangeTick,
     mut sender: ResMut<R>,
     mut query: RemovedComponents<Replicate<P>>,
     entity_check: &Entities,
@@ -31,7 +47,37 @@ fn handle_replicate_remove<P: Protocol, R: ReplicationSend<P>>(
     for entity in query.read() {
         if entity_check.contains(entity) {
             debug!("handling replicate component remove (delete from cache)");
-            sender.get_mut_replicate_component_cache().remove(&entity);
+            let Some(replicate) = sender.get_mut_replicate_component_cache().remove(&entity) else {
+                continue;
+            };
+            let target = replicate.replicated_to();
+            let _ = match replicate
+                .on_remove
+                .unwrap_or_else(|| sender.replicate_remove_behaviour())
+            {
+                ReplicateRemoveBehaviour::Freeze => Ok(()),
+                ReplicateRemoveBehaviour::Despawn => {
+                    trace!(?entity, "send entity despawn after replicate remove");
+                    sender.prepare_entity_despawn(
+                        entity,
+                        &replicate,
+                        target,
+                        system_bevy_ticks.this_run(),
+                    )
+                }
+                ReplicateRemoveBehaviour::Detach => {
+                    trace!(?entity, "send entity detach after replicate remove");
+                    sender.prepare_entity_detach(
+                        entity,
+                        &replicate,
+                        target,
+                        system_bevy_ticks.this_run(),
+                    )
+                }
+            }
+            .map_err(|e| {
+                error!("error handling replicate remove: {:?}", e);
+            });
         }
     }
 }
@@ -41,14 +87,19 @@ fn handle_replicate_remove<P: Protocol, R: ReplicationSend<P>>(
 /// This system adds DespawnTracker to each entity that was every replicated,
 /// so that we can track when they are despawned
 /// (we have a distinction between removing Replicate, which just stops replication; and despawning the entity)
+///
+/// It also keeps the cached `Replicate` up-to-date, so that we know how to handle the removal of the
+/// component or the despawn of the entity. (the entity can start being replicated again after `Replicate` was removed)
 fn add_despawn_tracker<P: Protocol, R: ReplicationSend<P>>(
     mut sender: ResMut<R>,
     mut commands: Commands,
-    query: Query<(Entity, &Replicate<P>), (Added<Replicate<P>>, Without<DespawnTracker>)>,
+    query: Query<(Entity, &Replicate<P>, Has<DespawnTracker>), Changed<Replicate<P>>>,
 ) {
-    for (entity, replicate) in query.iter() {
-        debug!("ADDING DESPAWN TRACKER");
-        commands.entity(entity).insert(DespawnTracker);
+    for (entity, replicate, has_despawn_tracker) in query.iter() {
+        if !has_despawn_tracker {
+            debug!("ADDING DESPAWN TRACKER");
+            commands.entity(entity).insert(DespawnTracker);
+        }
         sender
             .get_mut_replicate_component_cache()
             .insert(entity, replicate.clone());
@@ -63,7 +114,7 @@ fn send_entity_despawn<P: Protocol, R: ReplicationSend<P>>(
     mut despawn_removed: RemovedComponents<DespawnTracker>,
     mut sender: ResMut<R>,
 ) {
-    // Despawn entities for clients that lost visibility
+    // Notify the clients that lost visibility of the entities (the entities are not despawned)
     query.iter().for_each(|(entity, replicate)| {
         if matches!(replicate.replication_mode, ReplicationMode::Room) {
             replicate
@@ -73,17 +124,17 @@ fn send_entity_despawn<P: Protocol, R: ReplicationSend<P>>(
                     if replicate.replication_target.should_send_to(client_id)
                         && matches!(visibility, ClientVisibility::Lost)
                     {
-                        debug!("sending entity despawn for entity: {:?}", entity);
+                        debug!("sending entity lost visibility for entity: {:?}", entity);
                         // TODO: don't unwrap but handle errors
                         let _ = sender
-                            .prepare_entity_despawn(
+                            .prepare_entity_lost_visibility(
                                 entity,
                                 replicate,
                                 NetworkTarget::Only(vec![*client_id]),
                                 system_bevy_ticks.this_run(),
                             )
                             .map_err(|e| {
-                                error!("error sending entity despawn: {:?}", e);
+                                error!("error sending entity lost visibility: {:?}", e);
                             });
                     }
                 });
@@ -94,7 +145,8 @@ fn send_entity_despawn<P: Protocol, R: ReplicationSend<P>>(
     // Despawn entities when the entity got despawned on local world
     for entity in despawn_removed.read() {
         trace!("despawn tracker removed!");
-        // TODO: we still don't want to replicate the despawn if the entity was not in the same room as the client!
+        // NOTE: the despawn is sent to the whole replication target, including the clients that lost visibility
+        //  of the entity: they might still have kept the entity (with `LostVisibilityBehaviour::Keep`)
         // only replicate the despawn if the entity still had a Replicate component
         if let Some(replicate) = sender.get_mut_replicate_component_cache().remove(&entity) {
             // TODO: DO NOT SEND ENTITY DESPAWN TO THE CLIENT WHO JUST DISCONNECTED!
@@ -126,6 +178,8 @@ fn send_entity_spawn<P: Protocol, R: ReplicationSend<P>>(
             // for room mode, no need to handle newly-connected clients specially; they just need
             // to be added to the correct room
             ReplicationMode::Room => {
+                // the entity was held back until it is sent as part of the client's initial sync
+                let initial_sync_clients = sender.initial_sync_clients(entity);
                 replicate
                     .replication_clients_cache
                     .iter()
@@ -147,9 +201,11 @@ fn send_entity_spawn<P: Protocol, R: ReplicationSend<P>>(
                                 }
                                 ClientVisibility::Lost => {}
                                 ClientVisibility::Maintained => {
-                                    // TODO: is this even reachable?
-                                    // only try to replicate if the replicate component was just added
-                                    if replicate.is_added() {
+                                    // only try to replicate if the replicate component was just added,
+                                    // or if the entity is sent as part of the client's initial sync
+                                    if replicate.is_added()
+                                        || initial_sync_clients.contains(client_id)
+                                    {
                                         debug!("send entity spawn to maintained");
                                         sender
                                             .get_mut_replicate_component_cache()
@@ -173,7 +229,7 @@ fn send_entity_spawn<P: Protocol, R: ReplicationSend<P>>(
             ReplicationMode::NetworkTarget => {
                 let mut target = replicate.replication_target.clone();
 
-                let new_connected_clients = sender.new_connected_clients().clone();
+                let new_connected_clients = sender.full_sync_clients(entity);
                 if !new_connected_clients.is_empty() {
                     trace!("Replicate to newly connected clients");
                     // replicate to the newly connected clients that match our target
@@ -228,157 +284,255 @@ fn send_entity_spawn<P: Protocol, R: ReplicationSend<P>>(
 ///
 /// NOTE: cannot use ConnectEvents because they are reset every frame
 fn send_component_update<C: Component + Clone, P: Protocol, R: ReplicationSend<P>>(
-    query: Query<(Entity, Ref<C>, Ref<Replicate<P>>)>,
+    query: Query<(Entity, Ref<C>, Ref<Replicate<P>>), Without<Dormant>>,
     system_bevy_ticks: SystemChangeTick,
+    time_manager: Res<TimeManager>,
+    mut rate_limits: Local<EntityHashMap<RateLimitState>>,
     mut sender: ResMut<R>,
 ) where
     <P as Protocol>::Components: From<C>,
     P::ComponentKinds: FromType<C>,
 {
     let kind = <P::ComponentKinds as FromType<C>>::from_type();
+    let now = time_manager.current_time().to_duration();
+    let this_run = system_bevy_ticks.this_run();
     query.iter().for_each(|(entity, component, replicate)| {
-        // do not replicate components that are disabled
-        if replicate.is_disabled::<C>() {
-            return;
+        let update_tick = match replicate.send_interval_by_kind(kind) {
+            None => Some(component.last_changed()),
+            Some(send_interval) => rate_limits.entry(entity).or_default().update_tick(
+                send_interval,
+                component.is_changed(),
+                component.last_changed(),
+                now,
+                this_run,
+            ),
+        };
+        replicate_component_update::<P, R>(
+            entity,
+            kind,
+            || component.clone().into(),
+            component.is_added(),
+            update_tick,
+            &replicate,
+            sender.as_mut(),
+            this_run,
+        );
+    });
+    rate_limits.retain(|entity, _| query.contains(*entity));
+}
+
+/// Keeps track of the updates sent for a component that has a `send_interval`
+#[derive(Default)]
+struct RateLimitState {
+    /// Time at which we last sent an update for the component
+    last_send: Option<Duration>,
+    /// True if the component changed since the last update that we sent
+    dirty: bool,
+}
+
+impl RateLimitState {
+    /// Returns the change tick to use for the component update, or None if no update must be sent
+    /// for the component right now.
+    fn update_tick(
+        &mut self,
+        send_interval: Duration,
+        changed: bool,
+        last_changed: BevyTick,
+        now: Duration,
+        this_run: BevyTick,
+    ) -> Option<BevyTick> {
+        self.dirty |= changed;
+        if self.last_send.map_or(false, |last_send| {
+            now.saturating_sub(last_send) < send_interval
+        }) {
+            return None;
         }
-        match replicate.replication_mode {
-            ReplicationMode::Room => {
-                replicate
-                    .replication_clients_cache
-                    .iter()
-                    .for_each(|(client_id, visibility)| {
-                        if replicate.replication_target.should_send_to(client_id) {
-                            match visibility {
-                                // TODO: here we required the component to be clone because we send it to multiple clients.
-                                //  but maybe we can instead serialize it to Bytes early and then have the bytes be shared between clients?
-                                //  or just pass a reference?
-                                ClientVisibility::Gained => {
-                                    let target = replicate.target::<C>(NetworkTarget::Only(vec![*client_id]));
+        if self.dirty {
+            // the component changed since the last update that we sent: make sure that the latest value is sent,
+            // even if the group's updates were acked since the component changed
+            self.dirty = false;
+            self.last_send = Some(now);
+            Some(this_run)
+        } else {
+            // keep sending the latest value until it is acked
+            Some(last_changed)
+        }
+    }
+}
+
+/// Send a ComponentInsert or a ComponentUpdate for a component of an entity, depending on
+/// whether the component (or the entity's `Replicate`) was just added
+///
+/// `update_tick` is the change tick used for the ComponentUpdate; if None, no update is sent
+/// (because of the component's `send_interval`), but the inserts are still sent.
+#[allow(clippy::too_many_arguments)]
+fn replicate_component_update<P: Protocol, R: ReplicationSend<P>>(
+    entity: Entity,
+    kind: P::ComponentKinds,
+    component: impl Fn() -> P::Components,
+    component_added: bool,
+    update_tick: Option<BevyTick>,
+    replicate: &Ref<Replicate<P>>,
+    sender: &mut R,
+    this_run: BevyTick,
+) {
+    // do not replicate components that are disabled
+    if replicate.is_disabled_by_kind(kind) {
+        return;
+    }
+    match replicate.replication_mode {
+        ReplicationMode::Room => {
+            let initial_sync_clients = sender.initial_sync_clients(entity);
+            replicate
+                .replication_clients_cache
+                .iter()
+                .for_each(|(client_id, visibility)| {
+                    if replicate.replication_target.should_send_to(client_id) {
+                        match visibility {
+                            // TODO: here we required the component to be clone because we send it to multiple clients.
+                            //  but maybe we can instead serialize it to Bytes early and then have the bytes be shared between clients?
+                            //  or just pass a reference?
+                            ClientVisibility::Gained => {
+                                let target = replicate
+                                    .target_by_kind(kind, NetworkTarget::Only(vec![*client_id]));
+                                let _ = sender
+                                    .prepare_component_insert(
+                                        entity,
+                                        component(),
+                                        replicate.as_ref(),
+                                        target,
+                                        this_run,
+                                    )
+                                    .map_err(|e| {
+                                        error!("error sending component insert: {:?}", e);
+                                    });
+                            }
+                            ClientVisibility::Lost => {}
+                            ClientVisibility::Maintained => {
+                                // send an component_insert for components that were newly added,
+                                // or for all the components if the entity is part of the client's initial sync
+                                if component_added || initial_sync_clients.contains(client_id) {
+                                    let target = replicate.target_by_kind(
+                                        kind,
+                                        NetworkTarget::Only(vec![*client_id]),
+                                    );
                                     let _ = sender
                                         .prepare_component_insert(
                                             entity,
-                                            component.clone().into(),
+                                            component(),
                                             replicate.as_ref(),
                                             target,
-                                            system_bevy_ticks.this_run(),
+                                            this_run,
                                         )
                                         .map_err(|e| {
                                             error!("error sending component insert: {:?}", e);
                                         });
-                                }
-                                ClientVisibility::Lost => {}
-                                ClientVisibility::Maintained => {
-                                    // send an component_insert for components that were newly added
-                                    if component.is_added() {
-                                        let target = replicate.target::<C>(NetworkTarget::Only(vec![*client_id]));
-                                        let _ = sender
-                                            .prepare_component_insert(
-                                                entity,
-                                                component.clone().into(),
-                                                replicate.as_ref(),
-                                                target,
-                                                system_bevy_ticks.this_run(),
-                                            )
-                                            .map_err(|e| {
-                                                error!("error sending component insert: {:?}", e);
-                                            });
-                                        // only update components that were not newly added
-                                    } else {
-                                        // do not send updates for these components, only inserts/removes
-                                        if replicate.is_replicate_once::<C>() {
-                                            return;
-                                        }
-                                        let target = replicate.target::<C>(NetworkTarget::Only(vec![*client_id]));
-                                        let _ = sender
-                                            .prepare_component_update(
-                                                entity,
-                                                component.clone().into(),
-                                                replicate.as_ref(),
-                                                target,
-                                                component.last_changed(),
-                                                system_bevy_ticks.this_run(),
-                                            )
-                                            .map_err(|e| {
-                                                error!("error sending component update: {:?}", e);
-                                            });
+                                    // only update components that were not newly added
+                                } else {
+                                    // do not send updates for these components, only inserts/removes
+                                    if replicate.is_replicate_once_by_kind(kind) {
+                                        return;
                                     }
+                                    let Some(component_last_changed) = update_tick else {
+                                        return;
+                                    };
+                                    let target = replicate.target_by_kind(
+                                        kind,
+                                        NetworkTarget::Only(vec![*client_id]),
+                                    );
+                                    let _ = sender
+                                        .prepare_component_update(
+                                            entity,
+                                            component(),
+                                            replicate.as_ref(),
+                                            target,
+                                            component_last_changed,
+                                            this_run,
+                                        )
+                                        .map_err(|e| {
+                                            error!("error sending component update: {:?}", e);
+                                        });
                                 }
                             }
                         }
-                    })
-            }
-            ReplicationMode::NetworkTarget => {
-                let mut target = replicate.replication_target.clone();
+                    }
+                })
+        }
+        ReplicationMode::NetworkTarget => {
+            let mut target = replicate.replication_target.clone();
 
-                let new_connected_clients = sender.new_connected_clients().clone();
-                // replicate all components to newly connected clients
-                if !new_connected_clients.is_empty() {
-                    // replicate to the newly connected clients that match our target
-                    let mut new_connected_target = target.clone();
-                    new_connected_target
-                        .intersection(NetworkTarget::Only(new_connected_clients.clone()));
-                    let _ = sender
-                        .prepare_component_insert(
-                            entity,
-                            component.clone().into(),
-                            replicate.as_ref(),
-                            replicate.target::<C>(new_connected_target),
-                            system_bevy_ticks.this_run(),
-                        )
-                        .map_err(|e| {
-                            error!("error sending component insert: {:?}", e);
-                        });
-                    // don't re-send to newly connection client
-                    target.exclude(new_connected_clients.clone());
-                }
+            let new_connected_clients = sender.full_sync_clients(entity);
+            // replicate all components to newly connected clients
+            if !new_connected_clients.is_empty() {
+                // replicate to the newly connected clients that match our target
+                let mut new_connected_target = target.clone();
+                new_connected_target
+                    .intersection(NetworkTarget::Only(new_connected_clients.clone()));
+                let _ = sender
+                    .prepare_component_insert(
+                        entity,
+                        component(),
+                        replicate.as_ref(),
+                        replicate.target_by_kind(kind, new_connected_target),
+                        this_run,
+                    )
+                    .map_err(|e| {
+                        error!("error sending component insert: {:?}", e);
+                    });
+                // don't re-send to newly connection client
+                target.exclude(new_connected_clients.clone());
+            }
 
-                // send a component_insert for components that were newly added
-                // or if replicate was newly added.
-                // TODO: ideally what we should be checking is: is the component newly added
-                //  for the client we are sending to?
-                //  Otherwise another solution would be to also insert the component on ComponentUpdate if it's missing
-                //  Or should we just have ComponentInsert and ComponentUpdate be the same thing? Or we check
-                //  on the receiver's entity world mut to know if we emit a ComponentInsert or a ComponentUpdate?
-                if component.is_added() || replicate.is_added() {
-                    trace!("component is added");
-                    let _ = sender
-                        .prepare_component_insert(
-                            entity,
-                            component.clone().into(),
-                            replicate.as_ref(),
-                            replicate.target::<C>(target),
-                            system_bevy_ticks.this_run(),
-                        )
-                        .map_err(|e| {
-                            error!("error sending component insert: {:?}", e);
-                        });
-                } else {
-                    // do not send updates for these components, only inserts/removes
-                    if replicate.is_replicate_once::<C>() {
-                        trace!(?entity,
-                            "not replicating updates for {:?} because it is marked as replicate_once",
-                            kind
-                        );
-                        return;
-                    }
-                    // otherwise send an update for all components that changed since the
-                    // last update we have ack-ed
-                    let _ = sender
-                        .prepare_component_update(
-                            entity,
-                            component.clone().into(),
-                            replicate.as_ref(),
-                            replicate.target::<C>(target),
-                            component.last_changed(),
-                            system_bevy_ticks.this_run(),
-                        )
-                        .map_err(|e| {
-                            error!("error sending component update: {:?}", e);
-                        });
+            // send a component_insert for components that were newly added
+            // or if replicate was newly added.
+            // TODO: ideally what we should be checking is: is the component newly added
+            //  for the client we are sending to?
+            //  Otherwise another solution would be to also insert the component on ComponentUpdate if it's missing
+            //  Or should we just have ComponentInsert and ComponentUpdate be the same thing? Or we check
+            //  on the receiver's entity world mut to know if we emit a ComponentInsert or a ComponentUpdate?
+            if component_added || replicate.is_added() {
+                trace!("component is added");
+                let _ = sender
+                    .prepare_component_insert(
+                        entity,
+                        component(),
+                        replicate.as_ref(),
+                        replicate.target_by_kind(kind, target),
+                        this_run,
+                    )
+                    .map_err(|e| {
+                        error!("error sending component insert: {:?}", e);
+                    });
+            } else {
+                // do not send updates for these components, only inserts/removes
+                if replicate.is_replicate_once_by_kind(kind) {
+                    trace!(
+                        ?entity,
+                        "not replicating updates for {:?} because it is marked as replicate_once",
+                        kind
+                    );
+                    return;
                 }
+                let Some(component_last_changed) = update_tick else {
+                    return;
+                };
+                // otherwise send an update for all components that changed since the
+                // last update we have ack-ed
+                let _ = sender
+                    .prepare_component_update(
+                        entity,
+                        component(),
+                        replicate.as_ref(),
+                        replicate.target_by_kind(kind, target),
+                        component_last_changed,
+                        this_run,
+                    )
+                    .map_err(|e| {
+                        error!("error sending component update: {:?}", e);
+                    });
             }
         }
-    });
+    }
 }
 
 /// This system sends updates for all components that were removed
@@ -394,51 +548,175 @@ fn send_component_removed<C: Component + Clone, P: Protocol, R: ReplicationSend<
     let kind = <P::ComponentKinds as FromType<C>>::from_type();
     removed.read().for_each(|entity| {
         if let Ok(replicate) = query.get(entity) {
-            // do not replicate components that are disabled
-            if replicate.is_disabled::<C>() {
-                return;
-            }
-            match replicate.replication_mode {
-                ReplicationMode::Room => {
-                    replicate.replication_clients_cache.iter().for_each(
-                        |(client_id, visibility)| {
-                            if replicate.replication_target.should_send_to(client_id) {
-                                // TODO: maybe send no matter the vis?
-                                if matches!(visibility, ClientVisibility::Maintained) {
-                                    let _ = sender
-                                        .prepare_component_remove(
-                                            entity,
-                                            kind,
-                                            replicate,
-                                            replicate
-                                                .target::<C>(NetworkTarget::Only(vec![*client_id])),
-                                            system_bevy_ticks.this_run(),
-                                        )
-                                        .map_err(|e| {
-                                            error!("error sending component remove: {:?}", e);
-                                        });
-                                }
-                            }
-                        },
-                    )
+            replicate_component_removed::<P, R>(
+                entity,
+                kind,
+                replicate,
+                sender.as_mut(),
+                system_bevy_ticks.this_run(),
+            );
+        }
+    })
+}
+
+/// Send a ComponentRemove for a component that was removed from a replicated entity
+fn replicate_component_removed<P: Protocol, R: ReplicationSend<P>>(
+    entity: Entity,
+    kind: P::ComponentKinds,
+    replicate: &Replicate<P>,
+    sender: &mut R,
+    this_run: BevyTick,
+) {
+    // do not replicate components that are disabled
+    if replicate.is_disabled_by_kind(kind) {
+        return;
+    }
+    match replicate.replication_mode {
+        ReplicationMode::Room => {
+            replicate
+                .replication_clients_cache
+                .iter()
+                .for_each(|(client_id, visibility)| {
+                    if replicate.replication_target.should_send_to(client_id) {
+                        // TODO: maybe send no matter the vis?
+                        if matches!(visibility, ClientVisibility::Maintained) {
+                            let _ = sender
+                                .prepare_component_remove(
+                                    entity,
+                                    kind,
+                                    replicate,
+                                    replicate.target_by_kind(
+                                        kind,
+                                        NetworkTarget::Only(vec![*client_id]),
+                                    ),
+                                    this_run,
+                                )
+                                .map_err(|e| {
+                                    error!("error sending component remove: {:?}", e);
+                                });
+                        }
+                    }
+                })
+        }
+        ReplicationMode::NetworkTarget => {
+            trace!("sending component remove!");
+            let _ = sender
+                .prepare_component_remove(
+                    entity,
+                    kind,
+                    replicate,
+                    replicate.target_by_kind(kind, replicate.replication_target.clone()),
+                    this_run,
+                )
+                .map_err(|e| {
+                    error!("error sending component remove: {:?}", e);
+                });
+        }
+    }
+}
+
+/// This system sends inserts and updates for the components that were registered at runtime
+/// with [`AppRegistrationExt::register_component`](crate::protocol::registration::AppRegistrationExt::register_component)
+///
+/// The registered components are not known statically, so this is an exclusive system that reads the
+/// change ticks of each registered component.
+fn send_registered_component_update<P: Protocol, R: ReplicationSend<P>>(
+    world: &mut World,
+    query: &mut QueryState<(Entity, EntityRef), (With<Replicate<P>>, Without<Dormant>)>,
+    mut rate_limits: Local<HashMap<(Entity, P::ComponentKinds), RateLimitState>>,
+) {
+    if !world.contains_resource::<ProtocolRegistry>() {
+        return;
+    }
+    // in an exclusive system, the world's last change tick is the last time the system ran
+    let last_run = world.last_change_tick();
+    let this_run = world.read_change_tick();
+    let now = world.resource::<TimeManager>().current_time().to_duration();
+    world.resource_scope(|world, mut sender: Mut<R>| {
+        let registry = world.resource::<ProtocolRegistry>();
+        for (entity, entity_ref) in query.iter(world) {
+            let replicate = entity_ref.get_ref::<Replicate<P>>().unwrap();
+            for registration in registry.components() {
+                if !registration.can_send(&R::DIRECTION) {
+                    continue;
                 }
-                ReplicationMode::NetworkTarget => {
-                    trace!("sending component remove!");
-                    let _ = sender
-                        .prepare_component_remove(
-                            entity,
-                            kind,
-                            replicate,
-                            replicate.target::<C>(replicate.replication_target.clone()),
-                            system_bevy_ticks.this_run(),
+                let Some(ticks) = entity_ref.get_change_ticks_by_id(registration.component_id)
+                else {
+                    continue;
+                };
+                let component =
+                    match RegisteredComponent::from_entity(registration, world, &entity_ref) {
+                        Ok(component) => component,
+                        Err(e) => {
+                            error!("error serializing registered component: {:?}", e);
+                            continue;
+                        }
+                    };
+                let kind = P::ComponentKinds::from_registered(registration.net_id);
+                let update_tick = match replicate.send_interval_by_kind(kind) {
+                    None => Some(ticks.last_changed_tick()),
+                    Some(send_interval) => {
+                        rate_limits.entry((entity, kind)).or_default().update_tick(
+                            send_interval,
+                            ticks.is_changed(last_run, this_run),
+                            ticks.last_changed_tick(),
+                            now,
+                            this_run,
                         )
-                        .map_err(|e| {
-                            error!("error sending component remove: {:?}", e);
-                        });
+                    }
+                };
+                replicate_component_update::<P, R>(
+                    entity,
+                    kind,
+                    || component.clone().into(),
+                    ticks.is_added(last_run, this_run),
+                    update_tick,
+                    &replicate,
+                    sender.as_mut(),
+                    this_run,
+                );
+            }
+        }
+    });
+    rate_limits.retain(|(entity, _), _| world.get_entity(*entity).is_some());
+}
+
+/// This system sends removals for the components that were registered at runtime
+fn send_registered_component_removed<P: Protocol, R: ReplicationSend<P>>(
+    world: &mut World,
+    mut readers: Local<HashMap<ComponentId, ManualEventReader<RemovedComponentEntity>>>,
+) {
+    if !world.contains_resource::<ProtocolRegistry>() {
+        return;
+    }
+    let this_run = world.read_change_tick();
+    world.resource_scope(|world, mut sender: Mut<R>| {
+        let registry = world.resource::<ProtocolRegistry>();
+        for registration in registry.components() {
+            if !registration.can_send(&R::DIRECTION) {
+                continue;
+            }
+            let Some(events) = world.removed_components().get(registration.component_id) else {
+                continue;
+            };
+            let kind = P::ComponentKinds::from_registered(registration.net_id);
+            let reader = readers.entry(registration.component_id).or_default();
+            for entity in reader
+                .read(events)
+                .map(|removed| Entity::from(removed.clone()))
+            {
+                if let Some(replicate) = world.get::<Replicate<P>>(entity) {
+                    replicate_component_removed::<P, R>(
+                        entity,
+                        kind,
+                        replicate,
+                        sender.as_mut(),
+                        this_run,
+                    );
                 }
             }
         }
-    })
+    });
 }
 
 // add replication systems that are shared between client and server
@@ -466,6 +744,15 @@ pub fn add_replication_send_systems<P: Protocol, R: ReplicationSend<P>>(app: &mu
             )
                 .chain()
                 .in_set(InternalReplicationSet::<R::SetMarker>::SendDespawnsAndRemovals),
+            // the activity of the entities is tracked by the per-component systems in the same set
+            (
+                add_replication_activity::<P>,
+                track_registered_activity,
+                update_dormancy::<P, R>,
+                send_dormancy_changes::<P, R>,
+            )
+                .chain()
+                .in_set(InternalReplicationSet::<R::SetMarker>::HandleDormancy),
         ),
     );
 }
@@ -492,6 +779,23 @@ pub fn add_per_component_replication_send_systems<
             //  and use up all the bandwidth
             send_component_update::<C, P, R>
                 .in_set(InternalReplicationSet::<R::SetMarker>::SendComponentUpdates),
+            (track_activity::<C>, wake_up_on_change::<C>)
+                .after(add_replication_activity::<P>)
+                .before(update_dormancy::<P, R>)
+                .in_set(InternalReplicationSet::<R::SetMarker>::HandleDormancy),
+        ),
+    );
+}
+
+/// Add the systems that replicate the components registered at runtime
+pub fn add_registered_replication_send_systems<P: Protocol, R: ReplicationSend<P>>(app: &mut App) {
+    app.add_systems(
+        PostUpdate,
+        (
+            send_registered_component_removed::<P, R>
+                .in_set(InternalReplicationSet::<R::SetMarker>::SendDespawnsAndRemovals),
+            send_registered_component_update::<P, R>
+                .in_set(InternalReplicationSet::<R::SetMarker>::SendComponentUpdates),
         ),
     );
 }
@@ -19,6 +19,12 @@ pub(crate) enum InternalReplicationSet<M> {
     /// Needs to run once per frame instead of once per send_interval
     /// because they rely on bevy events that are cleared every frame
     SendDespawnsAndRemovals,
+    /// Select the entities that are sent to the clients that are waiting for their initial sync
+    /// These systems only run once every send_interval
+    PrepareInitialSync,
+    /// Put idle entities to sleep, and wake up dormant entities
+    /// Needs to run once per frame, and before the systems that gather the entity and component updates
+    HandleDormancy,
 
     /// System Set to gather all the replication updates to send
     /// These systems only run once every send_interval
@@ -31,6 +37,15 @@ pub(crate) enum InternalReplicationSet<M> {
     _Marker(std::marker::PhantomData<M>),
 }
 
+/// System sets related to the bevy events that are sent over the network
+#[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum NetworkEventSet {
+    /// Collect the events that were written during the frame, so that they can be sent
+    /// Needs to run once per frame instead of once per send_interval
+    /// because they rely on bevy events that are cleared every frame
+    Queue,
+}
+
 /// Main SystemSets used by lightyear to receive and send data
 #[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone, Copy)]
 pub(crate) enum InternalMainSet<M> {
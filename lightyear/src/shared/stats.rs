@@ -0,0 +1,147 @@
+//! Network statistics for a connection, aggregated per channel
+//!
+//! The statistics are cumulative since the start of the connection, so that you can compute rates
+//! by comparing two snapshots.
+use std::collections::{HashMap, VecDeque};
+
+use bevy::utils::Duration;
+
+use crate::packet::message::{FragmentData, MessageContainer, SingleData};
+use crate::prelude::{Channel, ChannelKind};
+
+/// Statistics for a single channel of a connection
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct ChannelStats {
+    /// Number of messages that were included in a packet, including the messages that were resent
+    /// (see `messages_resent`).
+    /// A fragmented message is counted once, when its last fragment is sent
+    pub messages_sent: u64,
+    /// Number of message bytes that were included in a packet (not including the packet headers)
+    pub bytes_sent: u64,
+    /// Number of messages that were fully received and read from the channel
+    pub messages_received: u64,
+    /// Number of message bytes that were received (not including the packet headers)
+    pub bytes_received: u64,
+    /// Number of messages (or fragments) that were sent again because they were not acked in time.
+    /// Only reliable channels resend messages
+    pub messages_resent: u64,
+    /// Number of fragments that were included in a packet
+    pub fragments_sent: u64,
+    /// Number of fragments that were received
+    pub fragments_received: u64,
+    /// Number of messages (or fragments) that were discarded by the priority filter because the bandwidth quota was reached.
+    /// For reliable channels the messages will still be resent later
+    pub messages_dropped: u64,
+    /// Number of reliable messages that were sent but not acked yet
+    pub messages_in_flight: usize,
+    /// Total time that the sent messages (or fragments) spent waiting in the send queue because of the bandwidth quota.
+    /// Only recorded when the bandwidth cap is enabled
+    pub(crate) total_queue_latency: Duration,
+    /// Number of messages (or fragments) included in `total_queue_latency`
+    pub(crate) num_queue_latency_samples: u64,
+}
+
+impl ChannelStats {
+    /// Average duration that a message (or fragment) waited in the send queue before being included in a packet,
+    /// because of the bandwidth quota
+    pub fn average_queue_latency(&self) -> Duration {
+        if self.num_queue_latency_samples == 0 {
+            return Duration::default();
+        }
+        self.total_queue_latency
+            .div_f64(self.num_queue_latency_samples as f64)
+    }
+
+    /// Record the messages that were included in a packet
+    pub(crate) fn record_sent(
+        &mut self,
+        single_data: &VecDeque<SingleData>,
+        fragment_data: &VecDeque<FragmentData>,
+    ) {
+        for single in single_data {
+            self.messages_sent += 1;
+            self.bytes_sent += single.bytes.len() as u64;
+        }
+        for fragment in fragment_data {
+            if fragment.is_last_fragment() {
+                self.messages_sent += 1;
+            }
+            self.fragments_sent += 1;
+            self.bytes_sent += fragment.bytes.len() as u64;
+        }
+    }
+
+    /// Record a message (or fragment) that was received in a packet
+    pub(crate) fn record_received(&mut self, message: &MessageContainer) {
+        if let MessageContainer::Fragment(_) = message {
+            self.fragments_received += 1;
+        }
+        self.bytes_received += message.bytes().len() as u64;
+    }
+}
+
+/// Statistics for a connection with a remote peer
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct ConnectionStats {
+    /// Estimated round-trip time
+    pub rtt: Duration,
+    /// Estimated jitter
+    pub jitter: Duration,
+    /// Ratio of sent packets that were lost, computed over the last few seconds
+    pub packet_loss: f32,
+    /// Number of packets sent
+    pub packets_sent: u64,
+    /// Number of packets received
+    pub packets_received: u64,
+    /// Number of sent packets that were acked by the remote
+    pub packets_acked: u64,
+    /// Number of sent packets that were considered lost (not acked in time)
+    pub packets_lost: u64,
+    /// Number of bytes sent (including the packet headers)
+    pub bytes_sent: u64,
+    /// Number of lost packets that were rebuilt using forward error correction
+    pub packets_recovered: u64,
+    /// Statistics for each channel
+    pub channels: HashMap<ChannelKind, ChannelStats>,
+}
+
+impl ConnectionStats {
+    /// Get the statistics for a given channel
+    pub fn channel<C: Channel>(&self) -> Option<&ChannelStats> {
+        self.channels.get(&ChannelKind::of::<C>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::packet::message::MessageId;
+
+    use super::*;
+
+    #[test]
+    fn test_average_queue_latency() {
+        let mut stats = ChannelStats::default();
+        assert_eq!(stats.average_queue_latency(), Duration::default());
+
+        let single = SingleData::new(None, Bytes::from(vec![0; 10]), 1.0);
+        let fragment = FragmentData {
+            message_id: MessageId(0),
+            tick: None,
+            fragment_id: 1,
+            num_fragments: 2,
+            bytes: Bytes::from(vec![0; 5]),
+            priority: 1.0,
+        };
+        stats.record_sent(&VecDeque::from([single]), &VecDeque::from([fragment]));
+        // sending messages does not record any latency sample by itself
+        assert_eq!(stats.num_queue_latency_samples, 0);
+        stats.total_queue_latency = Duration::from_millis(100);
+        stats.num_queue_latency_samples = 2;
+        assert_eq!(stats.messages_sent, 2);
+        assert_eq!(stats.fragments_sent, 1);
+        assert_eq!(stats.bytes_sent, 15);
+        assert_eq!(stats.average_queue_latency(), Duration::from_millis(50));
+    }
+}
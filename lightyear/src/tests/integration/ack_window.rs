@@ -0,0 +1,85 @@
+use bevy::utils::Duration;
+
+use crate::packet::header::AckBitfieldSize;
+use crate::prelude::client::{ClientConfig, InterpolationConfig, PredictionConfig, SyncConfig};
+use crate::prelude::server::ServerConfig;
+use crate::prelude::*;
+use crate::tests::protocol::*;
+use crate::tests::stepper::{BevyStepper, Step};
+
+fn build_stepper(
+    client_ack_bitfield_size: AckBitfieldSize,
+    server_ack_bitfield_size: AckBitfieldSize,
+) -> BevyStepper {
+    let frame_duration = Duration::from_millis(10);
+    let tick_duration = Duration::from_millis(10);
+    let shared_config = SharedConfig {
+        tick: TickConfig::new(tick_duration),
+        ..Default::default()
+    };
+    let link_conditioner = LinkConditionerConfig {
+        incoming_latency: Duration::from_millis(0),
+        incoming_jitter: Duration::from_millis(0),
+        incoming_loss: 0.0,
+    };
+    let mut stepper = BevyStepper::new(
+        shared_config,
+        SyncConfig::default().speedup_factor(1.0),
+        PredictionConfig::default(),
+        InterpolationConfig::default(),
+        link_conditioner,
+        frame_duration,
+    );
+    stepper
+        .client_app
+        .world
+        .resource_mut::<ClientConfig>()
+        .packet
+        .ack_bitfield_size = client_ack_bitfield_size;
+    stepper
+        .server_app
+        .world
+        .resource_mut::<ServerConfig>()
+        .packet
+        .ack_bitfield_size = server_ack_bitfield_size;
+    stepper.init();
+    for _ in 0..10 {
+        stepper.frame_step();
+    }
+    stepper
+}
+
+fn negotiated_ack_bitfield_sizes(stepper: &BevyStepper) -> (AckBitfieldSize, AckBitfieldSize) {
+    let client = stepper
+        .client_app
+        .world
+        .resource::<ClientConnectionManager>()
+        .message_manager
+        .ack_bitfield_size();
+    let server = stepper
+        .server_app
+        .world
+        .resource::<ServerConnectionManager>()
+        .connection(ClientId::Netcode(111))
+        .unwrap()
+        .message_manager
+        .ack_bitfield_size();
+    (client, server)
+}
+
+/// The client and the server use the smallest of the requested and the allowed ack windows
+#[test]
+fn test_negotiate_ack_window() {
+    let stepper = build_stepper(AckBitfieldSize::Bits128, AckBitfieldSize::Bits64);
+    assert_eq!(
+        negotiated_ack_bitfield_sizes(&stepper),
+        (AckBitfieldSize::Bits64, AckBitfieldSize::Bits64)
+    );
+
+    // the server does not allow a bigger window than the default
+    let stepper = build_stepper(AckBitfieldSize::Bits128, AckBitfieldSize::Bits32);
+    assert_eq!(
+        negotiated_ack_bitfield_sizes(&stepper),
+        (AckBitfieldSize::Bits32, AckBitfieldSize::Bits32)
+    );
+}
@@ -0,0 +1,111 @@
+use bevy::prelude::State;
+use bevy::utils::Duration;
+
+use crate::prelude::client::{
+    ClientConfig, InterpolationConfig, NetworkingState, PredictionConfig, SyncConfig,
+};
+use crate::prelude::server::ServerConfig;
+use crate::prelude::*;
+use crate::tests::protocol::*;
+use crate::tests::stepper::{BevyStepper, Step};
+
+/// The server disconnects a client that sends a message that exceeds the decode limits
+#[test]
+fn test_disconnect_client_exceeding_decode_limits() {
+    let frame_duration = Duration::from_millis(10);
+    let tick_duration = Duration::from_millis(10);
+    let shared_config = SharedConfig {
+        tick: TickConfig::new(tick_duration),
+        ..Default::default()
+    };
+    let link_conditioner = LinkConditionerConfig {
+        incoming_latency: Duration::from_millis(0),
+        incoming_jitter: Duration::from_millis(0),
+        incoming_loss: 0.0,
+    };
+    let mut stepper = BevyStepper::new(
+        shared_config,
+        SyncConfig::default().speedup_factor(1.0),
+        PredictionConfig::default(),
+        InterpolationConfig::default(),
+        link_conditioner,
+        frame_duration,
+    );
+    stepper
+        .server_app
+        .world
+        .resource_mut::<ServerConfig>()
+        .packet
+        .decode_limits = DecodeLimits::default().with_max_message_size(100);
+    stepper.init();
+    let is_connected = |stepper: &BevyStepper| {
+        stepper
+            .server_app
+            .world
+            .resource::<ServerConnectionManager>()
+            .connection_stats(ClientId::Netcode(111))
+            .is_some()
+    };
+    assert!(is_connected(&stepper));
+
+    stepper
+        .client_app
+        .world
+        .resource_mut::<ClientConnectionManager>()
+        .send_message::<Channel1, Message1>(Message1("a".repeat(200)))
+        .unwrap();
+    for _ in 0..5 {
+        stepper.frame_step();
+    }
+    assert!(!is_connected(&stepper));
+}
+
+/// The client disconnects from a server that sends a message that exceeds its decode limits, instead of panicking
+#[test]
+fn test_client_disconnects_on_exceeding_decode_limits() {
+    let frame_duration = Duration::from_millis(10);
+    let tick_duration = Duration::from_millis(10);
+    let shared_config = SharedConfig {
+        tick: TickConfig::new(tick_duration),
+        ..Default::default()
+    };
+    let link_conditioner = LinkConditionerConfig {
+        incoming_latency: Duration::from_millis(0),
+        incoming_jitter: Duration::from_millis(0),
+        incoming_loss: 0.0,
+    };
+    let mut stepper = BevyStepper::new(
+        shared_config,
+        SyncConfig::default().speedup_factor(1.0),
+        PredictionConfig::default(),
+        InterpolationConfig::default(),
+        link_conditioner,
+        frame_duration,
+    );
+    stepper
+        .client_app
+        .world
+        .resource_mut::<ClientConfig>()
+        .packet
+        .decode_limits = DecodeLimits::default().with_max_message_size(100);
+    stepper.init();
+    let state = |stepper: &BevyStepper| {
+        *stepper
+            .client_app
+            .world
+            .resource::<State<NetworkingState>>()
+            .get()
+    };
+    assert_eq!(state(&stepper), NetworkingState::Connected);
+
+    stepper
+        .server_app
+        .world
+        .resource_mut::<ServerConnectionManager>()
+        .send_message::<Channel1, Message1>(ClientId::Netcode(111), Message1("a".repeat(200)))
+        .unwrap();
+    for _ in 0..5 {
+        stepper.frame_step();
+    }
+    assert_eq!(state(&stepper), NetworkingState::Disconnected);
+}
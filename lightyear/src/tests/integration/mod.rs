@@ -1,2 +1,4 @@
+mod ack_window;
+mod decode_limits;
 mod multi_transport;
 mod tick_wrapping;
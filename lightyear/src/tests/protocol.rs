@@ -1,5 +1,5 @@
 use bevy::ecs::entity::MapEntities;
-use bevy::prelude::{default, Component, Entity, EntityMapper, Reflect, Resource};
+use bevy::prelude::{default, Component, Entity, EntityMapper, Reflect, Resource, States};
 use cfg_if::cfg_if;
 use derive_more::{Add, Mul};
 use std::ops::Mul;
@@ -48,6 +48,9 @@ impl MapEntities for Component4 {
     }
 }
 
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct Component5(pub Vec<u32>);
+
 #[component_protocol_internal(protocol = "MyProtocol")]
 pub enum MyComponentsProtocol {
     #[protocol(sync(mode = "full"))]
@@ -58,13 +61,24 @@ pub enum MyComponentsProtocol {
     Component3(Component3),
     #[protocol(sync(mode = "simple"), map_entities)]
     Component4(Component4),
+    #[protocol(sync(mode = "simple"), delta)]
+    Component5(Component5),
     Resource1(ReplicateResource<Resource1>),
+    State1(ReplicateResource<ReplicatedState<State1>>),
 }
 
 // Resources
 #[derive(Resource, Serialize, Deserialize, Debug, PartialEq, Clone, Add, Reflect)]
 pub struct Resource1(pub f32);
 
+// States
+#[derive(States, Serialize, Deserialize, Debug, Default, PartialEq, Eq, Hash, Clone, Reflect)]
+pub enum State1 {
+    #[default]
+    Lobby,
+    InGame,
+}
+
 // Inputs
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Reflect)]
@@ -41,6 +41,17 @@ pub struct BevyStepper {
 
 impl Default for BevyStepper {
     fn default() -> Self {
+        Self::default_with_client_replication(client::ReplicationConfig::default())
+    }
+}
+
+// Do not forget to use --features mock_time when using the LinkConditioner
+impl BevyStepper {
+    /// Same as [`BevyStepper::default`], with a custom client replication config
+    /// (for example to enable client->server replication)
+    pub(crate) fn default_with_client_replication(
+        client_replication: client::ReplicationConfig,
+    ) -> Self {
         let frame_duration = Duration::from_millis(10);
         let tick_duration = Duration::from_millis(10);
         let shared_config = SharedConfig {
@@ -55,21 +66,19 @@ impl Default for BevyStepper {
         let sync_config = SyncConfig::default().speedup_factor(1.0);
         let prediction_config = PredictionConfig::default().disable(false);
         let interpolation_config = InterpolationConfig::default();
-        let mut stepper = Self::new(
+        let mut stepper = Self::new_with_client_replication(
             shared_config,
             sync_config,
             prediction_config,
             interpolation_config,
             link_conditioner,
             frame_duration,
+            client_replication,
         );
         stepper.init();
         stepper
     }
-}
 
-// Do not forget to use --features mock_time when using the LinkConditioner
-impl BevyStepper {
     pub fn new(
         shared_config: SharedConfig,
         sync_config: SyncConfig,
@@ -77,6 +86,26 @@ impl BevyStepper {
         interpolation_config: InterpolationConfig,
         conditioner: LinkConditionerConfig,
         frame_duration: Duration,
+    ) -> Self {
+        Self::new_with_client_replication(
+            shared_config,
+            sync_config,
+            prediction_config,
+            interpolation_config,
+            conditioner,
+            frame_duration,
+            client::ReplicationConfig::default(),
+        )
+    }
+
+    pub(crate) fn new_with_client_replication(
+        shared_config: SharedConfig,
+        sync_config: SyncConfig,
+        prediction_config: PredictionConfig,
+        interpolation_config: InterpolationConfig,
+        conditioner: LinkConditionerConfig,
+        frame_duration: Duration,
+        client_replication: client::ReplicationConfig,
     ) -> Self {
         // tracing_subscriber::FmtSubscriber::builder()
         //     // .with_span_events(FmtSpan::ENTER)
@@ -142,6 +171,7 @@ impl BevyStepper {
             sync: sync_config,
             prediction: prediction_config,
             interpolation: interpolation_config,
+            replication: client_replication,
             ..default()
         };
         let plugin_config = client::PluginConfig::new(config, protocol());
@@ -187,6 +217,8 @@ impl BevyStepper {
         self.server_app.world.resource::<TickManager>().tick()
     }
     pub(crate) fn init(&mut self) {
+        self.server_app.finish();
+        self.client_app.finish();
         self.server_app
             .world
             .run_system_once(|mut commands: Commands| commands.start_server());
@@ -7,8 +7,8 @@ use quote::{format_ident, quote};
 use std::ops::Deref;
 use syn::punctuated::Punctuated;
 use syn::{
-    parse_macro_input, parse_quote, Field, Fields, GenericParam, Generics, ItemEnum, MetaList,
-    PathArguments, Token, Type, TypeParam,
+    parse_macro_input, parse_quote, Field, Fields, GenericParam, Generics, ItemEnum, LitStr,
+    MetaList, PathArguments, Token, Type, TypeParam,
 };
 
 // TODO: use FromDeriveInput ?
@@ -31,6 +31,8 @@ struct AttrField {
     sync: Option<SyncField>,
     #[darling(default)]
     map_entities: MapField,
+    /// If set, the updates of the component are delta-compressed against the last value acked by the remote
+    delta: Flag,
 }
 
 #[derive(Debug, FromMeta, PartialEq, Eq)]
@@ -112,6 +114,15 @@ pub fn component_protocol_impl(
         #[protocol(map_entities)]
         ParentSync(ParentSync)
     });
+    input.variants.push(parse_quote! {
+        ChildrenSync(ChildrenSync)
+    });
+    input.variants.push(parse_quote! {
+        Authority(Authority)
+    });
+    input.variants.push(parse_quote! {
+        AuthorityTransfer(AuthorityTransfer)
+    });
     #[cfg(feature = "leafwing")]
     for i in 1..3 {
         let variant = Ident::new(&format!("ActionState{}", i), Span::call_site());
@@ -122,6 +133,10 @@ pub fn component_protocol_impl(
         });
     }
 
+    input.variants.push(parse_quote! {
+        Registered(RegisteredComponent)
+    });
+
     // Helper Properties
     let fields = get_fields(&input);
     let input_without_attributes = strip_attributes(&input, ATTRIBUTES);
@@ -155,12 +170,15 @@ pub fn component_protocol_impl(
     let map_entities_method = map_entities_method(&attr_fields, &input, &enum_kind_name);
     let insert_method = insert_method(&input, &fields);
     let update_method = update_method(&input, &fields);
+    let entity_components_method = entity_components_method(&fields);
     let type_ids_method = type_ids_method(&fields, &enum_kind_name);
+    let schema_method = schema_method(&attr_fields, &shared_crate_name);
 
     // EnumKind methods
     let enum_kind = get_enum_kind(&input, &enum_kind_name);
     let from_method = from_method(&input, &enum_kind_name, &fields);
     let remove_method = remove_method(&input, &fields, &enum_kind_name);
+    let delta_compression_method = delta_compression_method(&input, &attr_fields, &enum_kind_name);
 
     let gen = quote! {
         #[doc(hidden)]
@@ -171,7 +189,7 @@ pub fn component_protocol_impl(
             use #shared_crate_name::prelude::*;
             use #shared_crate_name::prelude::client::*;
             use bevy::ecs::entity::{EntityHashSet, MapEntities, EntityMapper};
-            use bevy::prelude::{App, Entity, IntoSystemConfigs, EntityWorldMut, World, Reflect};
+            use bevy::prelude::{App, Entity, IntoSystemConfigs, EntityRef, EntityWorldMut, World, Reflect};
             use bevy::utils::HashMap;
             use std::any::TypeId;
             use #shared_crate_name::shared::events::components::{ComponentInsertEvent, ComponentRemoveEvent, ComponentUpdateEvent};
@@ -187,8 +205,10 @@ pub fn component_protocol_impl(
                 type Protocol = #protocol;
 
                 #type_ids_method
+                #schema_method
                 #insert_method
                 #update_method
+                #entity_components_method
                 #add_resource_send_method
                 #add_resource_receive_method
                 #add_systems_method
@@ -228,12 +248,17 @@ pub fn component_protocol_impl(
 
             impl ComponentProtocolKind for #enum_kind_name {
                 type Protocol = #protocol;
+
+                fn from_registered(net_id: u16) -> Self {
+                    #enum_kind_name::Registered(net_id)
+                }
             }
 
             #from_method
 
             impl ComponentKindBehaviour for #enum_kind_name {
                 #remove_method
+                #delta_compression_method
             }
 
             impl std::fmt::Display for #enum_kind_name {
@@ -254,6 +279,11 @@ pub fn component_protocol_impl(
     proc_macro::TokenStream::from(gen)
 }
 
+/// Returns true if the field is the variant that holds the components registered at runtime
+fn is_registered(ident: &Option<Ident>) -> bool {
+    matches!(ident, Some(ident) if ident == "Registered")
+}
+
 fn add_resource_send_method(fields: &Vec<Field>, protocol_name: &Ident) -> TokenStream {
     let mut body = quote! {};
     for field in fields {
@@ -305,6 +335,13 @@ fn add_per_component_replication_send_systems_method(
     let mut body = quote! {};
     for field in fields {
         let component_type = &field.ty;
+        if is_registered(&field.ident) {
+            body = quote! {
+                #body
+                add_registered_replication_send_systems::<#protocol_name, R>(app);
+            };
+            continue;
+        }
         body = quote! {
             #body
             add_per_component_replication_send_systems::<#component_type, #protocol_name, R>(app);
@@ -322,6 +359,13 @@ fn push_component_events_method(fields: &Vec<Field>, protocol_name: &Ident) -> T
     let mut body = quote! {};
     for field in fields {
         let component_type = &field.ty;
+        if is_registered(&field.ident) {
+            body = quote! {
+                #body
+                push_registered_component_events::<#protocol_name, E, Ctx>(world, events);
+            };
+            continue;
+        }
         body = quote! {
             #body
             push_component_insert_events::<#component_type, #protocol_name, E, Ctx>(world, events);
@@ -348,6 +392,10 @@ fn push_component_events_method(fields: &Vec<Field>, protocol_name: &Ident) -> T
 fn add_events_method(fields: &Vec<Field>) -> TokenStream {
     let mut body = quote! {};
     for field in fields {
+        // the events of registered components are added when they are registered
+        if is_registered(&field.ident) {
+            continue;
+        }
         let component_type = &field.ty;
         body = quote! {
             #body
@@ -462,7 +510,15 @@ fn decode_method() -> TokenStream {
 
 fn get_enum_kind(input: &ItemEnum, enum_kind_name: &Ident) -> TokenStream {
     // we use the original enum's names for the kind enum
-    let variants = input.variants.iter().map(|v| v.ident.clone());
+    let variants = input.variants.iter().map(|v| {
+        let ident = &v.ident;
+        // the kind of a registered component is identified by its net id
+        if is_registered(&Some(ident.clone())) {
+            quote! { #ident(u16) }
+        } else {
+            quote! { #ident }
+        }
+    });
     quote! {
         pub enum #enum_kind_name {
             #(#variants),*
@@ -477,6 +533,13 @@ fn from_method(input: &ItemEnum, enum_kind_name: &Ident, fields: &Vec<Field>) ->
     for field in fields {
         let ident = &field.ident;
         let ty = &field.ty;
+        if is_registered(ident) {
+            body = quote! {
+                #body
+                &#enum_name::#ident(ref x) => #enum_kind_name::#ident(x.net_id()),
+            };
+            continue;
+        }
         from_type_body = quote! {
             #from_type_body
             impl FromType<#ty> for #enum_kind_name {
@@ -514,9 +577,18 @@ fn remove_method(input: &ItemEnum, fields: &[Field], enum_kind_name: &Ident) ->
 
     let mut field_body = quote! {};
     for (component_type, component_kind_name) in component_types.zip(component_kind_names) {
+        if is_registered(&Some(component_kind_name.clone())) {
+            field_body = quote! {
+                #field_body
+                #enum_kind_name::#component_kind_name(net_id) => RegisteredComponent::remove(net_id, entity),
+            };
+            continue;
+        }
         field_body = quote! {
             #field_body
-            #enum_kind_name::#component_kind_name => entity.remove::<#component_type>(),
+            #enum_kind_name::#component_kind_name => {
+                entity.remove::<#component_type>();
+            }
         };
     }
     quote! {
@@ -528,6 +600,37 @@ fn remove_method(input: &ItemEnum, fields: &[Field], enum_kind_name: &Ident) ->
     }
 }
 
+fn delta_compression_method(
+    input: &ItemEnum,
+    fields: &[AttrField],
+    enum_kind_name: &Ident,
+) -> TokenStream {
+    let component_kind_names = input.variants.iter().map(|v| &v.ident);
+
+    let mut field_body = quote! {};
+    for (field, component_kind_name) in fields.iter().zip(component_kind_names) {
+        let delta = field.delta.is_present();
+        if is_registered(&field.ident) {
+            field_body = quote! {
+                #field_body
+                #enum_kind_name::#component_kind_name(_) => #delta,
+            };
+            continue;
+        }
+        field_body = quote! {
+            #field_body
+            #enum_kind_name::#component_kind_name => #delta,
+        };
+    }
+    quote! {
+        fn delta_compression(&self) -> bool {
+            match self {
+                #field_body
+            }
+        }
+    }
+}
+
 // fn mode_method(input: &ItemEnum, fields: &Vec<AttrField>) -> TokenStream {
 //     let mut body = quote! {};
 //     for field in fields {
@@ -617,6 +720,13 @@ fn insert_method(input: &ItemEnum, fields: &Vec<Field>) -> TokenStream {
     for field in fields {
         let ident = &field.ident;
         let component_type = &field.ty;
+        if is_registered(ident) {
+            body = quote! {
+                #body
+                Self::#ident(x) => x.insert(entity),
+            };
+            continue;
+        }
         body = quote! {
             #body
             Self::#ident(x) => {
@@ -655,11 +765,44 @@ fn insert_method(input: &ItemEnum, fields: &Vec<Field>) -> TokenStream {
     }
 }
 
+fn entity_components_method(fields: &Vec<Field>) -> TokenStream {
+    let mut body = quote! {};
+    for field in fields {
+        let ident = &field.ident;
+        let component_type = &field.ty;
+        // the components registered at runtime are handled by the ProtocolRegistry
+        if is_registered(ident) {
+            continue;
+        }
+        body = quote! {
+            #body
+            if let Some(x) = entity.get::<#component_type>() {
+                components.push(Self::#ident(x.clone()));
+            }
+        };
+    }
+
+    quote! {
+        fn entity_components(entity: &EntityRef) -> Vec<Self> {
+            let mut components = Vec::new();
+            #body
+            components
+        }
+    }
+}
+
 fn update_method(input: &ItemEnum, fields: &Vec<Field>) -> TokenStream {
     let mut body = quote! {};
     for field in fields {
         let ident = &field.ident;
         let component_type = &field.ty;
+        if is_registered(ident) {
+            body = quote! {
+                #body
+                Self::#ident(x) => x.update(entity),
+            };
+            continue;
+        }
         body = quote! {
             #body
             Self::#ident(x) => {
@@ -712,11 +855,49 @@ fn update_method(input: &ItemEnum, fields: &Vec<Field>) -> TokenStream {
     }
 }
 
+fn schema_method(fields: &[AttrField], shared_crate_name: &TokenStream) -> TokenStream {
+    let mut body = quote! {};
+    for (net_id, field) in fields.iter().enumerate() {
+        let component_type = &field.ty;
+        let net_id = net_id as u16;
+        let name = LitStr::new(
+            &field.ident.as_ref().unwrap().to_string(),
+            Span::call_site(),
+        );
+        let sync_mode = match &field.sync {
+            Some(sync) => {
+                let mode = LitStr::new(&format!("{:?}", sync.mode), Span::call_site());
+                quote! { Some(#mode.to_string()) }
+            }
+            None => quote! { None },
+        };
+        let delta_compression = field.delta.is_present();
+        body = quote! {
+            #body
+            #shared_crate_name::protocol::schema::ComponentSchema {
+                net_id: #net_id,
+                name: #name.to_string(),
+                type_name: std::any::type_name::<#component_type>().to_string(),
+                sync_mode: #sync_mode,
+                delta_compression: #delta_compression,
+            },
+        };
+    }
+    quote! {
+        fn schema() -> Vec<#shared_crate_name::protocol::schema::ComponentSchema> {
+            vec![#body]
+        }
+    }
+}
+
 fn type_ids_method(fields: &Vec<Field>, enum_kind_name: &Ident) -> TokenStream {
     let mut body = quote! {
         let mut res = HashMap::default();
     };
     for field in fields {
+        if is_registered(&field.ident) {
+            continue;
+        }
         let component_type = &field.ty;
         body = quote! {
             #body
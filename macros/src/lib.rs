@@ -11,10 +11,12 @@ use syn::{parse_macro_input, ItemEnum};
 use channel::channel_impl;
 use component::component_protocol_impl;
 use message::message_protocol_impl;
+use quantize::quantize_impl;
 
 mod channel;
 mod component;
 mod message;
+mod quantize;
 mod shared;
 
 // Channel
@@ -74,3 +76,24 @@ pub fn component_protocol(
     let shared_crate_name = quote! { lightyear };
     component_protocol_impl(args, input, shared_crate_name)
 }
+
+// Quantization
+
+#[doc(hidden)]
+#[proc_macro_derive(QuantizeInternal, attributes(protocol))]
+pub fn quantize_derive_internal(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let shared_crate_name = quote! { crate };
+    quantize_impl(input, shared_crate_name)
+}
+
+/// Derives `Serialize` and `Deserialize` for a struct, where the fields can be bit-packed:
+/// - `#[protocol(quantize(min = -1000.0, max = 1000.0, bits = 18))]` on a float or a vector of floats
+///   clamps each float to `[min, max]` and serializes it with `bits` bits
+/// - `#[protocol(bits = 5)]` on an integer serializes it with `bits` bits (values that don't fit are clamped)
+///
+/// The other fields are serialized normally.
+#[proc_macro_derive(Quantize, attributes(protocol))]
+pub fn quantize_derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let shared_crate_name = quote! { lightyear };
+    quantize_impl(input, shared_crate_name)
+}
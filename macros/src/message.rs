@@ -68,6 +68,10 @@ pub fn message_protocol_impl(
         InputMessage(#shared_crate_name::inputs::native::InputMessage<<#protocol as Protocol>::Input>)
     });
 
+    input.variants.push(parse_quote! {
+        Registered(#shared_crate_name::protocol::registration::RegisteredMessage)
+    });
+
     #[cfg(feature = "leafwing")]
     for i in 1..3 {
         let variant = Ident::new(&format!("LeafwingInput{}Message", i), Span::call_site());
@@ -101,6 +105,7 @@ pub fn message_protocol_impl(
     let add_events_method = add_events_method(&fields);
     let push_message_events_method = push_message_events_method(&fields, protocol);
     let name_method = name_method(&input, &fields);
+    let schema_method = schema_method(&fields, &shared_crate_name);
     let map_entities_impl = map_entities_impl(&input, &fields);
     let encode_method = encode_method();
     let decode_method = decode_method();
@@ -115,6 +120,7 @@ pub fn message_protocol_impl(
             use #shared_crate_name::_reexport::*;
             use #shared_crate_name::prelude::*;
             use #shared_crate_name::shared::events::systems::push_message_events;
+            use #shared_crate_name::protocol::registration::push_registered_message_events;
 
             #[derive(Serialize, Deserialize, Clone, PartialEq)]
             #extra_derives
@@ -126,6 +132,7 @@ pub fn message_protocol_impl(
                 #name_method
                 #message_kind_method
                 #input_message_kind_method
+                #schema_method
                 #add_events_method
                 #push_message_events_method
             }
@@ -152,10 +159,23 @@ pub fn message_protocol_impl(
     proc_macro::TokenStream::from(output)
 }
 
+/// Returns true if the field is the variant that holds the messages registered at runtime
+fn is_registered(field: &AttrField) -> bool {
+    matches!(&field.ident, Some(ident) if ident == "Registered")
+}
+
 fn push_message_events_method(fields: &Vec<AttrField>, protocol_name: &Ident) -> TokenStream {
     let mut body = quote! {};
     for field in fields {
         let message_type = &field.ty;
+        // registered messages are dispatched to the events of their actual type
+        if is_registered(field) {
+            body = quote! {
+                #body
+                push_registered_message_events::<#protocol_name, E, Ctx>(world, events);
+            };
+            continue;
+        }
         body = quote! {
             #body
             push_message_events::<#message_type, #protocol_name, E, Ctx>(world, events);
@@ -276,6 +296,10 @@ fn input_message_kind_method(input: &ItemEnum) -> TokenStream {
 fn add_events_method(fields: &Vec<AttrField>) -> TokenStream {
     let mut body = quote! {};
     for field in fields {
+        // the events of registered messages are added when they are registered
+        if is_registered(field) {
+            continue;
+        }
         let component_type = &field.ty;
         body = quote! {
             #body
@@ -310,6 +334,31 @@ fn name_method(input: &ItemEnum, fields: &Vec<AttrField>) -> TokenStream {
     }
 }
 
+fn schema_method(fields: &[AttrField], shared_crate_name: &TokenStream) -> TokenStream {
+    let mut body = quote! {};
+    for (net_id, field) in fields.iter().enumerate() {
+        let message_type = &field.ty;
+        let net_id = net_id as u16;
+        let name = LitStr::new(
+            &field.ident.as_ref().unwrap().to_string(),
+            Span::call_site(),
+        );
+        body = quote! {
+            #body
+            #shared_crate_name::protocol::schema::MessageSchema {
+                net_id: #net_id,
+                name: #name.to_string(),
+                type_name: std::any::type_name::<#message_type>().to_string(),
+            },
+        };
+    }
+    quote! {
+        fn schema() -> Vec<#shared_crate_name::protocol::schema::MessageSchema> {
+            vec![#body]
+        }
+    }
+}
+
 fn map_entities_impl(input: &ItemEnum, fields: &Vec<AttrField>) -> TokenStream {
     let enum_name = &input.ident;
     let mut map_entities_body = quote! {};
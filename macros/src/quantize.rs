@@ -0,0 +1,220 @@
+use darling::ast::{Data, Style};
+use darling::{Error, FromDeriveInput, FromField, FromMeta};
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, DeriveInput, Expr, Generics, Ident, Index, Lit, Type, UnOp};
+
+#[derive(Debug, FromDeriveInput)]
+#[darling(
+    attributes(protocol),
+    supports(struct_named, struct_newtype, struct_tuple)
+)]
+struct QuantizeInput {
+    ident: Ident,
+    generics: Generics,
+    data: Data<(), QuantizeField>,
+}
+
+#[derive(Debug, FromField)]
+#[darling(attributes(protocol))]
+struct QuantizeField {
+    ident: Option<Ident>,
+    ty: Type,
+    /// Quantize a float (or a vector of floats) in the given range
+    #[darling(default)]
+    quantize: Option<QuantizeRange>,
+    /// Number of bits used to serialize an integer
+    #[darling(default)]
+    bits: Option<u8>,
+}
+
+#[derive(Debug, FromMeta)]
+struct QuantizeRange {
+    min: Float,
+    max: Float,
+    bits: u8,
+}
+
+/// A float literal, that can be negative
+#[derive(Debug)]
+struct Float(f64);
+
+impl FromMeta for Float {
+    fn from_expr(expr: &Expr) -> darling::Result<Self> {
+        match expr {
+            Expr::Lit(lit) => match &lit.lit {
+                Lit::Float(f) => f.base10_parse().map(Float).map_err(Error::from),
+                Lit::Int(i) => i.base10_parse().map(Float).map_err(Error::from),
+                _ => Err(Error::unexpected_lit_type(&lit.lit)),
+            },
+            Expr::Unary(unary) if matches!(unary.op, UnOp::Neg(_)) => {
+                Self::from_expr(&unary.expr).map(|f| Float(-f.0))
+            }
+            Expr::Group(group) => Self::from_expr(&group.expr),
+            _ => Err(Error::unexpected_expr_type(expr)),
+        }
+        .map_err(|e| e.with_span(expr))
+    }
+}
+
+fn float_tokens(f: f64) -> TokenStream {
+    if f < 0.0 {
+        let f = -f;
+        quote! { -#f }
+    } else {
+        quote! { #f }
+    }
+}
+
+fn check_bits(bits: u8) -> darling::Result<()> {
+    if !(1..=64).contains(&bits) {
+        return Err(Error::custom("the number of bits must be between 1 and 64"));
+    }
+    Ok(())
+}
+
+pub fn quantize_impl(
+    input: proc_macro::TokenStream,
+    shared_crate_name: TokenStream,
+) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match quantize_impl_inner(&input, shared_crate_name) {
+        Ok(gen) => gen.into(),
+        Err(e) => e.write_errors().into(),
+    }
+}
+
+fn quantize_impl_inner(
+    input: &DeriveInput,
+    shared_crate_name: TokenStream,
+) -> darling::Result<TokenStream> {
+    let input = QuantizeInput::from_derive_input(input)?;
+    if !input.generics.params.is_empty() {
+        return Err(
+            Error::custom("Quantize cannot be derived on generic structs")
+                .with_span(&input.generics),
+        );
+    }
+    let struct_name = &input.ident;
+    let fields = input.data.take_struct().unwrap();
+
+    let mut errors = Error::accumulator();
+    let mut serialize_body = quote! {};
+    let mut deserialize_body = quote! {};
+    let mut variables = vec![];
+    for (i, field) in fields.iter().enumerate() {
+        let ty = &field.ty;
+        let member = match &field.ident {
+            Some(ident) => quote! { #ident },
+            None => {
+                let index = Index::from(i);
+                quote! { #index }
+            }
+        };
+        let variable = format_ident!("field_{}", i);
+        let (serialized, wire_ty, value) = match (&field.quantize, field.bits) {
+            (Some(_), Some(_)) => {
+                errors.push(
+                    Error::custom("`quantize` and `bits` cannot be used on the same field")
+                        .with_span(ty),
+                );
+                continue;
+            }
+            (Some(QuantizeRange { min, max, bits }), None) => {
+                if let Err(e) = check_bits(*bits) {
+                    errors.push(e.with_span(ty));
+                    continue;
+                }
+                if min.0 >= max.0 {
+                    errors.push(Error::custom("`min` must be smaller than `max`").with_span(ty));
+                    continue;
+                }
+                let (min, max) = (float_tokens(min.0), float_tokens(max.0));
+                (
+                    quote! { &Quantized::<#ty, #bits>::new(&self.#member, #min, #max) },
+                    quote! { Quantized<#ty, #bits> },
+                    quote! { value.get(#min, #max) },
+                )
+            }
+            (None, Some(bits)) => {
+                if let Err(e) = check_bits(bits) {
+                    errors.push(e.with_span(ty));
+                    continue;
+                }
+                (
+                    quote! { &Packed::<#bits>::from_int(self.#member) },
+                    quote! { Packed<#bits> },
+                    quote! { value.to_int::<#ty>() },
+                )
+            }
+            (None, None) => (quote! { &self.#member }, quote! { #ty }, quote! { value }),
+        };
+        serialize_body = quote! {
+            #serialize_body
+            tuple.serialize_element(#serialized)?;
+        };
+        deserialize_body = quote! {
+            #deserialize_body
+            let #variable = {
+                let value = seq
+                    .next_element::<#wire_ty>()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(#i, &self))?;
+                #value
+            };
+        };
+        variables.push((field.ident.clone(), variable));
+    }
+    errors.finish()?;
+
+    let construct = match fields.style {
+        Style::Struct => {
+            let fields = variables
+                .iter()
+                .map(|(ident, variable)| quote! { #ident: #variable });
+            quote! { #struct_name { #(#fields),* } }
+        }
+        _ => {
+            let fields = variables.iter().map(|(_, variable)| variable);
+            quote! { #struct_name ( #(#fields),* ) }
+        }
+    };
+    let num_fields = variables.len();
+    let expecting = format!("struct {}", struct_name);
+
+    Ok(quote! {
+        const _: () = {
+            use #shared_crate_name::_reexport::serde;
+            use #shared_crate_name::serialize::quantize::{Packed, Quantized};
+
+            impl serde::Serialize for #struct_name {
+                fn serialize<S: serde::Serializer>(&self, serializer: S) -> ::core::result::Result<S::Ok, S::Error> {
+                    use serde::ser::SerializeTuple;
+                    let mut tuple = serializer.serialize_tuple(#num_fields)?;
+                    #serialize_body
+                    tuple.end()
+                }
+            }
+
+            impl<'de> serde::Deserialize<'de> for #struct_name {
+                fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> ::core::result::Result<Self, D::Error> {
+                    struct QuantizeVisitor;
+
+                    impl<'de> serde::de::Visitor<'de> for QuantizeVisitor {
+                        type Value = #struct_name;
+
+                        fn expecting(&self, formatter: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+                            formatter.write_str(#expecting)
+                        }
+
+                        fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> ::core::result::Result<Self::Value, A::Error> {
+                            #deserialize_body
+                            ::core::result::Result::Ok(#construct)
+                        }
+                    }
+
+                    deserializer.deserialize_tuple(#num_fields, QuantizeVisitor)
+                }
+            }
+        };
+    })
+}